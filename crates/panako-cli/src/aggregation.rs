@@ -0,0 +1,180 @@
+//! Temporal aggregation of detections across consecutive monitoring segments
+
+use crate::merge::{merge_into, MERGE_TOLERANCE_S};
+use panako_core::matching::QueryResult;
+
+/// Default weight kept by older evidence per elapsed segment
+const DEFAULT_DECAY: f64 = 0.8;
+
+/// Default number of segments without a match before a detection is closed
+const DEFAULT_MAX_GAP_SEGMENTS: usize = 1;
+
+/// Accumulated score at which confidence reaches 1 - 1/e (~63%)
+const EVIDENCE_SCALE: f64 = 20.0;
+
+/// A detection being extended by consecutive segments
+struct Track {
+    detection: QueryResult,
+    evidence: f64,
+    segments: usize,
+    last_segment: usize,
+}
+
+impl Track {
+    fn finish(mut self) -> QueryResult {
+        self.detection.segment_count = Some(self.segments);
+        self.detection.confidence = Some(1.0 - (-self.evidence / EVIDENCE_SCALE).exp());
+        self.detection
+    }
+}
+
+/// Accumulates evidence for a reference across consecutive segments
+///
+/// Segment results are fed in order with `push_segment`. Detections of the
+/// same reference that agree on the query-to-reference offset are combined
+/// into one detection spanning all of them. Older evidence is multiplied by
+/// the decay factor for every segment that passes, so recent matches weigh
+/// more, and a reference seen in several consecutive segments ends up with a
+/// higher confidence than a one-off hit. A detection is reported once it has
+/// not been extended for more than `max_gap_segments` segments.
+pub struct TemporalAggregator {
+    decay: f64,
+    max_gap_segments: usize,
+    tracks: Vec<Track>,
+}
+
+impl Default for TemporalAggregator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TemporalAggregator {
+    pub fn new() -> Self {
+        Self {
+            decay: DEFAULT_DECAY,
+            max_gap_segments: DEFAULT_MAX_GAP_SEGMENTS,
+            tracks: Vec::new(),
+        }
+    }
+
+    /// Weight kept by older evidence per elapsed segment (0-1)
+    pub fn with_decay(mut self, decay: f64) -> Self {
+        self.decay = decay.clamp(0.0, 1.0);
+        self
+    }
+
+    /// Number of segments without a match tolerated before closing a detection
+    pub fn with_max_gap_segments(mut self, max_gap_segments: usize) -> Self {
+        self.max_gap_segments = max_gap_segments;
+        self
+    }
+
+    /// Add the results of one segment
+    ///
+    /// Returns the detections that can no longer be extended, sorted by
+    /// query start.
+    pub fn push_segment(&mut self, segment_index: usize, results: Vec<QueryResult>) -> Vec<QueryResult> {
+        for result in results {
+            if result.ref_identifier.is_none() {
+                continue;
+            }
+
+            let offset = result.query_start - result.ref_start;
+            let track = self.tracks.iter_mut().find(|t| {
+                t.last_segment < segment_index
+                    && t.detection.ref_identifier == result.ref_identifier
+                    && (t.detection.query_start - t.detection.ref_start - offset).abs() <= MERGE_TOLERANCE_S
+            });
+
+            match track {
+                Some(track) => {
+                    let elapsed = (segment_index - track.last_segment) as i32;
+                    track.evidence = track.evidence * self.decay.powi(elapsed) + result.score as f64;
+                    track.segments += 1;
+                    track.last_segment = segment_index;
+                    merge_into(&mut track.detection, result);
+                }
+                None => self.tracks.push(Track {
+                    evidence: result.score as f64,
+                    detection: result,
+                    segments: 1,
+                    last_segment: segment_index,
+                }),
+            }
+        }
+
+        let max_gap = self.max_gap_segments;
+        let (ended, active): (Vec<_>, Vec<_>) = std::mem::take(&mut self.tracks)
+            .into_iter()
+            .partition(|t| segment_index - t.last_segment > max_gap);
+        self.tracks = active;
+
+        finish_tracks(ended)
+    }
+
+    /// Close all remaining detections, sorted by query start
+    pub fn finish(self) -> Vec<QueryResult> {
+        finish_tracks(self.tracks)
+    }
+}
+
+fn finish_tracks(tracks: Vec<Track>) -> Vec<QueryResult> {
+    let mut detections: Vec<QueryResult> = tracks.into_iter().map(Track::finish).collect();
+    detections.sort_by(|a, b| {
+        a.query_start.partial_cmp(&b.query_start).unwrap_or(std::cmp::Ordering::Equal)
+    });
+    detections
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn detection(reference: &str, segment: usize, query_start: f64, query_stop: f64, ref_start: f64) -> QueryResult {
+        let mut result = QueryResult::empty("stream".to_string(), query_start, query_stop);
+        result.ref_identifier = Some(reference.to_string());
+        result.ref_path = Some(reference.to_string());
+        result.ref_start = ref_start;
+        result.ref_stop = ref_start + (query_stop - query_start);
+        result.score = 20;
+        result.segment_index = Some(segment);
+        result
+    }
+
+    #[test]
+    fn test_consecutive_segments_aggregate() {
+        // A 60 s program starting at 10 s, seen by three 25 s segments with 5 s overlap
+        let mut aggregator = TemporalAggregator::new();
+        let mut reported = Vec::new();
+        reported.extend(aggregator.push_segment(0, vec![detection("program", 0, 10.0, 25.0, 0.0)]));
+        reported.extend(aggregator.push_segment(1, vec![detection("program", 1, 20.0, 45.0, 10.0)]));
+        reported.extend(aggregator.push_segment(2, vec![detection("program", 2, 40.0, 65.0, 30.0)]));
+        assert!(reported.is_empty());
+        reported.extend(aggregator.finish());
+
+        let mut one_off = TemporalAggregator::new();
+        one_off.push_segment(0, vec![detection("program", 0, 10.0, 25.0, 0.0)]);
+        let single = one_off.finish().remove(0);
+
+        assert_eq!(reported.len(), 1);
+        let aggregated = &reported[0];
+        assert_eq!(aggregated.segment_count, Some(3));
+        assert!((aggregated.query_start - 10.0).abs() < 1e-9);
+        assert!((aggregated.query_stop - 65.0).abs() < 1e-9);
+        assert!(aggregated.confidence.unwrap() > 0.9);
+        assert!(aggregated.confidence.unwrap() > single.confidence.unwrap());
+    }
+
+    #[test]
+    fn test_gap_closes_detection() {
+        let mut aggregator = TemporalAggregator::new();
+        aggregator.push_segment(0, vec![detection("spot", 0, 5.0, 15.0, 0.0)]);
+        aggregator.push_segment(1, vec![]);
+        let closed = aggregator.push_segment(2, vec![detection("spot", 2, 42.0, 50.0, 0.0)]);
+
+        assert_eq!(closed.len(), 1);
+        assert_eq!(closed[0].segment_count, Some(1));
+        assert_eq!(aggregator.finish().len(), 1);
+    }
+}
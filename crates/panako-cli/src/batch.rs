@@ -0,0 +1,489 @@
+//! Matching many queries against one database
+//!
+//! Building the matcher index is the expensive part of a match run, so
+//! `BatchMatcher` builds it once and then answers any number of queries.
+
+use anyhow::Result;
+use panako_core::audio::{AudioData, AudioFormat, DecodeCache};
+use panako_core::config::PanakoConfig;
+use panako_core::storage_config::CorruptFilePolicy;
+use panako_core::matching::{Matcher, QueryResult};
+use panako_core::pipeline::{decode_input, open_decode_cache};
+use panako_fp::{FingerprintParams, FpJsonFile};
+use rayon::prelude::*;
+use std::path::{Path, PathBuf};
+use std::sync::{Condvar, Mutex};
+
+use crate::database::is_fingerprint_file;
+use crate::fingerprinting::fingerprint_audio;
+use crate::merge::{debounce_detections, merge_detections_within_gap, MERGE_TOLERANCE_S};
+
+/// Counting semaphore bounding how many decoded files are held at once
+///
+/// Each decode of a media query holds its whole `AudioData` until it is
+/// fingerprinted, so with many worker threads memory grows with the core
+/// count. The limiter caps that independently of the thread count.
+#[derive(Debug)]
+pub struct DecodeLimiter {
+    max_in_flight: usize,
+    in_flight: Mutex<usize>,
+    released: Condvar,
+}
+
+/// Slot held while decoding; released on drop
+pub struct DecodePermit<'a> {
+    limiter: &'a DecodeLimiter,
+}
+
+impl DecodeLimiter {
+    /// Allow at most `max_in_flight` (at least 1) concurrent decodes
+    pub fn new(max_in_flight: usize) -> Self {
+        Self {
+            max_in_flight: max_in_flight.max(1),
+            in_flight: Mutex::new(0),
+            released: Condvar::new(),
+        }
+    }
+
+    /// Block until a decode slot is free
+    pub fn acquire(&self) -> DecodePermit<'_> {
+        let mut in_flight = self.in_flight.lock().unwrap();
+        while *in_flight >= self.max_in_flight {
+            in_flight = self.released.wait(in_flight).unwrap();
+        }
+        *in_flight += 1;
+        DecodePermit { limiter: self }
+    }
+}
+
+impl Drop for DecodePermit<'_> {
+    fn drop(&mut self) {
+        *self.limiter.in_flight.lock().unwrap() -= 1;
+        self.limiter.released.notify_one();
+    }
+}
+
+/// A matcher index built once and reused for many query files
+pub struct BatchMatcher {
+    matcher: Matcher,
+    config: PanakoConfig,
+    merge_segments: bool,
+    decode_limiter: Option<DecodeLimiter>,
+    decode_cache: Option<DecodeCache>,
+}
+
+impl BatchMatcher {
+    /// Wrap an already built matcher
+    ///
+    /// Opens the decode cache when `config.decode_cache_dir` is set; it is
+    /// shared by every query decoded through this matcher.
+    pub fn new(matcher: Matcher, config: PanakoConfig) -> Result<Self> {
+        let decode_cache = open_decode_cache(&config)?;
+        Ok(Self {
+            matcher,
+            config,
+            merge_segments: false,
+            decode_limiter: None,
+            decode_cache,
+        })
+    }
+
+    /// Build the matcher index from a fingerprint database directory
+    ///
+    /// The index is built by `Matcher::index_directory`. All references must
+    /// share one sample rate; if it differs from `config.sample_rate`, the
+    /// config is switched to it (see `PanakoConfig::with_sample_rate`) so
+    /// queries are analysed the same way.
+    pub fn from_database_dir(db_dir: &Path, config: PanakoConfig) -> Result<Self> {
+        Self::from_database_dir_with_policy(db_dir, config, CorruptFilePolicy::SkipWithWarning)
+    }
+
+    /// Like `from_database_dir`, with an explicit policy for corrupt files
+    ///
+    /// With `CorruptFilePolicy::Collect` the references that loaded are
+    /// matched and every file that could not be read is logged as an error.
+    pub fn from_database_dir_with_policy(
+        db_dir: &Path,
+        config: PanakoConfig,
+        policy: CorruptFilePolicy,
+    ) -> Result<Self> {
+        let index = Matcher::index_directory(db_dir, config, policy)?;
+        if !index.failed.is_empty() {
+            let paths: Vec<String> = index.failed.iter().map(|p| p.display().to_string()).collect();
+            log::error!("{} fingerprint files failed to load: {}", index.failed.len(), paths.join(", "));
+        }
+        Self::new(index.matcher, index.config)
+    }
+
+    /// Merge duplicate detections reported by overlapping query segments
+    pub fn with_merge_segments(mut self, merge_segments: bool) -> Self {
+        self.merge_segments = merge_segments;
+        self
+    }
+
+    /// Decode at most `max_decodes` media queries at a time in `match_files`
+    pub fn with_max_concurrent_decodes(mut self, max_decodes: usize) -> Self {
+        self.decode_limiter = Some(DecodeLimiter::new(max_decodes));
+        self
+    }
+
+    /// The underlying matcher
+    pub fn matcher(&self) -> &Matcher {
+        &self.matcher
+    }
+
+    /// The matching configuration
+    pub fn config(&self) -> &PanakoConfig {
+        &self.config
+    }
+
+    /// Decode a media file at the configured sample rate, through the decode
+    /// cache when one is configured
+    pub fn decode_audio(&self, path: &str) -> Result<AudioData> {
+        decode_input(path, &self.config, self.decode_cache.as_ref())
+    }
+
+    /// Match one query file: a fingerprint file (JSON/BSON) or an audio/video file
+    pub fn match_file(&self, query_path: &Path) -> Result<Vec<QueryResult>> {
+        if !query_path.exists() {
+            anyhow::bail!("Query file not found: {}", query_path.display());
+        }
+
+        if is_fingerprint_file(query_path) {
+            let query_file = FpJsonFile::load_auto(query_path)?;
+            self.match_fingerprint_file(query_path, &query_file)
+        } else {
+            self.matcher.check_query_params(&self.config.fingerprint_params())?;
+            let fingerprints = {
+                // The decoded audio is dropped before the slot is released
+                let _permit = self.decode_limiter.as_ref().map(DecodeLimiter::acquire);
+                let audio = self.decode_audio(query_path.to_str().unwrap())?;
+                fingerprint_audio(&audio, &self.config)?
+            };
+            let query_fps: Vec<(u64, i32, i16, f32)> = fingerprints
+                .iter()
+                .map(|fp| (fp.hash, fp.t1, fp.f1, fp.m1))
+                .collect();
+            self.matcher.query(query_path.to_str().unwrap(), &query_fps, &self.config)
+        }
+    }
+
+    /// Match several query files in parallel on the rayon thread pool
+    ///
+    /// Results are returned in the order of `query_paths`. Concurrent decodes
+    /// are bounded by `with_max_concurrent_decodes`, if set.
+    pub fn match_files(&self, query_paths: &[PathBuf]) -> Vec<Result<Vec<QueryResult>>> {
+        query_paths.par_iter().map(|path| self.match_file(path)).collect()
+    }
+
+    /// Match a loaded fingerprint file, per segment if it has several
+    ///
+    /// Fails if the query was fingerprinted at a different sample rate than
+    /// the database, as its hashes and frame times would not be comparable.
+    pub fn match_fingerprint_file(&self, query_path: &Path, query_file: &FpJsonFile) -> Result<Vec<QueryResult>> {
+        let parts = query_parts(query_path, query_file, &self.config, |params| {
+            self.matcher.check_query_params(params)
+        })?;
+
+        let query_name = query_path.to_str().unwrap();
+        let mut results = Vec::with_capacity(parts.len());
+        for part in &parts {
+            results.push(self.matcher.query(query_name, &part.fingerprints, &self.config)?);
+        }
+        Ok(combine_part_results(&parts, results, self.merge_segments, &self.config))
+    }
+}
+
+/// Fingerprints of a query fingerprint file matched in one query
+#[derive(Debug, Clone)]
+pub struct QueryPart {
+    pub fingerprints: Vec<(u64, i32, i16, f32)>,
+    /// Segment id and start time (s), when the file is matched per segment
+    pub segment: Option<(usize, f64)>,
+}
+
+/// Split a query fingerprint file into the queries to run: the whole file,
+/// or each segment if it has several
+///
+/// Shared by every matcher backend, which run the parts and pass their
+/// results to `combine_part_results`. Fails if the query was fingerprinted
+/// at another sample rate than `config.sample_rate`, or if `check_params`
+/// rejects the query's fingerprint parameters (when the file has them).
+pub fn query_parts(
+    query_path: &Path,
+    query_file: &FpJsonFile,
+    config: &PanakoConfig,
+    check_params: impl FnOnce(&FingerprintParams) -> Result<()>,
+) -> Result<Vec<QueryPart>> {
+    if query_file.metadata.sample_rate != config.sample_rate {
+        anyhow::bail!(
+            "Query {} was fingerprinted at {} Hz but the database uses {} Hz",
+            query_path.display(),
+            query_file.metadata.sample_rate,
+            config.sample_rate
+        );
+    }
+    if let Some(params) = &query_file.metadata.fingerprint_params {
+        check_params(params)?;
+    }
+
+    log::info!(
+        "Query has {} fingerprints",
+        query_file.segments.iter().map(|s| s.fingerprints.len()).sum::<usize>()
+    );
+    if query_file.segments.len() <= 1 {
+        return Ok(vec![QueryPart {
+            fingerprints: query_file.get_all_fingerprints(),
+            segment: None,
+        }]);
+    }
+
+    log::info!("Query file has {} segments, processing individually...", query_file.segments.len());
+    Ok(query_file
+        .segments
+        .iter()
+        .map(|segment| QueryPart {
+            fingerprints: segment.fingerprints.iter().map(|fp| (fp.hash, fp.t1, fp.f1, fp.m1)).collect(),
+            segment: Some((segment.segment_id, segment.start_time_s)),
+        })
+        .collect())
+}
+
+/// Join the results of the parts from `query_parts` (in the same order)
+///
+/// Segment results are tagged with their segment and post-processed by
+/// `merge_segment_results`; a whole-file query is returned as is.
+pub fn combine_part_results(
+    parts: &[QueryPart],
+    part_results: Vec<Vec<QueryResult>>,
+    merge_segments: bool,
+    config: &PanakoConfig,
+) -> Vec<QueryResult> {
+    let mut results = Vec::new();
+    let mut segmented = false;
+    for (part, mut part_results) in parts.iter().zip(part_results) {
+        if let Some((segment_id, start_time_s)) = part.segment {
+            segmented = true;
+            for res in &mut part_results {
+                res.segment_index = Some(segment_id);
+                res.segment_start = Some(start_time_s);
+                res.from_segmented = true;
+            }
+        }
+        results.extend(part_results);
+    }
+
+    if segmented {
+        merge_segment_results(results, merge_segments, config)
+    } else {
+        results
+    }
+}
+
+/// Post-process the detections of a query matched segment by segment
+///
+/// Joins duplicates from overlapping segments (if `merge_segments` or
+/// `max_detection_gap_s` is set) and plays split by short dropouts, then
+/// applies `detection_debounce_s`.
+pub fn merge_segment_results(
+    mut results: Vec<QueryResult>,
+    merge_segments: bool,
+    config: &PanakoConfig,
+) -> Vec<QueryResult> {
+    let max_gap_s = config.max_detection_gap_s;
+    if merge_segments || max_gap_s.is_some() {
+        let before = results.len();
+        results = merge_detections_within_gap(results, max_gap_s.unwrap_or(MERGE_TOLERANCE_S));
+        log::info!("Merged {} segment detections into {}", before, results.len());
+    }
+    if let Some(min_gap_s) = config.detection_debounce_s {
+        results = debounce_detections(results, min_gap_s);
+    }
+    results
+}
+
+/// List the query files (fingerprint or media files) in a directory, sorted
+pub fn list_query_files(dir: &Path) -> Result<Vec<PathBuf>> {
+    if !dir.exists() {
+        anyhow::bail!("Query directory not found: {}", dir.display());
+    }
+
+    let mut files: Vec<PathBuf> = std::fs::read_dir(dir)?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| {
+            path.is_file()
+                && (is_fingerprint_file(path) || AudioFormat::from_path(path) != AudioFormat::Unknown)
+        })
+        .collect();
+    files.sort();
+
+    Ok(files)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use panako_fp::{FpJsonFingerprint, FpJsonSegment};
+
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("panako_batch_{}_{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn fingerprint_file(filename: &str, hash_base: u64, time_offset: i32) -> FpJsonFile {
+        let fingerprints: Vec<FpJsonFingerprint> = (0..12)
+            .map(|i| FpJsonFingerprint { hash: hash_base + i, t1: time_offset + i as i32 * 100, f1: 50, m1: 1.0 })
+            .collect();
+        let mut fp_file = FpJsonFile::new(format!("/audio/{}.wav", filename), filename.to_string(), 16000, 10000, 1);
+        fp_file.add_segment(FpJsonSegment {
+            segment_id: 0,
+            start_time_s: 0.0,
+            end_time_s: 10.0,
+            num_fingerprints: fingerprints.len(),
+            fingerprints,
+        });
+        fp_file
+    }
+
+    #[test]
+    fn test_batch_matcher_reuses_index() {
+        let db = test_dir("db");
+        let queries = test_dir("queries");
+        for (name, base) in [("ref_a", 1000), ("ref_b", 2000), ("ref_c", 3000)] {
+            fingerprint_file(name, base, 0).save(&db.join(format!("{}.json", name))).unwrap();
+        }
+        fingerprint_file("clip_1", 1000, 500).save(&queries.join("clip_1.json")).unwrap();
+        fingerprint_file("clip_2", 2000, 0).save_bson(&queries.join("clip_2.bson")).unwrap();
+        fingerprint_file("clip_3", 3000, 250).save(&queries.join("clip_3.json")).unwrap();
+        std::fs::write(queries.join("notes.txt"), "ignored").unwrap();
+
+        let batch = BatchMatcher::from_database_dir(&db, PanakoConfig::default()).unwrap();
+        // The index lives in memory: the database is not read again per query
+        std::fs::remove_dir_all(&db).unwrap();
+
+        let files = list_query_files(&queries).unwrap();
+        let matched: Vec<Option<String>> = files
+            .iter()
+            .map(|path| batch.match_file(path).unwrap()[0].ref_identifier.clone())
+            .collect();
+        std::fs::remove_dir_all(&queries).ok();
+
+        assert_eq!(files.len(), 3);
+        assert_eq!(
+            matched,
+            vec![Some("ref_a".to_string()), Some("ref_b".to_string()), Some("ref_c".to_string())]
+        );
+    }
+
+    #[test]
+    fn test_segmented_results_are_flagged() {
+        let db = test_dir("segmented_flag");
+        fingerprint_file("ref_a", 1000, 0).save(&db.join("ref_a.json")).unwrap();
+        let batch = BatchMatcher::from_database_dir(&db, PanakoConfig::default()).unwrap();
+        std::fs::remove_dir_all(&db).ok();
+
+        let whole = fingerprint_file("clip", 1000, 0);
+        let results = batch.match_fingerprint_file(Path::new("clip.json"), &whole).unwrap();
+        assert_eq!(results[0].ref_identifier, Some("ref_a".to_string()));
+        assert!(!results[0].from_segmented);
+        assert_eq!(results[0].segment_index, None);
+
+        let mut segmented = whole.clone();
+        let mut second = segmented.segments[0].clone();
+        second.segment_id = 1;
+        second.start_time_s = 5.0;
+        second.end_time_s = 15.0;
+        segmented.add_segment(second);
+        let results = batch.match_fingerprint_file(Path::new("clip.json"), &segmented).unwrap();
+        assert!(!results.is_empty());
+        for result in &results {
+            assert_eq!(result.ref_identifier, Some("ref_a".to_string()));
+            assert!(result.from_segmented);
+            assert!(result.segment_index.is_some());
+        }
+    }
+
+    #[test]
+    fn test_batch_matcher_sample_rates() {
+        let mut hi_res = fingerprint_file("hi_res", 1000, 0);
+        hi_res.metadata.sample_rate = 22050;
+
+        // Mixed databases are rejected
+        let mixed = test_dir("mixed_rates");
+        hi_res.save(&mixed.join("hi_res.json")).unwrap();
+        fingerprint_file("lo_res", 2000, 0).save(&mixed.join("lo_res.json")).unwrap();
+        let err = BatchMatcher::from_database_dir(&mixed, PanakoConfig::default()).err().unwrap();
+        std::fs::remove_dir_all(&mixed).ok();
+        assert!(err.to_string().contains("mixes sample rates"));
+
+        // A 22.05 kHz database switches the matcher to that rate
+        let db = test_dir("hi_rate");
+        hi_res.save(&db.join("hi_res.json")).unwrap();
+        let batch = BatchMatcher::from_database_dir(&db, PanakoConfig::default()).unwrap();
+        std::fs::remove_dir_all(&db).ok();
+        assert_eq!(batch.config().sample_rate, 22050);
+
+        let query = hi_res.clone();
+        let results = batch.match_fingerprint_file(Path::new("hi_res.json"), &query).unwrap();
+        assert_eq!(results[0].ref_identifier, Some("hi_res".to_string()));
+
+        // 16 kHz queries cannot be compared against it
+        let lo_query = fingerprint_file("lo_res", 1000, 0);
+        assert!(batch.match_fingerprint_file(Path::new("lo_res.json"), &lo_query).is_err());
+    }
+
+    #[test]
+    fn test_decode_limiter_bounds_concurrency() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let max_observed = |limit: usize| -> usize {
+            let limiter = DecodeLimiter::new(limit);
+            let active = AtomicUsize::new(0);
+            let peak = AtomicUsize::new(0);
+            let pool = rayon::ThreadPoolBuilder::new().num_threads(4).build().unwrap();
+            pool.install(|| {
+                (0..16).into_par_iter().for_each(|_| {
+                    let _permit = limiter.acquire();
+                    let now = active.fetch_add(1, Ordering::SeqCst) + 1;
+                    peak.fetch_max(now, Ordering::SeqCst);
+                    std::thread::sleep(std::time::Duration::from_millis(5));
+                    active.fetch_sub(1, Ordering::SeqCst);
+                });
+            });
+            peak.load(Ordering::SeqCst)
+        };
+
+        assert_eq!(max_observed(1), 1);
+        assert!(max_observed(2) <= 2);
+    }
+
+    #[test]
+    fn test_match_files_keeps_query_order() {
+        let db = test_dir("parallel_db");
+        let queries = test_dir("parallel_queries");
+        for (name, base) in [("ref_a", 1000), ("ref_b", 2000)] {
+            fingerprint_file(name, base, 0).save(&db.join(format!("{}.json", name))).unwrap();
+        }
+        fingerprint_file("clip_1", 2000, 0).save(&queries.join("clip_1.json")).unwrap();
+        fingerprint_file("clip_2", 1000, 0).save(&queries.join("clip_2.json")).unwrap();
+
+        let batch = BatchMatcher::from_database_dir(&db, PanakoConfig::default())
+            .unwrap()
+            .with_max_concurrent_decodes(1);
+        let mut files = list_query_files(&queries).unwrap();
+        files.push(queries.join("missing.json"));
+        let results = batch.match_files(&files);
+        std::fs::remove_dir_all(&db).ok();
+        std::fs::remove_dir_all(&queries).ok();
+
+        assert_eq!(results.len(), 3);
+        assert_eq!(results[0].as_ref().unwrap()[0].ref_identifier.as_deref(), Some("ref_b"));
+        assert_eq!(results[1].as_ref().unwrap()[0].ref_identifier.as_deref(), Some("ref_a"));
+        // The stored original path is reported, not the identifier
+        assert_eq!(results[1].as_ref().unwrap()[0].ref_path.as_deref(), Some("/audio/ref_a.wav"));
+        assert!(results[2].is_err());
+    }
+}
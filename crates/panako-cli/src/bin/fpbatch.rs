@@ -0,0 +1,151 @@
+//! fpbatch - Match many query files against one database
+//!
+//! Builds the matcher index once, then matches every query file from a
+//! directory (or one path per line on stdin), writing one JSON result per line.
+//!
+//! Usage:
+//!   fpbatch <db_dir> <query_dir>
+//!   find clips -name '*.json' | fpbatch <db_dir>
+//!   fpbatch --jobs 8 --max-decodes 2 <db_dir> <query_dir>
+
+use anyhow::Result;
+use clap::Parser;
+use panako_cli::batch::{list_query_files, BatchMatcher};
+use panako_cli::output::{write_jsonl_results, OutputFormat, QueryPosition};
+use panako_core::config::PanakoConfig;
+use std::io::BufRead;
+use std::path::{Path, PathBuf};
+
+#[derive(Parser, Debug)]
+#[command(name = "fpbatch")]
+#[command(about = "Match a batch of query files against a database", long_about = None)]
+struct Args {
+    /// Database directory containing fingerprint files
+    db_dir: String,
+
+    /// Directory of query files (reads query paths from stdin if omitted)
+    query_dir: Option<String>,
+
+    /// Merge duplicate detections reported by overlapping query segments
+    #[arg(long)]
+    merge_segments: bool,
+
+    /// Merge detections of the same reference separated by gaps of up to this
+    /// many seconds (e.g. brief dropouts)
+    #[arg(long)]
+    max_gap: Option<f64>,
+
+    /// Report at most one detection of a reference per burst of detections
+    /// less than this many seconds apart (e.g. one airing counted as one play)
+    #[arg(long)]
+    debounce: Option<f64>,
+
+    /// Result layout: raw matcher fields, labeled query/segment/reference times,
+    /// or Java Panako-compatible lines
+    #[arg(long, value_enum, default_value_t = OutputFormat::Raw)]
+    output_format: OutputFormat,
+
+    /// Number of query files matched in parallel
+    #[arg(long, default_value_t = 1)]
+    jobs: usize,
+
+    /// Maximum number of media queries decoded at the same time (bounds
+    /// memory independently of --jobs; default: no limit)
+    #[arg(long)]
+    max_decodes: Option<usize>,
+
+    /// Kill FFmpeg (MPEG-TS input) after this many seconds and fail the file
+    #[arg(long)]
+    ffmpeg_timeout_s: Option<f64>,
+
+    /// Extra FFmpeg attempts after a failed or timed out run
+    #[arg(long, default_value_t = 0)]
+    ffmpeg_retries: u32,
+
+    /// Enable verbose logging
+    #[arg(short, long)]
+    verbose: bool,
+}
+
+fn main() -> Result<()> {
+    let args = Args::parse();
+
+    // Initialize logger
+    if args.verbose {
+        env_logger::Builder::from_default_env()
+            .filter_level(log::LevelFilter::Info)
+            .init();
+    } else {
+        env_logger::Builder::from_default_env()
+            .filter_level(log::LevelFilter::Warn)
+            .init();
+    }
+
+    log::info!("Loading database from: {}", args.db_dir);
+    let config = PanakoConfig {
+        max_detection_gap_s: args.max_gap,
+        detection_debounce_s: args.debounce,
+        ffmpeg_timeout_s: args.ffmpeg_timeout_s,
+        ffmpeg_retries: args.ffmpeg_retries,
+        ..Default::default()
+    };
+    config.validate()?;
+    let mut batch = BatchMatcher::from_database_dir(Path::new(&args.db_dir), config)?
+        .with_merge_segments(args.merge_segments);
+    if let Some(max_decodes) = args.max_decodes {
+        batch = batch.with_max_concurrent_decodes(max_decodes);
+    }
+    let pool = rayon::ThreadPoolBuilder::new().num_threads(args.jobs.max(1)).build()?;
+
+    // Queries read from stdin are not counted in advance
+    let (mut query_files, total): (Box<dyn Iterator<Item = PathBuf>>, usize) = match &args.query_dir {
+        Some(dir) => {
+            let files = list_query_files(Path::new(dir))?;
+            let total = files.len();
+            (Box::new(files.into_iter()), total)
+        }
+        None => (
+            Box::new(
+                std::io::stdin()
+                    .lock()
+                    .lines()
+                    .map_while(|line| line.ok())
+                    .filter(|line| !line.trim().is_empty())
+                    .map(|line| PathBuf::from(line.trim())),
+            ),
+            0,
+        ),
+    };
+
+    let mut stdout = std::io::stdout().lock();
+    let mut failed = 0;
+    let mut index = 0;
+    // One file per job at a time, so stdin input is still processed as it arrives
+    loop {
+        let chunk: Vec<PathBuf> = query_files.by_ref().take(args.jobs.max(1)).collect();
+        if chunk.is_empty() {
+            break;
+        }
+        let chunk_results = pool.install(|| batch.match_files(&chunk));
+        for (query_path, result) in chunk.iter().zip(chunk_results) {
+            index += 1;
+            match result {
+                Ok(results) => {
+                    let position = QueryPosition { index, total };
+                    write_jsonl_results(&mut stdout, &results, args.output_format, position)?;
+                }
+                Err(e) => {
+                    // Keep going: one bad clip should not stop a monitoring pipeline
+                    log::warn!("Failed to match {}: {}", query_path.display(), e);
+                    failed += 1;
+                }
+            }
+        }
+    }
+
+    if failed > 0 {
+        anyhow::bail!("{} query files failed", failed);
+    }
+
+    Ok(())
+}
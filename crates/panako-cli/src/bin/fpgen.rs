@@ -4,16 +4,22 @@
 
 use anyhow::{Context, Result};
 use clap::Parser;
+use panako_cli::fingerprinting::offset_segment_fingerprints;
+use panako_cli::{cancel_on_ctrl_c, CancellationToken};
 use panako_core::{
+    analysis::silence_regions,
     audio::AudioData,
     config::PanakoConfig,
-    eventpoint::EventPointExtractor,
-    fingerprint::FingerprintGenerator,
+    pipeline::{decode_input, fingerprint_audio_timed, open_decode_cache, PipelineTimings},
     segmentation::{segment_audio, should_segment, SegmentationConfig},
+    storage_backend::{resolve_duplicate, DuplicatePolicy, FilesystemBackend, IngestOutcome},
     storage_config::{FileFormat, PanakoStorageConfig},
-    transform,
 };
-use panako_fp::{FpJsonFile, FpJsonFingerprint, FpJsonSegment, SegmentationInfo, SegmentMetadata};
+use panako_fp::{
+    FpJsonFile, FpJsonFingerprint, FpJsonMetadata, FpJsonSegment, SegmentationInfo,
+    SegmentMetadata,
+};
+use std::collections::HashMap;
 use std::path::Path;
 
 #[derive(Parser, Debug)]
@@ -30,19 +36,127 @@ struct Args {
     #[arg(short, long)]
     monitor: bool,
 
-    /// Path to configuration file (TOML)
+    /// Path to configuration file (TOML). If not provided, config.toml is
+    /// looked up in the current directory, then in $XDG_CONFIG_HOME/panako
     #[arg(short, long)]
     config: Option<String>,
 
+    /// Ignore any config.toml and use the built-in defaults
+    #[arg(long, conflicts_with = "config")]
+    no_config: bool,
+
     /// Override output format (json or bson)
     #[arg(long)]
     format: Option<String>,
 
+    /// Maximum accepted audio duration in seconds (rejects longer inputs)
+    #[arg(long)]
+    max_duration: Option<f64>,
+
+    /// Analysis sample rate in Hz (e.g. 22050 to keep content above 8 kHz)
+    #[arg(long)]
+    sample_rate: Option<u32>,
+
+    /// Transform long inputs in parallel chunks of this many seconds
+    #[arg(long)]
+    transform_chunk_s: Option<f64>,
+
+    /// Kill FFmpeg (MPEG-TS input) after this many seconds and fail the file
+    #[arg(long)]
+    ffmpeg_timeout_s: Option<f64>,
+
+    /// Extra FFmpeg attempts after a failed or timed out run
+    #[arg(long, default_value_t = 0)]
+    ffmpeg_retries: u32,
+
+    /// Cache decoded audio in this directory, so runs over unchanged inputs
+    /// (e.g. with other analysis parameters) skip decoding
+    #[arg(long)]
+    decode_cache_dir: Option<String>,
+
+    /// Identifier to store instead of the input file stem (also names the output file)
+    #[arg(long, value_parser = parse_identifier)]
+    identifier: Option<String>,
+
+    /// Original path to record instead of the input path
+    #[arg(long)]
+    original_path: Option<String>,
+
+    /// Custom metadata tag (repeatable), e.g. --tag title="Spring Sale"
+    #[arg(long = "tag", value_parser = parse_tag)]
+    tags: Vec<(String, String)>,
+
+    /// External content ID of the reference (ISRC, EIDR, ...), reported with every match
+    #[arg(long)]
+    content_id: Option<String>,
+
+    /// RMS level (linear, 0..1) below which audio is reported as a silent region
+    #[arg(long, default_value_t = 0.001)]
+    silence_threshold: f32,
+
+    /// Skip the input if audio with the same content hash is already in the output directory
+    ///
+    /// Content hashes are cached in `content_hashes.idx` there, so each run
+    /// only reads the fingerprint files written since the previous one.
+    #[arg(long)]
+    skip_duplicates: bool,
+
     /// Enable verbose logging
     #[arg(short, long)]
     verbose: bool,
 }
 
+/// Explicit metadata values that override those derived from the input path
+#[derive(Debug, Clone, Default)]
+struct MetadataOverrides {
+    identifier: Option<String>,
+    original_path: Option<String>,
+    tags: HashMap<String, String>,
+    content_id: Option<String>,
+}
+
+impl MetadataOverrides {
+    fn from_args(args: &Args) -> Self {
+        Self {
+            identifier: args.identifier.clone(),
+            original_path: args.original_path.clone(),
+            tags: args.tags.iter().cloned().collect(),
+            content_id: args.content_id.clone(),
+        }
+    }
+
+    fn apply(&self, metadata: &mut FpJsonMetadata) {
+        if let Some(identifier) = &self.identifier {
+            metadata.filename = identifier.clone();
+        }
+        if let Some(original_path) = &self.original_path {
+            metadata.original_path = original_path.clone();
+        }
+        metadata.tags.extend(self.tags.clone());
+        if let Some(content_id) = &self.content_id {
+            metadata.content_id = Some(content_id.clone());
+        }
+    }
+}
+
+/// Parse a `key=value` tag argument
+fn parse_tag(s: &str) -> std::result::Result<(String, String), String> {
+    match s.split_once('=') {
+        Some((key, value)) if !key.trim().is_empty() => {
+            Ok((key.trim().to_string(), value.to_string()))
+        }
+        _ => Err(format!("invalid tag '{}', expected key=value", s)),
+    }
+}
+
+/// Parse an `--identifier`, which names the output file in the output directory
+fn parse_identifier(s: &str) -> std::result::Result<String, String> {
+    if s.is_empty() || s == "." || s == ".." || s.contains(['/', '\\']) {
+        return Err(format!("invalid identifier '{}', it must be a plain file name", s));
+    }
+    Ok(s.to_string())
+}
+
 fn main() -> Result<()> {
     let args = Args::parse();
 
@@ -58,23 +172,13 @@ fn main() -> Result<()> {
     }
 
     // Determine format from args or config
-    let mut format = FileFormat::Json; // Default
-    
-    // 1. Check config file first
-    if let Some(config_path) = &args.config {
-        if let Ok(config) = PanakoStorageConfig::load(Path::new(config_path)) {
-            format = config.storage.filesystem.format;
-        } else {
-            log::warn!("Failed to load config file, using defaults");
-        }
-    } else {
-        // Try default config.toml if exists
-        if Path::new("config.toml").exists() {
-             if let Ok(config) = PanakoStorageConfig::load(Path::new("config.toml")) {
-                format = config.storage.filesystem.format;
-            }
-        }
-    }
+    // 1. Check config file first (explicit, discovered, or defaults)
+    let (storage_config, _) = PanakoStorageConfig::load_discovered(
+        args.config.as_deref().map(Path::new),
+        args.no_config,
+        PanakoStorageConfig::default_filesystem,
+    )?;
+    let mut format = storage_config.storage.filesystem.format;
 
     // 2. Override with CLI argument if provided
     if let Some(fmt_str) = &args.format {
@@ -93,18 +197,56 @@ fn main() -> Result<()> {
         format = FileFormat::Json;
     }
 
+    // Load configuration
+    let mut config = PanakoConfig::default();
+    if let Some(rate) = args.sample_rate {
+        config = config.with_sample_rate(rate);
+    }
+    config.parallel_transform_chunk_s = args.transform_chunk_s;
+    config.ffmpeg_timeout_s = args.ffmpeg_timeout_s;
+    config.ffmpeg_retries = args.ffmpeg_retries;
+    config.decode_cache_dir = args.decode_cache_dir.clone();
+    if let Some(max_s) = args.max_duration {
+        config.max_duration_ms = Some((max_s * 1000.0) as u32);
+    }
+    config.validate()?;
+
     // Run fingerprint generation
-    run_fpgen(&args.input_audio_path, &args.output_dir, args.monitor, format)?;
+    let overrides = MetadataOverrides::from_args(&args);
+    let options = RunOptions {
+        use_monitor_mode: args.monitor,
+        format,
+        skip_duplicates: args.skip_duplicates,
+        silence_threshold: args.silence_threshold,
+    };
+    run_fpgen(
+        &args.input_audio_path,
+        &args.output_dir,
+        &options,
+        &config,
+        &overrides,
+    )?;
 
     Ok(())
 }
 
+/// How a single input is processed and written
+struct RunOptions {
+    use_monitor_mode: bool,
+    format: FileFormat,
+    skip_duplicates: bool,
+    silence_threshold: f32,
+}
+
 fn run_fpgen(
     input_path: &str, 
     output_dir: &str, 
-    use_monitor_mode: bool,
-    format: FileFormat
+    options: &RunOptions,
+    config: &PanakoConfig,
+    overrides: &MetadataOverrides,
 ) -> Result<()> {
+    let use_monitor_mode = options.use_monitor_mode;
+    let format = options.format.clone();
     let input_path = Path::new(input_path);
     let output_dir = Path::new(output_dir);
 
@@ -117,19 +259,16 @@ fn run_fpgen(
     std::fs::create_dir_all(output_dir)
         .with_context(|| format!("Failed to create output directory: {}", output_dir.display()))?;
 
-    // Load configuration
-    let config = PanakoConfig::default();
-    config.validate()?;
-
     log::info!("Processing: {}", input_path.display());
 
     // Decode audio
     let start = std::time::Instant::now();
-    let audio_data = panako_core::audio::decode_audio(
-        input_path.to_str().unwrap(),
-        config.sample_rate,
-    )?;
-    let _decode_time = start.elapsed();
+    let audio_data = decode_input(input_path.to_str().unwrap(), config, open_decode_cache(config)?.as_ref())?;
+    panako_core::audio::check_max_duration(&audio_data, config.max_duration_ms)?;
+    let mut timings = PipelineTimings {
+        decode: start.elapsed(),
+        ..Default::default()
+    };
 
     log::info!(
         "Decoded audio: {:.1}s duration, {} samples @ {}Hz",
@@ -138,6 +277,40 @@ fn run_fpgen(
         audio_data.sample_rate
     );
 
+    // Extract filename without extension (unless overridden)
+    let filename = match &overrides.identifier {
+        Some(identifier) => identifier.clone(),
+        None => input_path
+            .file_stem()
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .to_string(),
+    };
+
+    // Skip re-ingestion of audio that is already in the database
+    let backend = FilesystemBackend::from_path(output_dir.to_str().unwrap(), format.clone());
+    let content_hash = audio_data.content_hash();
+    if options.skip_duplicates {
+        let runtime = tokio::runtime::Runtime::new()?;
+        let outcome = runtime.block_on(resolve_duplicate(
+            &backend,
+            &filename,
+            Some(&content_hash),
+            DuplicatePolicy::Skip,
+        ))?;
+        if let IngestOutcome::Skipped { existing } = outcome {
+            let result = serde_json::json!({
+                "status": "duplicate",
+                "input_file": input_path.display().to_string(),
+                "existing_identifier": existing,
+                "content_hash": content_hash,
+            });
+            println!("{}", serde_json::to_string_pretty(&result)?);
+            return Ok(());
+        }
+    }
+
     // Check if monitor mode is enabled and segmentation is needed
     let seg_config = SegmentationConfig::default();
     let use_segmentation = use_monitor_mode && should_segment(&audio_data, &seg_config);
@@ -150,7 +323,15 @@ fn run_fpgen(
             seg_config.overlap_duration_s
         );
 
-        process_with_segmentation(&audio_data, &config, &seg_config)?
+        // Ctrl-C stops between segments from here on; before, it exits as usual
+        let cancel = cancel_on_ctrl_c();
+        let (fingerprints, info, total) =
+            process_with_segmentation(&audio_data, config, &seg_config, &mut timings, &cancel)?;
+        if cancel.is_cancelled() {
+            let done = info.as_ref().map_or(0, |info| info.num_segments);
+            anyhow::bail!("Interrupted after {} of {} segments; no fingerprint file written", done, total);
+        }
+        (fingerprints, info, total)
     } else {
         if use_monitor_mode {
             log::info!(
@@ -162,26 +343,36 @@ fn run_fpgen(
             log::info!("Normal mode - Processing as single file");
         }
 
-        let fingerprints = generate_fingerprints_from_audio(&audio_data, &config)?;
+        let (fingerprints, stage_timings) = fingerprint_audio_timed(&audio_data, config)?;
+        timings += stage_timings;
         (fingerprints, None, 1)
     };
 
     let elapsed = start.elapsed();
 
+    // Silent stretches produce no fingerprints; report them as coverage gaps
+    let silence = silence_regions(&audio_data, options.silence_threshold);
+    if !silence.is_empty() {
+        log::info!(
+            "{} silent regions ({:.1}s total) without fingerprints",
+            silence.len(),
+            silence.iter().map(|(start, end)| end - start).sum::<f64>()
+        );
+    }
+
     log::info!(
         "Generated {} fingerprints in {:.2}s ({} segments)",
         all_fingerprints.len(),
         elapsed.as_secs_f64(),
         total_segments
     );
-
-    // Extract filename without extension
-    let filename = input_path
-        .file_stem()
-        .unwrap()
-        .to_str()
-        .unwrap()
-        .to_string();
+    log::info!(
+        "Stage timings: decode {:.3}s, transform {:.3}s, event points {:.3}s, fingerprints {:.3}s",
+        timings.decode.as_secs_f64(),
+        timings.transform.as_secs_f64(),
+        timings.event_points.as_secs_f64(),
+        timings.fingerprints.as_secs_f64()
+    );
 
     // Create output filename based on format
     let ext = match format {
@@ -194,11 +385,14 @@ fn run_fpgen(
     // Create fingerprint file object
     let mut fp_file = FpJsonFile::new(
         input_path.to_str().unwrap().to_string(),
-        filename,
+        filename.clone(),
         config.sample_rate,
         audio_data.duration_ms,
         1, // mono
     );
+    fp_file.metadata.content_hash = Some(content_hash);
+    fp_file.metadata.fingerprint_params = Some(config.fingerprint_params());
+    overrides.apply(&mut fp_file.metadata);
 
     // Add segmentation info if applicable
     if use_segmentation {
@@ -212,33 +406,14 @@ fn run_fpgen(
     // Build segments
     if let Some(seg_info) = &segmentation_info {
         // Multiple segments
-        for seg_meta in &seg_info.segments {
-            let start_idx = seg_meta.fingerprint_offset as usize;
-            let end_idx = start_idx + seg_meta.num_fingerprints as usize;
-            
-            let segment = FpJsonSegment {
-                segment_id: seg_meta.segment_id,
-                start_time_s: seg_meta.start_time_ms as f64 / 1000.0,
-                end_time_s: seg_meta.end_time_ms as f64 / 1000.0,
-                num_fingerprints: seg_meta.num_fingerprints as usize,
-                fingerprints: all_fingerprints[start_idx..end_idx]
-                    .iter()
-                    .map(|fp| FpJsonFingerprint {
-                        hash: fp.hash,
-                        t1: fp.t1,
-                        f1: fp.f1,
-                        m1: fp.m1,
-                    })
-                    .collect(),
-            };
-            
+        for segment in json_segments(seg_info, &all_fingerprints)? {
             fp_file.add_segment(segment);
         }
     } else {
         // Single segment
         let end_time_s = all_fingerprints
             .last()
-            .map(|fp| fp.t1 as f64 * 0.008)
+            .map(|fp| fp.t1 as f64 * config.frame_duration_s())
             .unwrap_or(audio_data.duration_ms as f64 / 1000.0);
         
         let segment = FpJsonSegment {
@@ -261,10 +436,7 @@ fn run_fpgen(
     }
 
     // Save file based on format
-    match format {
-        FileFormat::Bson => fp_file.save_bson(&output_path)?,
-        _ => fp_file.save(&output_path)?,
-    }
+    backend.save_file(&filename, &fp_file)?;
 
     // Print JSON output for CLI (still returning JSON status)
     let mut result = serde_json::json!({
@@ -274,6 +446,13 @@ fn run_fpgen(
         "format": ext,
         "num_fingerprints": all_fingerprints.len(),
         "processing_time_seconds": elapsed.as_secs_f64(),
+        "stage_time_seconds": {
+            "decode": timings.decode.as_secs_f64(),
+            "transform": timings.transform.as_secs_f64(),
+            "event_points": timings.event_points.as_secs_f64(),
+            "fingerprints": timings.fingerprints.as_secs_f64(),
+        },
+        "silence_regions": silence,
     });
 
     if use_segmentation {
@@ -288,14 +467,67 @@ fn run_fpgen(
 }
 
 
+/// Split whole-file fingerprints into the JSON segments `seg_info` describes
+///
+/// Fails instead of panicking when the metadata is inconsistent: a range
+/// beyond the fingerprints or overlapping the previous segment's.
+fn json_segments(
+    seg_info: &SegmentationInfo,
+    all_fingerprints: &[panako_core::Fingerprint],
+) -> Result<Vec<FpJsonSegment>> {
+    let mut previous_end = 0;
+    let mut segments = Vec::with_capacity(seg_info.segments.len());
+    for seg_meta in &seg_info.segments {
+        let start_idx = seg_meta.fingerprint_offset as usize;
+        let end_idx = start_idx + seg_meta.num_fingerprints as usize;
+        if start_idx < previous_end {
+            anyhow::bail!(
+                "Segment {} starts at fingerprint {}, inside the previous segment (which ends at {})",
+                seg_meta.segment_id,
+                start_idx,
+                previous_end
+            );
+        }
+        let segment_fps = all_fingerprints.get(start_idx..end_idx).with_context(|| {
+            format!(
+                "Segment {} covers fingerprints {}..{} but only {} exist",
+                seg_meta.segment_id,
+                start_idx,
+                end_idx,
+                all_fingerprints.len()
+            )
+        })?;
+        previous_end = end_idx;
+
+        segments.push(FpJsonSegment {
+            segment_id: seg_meta.segment_id,
+            start_time_s: seg_meta.start_time_ms as f64 / 1000.0,
+            end_time_s: seg_meta.end_time_ms as f64 / 1000.0,
+            num_fingerprints: segment_fps.len(),
+            fingerprints: segment_fps
+                .iter()
+                .map(|fp| FpJsonFingerprint {
+                    hash: fp.hash,
+                    t1: fp.t1,
+                    f1: fp.f1,
+                    m1: fp.m1,
+                })
+                .collect(),
+        });
+    }
+    Ok(segments)
+}
+
 /// Process audio with segmentation (monitor mode)
 fn process_with_segmentation(
     audio_data: &AudioData,
     config: &PanakoConfig,
     seg_config: &SegmentationConfig,
+    timings: &mut PipelineTimings,
+    cancel: &CancellationToken,
 ) -> Result<(Vec<panako_core::Fingerprint>, Option<SegmentationInfo>, usize)> {
     // Segment the audio
-    let segments = segment_audio(audio_data, seg_config);
+    let segments = segment_audio(audio_data, seg_config)?;
     log::info!(
         "Created {} segments with {}s overlap",
         segments.len(),
@@ -306,6 +538,10 @@ fn process_with_segmentation(
     let mut segment_metadata = Vec::new();
 
     for segment in &segments {
+        if cancel.is_cancelled() {
+            log::warn!("Cancelled after {} of {} segments", segment_metadata.len(), segments.len());
+            break;
+        }
         log::debug!(
             "Processing segment {}: {:.1}s - {:.1}s ({:.1}s duration)",
             segment.segment_id,
@@ -323,25 +559,16 @@ fn process_with_segmentation(
         };
 
         // Generate fingerprints for this segment
-        let segment_fps = generate_fingerprints_from_audio(&segment_audio, config)?;
+        let (segment_fps, segment_timings) = fingerprint_audio_timed(&segment_audio, config)?;
+        *timings += segment_timings;
 
         // Adjust timestamps to absolute time
-        let time_offset_frames = (segment.start_time_s / 0.008) as i32;
-        let adjusted_fps: Vec<_> = segment_fps
-            .iter()
-            .map(|fp| {
-                let mut adjusted = fp.clone();
-                adjusted.t1 += time_offset_frames;
-                adjusted.t2 += time_offset_frames;
-                adjusted.t3 += time_offset_frames;
-                adjusted
-            })
-            .collect();
+        let adjusted_fps = offset_segment_fingerprints(&segment_fps, segment.start_time_s, config);
 
         log::debug!(
-            "  Generated {} fingerprints (offset: {} frames)",
+            "  Generated {} fingerprints (offset: {:.1}s)",
             adjusted_fps.len(),
-            time_offset_frames
+            segment.start_time_s
         );
 
         // Store segment metadata
@@ -356,8 +583,9 @@ fn process_with_segmentation(
         all_fingerprints.extend(adjusted_fps);
     }
 
+    // Only the segments processed before a cancellation
     let segmentation_info = SegmentationInfo {
-        num_segments: segments.len(),
+        num_segments: segment_metadata.len(),
         segment_duration_ms: (seg_config.segment_duration_s * 1000.0) as u32,
         overlap_duration_ms: (seg_config.overlap_duration_s * 1000.0) as u32,
         segments: segment_metadata,
@@ -366,22 +594,122 @@ fn process_with_segmentation(
     Ok((all_fingerprints, Some(segmentation_info), segments.len()))
 }
 
-/// Generate fingerprints from audio data
-fn generate_fingerprints_from_audio(
-    audio: &AudioData,
-    config: &PanakoConfig,
-) -> Result<Vec<panako_core::Fingerprint>> {
-    // Convert to mono
-    let mono_samples = audio.to_mono();
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_metadata_overrides_written_to_file() {
+        let args = Args::parse_from([
+            "fpgen",
+            "/tmp/upload_1234.wav",
+            "./out",
+            "--identifier",
+            "RA00123-25",
+            "--original-path",
+            "/catalog/spots/RA00123-25.wav",
+            "--tag",
+            "title=Spring Sale",
+            "--tag",
+            "advertiser=ACME",
+            "--content-id",
+            "USRC17607839",
+        ]);
+        let overrides = MetadataOverrides::from_args(&args);
+
+        let mut fp_file = FpJsonFile::new(
+            args.input_audio_path.clone(),
+            "upload_1234".to_string(),
+            16000,
+            30000,
+            1,
+        );
+        overrides.apply(&mut fp_file.metadata);
+
+        let path = std::env::temp_dir().join(format!("panako_fpgen_overrides_{}.json", std::process::id()));
+        fp_file.save(&path).unwrap();
+        let loaded = FpJsonFile::load(&path).unwrap();
+        std::fs::remove_file(&path).ok();
+
+        assert_eq!(loaded.metadata.filename, "RA00123-25");
+        assert_eq!(loaded.metadata.original_path, "/catalog/spots/RA00123-25.wav");
+        assert_eq!(loaded.metadata.tags.get("title").map(String::as_str), Some("Spring Sale"));
+        assert_eq!(loaded.metadata.tags.get("advertiser").map(String::as_str), Some("ACME"));
+        assert_eq!(loaded.metadata.content_id.as_deref(), Some("USRC17607839"));
+    }
+
+    #[test]
+    fn test_identifier_must_be_a_file_name() {
+        for identifier in ["../escape", "..", "spots/RA00123", "spots\\RA00123", ""] {
+            let parsed = Args::try_parse_from(["fpgen", "in.wav", "./out", "--identifier", identifier]);
+            assert!(parsed.is_err(), "{:?} accepted", identifier);
+        }
+        let parsed = Args::try_parse_from(["fpgen", "in.wav", "./out", "--identifier", "RA00123..25"]).unwrap();
+        assert_eq!(parsed.identifier.as_deref(), Some("RA00123..25"));
+    }
+
+    #[test]
+    fn test_inconsistent_segment_metadata_is_an_error() {
+        let fingerprints: Vec<panako_core::Fingerprint> = (0..10)
+            .map(|i| {
+                panako_core::Fingerprint::new(
+                    &panako_core::EventPoint::new(i, 40, 1.0),
+                    &panako_core::EventPoint::new(i + 5, 50, 1.0),
+                    &panako_core::EventPoint::new(i + 10, 60, 1.0),
+                )
+            })
+            .collect();
+        let info = |ranges: &[(u32, u32)]| SegmentationInfo {
+            num_segments: ranges.len(),
+            segment_duration_ms: 10000,
+            overlap_duration_ms: 0,
+            segments: ranges
+                .iter()
+                .enumerate()
+                .map(|(i, &(offset, count))| SegmentMetadata {
+                    segment_id: i,
+                    start_time_ms: i as u32 * 10000,
+                    end_time_ms: i as u32 * 10000 + 10000,
+                    num_fingerprints: count,
+                    fingerprint_offset: offset,
+                })
+                .collect(),
+        };
 
-    // Compute spectral transform
-    let spectrogram = transform::compute_transform(&mono_samples, config)?;
+        let segments = json_segments(&info(&[(0, 4), (4, 6)]), &fingerprints).unwrap();
+        assert_eq!(segments.iter().map(|s| s.num_fingerprints).collect::<Vec<_>>(), [4, 6]);
+        assert_eq!(segments[1].fingerprints[0].t1, 4);
 
-    // Extract event points
-    let event_points = EventPointExtractor::new(config).extract(&spectrogram)?;
+        // Count beyond the fingerprints, offset beyond them, overlapping ranges
+        for ranges in [&[(0, 4), (4, 7)][..], &[(0, 4), (12, 1)], &[(0, 6), (4, 6)]] {
+            assert!(json_segments(&info(ranges), &fingerprints).is_err(), "{:?}", ranges);
+        }
+    }
 
-    // Generate fingerprints
-    let fingerprints = FingerprintGenerator::new(config).generate(&event_points)?;
+    #[test]
+    fn test_parse_tag() {
+        assert_eq!(parse_tag("k=v=w").unwrap(), ("k".to_string(), "v=w".to_string()));
+        assert!(parse_tag("novalue").is_err());
+        assert!(parse_tag("=value").is_err());
+    }
 
-    Ok(fingerprints)
+    #[test]
+    fn test_cancelled_segmentation_stops_before_next_segment() {
+        let audio = AudioData {
+            samples: vec![0.0; 16000 * 80],
+            sample_rate: 16000,
+            channels: 1,
+            duration_ms: 80_000,
+        };
+        let seg_config = SegmentationConfig::default();
+        let cancel = CancellationToken::new();
+        cancel.cancel();
+
+        let mut timings = PipelineTimings::default();
+        let (fingerprints, info, total) =
+            process_with_segmentation(&audio, &PanakoConfig::default(), &seg_config, &mut timings, &cancel).unwrap();
+        assert!(total > 1);
+        assert!(fingerprints.is_empty());
+        assert_eq!(info.unwrap().num_segments, 0);
+    }
 }
@@ -0,0 +1,63 @@
+//! fpindexstats - Hash bucket statistics of a fingerprint database
+//!
+//! Usage: fpindexstats <db_dir> [--csv <file>]
+//!
+//! Prints the inverted index statistics (bucket sizes, hashes shared by
+//! several references) as JSON and optionally dumps every posting to a CSV
+//! file for external analysis.
+
+use anyhow::{Context, Result};
+use clap::Parser;
+use panako_cli::batch::BatchMatcher;
+use panako_core::PanakoConfig;
+use std::path::Path;
+
+#[derive(Parser, Debug)]
+#[command(name = "fpindexstats")]
+#[command(about = "Report hash bucket statistics of a fingerprint database", long_about = None)]
+struct Args {
+    /// Fingerprint database directory
+    db_dir: String,
+
+    /// Also write every posting (hash, identifier, t1, f1) to this CSV file
+    #[arg(long)]
+    csv: Option<String>,
+
+    /// Enable verbose logging
+    #[arg(short, long)]
+    verbose: bool,
+}
+
+fn main() -> Result<()> {
+    let args = Args::parse();
+
+    // Initialize logger
+    if args.verbose {
+        env_logger::Builder::from_default_env()
+            .filter_level(log::LevelFilter::Info)
+            .init();
+    } else {
+        env_logger::Builder::from_default_env()
+            .filter_level(log::LevelFilter::Warn)
+            .init();
+    }
+
+    let db_path = Path::new(&args.db_dir);
+    if !db_path.is_dir() {
+        anyhow::bail!("Database directory not found: {}", db_path.display());
+    }
+
+    let batch = BatchMatcher::from_database_dir(db_path, PanakoConfig::default())?;
+    let matcher = batch.matcher();
+    let stats = matcher.export_index_stats();
+
+    if let Some(csv_path) = &args.csv {
+        let file = std::fs::File::create(csv_path).with_context(|| format!("Failed to create {}", csv_path))?;
+        let rows = matcher.write_index_csv(&mut std::io::BufWriter::new(file))?;
+        log::info!("Wrote {} postings to {}", rows, csv_path);
+    }
+
+    println!("{}", serde_json::to_string_pretty(&stats)?);
+
+    Ok(())
+}
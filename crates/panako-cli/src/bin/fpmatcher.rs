@@ -1,34 +1,98 @@
 //! fpmatcher - Fingerprint matcher
 //!
 //! Usage: 
-//!   fpmatcher <query_fp>                    # Uses config.toml
+//!   fpmatcher <query_fp>                    # Uses the discovered config.toml (or defaults)
 //!   fpmatcher --config <path> <query_fp>    # Uses custom config
 //!   fpmatcher <db_dir> <query_fp>           # Legacy mode (filesystem)
+//!
+//! The query may also be an audio or video file, fingerprinted in-process.
 
 use anyhow::Result;
 use clap::Parser;
-use panako_cli::output::print_json_results;
-use panako_core::matching::Matcher;
-use panako_core::{PanakoStorageConfig, StorageBackend};
+use panako_cli::batch::{combine_part_results, query_parts, BatchMatcher};
+use panako_cli::output::{print_json_results, sort_results, write_jsonl_results, OutputFormat, QueryPosition, SortBy};
+use panako_cli::database::is_fingerprint_file;
+use panako_cli::fingerprinting::fingerprint_audio;
+use panako_core::config::PanakoConfig;
+use panako_core::matching::{LazyMatcher, QueryResult};
+use panako_core::pipeline::{decode_input, open_decode_cache};
+use panako_core::{CorruptFilePolicy, PanakoStorageConfig, PostgresqlBackend, StorageBackend};
 use panako_fp::FpJsonFile;
-use rayon::prelude::*;
-use std::path::{Path, PathBuf};
+use std::path::Path;
 
 #[derive(Parser, Debug)]
 #[command(name = "fpmatcher")]
 #[command(about = "Match fingerprints against a database", long_about = None)]
 struct Args {
-    /// Path to configuration file (TOML). If not provided, uses config.toml
+    /// Path to configuration file (TOML). If not provided, config.toml is
+    /// looked up in the current directory, then in $XDG_CONFIG_HOME/panako
     #[arg(short, long)]
     config: Option<String>,
 
+    /// Ignore any config.toml and use the built-in defaults
+    #[arg(long, conflicts_with = "config")]
+    no_config: bool,
+
     /// Database directory (legacy mode, overrides config if provided)
-    /// OR query fingerprint file if using config mode
+    /// OR query file if using config mode
     first_arg: String,
 
-    /// Query fingerprint file (only used in legacy mode)
+    /// Query file (only used in legacy mode): a fingerprint file (.json/.bson)
+    /// or an audio/video file to fingerprint before matching
     second_arg: Option<String>,
 
+    /// Merge duplicate detections reported by overlapping query segments
+    #[arg(long)]
+    merge_segments: bool,
+
+    /// Merge detections of the same reference separated by gaps of up to this
+    /// many seconds (e.g. brief dropouts)
+    #[arg(long)]
+    max_gap: Option<f64>,
+
+    /// Report at most one detection of a reference per burst of detections
+    /// less than this many seconds apart (e.g. one airing counted as one play)
+    #[arg(long)]
+    debounce: Option<f64>,
+
+    /// Only match references registered (fingerprinted) at or after this
+    /// Unix time in seconds, e.g. to look for this week's new content only
+    #[arg(long)]
+    registered_after: Option<i64>,
+
+    /// Flag matches whose time factor differs from 1.0 by more than this
+    /// fraction as `speed_altered`, e.g. 0.02 for airings sped up by over 2%
+    #[arg(long)]
+    speed_tolerance: Option<f64>,
+
+    /// Include the aligned (query, reference) times in seconds of each match
+    /// (`aligned_points`), e.g. to plot its linearity
+    #[arg(long)]
+    with_points: bool,
+
+    /// Also report the match bounds in frame indices (`frames`), with the
+    /// frame duration used to convert them to seconds
+    #[arg(long)]
+    with_frames: bool,
+
+    /// Directory holding the reference media files: each result then reports
+    /// `media_path`, the reference's original file name under it
+    #[arg(long)]
+    media_root: Option<String>,
+
+    /// Write one compact JSON result per line instead of a single JSON document
+    #[arg(long)]
+    jsonl: bool,
+
+    /// Result layout: raw matcher fields, labeled query/segment/reference times,
+    /// or Java Panako-compatible lines
+    #[arg(long, value_enum, default_value_t = OutputFormat::Raw)]
+    output_format: OutputFormat,
+
+    /// Order of the reported detections (ties broken by query time, then reference)
+    #[arg(long, value_enum, default_value_t = SortBy::QueryTime)]
+    sort_by: SortBy,
+
     /// Enable verbose logging
     #[arg(short, long)]
     verbose: bool,
@@ -59,122 +123,168 @@ fn main() -> Result<()> {
         (None, args.first_arg.clone())
     };
 
+    let options = MatchOptions {
+        merge_segments: args.merge_segments,
+        max_gap_s: args.max_gap,
+        debounce_s: args.debounce,
+        registered_after_s: args.registered_after,
+        speed_tolerance: args.speed_tolerance,
+        with_points: args.with_points,
+        with_frames: args.with_frames,
+        media_root: args.media_root.clone(),
+        jsonl: args.jsonl,
+        format: args.output_format,
+        sort_by: args.sort_by,
+    };
+
     // Run matching
     if let Some(db_dir) = db_dir {
         // Legacy mode: use filesystem directly
-        run_fpmatcher(&db_dir, &query_fp)?;
+        run_fpmatcher(&db_dir, &query_fp, &options, CorruptFilePolicy::SkipWithWarning)?;
     } else {
         // Config mode: load config and use appropriate backend
-        let config_path = args.config.as_deref().unwrap_or("config.toml");
-        run_fpmatcher_with_config(config_path, &query_fp)?;
+        let (config, _) = PanakoStorageConfig::load_discovered(
+            args.config.as_deref().map(Path::new),
+            args.no_config,
+            PanakoStorageConfig::default_filesystem,
+        )?;
+        run_fpmatcher_with_config(&config, &query_fp, &options)?;
     }
 
     Ok(())
 }
 
-fn run_fpmatcher(db_dir: &str, query_fp: &str) -> Result<()> {
-    let db_path = Path::new(db_dir);
-    let query_path = Path::new(query_fp);
+/// How detections are post-processed and reported
+struct MatchOptions {
+    merge_segments: bool,
+    max_gap_s: Option<f64>,
+    debounce_s: Option<f64>,
+    registered_after_s: Option<i64>,
+    speed_tolerance: Option<f64>,
+    with_points: bool,
+    with_frames: bool,
+    media_root: Option<String>,
+    jsonl: bool,
+    format: OutputFormat,
+    sort_by: SortBy,
+}
 
-    // Validate paths
-    if !db_path.exists() {
-        anyhow::bail!("Database directory not found: {}", db_path.display());
+fn run_fpmatcher(
+    db_dir: &str,
+    query_fp: &str,
+    options: &MatchOptions,
+    on_corrupt_file: CorruptFilePolicy,
+) -> Result<()> {
+    let results = match_query(db_dir, query_fp, options, on_corrupt_file)?;
+    print_results(query_fp, results, options)
+}
+
+fn print_results(query_fp: &str, mut results: Vec<QueryResult>, options: &MatchOptions) -> Result<()> {
+    if options.jsonl {
+        sort_results(&mut results, options.sort_by);
+        write_jsonl_results(&mut std::io::stdout().lock(), &results, options.format, QueryPosition::SINGLE)?;
+    } else {
+        print_json_results(query_fp, &results, options.format, options.sort_by);
+    }
+
+    Ok(())
+}
+
+/// Matching configuration for the given options
+fn match_config(options: &MatchOptions) -> PanakoConfig {
+    PanakoConfig {
+        max_detection_gap_s: options.max_gap_s,
+        detection_debounce_s: options.debounce_s,
+        registered_after_s: options.registered_after_s,
+        speed_altered_tolerance: options.speed_tolerance,
+        include_aligned_points: options.with_points,
+        include_frame_times: options.with_frames,
+        reference_media_root: options.media_root.clone(),
+        ..Default::default()
     }
+}
+
+/// Match a query against PostgreSQL, fetching only the postings of its hashes
+///
+/// A fingerprint file goes through `query_parts` and `combine_part_results`
+/// like in `BatchMatcher::match_fingerprint_file`. PostgreSQL does not store
+/// fingerprint parameters, so a query fingerprint file must have been made
+/// at the matching sample rate and parameters.
+async fn match_query_lazy(
+    config: &PanakoStorageConfig,
+    query_fp: &str,
+    options: &MatchOptions,
+) -> Result<Vec<QueryResult>> {
+    let query_path = Path::new(query_fp);
     if !query_path.exists() {
         anyhow::bail!("Query file not found: {}", query_path.display());
     }
+    let match_config = match_config(options);
+    let backend = PostgresqlBackend::new(&config.storage.postgresql).await?;
+    let mut matcher = LazyMatcher::new(&backend, &match_config)?;
 
-    log::info!("Loading database from: {}", db_path.display());
-
-    // Find all .json and .bson files in database directory
-    let fp_files: Vec<PathBuf> = std::fs::read_dir(db_path)?
-        .filter_map(|entry| entry.ok())
-        .map(|entry| entry.path())
-        .filter(|path| {
-            path.extension()
-                .and_then(|s| s.to_str())
-                .map(|ext| ext == "json" || ext == "bson")
-                .unwrap_or(false)
-        })
-        .collect();
-
-    log::info!("Found {} fingerprint files, loading in parallel...", fp_files.len());
-
-    // Load all files in parallel
-    let load_start = std::time::Instant::now();
-    let loaded_files: Vec<(String, FpJsonFile)> = fp_files
-        .par_iter()
-        .filter_map(|path| {
-            log::debug!("Loading: {}", path.display());
-            // Use load_auto to handle both JSON and BSON automatically
-            match FpJsonFile::load_auto(path) {
-                Ok(fp_file) => {
-                    let identifier = fp_file.metadata.filename.clone();
-                    Some((identifier, fp_file))
-                }
-                Err(e) => {
-                    log::warn!("Failed to load {}: {}", path.display(), e);
-                    None
-                }
+    let results = if is_fingerprint_file(query_path) {
+        let query_file = FpJsonFile::load_auto(query_path)?;
+        let parts = query_parts(query_path, &query_file, &match_config, |params| {
+            let differences = params.differences(&match_config.fingerprint_params());
+            if !differences.is_empty() {
+                anyhow::bail!(
+                    "Query fingerprint parameters differ from the matching configuration (query vs config): {}",
+                    differences.join(", ")
+                );
             }
-        })
-        .collect();
+            Ok(())
+        })?;
 
-    let load_duration = load_start.elapsed();
+        let mut part_results = Vec::with_capacity(parts.len());
+        for part in &parts {
+            part_results.push(matcher.query(query_fp, &part.fingerprints, &match_config).await?);
+        }
+        combine_part_results(&parts, part_results, options.merge_segments, &match_config)
+    } else {
+        let audio = decode_input(query_fp, &match_config, open_decode_cache(&match_config)?.as_ref())?;
+        let query_fps: Vec<_> = fingerprint_audio(&audio, &match_config)?
+            .iter()
+            .map(|fp| (fp.hash, fp.t1, fp.f1, fp.m1))
+            .collect();
+        matcher.query(query_fp, &query_fps, &match_config).await?
+    };
     log::info!(
-        "Loaded {} files in {:.2}s ({:.0} files/sec)",
-        loaded_files.len(),
-        load_duration.as_secs_f64(),
-        loaded_files.len() as f64 / load_duration.as_secs_f64()
+        "Fetched {} hashes from {} references, found {} results",
+        matcher.fetched_hashes(),
+        matcher.known_references(),
+        results.len()
     );
+    Ok(results)
+}
 
-    // Build matcher
-    let mut matcher = Matcher::new();
-    for (identifier, fp_file) in loaded_files {
-        // Get all fingerprints from all segments
-        let all_fps = fp_file.get_all_fingerprints();
-        matcher.add_fingerprints(identifier.clone(), &all_fps);
-        // Store reference duration
-        matcher.add_duration(identifier, fp_file.metadata.duration_ms);
+/// Match a query fingerprint file, or an audio/video file fingerprinted with
+/// the database's parameters, against the database in `db_dir`
+fn match_query(
+    db_dir: &str,
+    query_fp: &str,
+    options: &MatchOptions,
+    on_corrupt_file: CorruptFilePolicy,
+) -> Result<Vec<QueryResult>> {
+    let db_path = Path::new(db_dir);
+    let query_path = Path::new(query_fp);
+
+    // Validate paths
+    if !db_path.exists() {
+        anyhow::bail!("Database directory not found: {}", db_path.display());
+    }
+    if !query_path.exists() {
+        anyhow::bail!("Query file not found: {}", query_path.display());
     }
 
-    // Load query
-    log::info!("Loading query: {}", query_path.display());
-    let query_file = FpJsonFile::load_auto(query_path)?;
-    let query_fps = query_file.get_all_fingerprints();
-    log::info!("Query has {} fingerprints", query_fps.len());
+    log::info!("Loading database from: {}", db_path.display());
+    let batch = BatchMatcher::from_database_dir_with_policy(db_path, match_config(options), on_corrupt_file)?
+        .with_merge_segments(options.merge_segments);
 
-    // Perform matching (per segment if available)
+    // Load (or fingerprint) the query and match it, per segment if available
+    log::info!("Loading query: {}", query_path.display());
     let match_start = std::time::Instant::now();
-    let config = panako_core::config::PanakoConfig::default();
-    
-    let mut results = Vec::new();
-    
-    if query_file.segments.len() > 1 {
-        log::info!("Query file has {} segments, processing individually...", query_file.segments.len());
-        for segment in &query_file.segments {
-            let seg_fps: Vec<_> = segment.fingerprints.iter().map(|fp| (fp.hash, fp.t1, fp.f1, fp.m1)).collect();
-            let mut seg_results = matcher.query(
-                query_path.to_str().unwrap(),
-                &seg_fps,
-                &config,
-            )?;
-            
-            // Add segment info
-            for res in &mut seg_results {
-                res.segment_index = Some(segment.segment_id);
-            }
-            results.extend(seg_results);
-        }
-    } else {
-        let query_fps = query_file.get_all_fingerprints();
-        let query_results = matcher.query(
-            query_path.to_str().unwrap(),
-            &query_fps,
-            &config,
-        )?;
-        results.extend(query_results);
-    }
+    let results = batch.match_file(query_path)?;
     let match_duration = match_start.elapsed();
 
     log::info!(
@@ -183,18 +293,11 @@ fn run_fpmatcher(db_dir: &str, query_fp: &str) -> Result<()> {
         results.len()
     );
 
-    // Print results
-    print_json_results(&results);
-
-    Ok(())
+    Ok(results)
 }
 
 /// Config-based matching (supports filesystem or PostgreSQL)
-fn run_fpmatcher_with_config(config_path: &str, query_fp: &str) -> Result<()> {
-    // Load configuration
-    let config = PanakoStorageConfig::load(Path::new(config_path))?;
-    
-    log::info!("Loaded configuration from: {}", config_path);
+fn run_fpmatcher_with_config(config: &PanakoStorageConfig, query_fp: &str, options: &MatchOptions) -> Result<()> {
     log::info!("Storage backend: {:?}", config.storage.backend);
     
     match config.storage.backend {
@@ -202,11 +305,94 @@ fn run_fpmatcher_with_config(config_path: &str, query_fp: &str) -> Result<()> {
             // Use filesystem backend
             let db_dir = &config.storage.filesystem.base_directory;
             log::info!("Using filesystem backend: {}", db_dir);
-            run_fpmatcher(db_dir, query_fp)
+            let on_corrupt_file = config.storage.filesystem.on_corrupt_file;
+            run_fpmatcher(db_dir, query_fp, options, on_corrupt_file)
         }
         StorageBackend::Postgresql => {
-            // TODO: Implement PostgreSQL backend matching
-            anyhow::bail!("PostgreSQL backend not yet implemented. Please use filesystem backend for now.")
+            let runtime = tokio::runtime::Runtime::new()?;
+            let results = runtime.block_on(match_query_lazy(config, query_fp, options))?;
+            print_results(query_fp, results, options)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use panako_cli::fingerprinting::fingerprint_audio;
+    use panako_cli::selftest::synthesize_signal;
+    use panako_core::audio::AudioData;
+    use panako_core::config::PanakoConfig;
+    use panako_fp::{FpJsonFile, FpJsonFingerprint, FpJsonSegment};
+
+    #[test]
+    fn test_audio_query_matches_reference() {
+        let config = PanakoConfig::default();
+        let dir = std::env::temp_dir().join(format!("panako_fpmatcher_audio_{}", std::process::id()));
+        let db_dir = dir.join("db");
+        std::fs::create_dir_all(&db_dir).unwrap();
+
+        // Reference: 20 s of audio, indexed from its fingerprint file
+        let signal = synthesize_signal(config.sample_rate, 20.0);
+        let audio = AudioData {
+            samples: signal.clone(),
+            sample_rate: config.sample_rate,
+            channels: 1,
+            duration_ms: 20000,
+        };
+        let fingerprints: Vec<FpJsonFingerprint> = fingerprint_audio(&audio, &config)
+            .unwrap()
+            .iter()
+            .map(|fp| FpJsonFingerprint { hash: fp.hash, t1: fp.t1, f1: fp.f1, m1: fp.m1 })
+            .collect();
+        let mut reference = FpJsonFile::new("/audio/jingle.wav".to_string(), "jingle".to_string(), 16000, 20000, 1);
+        reference.metadata.fingerprint_params = Some(config.fingerprint_params());
+        reference.add_segment(FpJsonSegment {
+            segment_id: 0,
+            start_time_s: 0.0,
+            end_time_s: 20.0,
+            num_fingerprints: fingerprints.len(),
+            fingerprints,
+        });
+        reference.save(&db_dir.join("jingle.json")).unwrap();
+
+        // Query: a 10 s WAV excerpt of the same audio
+        let query_path = dir.join("clip.wav");
+        let spec = hound::WavSpec {
+            channels: 1,
+            sample_rate: config.sample_rate,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        };
+        let mut writer = hound::WavWriter::create(&query_path, spec).unwrap();
+        for &sample in &signal[16000 * 5..16000 * 15] {
+            writer.write_sample((sample.clamp(-1.0, 1.0) * 32767.0) as i16).unwrap();
         }
+        writer.finalize().unwrap();
+
+        let options = MatchOptions {
+            merge_segments: false,
+            max_gap_s: None,
+            debounce_s: None,
+            registered_after_s: None,
+            speed_tolerance: None,
+            with_points: false,
+            with_frames: false,
+            media_root: None,
+            jsonl: false,
+            format: OutputFormat::Raw,
+            sort_by: SortBy::QueryTime,
+        };
+        let results = match_query(
+            db_dir.to_str().unwrap(),
+            query_path.to_str().unwrap(),
+            &options,
+            CorruptFilePolicy::SkipWithWarning,
+        );
+        std::fs::remove_dir_all(&dir).ok();
+
+        let results = results.unwrap();
+        assert_eq!(results[0].ref_identifier.as_deref(), Some("jingle"));
+        assert!((results[0].ref_start - 5.0).abs() < 0.5, "ref_start {}", results[0].ref_start);
     }
 }
@@ -0,0 +1,67 @@
+//! fpmerge - Merge several fingerprint database directories into one
+//!
+//! Usage: fpmerge --dest <dest_dir> [--on-collision skip|overwrite|rename] <source_dir>...
+
+use anyhow::Result;
+use clap::Parser;
+use panako_cli::catalog::{merge_databases, CollisionPolicy};
+use panako_core::storage_config::FileFormat;
+use std::path::PathBuf;
+
+#[derive(Parser, Debug)]
+#[command(name = "fpmerge")]
+#[command(about = "Merge fingerprint database directories", long_about = None)]
+struct Args {
+    /// Source database directories, merged in the given order
+    #[arg(required = true)]
+    sources: Vec<PathBuf>,
+
+    /// Destination database directory (created if missing)
+    #[arg(long)]
+    dest: PathBuf,
+
+    /// How to resolve identifiers that already exist in the destination
+    #[arg(long, value_enum, default_value_t = CollisionPolicy::Skip)]
+    on_collision: CollisionPolicy,
+
+    /// Output format for written files (json or bson)
+    #[arg(long, default_value = "json")]
+    format: String,
+
+    /// Enable verbose logging
+    #[arg(short, long)]
+    verbose: bool,
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let args = Args::parse();
+
+    // Initialize logger
+    if args.verbose {
+        env_logger::Builder::from_default_env()
+            .filter_level(log::LevelFilter::Info)
+            .init();
+    } else {
+        env_logger::Builder::from_default_env()
+            .filter_level(log::LevelFilter::Off)
+            .init();
+    }
+
+    let format = match args.format.to_lowercase().as_str() {
+        "json" => FileFormat::Json,
+        "bson" => FileFormat::Bson,
+        other => anyhow::bail!("Unknown format '{}', expected json or bson", other),
+    };
+
+    let report = merge_databases(&args.sources, &args.dest, format, args.on_collision).await?;
+
+    let result = serde_json::json!({
+        "status": "success",
+        "destination": args.dest.display().to_string(),
+        "report": report,
+    });
+    println!("{}", serde_json::to_string_pretty(&result)?);
+
+    Ok(())
+}
@@ -1,32 +1,45 @@
 //! Migration tool for transferring fingerprints from filesystem to PostgreSQL
+//! (or exporting them back from PostgreSQL to a directory)
 //!
 //! Usage:
 //!   fpmigrate --source-dir ./fingerprints --config config.postgresql.toml
 //!   fpmigrate --source-config config.toml --dest-config config.postgresql.toml
+//!   fpmigrate --source-config config.postgresql.toml --dest-dir ./export
 
 use anyhow::{Context, Result};
 use clap::Parser;
+use panako_cli::{cancel_on_ctrl_c, CancellationToken};
 use panako_core::{
-    storage_backend::{FilesystemBackend, PostgresqlBackend, StorageBackend},
-    storage_config::{FileFormat, FilesystemConfig, PanakoStorageConfig, StorageBackend as BackendType},
+    storage_backend::{FilesystemBackend, IngestProgress, PostgresqlBackend, StorageBackend},
+    storage_config::{
+        CorruptFilePolicy, FileFormat, FilesystemConfig, IngestTransaction, PanakoStorageConfig,
+        StorageBackend as BackendType,
+    },
 };
+use serde::Serialize;
+use std::io::Write;
 use std::path::Path;
 
 #[derive(Parser, Debug)]
 #[command(name = "fpmigrate")]
-#[command(about = "Migrate fingerprints from filesystem to PostgreSQL", long_about = None)]
+#[command(about = "Migrate fingerprints from filesystem to PostgreSQL, or export them to a directory", long_about = None)]
 struct Args {
     /// Source directory containing fingerprint files (JSON/BSON)
     #[arg(long, conflicts_with = "source_config")]
     source_dir: Option<String>,
 
-    /// Source configuration file (filesystem backend)
+    /// Source configuration file (filesystem or PostgreSQL backend)
     #[arg(long, conflicts_with = "source_dir")]
     source_config: Option<String>,
 
     /// Destination configuration file (PostgreSQL backend)
-    #[arg(long, required = true)]
-    dest_config: String,
+    #[arg(long, required_unless_present = "dest_dir")]
+    dest_config: Option<String>,
+
+    /// Destination directory to export to as JSON fingerprint files; the
+    /// references are read from the source one at a time
+    #[arg(long, conflicts_with = "dest_config")]
+    dest_dir: Option<String>,
 
     /// Dry run - show what would be migrated without actually migrating
     #[arg(long, default_value = "false")]
@@ -36,11 +49,74 @@ struct Args {
     #[arg(long, default_value = "true")]
     skip_existing: bool,
 
+    /// Write one JSON event per file and a final summary to stdout
+    /// (the human-readable log still goes to stderr)
+    #[arg(long)]
+    json_progress: bool,
+
+    /// Transaction scope of the ingestion (default: `ingest_transaction`
+    /// of the destination configuration)
+    #[arg(long, value_enum)]
+    transaction: Option<TransactionArg>,
+
+    /// Files per commit with `--transaction batched` (default:
+    /// `ingest_batch_size` of the destination configuration)
+    #[arg(long)]
+    batch_size: Option<usize>,
+
     /// Enable verbose logging
     #[arg(short, long)]
     verbose: bool,
 }
 
+/// `IngestTransaction` as a command-line value
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+enum TransactionArg {
+    PerFile,
+    AllOrNothing,
+    Batched,
+}
+
+impl From<TransactionArg> for IngestTransaction {
+    fn from(arg: TransactionArg) -> Self {
+        match arg {
+            TransactionArg::PerFile => IngestTransaction::PerFile,
+            TransactionArg::AllOrNothing => IngestTransaction::AllOrNothing,
+            TransactionArg::Batched => IngestTransaction::Batched,
+        }
+    }
+}
+
+/// Machine-readable progress event, one JSON object per line
+#[derive(Debug, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+enum ProgressEvent<'a> {
+    Migrated { identifier: &'a str, fingerprints: usize },
+    /// Dry run: the file would have been migrated
+    WouldMigrate { identifier: &'a str, fingerprints: usize },
+    Skipped { identifier: &'a str, reason: &'a str },
+    Failed { identifier: &'a str, error: String },
+    /// `cancelled`: interrupted before every file was processed; the
+    /// counts cover the files handled until then
+    Summary { total: usize, migrated: usize, skipped: usize, failed: usize, dry_run: bool, cancelled: bool },
+}
+
+/// Writes `ProgressEvent`s as JSON lines, or nothing when disabled
+struct ProgressWriter<'w> {
+    out: Option<&'w mut dyn Write>,
+}
+
+impl ProgressWriter<'_> {
+    fn emit(&mut self, event: ProgressEvent) -> Result<()> {
+        if let Some(out) = self.out.as_mut() {
+            serde_json::to_writer(&mut *out, &event)?;
+            out.write_all(b"\n")?;
+            out.flush()?;
+        }
+        Ok(())
+    }
+}
+
 #[tokio::main]
 async fn main() -> Result<()> {
     let args = Args::parse();
@@ -52,33 +128,48 @@ async fn main() -> Result<()> {
     log::info!("🚀 Starting fingerprint migration");
 
     // Create source backend
-    let source_backend = create_source_backend(&args)?;
-    
-    // Create destination backend
-    let dest_backend = create_dest_backend(&args).await?;
+    let (source_backend, source_dir) = create_source_backend(&args).await?;
 
     // Perform migration
-    migrate_fingerprints(
-        source_backend.as_ref(),
-        dest_backend.as_ref(),
-        args.dry_run,
-        args.skip_existing,
-    )
-    .await?;
+    let mut stdout = std::io::stdout();
+    let progress = ProgressWriter {
+        out: if args.json_progress { Some(&mut stdout) } else { None },
+    };
+    if let Some(dest_dir) = &args.dest_dir {
+        log::info!("📁 Destination: Filesystem directory '{}'", dest_dir);
+        let dest_backend = FilesystemBackend::from_path(dest_dir, FileFormat::Json);
+        migrate_per_file(source_backend.as_ref(), &dest_backend, &args, progress).await?;
+        log::info!("✅ Migration completed successfully");
+        return Ok(());
+    }
+
+    // Create destination backend
+    let dest_backend = create_dest_backend(&args).await?;
+    match source_dir {
+        Some(source_dir) if dest_backend.ingest_transaction() != IngestTransaction::PerFile => {
+            if args.dry_run {
+                anyhow::bail!("--dry-run is only supported with per-file transactions");
+            }
+            ingest_directory(&dest_backend, Path::new(&source_dir), args.skip_existing, progress).await?;
+        }
+        _ => migrate_per_file(source_backend.as_ref(), &dest_backend, &args, progress).await?,
+    }
 
     log::info!("✅ Migration completed successfully");
 
     Ok(())
 }
 
-fn create_source_backend(args: &Args) -> Result<Box<dyn StorageBackend>> {
+/// Source backend and, for a filesystem source, the directory it reads from
+async fn create_source_backend(args: &Args) -> Result<(Box<dyn StorageBackend>, Option<String>)> {
     if let Some(source_dir) = &args.source_dir {
         log::info!("📂 Source: Filesystem directory '{}'", source_dir);
         let config = FilesystemConfig {
             base_directory: source_dir.clone(),
             format: FileFormat::Auto,
+            on_corrupt_file: CorruptFilePolicy::default(),
         };
-        Ok(Box::new(FilesystemBackend::new(&config)))
+        Ok((Box::new(FilesystemBackend::new(&config)), Some(source_dir.clone())))
     } else if let Some(source_config) = &args.source_config {
         log::info!("📂 Source: Configuration file '{}'", source_config);
         let config = PanakoStorageConfig::load(Path::new(source_config))
@@ -86,10 +177,14 @@ fn create_source_backend(args: &Args) -> Result<Box<dyn StorageBackend>> {
         
         match config.storage.backend {
             BackendType::Filesystem => {
-                Ok(Box::new(FilesystemBackend::new(&config.storage.filesystem)))
+                let filesystem = &config.storage.filesystem;
+                Ok((Box::new(FilesystemBackend::new(filesystem)), Some(filesystem.base_directory.clone())))
             }
             BackendType::Postgresql => {
-                anyhow::bail!("Source backend must be filesystem, not PostgreSQL")
+                let backend = PostgresqlBackend::new(&config.storage.postgresql)
+                    .await
+                    .context("Failed to create PostgreSQL source backend")?;
+                Ok((Box::new(backend), None))
             }
         }
     } else {
@@ -97,18 +192,22 @@ fn create_source_backend(args: &Args) -> Result<Box<dyn StorageBackend>> {
     }
 }
 
-async fn create_dest_backend(args: &Args) -> Result<Box<dyn StorageBackend>> {
-    log::info!("🗄️  Destination: PostgreSQL from '{}'", args.dest_config);
+async fn create_dest_backend(args: &Args) -> Result<PostgresqlBackend> {
+    let dest_config = args.dest_config.as_deref().context("Either --dest-config or --dest-dir must be provided")?;
+    log::info!("🗄️  Destination: PostgreSQL from '{}'", dest_config);
     
-    let config = PanakoStorageConfig::load(Path::new(&args.dest_config))
+    let config = PanakoStorageConfig::load(Path::new(dest_config))
         .context("Failed to load destination configuration")?;
     
     match config.storage.backend {
         BackendType::Postgresql => {
-            let backend = PostgresqlBackend::new(&config.storage.postgresql)
+            let postgresql = &config.storage.postgresql;
+            let backend = PostgresqlBackend::new(postgresql)
                 .await
                 .context("Failed to create PostgreSQL backend")?;
-            Ok(Box::new(backend))
+            let transaction = args.transaction.map_or(postgresql.ingest_transaction, Into::into);
+            let batch_size = args.batch_size.unwrap_or(postgresql.ingest_batch_size);
+            Ok(backend.with_ingest_transaction(transaction, batch_size))
         }
         BackendType::Filesystem => {
             anyhow::bail!("Destination backend must be PostgreSQL, not filesystem")
@@ -116,28 +215,54 @@ async fn create_dest_backend(args: &Args) -> Result<Box<dyn StorageBackend>> {
     }
 }
 
+/// `migrate_fingerprints` with Ctrl-C handling, failing if interrupted
+async fn migrate_per_file(
+    source: &dyn StorageBackend,
+    dest: &dyn StorageBackend,
+    args: &Args,
+    progress: ProgressWriter<'_>,
+) -> Result<()> {
+    let cancel = cancel_on_ctrl_c();
+    migrate_fingerprints(source, dest, args.dry_run, args.skip_existing, progress, &cancel).await?;
+    if cancel.is_cancelled() {
+        anyhow::bail!("Migration interrupted; rerun with --skip-existing to resume");
+    }
+    Ok(())
+}
+
+/// Copy every reference of `source` to `dest`, one reference at a time
+///
+/// Only one reference's fingerprints are held in memory; a PostgreSQL
+/// source reads them through a cursor (see `PostgresqlBackend::stream_fingerprints`).
 async fn migrate_fingerprints(
     source: &dyn StorageBackend,
     dest: &dyn StorageBackend,
     dry_run: bool,
     skip_existing: bool,
+    mut progress: ProgressWriter<'_>,
+    cancel: &CancellationToken,
 ) -> Result<()> {
-    log::info!("📊 Loading fingerprints from source...");
+    log::info!("📊 Listing fingerprints in source...");
     
-    // Load all fingerprints from source
-    let all_fingerprints = source
-        .load_all_fingerprints()
+    let identifiers = source
+        .identifiers()
         .await
-        .context("Failed to load fingerprints from source")?;
+        .context("Failed to list fingerprints in source")?;
     
-    let total_files = all_fingerprints.len();
+    let total_files = identifiers.len();
     log::info!("Found {} files to migrate", total_files);
 
     let mut migrated = 0;
     let mut skipped = 0;
     let mut failed = 0;
+    let mut cancelled = false;
 
-    for (identifier, fingerprints) in all_fingerprints {
+    for identifier in identifiers {
+        if cancel.is_cancelled() {
+            log::warn!("Cancelled after {} of {} files", migrated + skipped + failed, total_files);
+            cancelled = true;
+            break;
+        }
         log::debug!("Processing '{}'...", identifier);
 
         // Check if already exists in destination
@@ -145,6 +270,7 @@ async fn migrate_fingerprints(
             match dest.get_metadata(&identifier).await {
                 Ok(Some(_)) => {
                     log::debug!("  ⏭️  Skipping '{}' (already exists)", identifier);
+                    progress.emit(ProgressEvent::Skipped { identifier: &identifier, reason: "already_exists" })?;
                     skipped += 1;
                     continue;
                 }
@@ -158,12 +284,23 @@ async fn migrate_fingerprints(
             }
         }
 
+        let fingerprints = match source.load_fingerprints(&identifier).await {
+            Ok(fingerprints) => fingerprints,
+            Err(e) => {
+                log::error!("  ❌ Failed to load '{}': {}", identifier, e);
+                progress.emit(ProgressEvent::Failed { identifier: &identifier, error: format!("{:#}", e) })?;
+                failed += 1;
+                continue;
+            }
+        };
+
         if dry_run {
             log::info!(
                 "  [DRY RUN] Would migrate '{}' ({} fingerprints)",
                 identifier,
                 fingerprints.len()
             );
+            progress.emit(ProgressEvent::WouldMigrate { identifier: &identifier, fingerprints: fingerprints.len() })?;
             migrated += 1;
             continue;
         }
@@ -173,11 +310,13 @@ async fn migrate_fingerprints(
             Ok(Some(meta)) => meta,
             Ok(None) => {
                 log::warn!("  ⚠️  No metadata found for '{}', skipping", identifier);
+                progress.emit(ProgressEvent::Failed { identifier: &identifier, error: "no metadata found".to_string() })?;
                 failed += 1;
                 continue;
             }
             Err(e) => {
                 log::error!("  ❌ Failed to get metadata for '{}': {}", identifier, e);
+                progress.emit(ProgressEvent::Failed { identifier: &identifier, error: format!("{:#}", e) })?;
                 failed += 1;
                 continue;
             }
@@ -194,10 +333,12 @@ async fn migrate_fingerprints(
                     identifier,
                     fingerprints.len()
                 );
+                progress.emit(ProgressEvent::Migrated { identifier: &identifier, fingerprints: fingerprints.len() })?;
                 migrated += 1;
             }
             Err(e) => {
                 log::error!("  ❌ Failed to migrate '{}': {}", identifier, e);
+                progress.emit(ProgressEvent::Failed { identifier: &identifier, error: format!("{:#}", e) })?;
                 failed += 1;
             }
         }
@@ -210,7 +351,11 @@ async fn migrate_fingerprints(
     log::info!("   ✅ Migrated:    {}", migrated);
     log::info!("   ⏭️  Skipped:     {}", skipped);
     log::info!("   ❌ Failed:      {}", failed);
+    if cancelled {
+        log::info!("   ⛔ Not reached: {}", total_files - migrated - skipped - failed);
+    }
     log::info!("━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━");
+    progress.emit(ProgressEvent::Summary { total: total_files, migrated, skipped, failed, dry_run, cancelled })?;
 
     if failed > 0 {
         anyhow::bail!("{} files failed to migrate", failed);
@@ -219,6 +364,82 @@ async fn migrate_fingerprints(
     Ok(())
 }
 
+/// Ingest the source directory with the destination's transaction scope
+///
+/// Unlike `migrate_fingerprints`, the first failing file aborts the
+/// ingestion and rolls back the open transaction.
+async fn ingest_directory(
+    dest: &PostgresqlBackend,
+    source_dir: &Path,
+    skip_existing: bool,
+    mut progress: ProgressWriter<'_>,
+) -> Result<()> {
+    log::info!(
+        "📊 Ingesting '{}' ({:?} transaction)...",
+        source_dir.display(),
+        dest.ingest_transaction()
+    );
+
+    // Inserted files are only reported as migrated once their transaction
+    // commits; a rollback drops them
+    let mut uncommitted: Vec<(String, usize)> = Vec::new();
+    let mut emit_error = None;
+    let result = dest
+        .ingest_directory(source_dir, skip_existing, |event| {
+            let emitted = match event {
+                IngestProgress::Ingested { identifier, fingerprints } => {
+                    log::debug!("  Inserted '{}' ({} fingerprints)", identifier, fingerprints);
+                    uncommitted.push((identifier.to_string(), fingerprints));
+                    Ok(())
+                }
+                IngestProgress::Skipped { identifier } => {
+                    log::debug!("  ⏭️  Skipping '{}' (already exists)", identifier);
+                    progress.emit(ProgressEvent::Skipped { identifier, reason: "already_exists" })
+                }
+                IngestProgress::Committed { files } => {
+                    log::info!("  💾 Committed {} files", files);
+                    uncommitted.drain(..).try_for_each(|(identifier, fingerprints)| {
+                        log::info!("  ✅ Inserted '{}' ({} fingerprints)", identifier, fingerprints);
+                        progress.emit(ProgressEvent::Migrated { identifier: &identifier, fingerprints })
+                    })
+                }
+            };
+            if let Err(e) = emitted {
+                emit_error.get_or_insert(e);
+            }
+        })
+        .await;
+    if let Some(e) = emit_error {
+        return Err(e);
+    }
+
+    // A failed ingestion still reports what was committed before it; the
+    // failing file and the rolled back ones count as failed
+    let (summary, failed) = match &result {
+        Ok(summary) => (*summary, 0),
+        Err(e) => {
+            log::error!("  ❌ {}: {:#}", e, e.source);
+            (e.summary, e.rolled_back + 1)
+        }
+    };
+    log::info!("━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━");
+    log::info!("📈 Migration Summary:");
+    log::info!("   ✅ Migrated:    {}", summary.ingested);
+    log::info!("   ⏭️  Skipped:     {}", summary.skipped);
+    log::info!("   ❌ Failed:      {}", failed);
+    log::info!("━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━");
+    progress.emit(ProgressEvent::Summary {
+        total: summary.ingested + summary.skipped + failed,
+        migrated: summary.ingested,
+        skipped: summary.skipped,
+        failed,
+        dry_run: false,
+        cancelled: false,
+    })?;
+
+    result.map(|_| ()).map_err(anyhow::Error::from)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -226,7 +447,7 @@ mod tests {
     #[test]
     fn test_args_parsing() {
         // Test with source-dir
-        let args = Args::parse_from(&[
+        let args = Args::parse_from([
             "fpmigrate",
             "--source-dir",
             "./fingerprints",
@@ -234,10 +455,10 @@ mod tests {
             "config.postgresql.toml",
         ]);
         assert_eq!(args.source_dir, Some("./fingerprints".to_string()));
-        assert_eq!(args.dest_config, "config.postgresql.toml");
+        assert_eq!(args.dest_config.as_deref(), Some("config.postgresql.toml"));
 
         // Test with source-config
-        let args = Args::parse_from(&[
+        let args = Args::parse_from([
             "fpmigrate",
             "--source-config",
             "config.toml",
@@ -245,5 +466,215 @@ mod tests {
             "config.postgresql.toml",
         ]);
         assert_eq!(args.source_config, Some("config.toml".to_string()));
+
+        // Exporting to a directory instead
+        let args = Args::parse_from([
+            "fpmigrate",
+            "--source-config",
+            "config.postgresql.toml",
+            "--dest-dir",
+            "./export",
+        ]);
+        assert_eq!(args.dest_dir.as_deref(), Some("./export"));
+        assert!(Args::try_parse_from(["fpmigrate", "--source-dir", "./fingerprints"]).is_err());
+        assert!(Args::try_parse_from([
+            "fpmigrate",
+            "--source-dir",
+            "./fingerprints",
+            "--dest-config",
+            "config.postgresql.toml",
+            "--dest-dir",
+            "./export",
+        ])
+        .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_json_progress_accounts_for_every_file() {
+        use panako_core::storage_backend::FingerprintMetadata;
+        use std::collections::{HashMap, HashSet};
+
+        let base = std::env::temp_dir().join(format!("panako_fpmigrate_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&base);
+        let source = FilesystemBackend::from_path(base.join("src").to_str().unwrap(), FileFormat::Json);
+        let dest = FilesystemBackend::from_path(base.join("dst").to_str().unwrap(), FileFormat::Json);
+        let names = ["alpha", "beta", "gamma"];
+        for (i, name) in names.iter().enumerate() {
+            let metadata = FingerprintMetadata {
+                filename: name.to_string(),
+                original_path: format!("/audio/{}.wav", name),
+                algorithm: "panako".to_string(),
+                sample_rate: 16000,
+                duration_ms: 1000,
+                channels: 1,
+                created_at: String::new(),
+                tags: HashMap::new(),
+                content_hash: None,
+                content_id: None,
+            };
+            let fingerprints: Vec<_> = (0..=i as u64).map(|k| (k, k as i32, 10, 1.0)).collect();
+            source.save_fingerprints(name, &fingerprints, &metadata).await.unwrap();
+            if *name == "gamma" {
+                dest.save_fingerprints(name, &fingerprints, &metadata).await.unwrap();
+            }
+        }
+
+        let mut out = Vec::new();
+        let progress = ProgressWriter { out: Some(&mut out) };
+        migrate_fingerprints(&source, &dest, false, true, progress, &CancellationToken::new()).await.unwrap();
+        std::fs::remove_dir_all(&base).ok();
+
+        let events: Vec<serde_json::Value> = String::from_utf8(out)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        let (summary, per_file) = events.split_last().unwrap();
+        assert_eq!(summary["event"], "summary");
+        assert_eq!(summary["total"], 3);
+        assert_eq!(summary["migrated"], 2);
+        assert_eq!(summary["skipped"], 1);
+        assert_eq!(summary["failed"], 0);
+
+        let identifiers: HashSet<&str> = per_file.iter().map(|e| e["identifier"].as_str().unwrap()).collect();
+        assert_eq!(per_file.len(), names.len());
+        assert_eq!(identifiers, names.into_iter().collect());
+        for event in per_file {
+            match event["identifier"].as_str().unwrap() {
+                "gamma" => assert_eq!(event["event"], "skipped"),
+                "alpha" => assert_eq!((&event["event"], &event["fingerprints"]), (&"migrated".into(), &1.into())),
+                _ => assert_eq!((&event["event"], &event["fingerprints"]), (&"migrated".into(), &2.into())),
+            }
+        }
+    }
+
+    /// Progress sink that cancels the migration once it has seen `after` events
+    struct CancelAfter {
+        lines: Vec<u8>,
+        after: usize,
+        cancel: CancellationToken,
+    }
+
+    impl Write for CancelAfter {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.lines.extend_from_slice(buf);
+            if self.lines.iter().filter(|&&b| b == b'\n').count() >= self.after {
+                self.cancel.cancel();
+            }
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_cancel_mid_batch_reports_completed_files() {
+        use panako_core::storage_backend::FingerprintMetadata;
+        use std::collections::HashMap;
+
+        let base = std::env::temp_dir().join(format!("panako_fpmigrate_cancel_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&base);
+        let source = FilesystemBackend::from_path(base.join("src").to_str().unwrap(), FileFormat::Json);
+        let dest = FilesystemBackend::from_path(base.join("dst").to_str().unwrap(), FileFormat::Json);
+        for name in ["alpha", "beta", "gamma", "delta"] {
+            let metadata = FingerprintMetadata {
+                filename: name.to_string(),
+                original_path: format!("/audio/{}.wav", name),
+                algorithm: "panako".to_string(),
+                sample_rate: 16000,
+                duration_ms: 1000,
+                channels: 1,
+                created_at: String::new(),
+                tags: HashMap::new(),
+                content_hash: None,
+                content_id: None,
+            };
+            source.save_fingerprints(name, &[(1, 1, 10, 1.0)], &metadata).await.unwrap();
+        }
+
+        let cancel = CancellationToken::new();
+        let mut sink = CancelAfter { lines: Vec::new(), after: 2, cancel: cancel.clone() };
+        let progress = ProgressWriter { out: Some(&mut sink) };
+        migrate_fingerprints(&source, &dest, false, false, progress, &cancel).await.unwrap();
+        let migrated_to_dest = dest.load_all_fingerprints().await.unwrap();
+        std::fs::remove_dir_all(&base).ok();
+
+        let events: Vec<serde_json::Value> = String::from_utf8(sink.lines)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        let (summary, per_file) = events.split_last().unwrap();
+        // The file in flight when cancelled finishes; no further file starts
+        assert_eq!(per_file.len(), 2);
+        assert!(per_file.iter().all(|e| e["event"] == "migrated"));
+        assert_eq!(summary["event"], "summary");
+        assert_eq!(summary["cancelled"], true);
+        assert_eq!(summary["total"], 4);
+        assert_eq!(summary["migrated"], 2);
+        assert_eq!(summary["failed"], 0);
+        assert_eq!(migrated_to_dest.len(), 2);
+    }
+
+    #[tokio::test]
+    #[ignore] // Requires a running PostgreSQL database
+    async fn test_export_postgresql_to_directory() {
+        use panako_core::storage_backend::FingerprintMetadata;
+        use panako_core::storage_config::PostgresqlConfig;
+        use std::collections::HashMap;
+
+        let config = PostgresqlConfig {
+            database: "panako".to_string(),
+            user: "panako_user".to_string(),
+            password: "panako_pass".to_string(),
+            ..PostgresqlConfig::default()
+        };
+        let source = PostgresqlBackend::new(&config).await.unwrap();
+        let name = format!("export_{}", std::process::id());
+        let metadata = FingerprintMetadata {
+            filename: name.clone(),
+            original_path: format!("/audio/{}.wav", name),
+            algorithm: "panako".to_string(),
+            sample_rate: 16000,
+            duration_ms: 1000,
+            channels: 1,
+            created_at: String::new(),
+            tags: HashMap::new(),
+            content_hash: None,
+            content_id: None,
+        };
+        // More rows than one cursor batch
+        let fingerprints: Vec<(u64, i32, i16, f32)> =
+            (0..25_000).map(|i| (i as u64 * 31, i, (i % 200) as i16, 0.5)).collect();
+        source.save_fingerprints(&name, &fingerprints, &metadata).await.unwrap();
+
+        let mut batches = 0;
+        let streamed = source
+            .stream_fingerprints(&name, |batch| {
+                assert!(batch.len() <= 10_000);
+                batches += 1;
+                Ok(())
+            })
+            .await
+            .unwrap();
+
+        let base = std::env::temp_dir().join(format!("panako_fpmigrate_export_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&base);
+        let dest = FilesystemBackend::from_path(base.to_str().unwrap(), FileFormat::Json);
+        let mut out = Vec::new();
+        let progress = ProgressWriter { out: Some(&mut out) };
+        let result = migrate_fingerprints(&source, &dest, false, false, progress, &CancellationToken::new()).await;
+        let mut exported = dest.load_fingerprints(&name).await;
+        source.delete_fingerprints(&name).await.unwrap();
+        std::fs::remove_dir_all(&base).ok();
+
+        result.unwrap();
+        assert_eq!(streamed, fingerprints.len() as u64);
+        assert_eq!(batches, 3);
+        let exported = exported.as_mut().unwrap();
+        exported.sort_by_key(|fp| fp.1);
+        assert_eq!(*exported, fingerprints);
     }
 }
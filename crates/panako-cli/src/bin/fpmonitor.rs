@@ -7,15 +7,17 @@
 
 use anyhow::Result;
 use clap::Parser;
-use panako_cli::output::print_json_results;
+use panako_cli::aggregation::TemporalAggregator;
+use panako_cli::batch::{merge_segment_results, BatchMatcher};
+use panako_cli::cancel::cancel_on_ctrl_c;
+use panako_cli::fingerprinting::offset_segment_fingerprints;
+use panako_cli::output::{print_json_results, sort_results, write_jsonl_results, OutputFormat, QueryPosition, SortBy};
 use panako_core::{
-    audio::AudioData, config::PanakoConfig, eventpoint::EventPointExtractor,
-    fingerprint::FingerprintGenerator, matching::{Matcher, QueryResult},
-    segmentation::{segment_audio, SegmentationConfig}, transform,
+    audio::AudioData, config::PanakoConfig, matching::{Matcher, QueryResult},
+    pipeline::{fingerprint_audio_timed, PipelineTimings},
+    segmentation::{segment_audio, SegmentationConfig},
 };
-use panako_fp::FpJsonFile;
-use rayon::prelude::*;
-use std::path::{Path, PathBuf};
+use std::path::Path;
 
 #[derive(Parser, Debug)]
 #[command(name = "fpmonitor")]
@@ -27,6 +29,44 @@ struct Args {
     /// Input video/audio file (.ts, .mp4, .mp3, etc.)
     input_file: String,
 
+    /// Merge duplicate detections reported by overlapping segments
+    #[arg(long)]
+    merge_segments: bool,
+
+    /// Merge detections of the same reference separated by gaps of up to this
+    /// many seconds (e.g. brief dropouts)
+    #[arg(long)]
+    max_gap: Option<f64>,
+
+    /// Report at most one detection of a reference per burst of detections
+    /// less than this many seconds apart (e.g. one airing counted as one play)
+    #[arg(long)]
+    debounce: Option<f64>,
+
+    /// Flag detections whose time factor differs from 1.0 by more than this
+    /// fraction as `speed_altered`, e.g. 0.02 for airings sped up by over 2%
+    #[arg(long)]
+    speed_tolerance: Option<f64>,
+
+    /// Combine detections of a reference across consecutive segments and
+    /// report them with a confidence (implies merging segment duplicates)
+    #[arg(long)]
+    aggregate: bool,
+
+    /// Write one compact JSON result per line as segments are processed
+    #[arg(long)]
+    jsonl: bool,
+
+    /// Result layout: raw matcher fields, labeled query/segment/reference times,
+    /// or Java Panako-compatible lines
+    #[arg(long, value_enum, default_value_t = OutputFormat::Raw)]
+    output_format: OutputFormat,
+
+    /// Order of the reported detections (ties broken by query time, then
+    /// reference); not applied to results streamed with --jsonl
+    #[arg(long, value_enum, default_value_t = SortBy::QueryTime)]
+    sort_by: SortBy,
+
     /// Enable verbose logging
     #[arg(short, long)]
     verbose: bool,
@@ -49,12 +89,39 @@ fn main() -> Result<()> {
     }
 
     // Run monitor
-    run_fpmonitor(&args.db_dir, &args.input_file)?;
+    let options = MonitorOptions {
+        merge_segments: args.merge_segments,
+        max_gap_s: args.max_gap,
+        debounce_s: args.debounce,
+        speed_tolerance: args.speed_tolerance,
+        aggregate: args.aggregate,
+        jsonl: args.jsonl,
+        format: args.output_format,
+        sort_by: args.sort_by,
+    };
+    run_fpmonitor(&args.db_dir, &args.input_file, &options)?;
 
     Ok(())
 }
 
-fn run_fpmonitor(db_dir: &str, input_file: &str) -> Result<()> {
+/// How segment detections are combined and reported
+struct MonitorOptions {
+    merge_segments: bool,
+    max_gap_s: Option<f64>,
+    debounce_s: Option<f64>,
+    speed_tolerance: Option<f64>,
+    aggregate: bool,
+    jsonl: bool,
+    format: OutputFormat,
+    sort_by: SortBy,
+}
+
+/// A Ctrl-C during the segment loop stops before the next segment, reports
+/// the detections of the segments processed so far and fails the run
+fn run_fpmonitor(db_dir: &str, input_file: &str, options: &MonitorOptions) -> Result<()> {
+    let MonitorOptions { merge_segments, max_gap_s, debounce_s, speed_tolerance, aggregate, jsonl, format, sort_by } = *options;
+    let merge_segments = merge_segments || max_gap_s.is_some();
+
     let db_path = Path::new(db_dir);
     let input_path = Path::new(input_file);
 
@@ -67,81 +134,40 @@ fn run_fpmonitor(db_dir: &str, input_file: &str) -> Result<()> {
     }
 
     log::info!("Loading database from: {}", db_path.display());
-
-    // Find all .json and .bson files in database directory
-    let fp_files: Vec<PathBuf> = std::fs::read_dir(db_path)?
-        .filter_map(|entry| entry.ok())
-        .map(|entry| entry.path())
-        .filter(|path| {
-            path.extension()
-                .and_then(|s| s.to_str())
-                .map(|ext| ext == "json" || ext == "bson")
-                .unwrap_or(false)
-        })
-        .collect();
-
-    log::info!("Found {} .json/.bson files, loading in parallel...", fp_files.len());
-
-    // Load all files in parallel
-    let load_start = std::time::Instant::now();
-    let loaded_files: Vec<(String, FpJsonFile)> = fp_files
-        .par_iter()
-        .filter_map(|path| {
-            log::debug!("Loading: {}", path.display());
-            match FpJsonFile::load_auto(path) {
-                Ok(fp_file) => {
-                    let identifier = fp_file.metadata.filename.clone();
-                    Some((identifier, fp_file))
-                }
-                Err(e) => {
-                    log::warn!("Failed to load {}: {}", path.display(), e);
-                    None
-                }
-            }
-        })
-        .collect();
-
-    let load_duration = load_start.elapsed();
-    log::info!(
-        "Loaded {} files in {:.2}s ({:.0} files/sec)",
-        loaded_files.len(),
-        load_duration.as_secs_f64(),
-        loaded_files.len() as f64 / load_duration.as_secs_f64()
-    );
-
-    // Build matcher
-    let mut matcher = Matcher::new();
-    for (identifier, fp_file) in loaded_files {
-        let all_fps = fp_file.get_all_fingerprints();
-        matcher.add_fingerprints(identifier.clone(), &all_fps);
-        matcher.add_duration(identifier, fp_file.metadata.duration_ms);
-    }
+    let batch = BatchMatcher::from_database_dir(
+        db_path,
+        PanakoConfig {
+            // Aggregation already joins detections across segments
+            max_detection_gap_s: max_gap_s.filter(|_| !aggregate),
+            detection_debounce_s: debounce_s,
+            speed_altered_tolerance: speed_tolerance,
+            ..Default::default()
+        },
+    )?;
+    let matcher = batch.matcher();
+    let config = batch.config();
 
     log::info!("Processing input file: {}", input_path.display());
 
-    // Load configuration
-    let config = PanakoConfig::default();
-    config.validate()?;
-
     // Decode entire audio file
     let decode_start = std::time::Instant::now();
-    let audio_data = panako_core::audio::decode_audio(
-        input_path.to_str().unwrap(),
-        config.sample_rate,
-    )?;
-    let decode_duration = decode_start.elapsed();
+    let audio_data = batch.decode_audio(input_path.to_str().unwrap())?;
+    let mut timings = PipelineTimings {
+        decode: decode_start.elapsed(),
+        ..Default::default()
+    };
 
     log::info!(
         "Decoded audio: {:.1}s duration, {} samples @ {}Hz (took {:.2}s)",
         audio_data.duration_ms as f64 / 1000.0,
         audio_data.samples.len(),
         audio_data.sample_rate,
-        decode_duration.as_secs_f64()
+        timings.decode.as_secs_f64()
     );
 
     // Segment audio
     let seg_config = SegmentationConfig::default();
-    let segments = segment_audio(&audio_data, &seg_config);
+    let segments = segment_audio(&audio_data, &seg_config)?;
 
     log::info!(
         "Segmented into {} segments ({}s duration, {}s overlap)",
@@ -150,11 +176,22 @@ fn run_fpmonitor(db_dir: &str, input_file: &str) -> Result<()> {
         seg_config.overlap_duration_s
     );
 
-    // Process each segment
+    // Stream results per segment unless they must be merged or debounced first
+    let stream_jsonl = jsonl && !merge_segments && debounce_s.is_none();
+    let mut aggregator = aggregate.then(TemporalAggregator::new);
+    let mut stdout = std::io::stdout().lock();
+
+    // Process each segment; Ctrl-C stops between segments from here on
+    let cancel = cancel_on_ctrl_c();
     let mut all_results = Vec::new();
     let process_start = std::time::Instant::now();
+    let mut processed = 0;
 
     for (idx, segment) in segments.iter().enumerate() {
+        if cancel.is_cancelled() {
+            log::warn!("Cancelled after {} of {} segments, reporting partial results", idx, segments.len());
+            break;
+        }
         log::info!(
             "Processing segment {}/{}: {:.1}s - {:.1}s",
             idx + 1,
@@ -166,14 +203,17 @@ fn run_fpmonitor(db_dir: &str, input_file: &str) -> Result<()> {
         // Process segment and query
         let mut segment_results = process_segment_and_query(
             segment,
-            &matcher,
-            &config,
+            matcher,
+            config,
             input_path.to_str().unwrap(),
+            &mut timings,
         )?;
 
         // Add segment info to results
         for res in &mut segment_results {
             res.segment_index = Some(idx);
+            res.segment_start = Some(segment.start_time_s);
+            res.from_segmented = true;
         }
 
         log::info!(
@@ -182,22 +222,49 @@ fn run_fpmonitor(db_dir: &str, input_file: &str) -> Result<()> {
             segment_results.len()
         );
 
-        all_results.extend(segment_results);
+        // Aggregated detections are only reported once they stop being extended
+        let segment_results = match aggregator.as_mut() {
+            Some(aggregator) => aggregator.push_segment(idx, segment_results),
+            None => segment_results,
+        };
+
+        if stream_jsonl {
+            let mut segment_results = segment_results;
+            sort_results(&mut segment_results, sort_by);
+            write_jsonl_results(&mut stdout, &segment_results, format, QueryPosition::SINGLE)?;
+        } else {
+            all_results.extend(segment_results);
+        }
+        processed += 1;
+    }
+
+    if let Some(aggregator) = aggregator {
+        let mut remaining = aggregator.finish();
+        if stream_jsonl {
+            sort_results(&mut remaining, sort_by);
+            write_jsonl_results(&mut stdout, &remaining, format, QueryPosition::SINGLE)?;
+        } else {
+            all_results.extend(remaining);
+        }
     }
 
     let process_duration = process_start.elapsed();
     log::info!(
         "Processed {} segments in {:.2}s",
-        segments.len(),
+        processed,
         process_duration.as_secs_f64()
     );
+    log::info!(
+        "Stage timings: decode {:.3}s, transform {:.3}s, event points {:.3}s, fingerprints {:.3}s",
+        timings.decode.as_secs_f64(),
+        timings.transform.as_secs_f64(),
+        timings.event_points.as_secs_f64(),
+        timings.fingerprints.as_secs_f64()
+    );
+
+    all_results = merge_segment_results(all_results, merge_segments && !aggregate, config);
 
-    // Sort results by absolute start time
-    all_results.sort_by(|a, b| {
-        let a_start = a.absolute_start.unwrap_or(a.query_start);
-        let b_start = b.absolute_start.unwrap_or(b.query_start);
-        a_start.partial_cmp(&b_start).unwrap_or(std::cmp::Ordering::Equal)
-    });
+    sort_results(&mut all_results, sort_by);
 
     log::info!(
         "Final results: {} detections (per-segment reporting)",
@@ -205,8 +272,15 @@ fn run_fpmonitor(db_dir: &str, input_file: &str) -> Result<()> {
     );
 
     // Print results
-    print_json_results(&all_results);
+    if jsonl {
+        write_jsonl_results(&mut stdout, &all_results, format, QueryPosition::SINGLE)?;
+    } else {
+        print_json_results(input_file, &all_results, format, sort_by);
+    }
 
+    if cancel.is_cancelled() {
+        anyhow::bail!("Interrupted after {} of {} segments; results are partial", processed, segments.len());
+    }
     Ok(())
 }
 
@@ -216,6 +290,7 @@ fn process_segment_and_query(
     matcher: &Matcher,
     config: &PanakoConfig,
     query_path: &str,
+    timings: &mut PipelineTimings,
 ) -> Result<Vec<QueryResult>> {
     // Create AudioData for this segment
     let segment_audio = AudioData {
@@ -226,24 +301,15 @@ fn process_segment_and_query(
     };
 
     // Generate fingerprints
-    let fingerprints = generate_fingerprints_from_audio(&segment_audio, config)?;
+    let (fingerprints, stage_timings) = fingerprint_audio_timed(&segment_audio, config)?;
+    *timings += stage_timings;
 
     if fingerprints.is_empty() {
         return Ok(vec![]);
     }
 
     // Adjust timestamps to absolute time (relative to full file)
-    let time_offset_frames = (segment.start_time_s / 0.008) as i32;
-    let adjusted_fps: Vec<_> = fingerprints
-        .iter()
-        .map(|fp| {
-            let mut adjusted = fp.clone();
-            adjusted.t1 += time_offset_frames;
-            adjusted.t2 += time_offset_frames;
-            adjusted.t3 += time_offset_frames;
-            adjusted
-        })
-        .collect();
+    let adjusted_fps = offset_segment_fingerprints(&fingerprints, segment.start_time_s, config);
 
     // Convert to tuple format for matcher
     let fp_tuples: Vec<(u64, i32, i16, f32)> = adjusted_fps
@@ -251,33 +317,11 @@ fn process_segment_and_query(
         .map(|fp| (fp.hash, fp.t1, fp.f1, fp.m1))
         .collect();
 
-    // Query matcher
-    let results = matcher.query(query_path, &fp_tuples, config)?;
+    // Query matcher; results are sorted once all segments are in
+    let results = matcher.query_unsorted(query_path, &fp_tuples, config)?;
 
     // Adjust query result times (they're already absolute due to adjusted fingerprints)
     // No additional adjustment needed since we adjusted the fingerprints before querying
 
     Ok(results)
 }
-
-/// Generate fingerprints from audio data
-fn generate_fingerprints_from_audio(
-    audio: &AudioData,
-    config: &PanakoConfig,
-) -> Result<Vec<panako_core::Fingerprint>> {
-    // Convert to mono
-    let mono_samples = audio.to_mono();
-
-    // Compute spectral transform
-    let spectrogram = transform::compute_transform(&mono_samples, config)?;
-
-    // Extract event points
-    let event_points = EventPointExtractor::new(config).extract(&spectrogram)?;
-
-    // Generate fingerprints
-    let fingerprints = FingerprintGenerator::new(config).generate(&event_points)?;
-
-    Ok(fingerprints)
-}
-
-
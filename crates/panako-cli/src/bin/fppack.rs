@@ -0,0 +1,77 @@
+//! fppack - Consolidate a fingerprint directory into a single pack file
+//!
+//! Usage: fppack <db_dir> [--remove-sources]
+//!
+//! The pack loads much faster than thousands of small .json/.bson files and
+//! is picked up automatically by fpmatcher, fpbatch and the filesystem backend.
+
+use anyhow::{Context, Result};
+use clap::Parser;
+use panako_core::storage_config::{CorruptFilePolicy, FileFormat};
+use panako_core::FilesystemBackend;
+
+#[derive(Parser, Debug)]
+#[command(name = "fppack")]
+#[command(about = "Pack a fingerprint directory into a single fast-loading file", long_about = None)]
+struct Args {
+    /// Fingerprint database directory
+    db_dir: String,
+
+    /// Delete the loose .json/.bson files once they are in the pack
+    #[arg(long)]
+    remove_sources: bool,
+
+    /// Enable verbose logging
+    #[arg(short, long)]
+    verbose: bool,
+}
+
+fn main() -> Result<()> {
+    let args = Args::parse();
+
+    // Initialize logger
+    if args.verbose {
+        env_logger::Builder::from_default_env()
+            .filter_level(log::LevelFilter::Info)
+            .init();
+    } else {
+        env_logger::Builder::from_default_env()
+            .filter_level(log::LevelFilter::Warn)
+            .init();
+    }
+
+    if !std::path::Path::new(&args.db_dir).is_dir() {
+        anyhow::bail!("Database directory not found: {}", args.db_dir);
+    }
+
+    // Never pack a partial database: unreadable files abort
+    let backend = FilesystemBackend::from_path(&args.db_dir, FileFormat::Auto)
+        .with_corrupt_file_policy(CorruptFilePolicy::FailFast);
+    let start = std::time::Instant::now();
+    let summary = backend.pack()?;
+    log::info!(
+        "Packed {} references ({} fingerprints) in {:.2}s",
+        summary.references,
+        summary.fingerprints,
+        start.elapsed().as_secs_f64()
+    );
+
+    let mut removed = 0;
+    if args.remove_sources {
+        for path in &summary.loose_files {
+            std::fs::remove_file(path).with_context(|| format!("Failed to remove {}", path.display()))?;
+            removed += 1;
+        }
+    }
+
+    let result = serde_json::json!({
+        "status": "success",
+        "pack_file": std::path::Path::new(&args.db_dir).join(panako_fp::PACK_FILE_NAME).display().to_string(),
+        "references": summary.references,
+        "fingerprints": summary.fingerprints,
+        "removed_files": removed,
+    });
+    println!("{}", serde_json::to_string_pretty(&result)?);
+
+    Ok(())
+}
@@ -0,0 +1,63 @@
+//! fpselftest - Check the whole pipeline on synthetic audio
+//!
+//! Usage: fpselftest [--sample-rate <hz>] [--json]
+//!
+//! Exits with a non-zero status if any check fails.
+
+use anyhow::Result;
+use clap::Parser;
+use panako_cli::selftest::run_selftest;
+use panako_core::PanakoConfig;
+
+#[derive(Parser, Debug)]
+#[command(name = "fpselftest")]
+#[command(about = "Fingerprint and match synthetic audio to check the pipeline", long_about = None)]
+struct Args {
+    /// Sample rate to run the pipeline at (default: 16000 Hz)
+    #[arg(long)]
+    sample_rate: Option<u32>,
+
+    /// Print the report as JSON
+    #[arg(long)]
+    json: bool,
+
+    /// Enable verbose logging
+    #[arg(short, long)]
+    verbose: bool,
+}
+
+fn main() -> Result<()> {
+    let args = Args::parse();
+
+    // Initialize logger
+    if args.verbose {
+        env_logger::Builder::from_default_env()
+            .filter_level(log::LevelFilter::Info)
+            .init();
+    } else {
+        env_logger::Builder::from_default_env()
+            .filter_level(log::LevelFilter::Off)
+            .init();
+    }
+
+    let mut config = PanakoConfig::default();
+    if let Some(rate) = args.sample_rate {
+        config = config.with_sample_rate(rate);
+    }
+
+    let report = run_selftest(&config)?;
+
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&report)?);
+    } else {
+        for check in &report.checks {
+            println!("[{}] {}: {}", if check.passed { "PASS" } else { "FAIL" }, check.name, check.detail);
+        }
+        println!("Self-test {}", if report.passed { "passed" } else { "FAILED" });
+    }
+
+    if !report.passed {
+        std::process::exit(1);
+    }
+    Ok(())
+}
@@ -0,0 +1,90 @@
+//! fpverify - Decide whether two files are the same recording
+//!
+//! Usage: fpverify <file_a> <file_b> [--threshold <0..1>] [--json]
+//!
+//! Either file may be audio/video or a fingerprint file (.json/.bson).
+//! Exits with 0 when the files match, 1 when they don't and 2 on errors.
+
+use anyhow::Result;
+use clap::Parser;
+use panako_cli::verify::{verify_files, DEFAULT_VERIFY_THRESHOLD};
+use panako_core::PanakoConfig;
+use std::path::Path;
+
+#[derive(Parser, Debug)]
+#[command(name = "fpverify")]
+#[command(about = "Check whether two files are the same recording", long_about = None)]
+struct Args {
+    /// First audio or fingerprint file
+    file_a: String,
+
+    /// Second audio or fingerprint file
+    file_b: String,
+
+    /// Minimum similarity (0..1) to report a match
+    #[arg(long, default_value_t = DEFAULT_VERIFY_THRESHOLD)]
+    threshold: f64,
+
+    /// Analysis sample rate in Hz for audio inputs
+    #[arg(long)]
+    sample_rate: Option<u32>,
+
+    /// Print the report as JSON
+    #[arg(long)]
+    json: bool,
+
+    /// Enable verbose logging
+    #[arg(short, long)]
+    verbose: bool,
+}
+
+fn main() {
+    let args = Args::parse();
+
+    // Initialize logger
+    if args.verbose {
+        env_logger::Builder::from_default_env()
+            .filter_level(log::LevelFilter::Info)
+            .init();
+    } else {
+        env_logger::Builder::from_default_env()
+            .filter_level(log::LevelFilter::Warn)
+            .init();
+    }
+
+    match run(&args) {
+        Ok(code) => std::process::exit(code),
+        Err(e) => {
+            eprintln!("Error: {:#}", e);
+            std::process::exit(2);
+        }
+    }
+}
+
+fn run(args: &Args) -> Result<i32> {
+    if !(0.0..=1.0).contains(&args.threshold) {
+        anyhow::bail!("--threshold must be in [0, 1], got {}", args.threshold);
+    }
+    let mut config = PanakoConfig::default();
+    if let Some(rate) = args.sample_rate {
+        config = config.with_sample_rate(rate);
+    }
+    config.validate()?;
+
+    let report = verify_files(Path::new(&args.file_a), Path::new(&args.file_b), &config, args.threshold)?;
+
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&report)?);
+    } else {
+        println!(
+            "{} similarity {:.3} (threshold {:.3}, {} vs {} fingerprints)",
+            if report.is_match { "MATCH" } else { "NO MATCH" },
+            report.similarity,
+            report.threshold,
+            report.fingerprints_a,
+            report.fingerprints_b
+        );
+    }
+
+    Ok(report.exit_code())
+}
@@ -0,0 +1,216 @@
+//! Merging of several filesystem fingerprint databases into one catalog
+
+use anyhow::{Context, Result};
+use panako_core::storage_backend::{FilesystemBackend, StorageBackend};
+use panako_core::storage_config::FileFormat;
+use serde::Serialize;
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+
+/// How to resolve an identifier that already exists in the destination
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum CollisionPolicy {
+    /// Keep the destination entry and drop the incoming one
+    #[default]
+    Skip,
+    /// Replace the destination entry with the incoming one
+    Overwrite,
+    /// Store the incoming entry under a new identifier (`<id>_2`, `<id>_3`, ...)
+    Rename,
+}
+
+/// Outcome of a database merge
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct MergeReport {
+    /// Identifiers copied without collision
+    pub copied: Vec<String>,
+    /// Colliding identifiers that were left untouched
+    pub skipped: Vec<String>,
+    /// Colliding identifiers whose destination entry was replaced
+    pub overwritten: Vec<String>,
+    /// Colliding identifiers stored under a new name, as `(original, new)`
+    pub renamed: Vec<(String, String)>,
+}
+
+/// Merge fingerprint databases from `sources` into `dest_dir`
+///
+/// Sources are processed in order, so with `CollisionPolicy::Skip` the first
+/// directory providing an identifier wins, and with `Overwrite` the last one.
+/// Collisions with entries already present in the destination are resolved
+/// the same way. Renamed entries also get the new identifier in their metadata
+/// so matches report it. Files are copied whole, keeping their segments and
+/// all metadata.
+pub async fn merge_databases(
+    sources: &[PathBuf],
+    dest_dir: &Path,
+    format: FileFormat,
+    policy: CollisionPolicy,
+) -> Result<MergeReport> {
+    let dest = FilesystemBackend::from_path(&dest_dir.to_string_lossy(), format);
+    let mut existing: HashSet<String> = dest.identifiers()?.into_iter().collect();
+    let mut report = MergeReport::default();
+
+    for source_dir in sources {
+        if !source_dir.exists() {
+            anyhow::bail!("Source directory not found: {}", source_dir.display());
+        }
+        let source = FilesystemBackend::from_path(&source_dir.to_string_lossy(), FileFormat::Auto);
+
+        for identifier in source.identifiers()? {
+            let mut fp_file = source
+                .load_file(&identifier)
+                .with_context(|| format!("Failed to load '{}' from {}", identifier, source_dir.display()))?;
+
+            let target = if !existing.contains(&identifier) {
+                report.copied.push(identifier.clone());
+                identifier.clone()
+            } else {
+                match policy {
+                    CollisionPolicy::Skip => {
+                        log::info!("Skipping '{}' from {} (already present)", identifier, source_dir.display());
+                        report.skipped.push(identifier);
+                        continue;
+                    }
+                    CollisionPolicy::Overwrite => {
+                        dest.delete_fingerprints(&identifier).await?;
+                        report.overwritten.push(identifier.clone());
+                        identifier.clone()
+                    }
+                    CollisionPolicy::Rename => {
+                        let renamed = (2..)
+                            .map(|n| format!("{}_{}", identifier, n))
+                            .find(|candidate| !existing.contains(candidate))
+                            .unwrap();
+                        log::info!("Renaming '{}' from {} to '{}'", identifier, source_dir.display(), renamed);
+                        report.renamed.push((identifier.clone(), renamed.clone()));
+                        renamed
+                    }
+                }
+            };
+
+            fp_file.metadata.filename = target.clone();
+            dest.save_file(&target, &fp_file)?;
+            existing.insert(target);
+        }
+    }
+
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use panako_fp::{FpJsonFile, FpJsonFingerprint, FpJsonSegment};
+
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("panako_catalog_{}_{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn write_reference(dir: &Path, identifier: &str, hash: u64) {
+        let mut fp_file = FpJsonFile::new(
+            format!("/audio/{}.wav", identifier),
+            identifier.to_string(),
+            16000,
+            5000,
+            1,
+        );
+        fp_file.add_segment(FpJsonSegment {
+            segment_id: 0,
+            start_time_s: 0.0,
+            end_time_s: 5.0,
+            num_fingerprints: 1,
+            fingerprints: vec![FpJsonFingerprint { hash, t1: 10, f1: 20, m1: 1.0 }],
+        });
+        fp_file.save(&dir.join(format!("{}.json", identifier))).unwrap();
+    }
+
+    async fn merge_with(policy: CollisionPolicy) -> (MergeReport, Vec<(String, u64)>) {
+        let name = format!("{:?}", policy).to_lowercase();
+        let a = test_dir(&format!("{}_a", name));
+        let b = test_dir(&format!("{}_b", name));
+        let dest = test_dir(&format!("{}_dest", name));
+        write_reference(&a, "jingle", 1);
+        write_reference(&a, "spot_a", 2);
+        write_reference(&b, "jingle", 3);
+        write_reference(&b, "spot_b", 4);
+
+        let report = merge_databases(&[a.clone(), b.clone()], &dest, FileFormat::Json, policy)
+            .await
+            .unwrap();
+
+        let backend = FilesystemBackend::from_path(dest.to_str().unwrap(), FileFormat::Json);
+        let mut contents = Vec::new();
+        for identifier in backend.identifiers().unwrap() {
+            let fps = backend.load_fingerprints(&identifier).await.unwrap();
+            let metadata = backend.get_metadata(&identifier).await.unwrap().unwrap();
+            assert_eq!(metadata.filename, identifier);
+            contents.push((identifier, fps[0].0));
+        }
+
+        for dir in [a, b, dest] {
+            std::fs::remove_dir_all(dir).ok();
+        }
+        (report, contents)
+    }
+
+    #[tokio::test]
+    async fn test_merge_keeps_segments_and_metadata() {
+        let source = test_dir("segmented_src");
+        let dest = test_dir("segmented_dest");
+        let mut fp_file = FpJsonFile::new("/audio/show.wav".to_string(), "show".to_string(), 16000, 25000, 2);
+        fp_file.metadata.content_id = Some("ISRC-1".to_string());
+        fp_file.metadata.tags.insert("label".to_string(), "news".to_string());
+        for segment_id in 0..3 {
+            fp_file.add_segment(FpJsonSegment {
+                segment_id,
+                start_time_s: segment_id as f64 * 8.0,
+                end_time_s: segment_id as f64 * 8.0 + 10.0,
+                num_fingerprints: 2,
+                fingerprints: (0..2)
+                    .map(|i| FpJsonFingerprint { hash: segment_id as u64 * 10 + i, t1: i as i32, f1: 20, m1: 0.5 })
+                    .collect(),
+            });
+        }
+        fp_file.save(&source.join("show.json")).unwrap();
+
+        merge_databases(std::slice::from_ref(&source), &dest, FileFormat::Json, CollisionPolicy::Skip)
+            .await
+            .unwrap();
+        let merged = FpJsonFile::load_auto(&dest.join("show.json")).unwrap();
+        for dir in [source, dest] {
+            std::fs::remove_dir_all(dir).ok();
+        }
+
+        assert_eq!(serde_json::to_value(&merged).unwrap(), serde_json::to_value(&fp_file).unwrap());
+        assert_eq!(merged.segments.len(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_merge_collision_skip() {
+        let (report, contents) = merge_with(CollisionPolicy::Skip).await;
+        assert_eq!(report.copied, vec!["jingle", "spot_a", "spot_b"]);
+        assert_eq!(report.skipped, vec!["jingle"]);
+        assert!(contents.contains(&("jingle".to_string(), 1)));
+        assert_eq!(contents.len(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_merge_collision_overwrite() {
+        let (report, contents) = merge_with(CollisionPolicy::Overwrite).await;
+        assert_eq!(report.overwritten, vec!["jingle"]);
+        assert!(contents.contains(&("jingle".to_string(), 3)));
+        assert_eq!(contents.len(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_merge_collision_rename() {
+        let (report, contents) = merge_with(CollisionPolicy::Rename).await;
+        assert_eq!(report.renamed, vec![("jingle".to_string(), "jingle_2".to_string())]);
+        assert!(contents.contains(&("jingle".to_string(), 1)));
+        assert!(contents.contains(&("jingle_2".to_string(), 3)));
+        assert_eq!(contents.len(), 4);
+    }
+}
@@ -0,0 +1,167 @@
+//! Loading of fingerprint database directories
+
+use anyhow::Result;
+use panako_core::{load_reference_dir, CorruptFilePolicy};
+use panako_fp::FpJsonFile;
+use std::path::{Path, PathBuf};
+
+pub use panako_core::resolve_identifier_collisions;
+
+/// Check whether a path has a fingerprint file extension (.json or .bson)
+pub fn is_fingerprint_file(path: &Path) -> bool {
+    path.extension()
+        .and_then(|s| s.to_str())
+        .map(|ext| ext.eq_ignore_ascii_case("json") || ext.eq_ignore_ascii_case("bson"))
+        .unwrap_or(false)
+}
+
+/// Load all fingerprint files (JSON and BSON) from a database directory
+///
+/// Files are loaded in parallel with format auto-detection. Files that fail to
+/// load are logged and skipped. Returns `(identifier, file)` pairs where the
+/// identifier is the `filename` stored in the file metadata, made unique with
+/// `resolve_identifier_collisions` (collisions are logged as warnings).
+pub fn load_database_dir(db_dir: &Path) -> Result<Vec<(String, FpJsonFile)>> {
+    let (loaded_files, _failed) = load_database_dir_with_policy(db_dir, CorruptFilePolicy::SkipWithWarning)?;
+    Ok(loaded_files)
+}
+
+/// `(identifier, file)` pairs loaded from a database directory
+type LoadedReferences = Vec<(String, FpJsonFile)>;
+
+/// Load a database directory with an explicit policy for corrupt files
+///
+/// Like `load_database_dir`, but `policy` decides whether unreadable files are
+/// skipped, abort the load, or are returned as the second element
+/// (`CorruptFilePolicy::Collect`).
+pub fn load_database_dir_with_policy(
+    db_dir: &Path,
+    policy: CorruptFilePolicy,
+) -> Result<(LoadedReferences, Vec<PathBuf>)> {
+    let loaded = load_reference_dir(db_dir, policy)?;
+    Ok((loaded.references, loaded.failed))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use panako_fp::{FpJsonFingerprint, FpJsonSegment};
+
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("panako_db_{}_{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn sample_file(filename: &str) -> FpJsonFile {
+        let mut fp_file = FpJsonFile::new(
+            format!("/audio/{}.wav", filename),
+            filename.to_string(),
+            16000,
+            5000,
+            1,
+        );
+        fp_file.add_segment(FpJsonSegment {
+            segment_id: 0,
+            start_time_s: 0.0,
+            end_time_s: 5.0,
+            num_fingerprints: 1,
+            fingerprints: vec![FpJsonFingerprint { hash: 42, t1: 10, f1: 20, m1: 1.0 }],
+        });
+        fp_file
+    }
+
+    #[test]
+    fn test_is_fingerprint_file() {
+        assert!(is_fingerprint_file(Path::new("a.json")));
+        assert!(is_fingerprint_file(Path::new("a.bson")));
+        assert!(is_fingerprint_file(Path::new("a.JSON")));
+        assert!(!is_fingerprint_file(Path::new("a.fp")));
+        assert!(!is_fingerprint_file(Path::new("a")));
+    }
+
+    #[test]
+    fn test_load_database_dir_mixed_formats() {
+        let dir = test_dir("mixed");
+        sample_file("json_ref").save(&dir.join("json_ref.json")).unwrap();
+        sample_file("bson_ref").save_bson(&dir.join("bson_ref.bson")).unwrap();
+        sample_file("upper_ref").save_bson(&dir.join("upper_ref.BSON")).unwrap();
+        std::fs::write(dir.join("notes.txt"), "not a fingerprint").unwrap();
+        std::fs::write(dir.join("broken.json"), "{ not json").unwrap();
+
+        let mut loaded = load_database_dir(&dir).unwrap();
+        loaded.sort_by(|a, b| a.0.cmp(&b.0));
+        std::fs::remove_dir_all(&dir).ok();
+
+        let ids: Vec<&str> = loaded.iter().map(|(id, _)| id.as_str()).collect();
+        assert_eq!(ids, vec!["bson_ref", "json_ref", "upper_ref"]);
+        assert!(loaded.iter().all(|(_, f)| f.get_all_fingerprints().len() == 1));
+    }
+
+    #[test]
+    fn test_load_database_dir_bson_only() {
+        // A database built with `fpgen --format bson` (as loaded by fpmonitor)
+        let dir = test_dir("bson_only");
+        for name in ["spot_a", "spot_b"] {
+            sample_file(name).save_bson(&dir.join(format!("{}.bson", name))).unwrap();
+        }
+
+        let loaded = load_database_dir(&dir).unwrap();
+        std::fs::remove_dir_all(&dir).ok();
+
+        assert_eq!(loaded.len(), 2);
+        assert!(loaded.iter().all(|(_, f)| f.get_all_fingerprints() == vec![(42, 10, 20, 1.0)]));
+    }
+
+    #[test]
+    fn test_same_stem_identifiers_are_disambiguated() {
+        let dir = test_dir("same_stem");
+        let mut mp3 = sample_file("song");
+        mp3.metadata.original_path = "/audio/song.mp3".to_string();
+        mp3.save(&dir.join("song.json")).unwrap();
+        let mut wav = sample_file("song");
+        wav.metadata.original_path = "/audio/song.wav".to_string();
+        wav.save_bson(&dir.join("song_1.bson")).unwrap();
+        sample_file("other").save(&dir.join("other.json")).unwrap();
+
+        let mut loaded = load_database_dir(&dir).unwrap();
+        std::fs::remove_dir_all(&dir).ok();
+        loaded.sort_by(|a, b| a.0.cmp(&b.0));
+        let ids: Vec<&str> = loaded.iter().map(|(id, _)| id.as_str()).collect();
+        assert_eq!(ids, vec!["other", "song.mp3", "song.wav"]);
+
+        // The collision itself is reported
+        let mut files = vec![
+            ("song".to_string(), sample_file("song")),
+            ("song".to_string(), sample_file("song")),
+            ("other".to_string(), sample_file("other")),
+        ];
+        assert_eq!(resolve_identifier_collisions(&mut files), vec!["song".to_string()]);
+        let ids: Vec<&str> = files.iter().map(|(id, _)| id.as_str()).collect();
+        assert_eq!(ids, vec!["song.wav", "song.wav#2", "other"]);
+    }
+
+    #[test]
+    fn test_load_database_dir_corrupt_file_policies() {
+        let dir = test_dir("corrupt");
+        sample_file("good_ref").save(&dir.join("good_ref.json")).unwrap();
+        std::fs::write(dir.join("broken.bson"), b"\x05\x00").unwrap();
+
+        let skipped = load_database_dir(&dir).unwrap();
+        let fail_fast = load_database_dir_with_policy(&dir, CorruptFilePolicy::FailFast);
+        let (collected, failed) = load_database_dir_with_policy(&dir, CorruptFilePolicy::Collect).unwrap();
+        std::fs::remove_dir_all(&dir).ok();
+
+        assert_eq!(skipped.len(), 1);
+        assert!(format!("{:#}", fail_fast.unwrap_err()).contains("broken.bson"));
+        assert_eq!(collected.len(), 1);
+        assert_eq!(collected[0].0, "good_ref");
+        assert_eq!(failed, vec![dir.join("broken.bson")]);
+    }
+
+    #[test]
+    fn test_load_database_dir_missing() {
+        assert!(load_database_dir(Path::new("/nonexistent/panako/db")).is_err());
+    }
+}
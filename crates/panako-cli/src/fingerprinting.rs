@@ -0,0 +1,175 @@
+//! Fingerprint generation helpers shared by the CLI binaries
+
+use anyhow::Result;
+use panako_core::{audio::AudioData, config::PanakoConfig, fingerprint_audio_timed, Fingerprint};
+
+/// Generate fingerprints from decoded audio data
+pub fn fingerprint_audio(audio: &AudioData, config: &PanakoConfig) -> Result<Vec<Fingerprint>> {
+    let (fingerprints, _timings) = fingerprint_audio_timed(audio, config)?;
+    Ok(fingerprints)
+}
+
+/// Shift segment-relative fingerprint times to absolute (whole-file) frames
+///
+/// The segment start is rounded to the nearest frame: truncating would put
+/// segments whose start is not a whole number of frames (or is just below one
+/// due to floating-point error) a frame early.
+pub fn offset_segment_fingerprints(
+    fingerprints: &[Fingerprint],
+    start_time_s: f64,
+    config: &PanakoConfig,
+) -> Vec<Fingerprint> {
+    let time_offset_frames = (start_time_s / config.frame_duration_s()).round() as i32;
+
+    fingerprints
+        .iter()
+        .map(|fp| {
+            let mut adjusted = *fp;
+            adjusted.t1 += time_offset_frames;
+            adjusted.t2 += time_offset_frames;
+            adjusted.t3 += time_offset_frames;
+            adjusted
+        })
+        .collect()
+}
+
+/// Map fingerprint times from one frame grid to another
+///
+/// `from_spf` and `to_spf` are the frame durations in seconds
+/// (`PanakoConfig::frame_duration_s`) the fingerprints were made with and the
+/// reference grid to match against. `t1` is rounded to the nearest target
+/// frame. Only times are rescaled: hashes and `f1` still encode the original
+/// analysis, so matching also needs compatible frequency bins.
+pub fn rescale_fingerprint_times(
+    fingerprints: &[(u64, i32, i16, f32)],
+    from_spf: f64,
+    to_spf: f64,
+) -> Vec<(u64, i32, i16, f32)> {
+    let ratio = from_spf / to_spf;
+    fingerprints
+        .iter()
+        .map(|&(hash, t1, f1, m1)| (hash, (t1 as f64 * ratio).round() as i32, f1, m1))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use panako_core::EventPoint;
+
+    #[test]
+    fn test_fingerprint_audio_silence_and_short_input() {
+        let config = PanakoConfig::default();
+
+        let silence = AudioData {
+            samples: vec![0.0; 16000 * 2],
+            sample_rate: 16000,
+            channels: 1,
+            duration_ms: 2000,
+        };
+        assert!(fingerprint_audio(&silence, &config).unwrap().is_empty());
+
+        // Shorter than a single hop: no frames, no panic
+        let tiny = AudioData {
+            samples: vec![0.5; 64],
+            sample_rate: 16000,
+            channels: 1,
+            duration_ms: 4,
+        };
+        assert!(fingerprint_audio(&tiny, &config).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_offset_segment_fingerprints() {
+        let fp = Fingerprint::new(
+            &EventPoint::new(0, 100, 0.5),
+            &EventPoint::new(10, 120, 0.7),
+            &EventPoint::new(20, 110, 0.6),
+        );
+
+        let shifted = offset_segment_fingerprints(&[fp], 20.0, &PanakoConfig::default());
+        assert_eq!(shifted[0].t1, 2500);
+        assert_eq!(shifted[0].t2, 2510);
+        assert_eq!(shifted[0].t3, 2520);
+        assert_eq!(shifted[0].hash, fp.hash);
+
+        let unshifted = offset_segment_fingerprints(&[fp], 0.0, &PanakoConfig::default());
+        assert_eq!(unshifted[0], fp);
+
+        // Just below a frame boundary (accumulated segment steps)
+        let shifted = offset_segment_fingerprints(&[fp], 74.999_999_99, &PanakoConfig::default());
+        assert_eq!(shifted[0].t1, 9375);
+    }
+
+    #[test]
+    fn test_segmented_times_match_whole_file() {
+        use crate::selftest::synthesize_signal;
+        use panako_core::segmentation::{segment_audio, SegmentationConfig};
+        use std::collections::HashMap;
+
+        let config = PanakoConfig::default();
+        let samples = synthesize_signal(config.sample_rate, 30.0);
+        let audio = AudioData {
+            samples,
+            sample_rate: config.sample_rate,
+            channels: 1,
+            duration_ms: 30000,
+        };
+        // 7.048 s steps (881 frames): the segment starts are whole frames, but
+        // the fourth is just below one in floating point, so truncating it
+        // would put that segment a frame early
+        let seg_config = SegmentationConfig {
+            segment_duration_s: 8.0,
+            overlap_duration_s: 0.952,
+            min_segment_duration_s: 2.0,
+            ..SegmentationConfig::default()
+        };
+
+        let mut whole_times: HashMap<u64, Vec<i32>> = HashMap::new();
+        for fp in fingerprint_audio(&audio, &config).unwrap() {
+            whole_times.entry(fp.hash).or_default().push(fp.t1);
+        }
+
+        let segments = segment_audio(&audio, &seg_config).unwrap();
+        assert!(segments.len() >= 4);
+        for segment in &segments {
+            let segment_audio = AudioData {
+                samples: segment.samples.clone(),
+                sample_rate: segment.sample_rate,
+                channels: 1,
+                duration_ms: ((segment.end_time_s - segment.start_time_s) * 1000.0) as u32,
+            };
+            let fingerprints = fingerprint_audio(&segment_audio, &config).unwrap();
+            let shifted = offset_segment_fingerprints(&fingerprints, segment.start_time_s, &config);
+
+            // Most common difference to the whole-file time of the same hash
+            // (nearby only: the test signal repeats itself)
+            let mut offsets: HashMap<i32, usize> = HashMap::new();
+            for fp in &shifted {
+                for &t1 in whole_times.get(&fp.hash).into_iter().flatten() {
+                    if (fp.t1 - t1).abs() <= 50 {
+                        *offsets.entry(fp.t1 - t1).or_default() += 1;
+                    }
+                }
+            }
+            let (&offset, _) = offsets.iter().max_by_key(|&(&offset, &count)| (count, -offset.abs())).unwrap();
+            assert_eq!(offset, 0, "segment {} is off by {} frames", segment.segment_id, offset);
+        }
+    }
+
+    #[test]
+    fn test_rescale_fingerprint_times_to_16k_grid() {
+        let from_spf = PanakoConfig::default().with_sample_rate(22050).frame_duration_s();
+        let to_spf = PanakoConfig::default().frame_duration_s();
+
+        let fingerprints = [(7, 0, 10, 0.5), (8, 100, 20, 0.5), (9, 22050, 30, 0.5)];
+        let rescaled = rescale_fingerprint_times(&fingerprints, from_spf, to_spf);
+
+        // 128 samples per frame: 5.805 ms at 22.05 kHz, 8 ms at 16 kHz
+        assert_eq!(rescaled[0], (7, 0, 10, 0.5));
+        // 100 * 128 / 22050 s = 0.5805 s = frame 72.56
+        assert_eq!(rescaled[1], (8, 73, 20, 0.5));
+        // 22050 frames of 128 samples = 128 s = 16000 frames
+        assert_eq!(rescaled[2], (9, 16000, 30, 0.5));
+    }
+}
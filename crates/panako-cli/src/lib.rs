@@ -1,5 +1,26 @@
 //! Shared CLI utilities
 
+pub mod aggregation;
+pub mod batch;
+pub mod cancel;
+pub mod catalog;
+pub mod database;
+pub mod fingerprinting;
+pub mod merge;
 pub mod output;
+pub mod selftest;
+pub mod verify;
 
-pub use output::print_json_result;
+pub use aggregation::TemporalAggregator;
+pub use batch::{BatchMatcher, DecodeLimiter};
+pub use cancel::{cancel_on_ctrl_c, CancellationToken};
+pub use catalog::{merge_databases, CollisionPolicy, MergeReport};
+pub use database::{load_database_dir, load_database_dir_with_policy};
+pub use fingerprinting::{fingerprint_audio, offset_segment_fingerprints, rescale_fingerprint_times};
+pub use merge::{debounce_detections, merge_detections_within_gap, merge_overlapping_detections};
+pub use output::{
+    format_java_result, print_json_result, sort_results, write_json_results, write_jsonl_results, OutputFormat,
+    QueryPosition, SortBy, TimelineResult,
+};
+pub use selftest::{run_selftest, SelfTestReport};
+pub use verify::{verify_files, VerifyReport};
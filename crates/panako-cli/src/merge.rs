@@ -0,0 +1,241 @@
+//! Merging of duplicate detections from overlapping segments
+
+use panako_core::matching::{FrameTimes, QueryResult};
+
+/// Maximum disagreement (seconds) between two detections' alignment offsets
+/// or the gap between their query spans for them to count as the same detection
+pub const MERGE_TOLERANCE_S: f64 = 1.0;
+
+/// Merge detections of the same reference reported by overlapping segments
+///
+/// Overlapping query segments see the same audio twice, so one occurrence of a
+/// reference can be reported once per segment. Detections are merged when they
+/// share a reference, agree on the query-to-reference offset and their query
+/// spans overlap or touch. The merged result spans both detections and keeps
+/// the fields of the highest-scoring one. Output is sorted by query start.
+pub fn merge_overlapping_detections(results: Vec<QueryResult>) -> Vec<QueryResult> {
+    merge_detections_within_gap(results, MERGE_TOLERANCE_S)
+}
+
+/// Merge detections of the same reference separated by short gaps
+///
+/// Like `merge_overlapping_detections`, but detections that agree on the
+/// query-to-reference offset are joined as long as the gap between their
+/// query spans is at most `max_gap_s`. A continuous play interrupted by a
+/// brief dropout is then reported as one detection spanning both parts.
+pub fn merge_detections_within_gap(mut results: Vec<QueryResult>, max_gap_s: f64) -> Vec<QueryResult> {
+    results.sort_by(|a, b| {
+        a.ref_identifier
+            .cmp(&b.ref_identifier)
+            .then(a.query_start.partial_cmp(&b.query_start).unwrap_or(std::cmp::Ordering::Equal))
+    });
+
+    let mut merged: Vec<QueryResult> = Vec::with_capacity(results.len());
+
+    for result in results {
+        if let Some(last) = merged.last_mut() {
+            if is_same_detection(last, &result, max_gap_s) {
+                merge_into(last, result);
+                continue;
+            }
+        }
+        merged.push(result);
+    }
+
+    merged.sort_by(|a, b| {
+        a.query_start.partial_cmp(&b.query_start).unwrap_or(std::cmp::Ordering::Equal)
+    });
+
+    merged
+}
+
+/// Report at most one detection of a reference per burst of detections
+///
+/// Detections of the same reference whose query spans are separated by less
+/// than `min_gap_s` (from the end of one to the start of the next) form a
+/// burst, whatever their offsets; only the highest-scoring detection of each
+/// burst is kept, unchanged. Unlike merging, this also collapses clusters
+/// that disagree on the offset, so one airing counts as one play. Output is
+/// sorted by query start.
+pub fn debounce_detections(mut results: Vec<QueryResult>, min_gap_s: f64) -> Vec<QueryResult> {
+    results.sort_by(|a, b| {
+        a.ref_identifier
+            .cmp(&b.ref_identifier)
+            .then(a.query_start.total_cmp(&b.query_start))
+    });
+
+    let mut kept: Vec<QueryResult> = Vec::with_capacity(results.len());
+    // End of the burst the last kept detection belongs to
+    let mut burst_stop = f64::NEG_INFINITY;
+
+    for result in results {
+        if let Some(last) = kept.last_mut() {
+            if result.ref_identifier.is_some()
+                && result.ref_identifier == last.ref_identifier
+                && result.query_start - burst_stop < min_gap_s
+            {
+                burst_stop = burst_stop.max(result.query_stop);
+                if result.score > last.score {
+                    *last = result;
+                }
+                continue;
+            }
+        }
+        burst_stop = result.query_stop;
+        kept.push(result);
+    }
+
+    kept.sort_by(|a, b| a.query_start.total_cmp(&b.query_start));
+    kept
+}
+
+fn is_same_detection(a: &QueryResult, b: &QueryResult, max_gap_s: f64) -> bool {
+    if a.ref_identifier.is_none() || a.ref_identifier != b.ref_identifier {
+        return false;
+    }
+
+    let offset_a = a.query_start - a.ref_start;
+    let offset_b = b.query_start - b.ref_start;
+    if (offset_a - offset_b).abs() > MERGE_TOLERANCE_S {
+        return false;
+    }
+
+    b.query_start <= a.query_stop + max_gap_s
+}
+
+pub(crate) fn merge_into(target: &mut QueryResult, other: QueryResult) {
+    let query_start = target.query_start.min(other.query_start);
+    let query_stop = target.query_stop.max(other.query_stop);
+    let ref_start = target.ref_start.min(other.ref_start);
+    let ref_stop = target.ref_stop.max(other.ref_stop);
+    let frames = match (target.frames, other.frames) {
+        (Some(a), Some(b)) => Some(FrameTimes {
+            query_start: a.query_start.min(b.query_start),
+            query_stop: a.query_stop.max(b.query_stop),
+            ref_start: a.ref_start.min(b.ref_start),
+            ref_stop: a.ref_stop.max(b.ref_stop),
+            seconds_per_frame: a.seconds_per_frame,
+        }),
+        (a, b) => a.or(b),
+    };
+
+    if other.score > target.score {
+        *target = other;
+    }
+
+    target.query_start = query_start;
+    target.query_stop = query_stop;
+    target.ref_start = ref_start;
+    target.ref_stop = ref_stop;
+    target.frames = frames;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn detection(reference: &str, query_start: f64, query_stop: f64, ref_start: f64, score: i32) -> QueryResult {
+        let mut result = QueryResult::empty("query".to_string(), query_start, query_stop);
+        result.ref_identifier = Some(reference.to_string());
+        result.ref_path = Some(reference.to_string());
+        result.ref_start = ref_start;
+        result.ref_stop = ref_start + (query_stop - query_start);
+        result.score = score;
+        result
+    }
+
+    #[test]
+    fn test_overlapping_segments_collapse() {
+        // Segment 0 (0-25s) and segment 1 (20-45s) both see the spot at 18-28s
+        let mut first = detection("spot", 18.0, 24.8, 0.0, 40);
+        first.segment_index = Some(0);
+        let mut second = detection("spot", 20.1, 28.0, 2.1, 55);
+        second.segment_index = Some(1);
+
+        let merged = merge_overlapping_detections(vec![second, first]);
+
+        assert_eq!(merged.len(), 1);
+        assert_eq!(merged[0].segment_index, Some(1));
+        assert_eq!(merged[0].score, 55);
+        assert!((merged[0].query_start - 18.0).abs() < 1e-9);
+        assert!((merged[0].query_stop - 28.0).abs() < 1e-9);
+        assert!((merged[0].ref_start - 0.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_merge_spans_frame_times() {
+        let frames = |query_start, query_stop, ref_start, ref_stop| FrameTimes {
+            query_start,
+            query_stop,
+            ref_start,
+            ref_stop,
+            seconds_per_frame: 0.008,
+        };
+        let mut first = detection("spot", 18.0, 24.8, 0.0, 40);
+        first.frames = Some(frames(2250, 3100, 0, 850));
+        let mut second = detection("spot", 20.1, 28.0, 2.1, 55);
+        second.frames = Some(frames(2512, 3500, 262, 1250));
+
+        let merged = merge_overlapping_detections(vec![first, second]);
+
+        assert_eq!(merged.len(), 1);
+        assert_eq!(merged[0].frames, Some(frames(2250, 3500, 0, 1250)));
+    }
+
+    #[test]
+    fn test_distinct_detections_are_kept() {
+        let results = vec![
+            // Same reference aired twice at different offsets
+            detection("spot", 10.0, 20.0, 0.0, 30),
+            detection("spot", 60.0, 70.0, 0.0, 30),
+            // Different reference overlapping the first airing
+            detection("other", 12.0, 18.0, 2.0, 20),
+        ];
+
+        let merged = merge_overlapping_detections(results);
+
+        assert_eq!(merged.len(), 3);
+        assert!(merged.windows(2).all(|w| w[0].query_start <= w[1].query_start));
+    }
+
+    #[test]
+    fn test_dropout_gap_merges_within_threshold() {
+        // 40 s play of the same reference, interrupted by a 1 s dropout at 20-21 s
+        let first = detection("program", 0.0, 20.0, 0.0, 60);
+        let second = detection("program", 21.0, 40.0, 21.0, 50);
+
+        let merged = merge_detections_within_gap(vec![first.clone(), second.clone()], 2.0);
+        assert_eq!(merged.len(), 1);
+        assert!((merged[0].query_start - 0.0).abs() < 1e-9);
+        assert!((merged[0].query_stop - 40.0).abs() < 1e-9);
+        assert!((merged[0].ref_start - 0.0).abs() < 1e-9);
+        assert!((merged[0].ref_stop - 40.0).abs() < 1e-9);
+
+        // A smaller grace period keeps them apart
+        assert_eq!(merge_detections_within_gap(vec![first, second], 0.5).len(), 2);
+    }
+
+    #[test]
+    fn test_debounce_collapses_bursts() {
+        // One airing reported three times, 2 s apart, at disagreeing offsets
+        let results = vec![
+            detection("spot", 100.0, 104.0, 0.0, 20),
+            detection("spot", 106.0, 110.0, 9.0, 35),
+            detection("spot", 112.0, 116.0, 3.0, 25),
+            // The next airing, well after the burst
+            detection("spot", 200.0, 215.0, 0.0, 40),
+            detection("other", 107.0, 112.0, 0.0, 15),
+        ];
+
+        let debounced = debounce_detections(results.clone(), 10.0);
+        let spots: Vec<_> = debounced.iter().filter(|r| r.ref_identifier.as_deref() == Some("spot")).collect();
+        assert_eq!(debounced.len(), 3);
+        assert_eq!(spots.len(), 2);
+        assert_eq!((spots[0].query_start, spots[0].score), (106.0, 35));
+        assert_eq!(spots[1].query_start, 200.0);
+        assert!(debounced.windows(2).all(|w| w[0].query_start <= w[1].query_start));
+
+        // Gaps at or above the debounce are separate plays
+        assert_eq!(debounce_detections(results, 2.0).len(), 5);
+    }
+}
@@ -1,13 +1,244 @@
 //! JSON output formatting
 
 use panako_core::matching::QueryResult;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
+use std::io::Write;
+
+/// Minimum detection duration in seconds for a result to be reported
+const MIN_DURATION_SECONDS: f64 = 2.0;
 
 #[derive(Serialize)]
 struct MatchOutput {
     query_path: String,
     detections: usize,
-    results: Vec<QueryResult>,
+    results: Vec<serde_json::Value>,
+}
+
+/// Layout of reported detections
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum OutputFormat {
+    /// `QueryResult` fields as produced by the matcher
+    #[default]
+    Raw,
+    /// `TimelineResult`: every time labeled with the timeline it refers to
+    Timeline,
+    /// Java Panako's semicolon-separated result lines (not JSON)
+    Java,
+}
+
+/// Order of reported detections
+///
+/// Ties are broken by query start, then reference identifier, then
+/// reference start, so the order never depends on the matcher's output order.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum SortBy {
+    /// Highest score first
+    Score,
+    /// Start of the match in the query file
+    #[default]
+    QueryTime,
+    /// Query file time at which the reference begins (`absolute_start`),
+    /// falling back to the query start when it is unknown
+    AbsoluteTime,
+    /// Reference identifier, alphabetically
+    Reference,
+}
+
+/// Sort results for output in the order `sort_by` asks for
+pub fn sort_results(results: &mut [QueryResult], sort_by: SortBy) {
+    let ties = |a: &QueryResult, b: &QueryResult| {
+        a.query_start
+            .total_cmp(&b.query_start)
+            .then_with(|| a.ref_identifier.cmp(&b.ref_identifier))
+            .then_with(|| a.ref_start.total_cmp(&b.ref_start))
+    };
+    results.sort_by(|a, b| {
+        let primary = match sort_by {
+            SortBy::Score => b.score.cmp(&a.score),
+            SortBy::QueryTime => std::cmp::Ordering::Equal,
+            SortBy::AbsoluteTime => a
+                .absolute_start
+                .unwrap_or(a.query_start)
+                .total_cmp(&b.absolute_start.unwrap_or(b.query_start)),
+            SortBy::Reference => a.ref_identifier.cmp(&b.ref_identifier),
+        };
+        primary.then_with(|| ties(a, b))
+    });
+}
+
+/// Column header printed by Java Panako before its result lines
+pub const JAVA_RESULT_HEADER: &str = "Index ; Total ; Query path ; Query start (s) ; Query stop (s) ; \
+Match path ; Match id ; Match start (s) ; Match stop (s) ; Match score ; Time factor (%) ; \
+Frequency factor (%) ; Seconds with match (%)";
+
+/// Position of a query in a run, reported in Java Panako's result lines
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QueryPosition {
+    /// 1-based index of the query
+    pub index: usize,
+    /// Number of queries in the run (0 when not known in advance)
+    pub total: usize,
+}
+
+impl QueryPosition {
+    /// The only query of a run
+    pub const SINGLE: Self = Self { index: 1, total: 1 };
+}
+
+/// Format a result like Java Panako's query result handler
+///
+/// `index` and `total` are the 1-based position of the query and the number of
+/// queries in the run. Factors are printed as percentages, as Java does, and
+/// numbers are rounded like Java's `%.Nf` (see `java_fixed`).
+pub fn format_java_result(index: usize, total: usize, r: &QueryResult) -> String {
+    format!(
+        "{} ; {} ; {} ; {} ; {} ; {} ; {} ; {} ; {} ; {} ; {} ; {} ; {}",
+        index,
+        total,
+        r.query_path,
+        java_fixed(r.query_start, 3),
+        java_fixed(r.query_stop, 3),
+        r.ref_path.as_deref().unwrap_or("null"),
+        r.ref_identifier.as_deref().unwrap_or("null"),
+        java_fixed(r.ref_start, 3),
+        java_fixed(r.ref_stop, 3),
+        java_fixed(r.score as f64, 0),
+        java_fixed(r.time_factor * 100.0, 3),
+        java_fixed(r.frequency_factor * 100.0, 3),
+        java_fixed(r.percent_seconds_with_match * 100.0, 2)
+    )
+}
+
+/// `value` with `decimals` fraction digits, as Java's `%.Nf` prints it
+///
+/// Java rounds the shortest decimal representation of the double half-up,
+/// where Rust's `{:.N}` rounds its exact binary value half-to-even: 0.0625
+/// is `0.063` in Java but `0.062` in Rust.
+fn java_fixed(value: f64, decimals: usize) -> String {
+    if value.is_nan() {
+        return "NaN".to_string();
+    }
+    if value.is_infinite() {
+        return if value > 0.0 { "Infinity" } else { "-Infinity" }.to_string();
+    }
+
+    // Shortest round-trip digits and the number of them before the point
+    let scientific = format!("{:e}", value.abs());
+    let (mantissa, exponent) = scientific.split_once('e').unwrap();
+    let mut digits: Vec<u8> = mantissa.bytes().filter(u8::is_ascii_digit).map(|b| b - b'0').collect();
+    let mut point = exponent.parse::<i64>().unwrap() + 1;
+    if point < 0 {
+        digits.splice(0..0, std::iter::repeat_n(0, point.unsigned_abs() as usize));
+        point = 0;
+    }
+    let point = point as usize;
+    digits.resize(digits.len().max(point + decimals + 1), 0);
+
+    // Round half-up at the last kept digit
+    let round_up = digits[point + decimals] >= 5;
+    digits.truncate(point + decimals);
+    let mut point = point;
+    if round_up {
+        let mut i = digits.len();
+        loop {
+            if i == 0 {
+                digits.insert(0, 1);
+                point += 1;
+                break;
+            }
+            i -= 1;
+            if digits[i] == 9 {
+                digits[i] = 0;
+            } else {
+                digits[i] += 1;
+                break;
+            }
+        }
+    }
+
+    let to_str = |digits: &[u8]| -> String { digits.iter().map(|d| char::from(b'0' + d)).collect() };
+    let integer = if point == 0 { "0".to_string() } else { to_str(&digits[..point]) };
+    let sign = if value.is_sign_negative() { "-" } else { "" };
+    if decimals == 0 {
+        format!("{}{}", sign, integer)
+    } else {
+        format!("{}{}.{}", sign, integer, to_str(&digits[point..]))
+    }
+}
+
+/// A detection with every time explicitly labeled by its time base
+///
+/// All times are in seconds. The query file timeline is the single source of
+/// truth: segment-relative times and the placement of the reference in the
+/// query file are derived from it.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TimelineResult {
+    /// Path of the query file
+    pub query_path: String,
+    /// Internal identifier of the matched reference
+    pub ref_identifier: Option<String>,
+    /// Match score (number of matching fingerprints)
+    pub score: i32,
+
+    /// Start of the match, from the start of the query file
+    pub query_file_start_s: f64,
+    /// End of the match, from the start of the query file
+    pub query_file_end_s: f64,
+
+    /// Whether the detection comes from one segment of a segmented query
+    pub from_segmented: bool,
+    /// Index of the query segment that reported the detection
+    pub segment_index: Option<usize>,
+    /// Start of that segment, from the start of the query file
+    pub segment_start_s: Option<f64>,
+    /// Start of the match, from the start of the segment
+    pub segment_relative_start_s: Option<f64>,
+    /// End of the match, from the start of the segment
+    pub segment_relative_end_s: Option<f64>,
+
+    /// Start of the matched part, from the start of the reference
+    pub reference_start_s: f64,
+    /// End of the matched part, from the start of the reference
+    pub reference_end_s: f64,
+
+    /// Query file time at which the reference begins (may be negative if it
+    /// started before the query)
+    pub reference_in_query_start_s: f64,
+    /// Query file time at which the reference ends, if its duration is known
+    /// or estimated
+    pub reference_in_query_end_s: Option<f64>,
+}
+
+impl From<&QueryResult> for TimelineResult {
+    fn from(r: &QueryResult) -> Self {
+        let reference_in_query_start_s = r.query_start - r.ref_start;
+        let ref_duration_s = r.absolute_start.zip(r.absolute_end).map(|(start, end)| end - start);
+
+        Self {
+            query_path: r.query_path.clone(),
+            ref_identifier: r.ref_identifier.clone(),
+            score: r.score,
+            query_file_start_s: r.query_start,
+            query_file_end_s: r.query_stop,
+            from_segmented: r.from_segmented,
+            segment_index: r.segment_index,
+            segment_start_s: r.segment_start,
+            segment_relative_start_s: r.segment_start.map(|offset| r.query_start - offset),
+            segment_relative_end_s: r.segment_start.map(|offset| r.query_stop - offset),
+            reference_start_s: r.ref_start,
+            reference_end_s: r.ref_stop,
+            reference_in_query_start_s,
+            reference_in_query_end_s: ref_duration_s.map(|duration| reference_in_query_start_s + duration),
+        }
+    }
+}
+
+/// Serialize a result in the requested JSON layout
+fn to_json_value(result: &QueryResult, format: OutputFormat) -> serde_json::Result<serde_json::Value> {
+    match format {
+        OutputFormat::Raw | OutputFormat::Java => serde_json::to_value(result),
+        OutputFormat::Timeline => serde_json::to_value(TimelineResult::from(result)),
+    }
 }
 
 /// Print query result as JSON
@@ -18,42 +249,82 @@ pub fn print_json_result(result: &QueryResult) {
     }
 }
 
-/// Print multiple results as JSON array with detection count
-pub fn print_json_results(results: &[QueryResult]) {
-    // Minimum duration threshold in seconds
-    const MIN_DURATION_SECONDS: f64 = 2.0;
+/// Check whether a result should be reported
+///
+/// Results with no reference identifier or a duration below
+/// `MIN_DURATION_SECONDS` are filtered out.
+fn is_reportable(r: &QueryResult) -> bool {
+    // Filter out results with no reference identifier
+    if r.ref_identifier.is_none() {
+        log::debug!("Filtered match: no reference identifier");
+        return false;
+    }
+    
+    // Filter out detections with duration < 2 seconds
+    let duration = r.query_stop - r.query_start;
+    if duration < MIN_DURATION_SECONDS {
+        log::debug!(
+            "Filtered match: duration {:.2}s < {:.2}s (ref: {:?})",
+            duration,
+            MIN_DURATION_SECONDS,
+            r.ref_identifier
+        );
+        return false;
+    }
+    
+    true
+}
+
+/// Write results as JSON lines: one compact object per line
+///
+/// With `OutputFormat::Java` each line is a Java Panako result line instead,
+/// numbered with the query's `position`.
+/// Applies the same filtering as `print_json_results` but does not buffer or
+/// sort, so it can be called repeatedly as results are produced. The writer
+/// is flushed so downstream consumers see each batch immediately. Returns the
+/// number of lines written.
+pub fn write_jsonl_results<W: Write>(
+    writer: &mut W,
+    results: &[QueryResult],
+    format: OutputFormat,
+    position: QueryPosition,
+) -> anyhow::Result<usize> {
+    let mut written = 0;
+    for result in results.iter().filter(|r| is_reportable(r)) {
+        if format == OutputFormat::Java {
+            writer.write_all(format_java_result(position.index, position.total, result).as_bytes())?;
+        } else {
+            serde_json::to_writer(&mut *writer, &to_json_value(result, format)?)?;
+        }
+        writer.write_all(b"\n")?;
+        written += 1;
+    }
+    writer.flush()?;
     
+    Ok(written)
+}
+
+/// Write results as one pretty JSON object with detection count, in `sort_by` order
+///
+/// `query_path` is the file that was queried; it is reported even when no
+/// detection remains. With `OutputFormat::Java` the Java Panako header and
+/// result lines, numbered with the query's `position`, are written instead.
+pub fn write_json_results<W: Write>(
+    writer: &mut W,
+    query_path: &str,
+    results: &[QueryResult],
+    format: OutputFormat,
+    sort_by: SortBy,
+    position: QueryPosition,
+) -> anyhow::Result<()> {
     // Filter out results with no reference and duration < 2 seconds
     let mut valid_results: Vec<_> = results
         .iter()
-        .filter(|r| {
-            // Filter out results with no reference identifier
-            if r.ref_identifier.is_none() {
-                log::debug!("Filtered match: no reference identifier");
-                return false;
-            }
-            
-            // Filter out detections with duration < 2 seconds
-            let duration = r.query_stop - r.query_start;
-            if duration < MIN_DURATION_SECONDS {
-                log::debug!(
-                    "Filtered match: duration {:.2}s < {:.2}s (ref: {:?})",
-                    duration,
-                    MIN_DURATION_SECONDS,
-                    r.ref_identifier
-                );
-                return false;
-            }
-            
-            true
-        })
+        .filter(|r| is_reportable(r))
         .cloned()
         .collect();
     
-    // Sort by query_start time (chronological order)
-    valid_results.sort_by(|a, b| {
-        a.query_start.partial_cmp(&b.query_start).unwrap_or(std::cmp::Ordering::Equal)
-    });
+    sort_results(&mut valid_results, sort_by);
     
     if valid_results.len() < results.len() {
         log::info!(
@@ -63,20 +334,249 @@ pub fn print_json_results(results: &[QueryResult]) {
         );
     }
     
-    // Extract query path from first result, or use empty string
-    let query_path = valid_results
-        .first()
-        .map(|r| r.query_path.clone())
-        .unwrap_or_else(|| results.first().map(|r| r.query_path.clone()).unwrap_or_default());
+    if format == OutputFormat::Java {
+        writeln!(writer, "{}", JAVA_RESULT_HEADER)?;
+        for result in &valid_results {
+            writeln!(writer, "{}", format_java_result(position.index, position.total, result))?;
+        }
+        return Ok(());
+    }
+    
+    let values = valid_results
+        .iter()
+        .map(|r| to_json_value(r, format))
+        .collect::<serde_json::Result<Vec<_>>>()?;
     
     let output = MatchOutput {
-        query_path,
-        detections: valid_results.len(),
-        results: valid_results,
+        query_path: query_path.to_string(),
+        detections: values.len(),
+        results: values,
     };
     
-    match serde_json::to_string_pretty(&output) {
-        Ok(json) => println!("{}", json),
-        Err(e) => eprintln!("Error serializing results: {}", e),
+    serde_json::to_writer_pretty(&mut *writer, &output)?;
+    writer.write_all(b"\n")?;
+    writer.flush()?;
+    Ok(())
+}
+
+/// Print results of the only query of a run, `query_path`, to stdout (see `write_json_results`)
+pub fn print_json_results(query_path: &str, results: &[QueryResult], format: OutputFormat, sort_by: SortBy) {
+    let mut stdout = std::io::stdout().lock();
+    if let Err(e) = write_json_results(&mut stdout, query_path, results, format, sort_by, QueryPosition::SINGLE) {
+        eprintln!("Error serializing results: {}", e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn detection(ref_id: Option<&str>, query_start: f64, query_stop: f64) -> QueryResult {
+        let mut result = QueryResult::empty("long_capture.ts".to_string(), query_start, query_stop);
+        result.ref_path = ref_id.map(str::to_string);
+        result.ref_identifier = ref_id.map(str::to_string);
+        result.ref_start = 0.25;
+        result.ref_stop = query_stop - query_start + 0.25;
+        result.score = 42;
+        result.time_factor = 1.0;
+        result.frequency_factor = 0.998;
+        result.percent_seconds_with_match = 0.8;
+        result.ref_duration_ms = Some(30000);
+        result.segment_index = Some(3);
+        result
+    }
+
+    #[test]
+    fn test_jsonl_one_object_per_line_round_trip() {
+        let results = vec![
+            detection(Some("spot_a"), 10.0, 25.5),
+            detection(Some("spot_b"), 40.125, 70.0),
+            detection(None, 80.0, 90.0),
+            detection(Some("spot_c"), 95.0, 96.0),
+        ];
+
+        let mut buffer = Vec::new();
+        let written = write_jsonl_results(&mut buffer, &results, OutputFormat::Raw, QueryPosition::SINGLE).unwrap();
+        let output = String::from_utf8(buffer).unwrap();
+
+        assert_eq!(written, 2);
+        let parsed: Vec<QueryResult> = output
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(parsed, results[..2].to_vec());
+        assert!(output.ends_with('\n'));
+    }
+
+    #[test]
+    fn test_empty_results_report_query_path() {
+        let json = |results: &[QueryResult]| -> serde_json::Value {
+            let mut buffer = Vec::new();
+            write_json_results(&mut buffer, "queries/silence.wav", results, OutputFormat::Raw, SortBy::default(), QueryPosition::SINGLE).unwrap();
+            serde_json::from_slice(&buffer).unwrap()
+        };
+
+        // No match at all
+        let output = json(&[]);
+        assert_eq!(output["query_path"], "queries/silence.wav");
+        assert_eq!(output["detections"], 0);
+        assert_eq!(output["results"], serde_json::json!([]));
+
+        // Only filtered-out matches, whose own query path is not reported
+        let output = json(&[detection(None, 80.0, 90.0), detection(Some("spot_c"), 95.0, 96.0)]);
+        assert_eq!(output["query_path"], "queries/silence.wav");
+        assert_eq!(output["detections"], 0);
+    }
+
+    #[test]
+    fn test_sort_modes() {
+        let result = |ref_id: &str, query_start: f64, score: i32, absolute_start: Option<f64>| {
+            let mut result = detection(Some(ref_id), query_start, query_start + 5.0);
+            result.score = score;
+            result.absolute_start = absolute_start;
+            result
+        };
+        let results = vec![
+            result("spot_b", 30.0, 90, Some(28.0)),
+            result("spot_a", 10.0, 80, Some(40.0)),
+            result("spot_c", 20.0, 50, None),
+            result("spot_a", 20.0, 50, Some(15.0)),
+            result("spot_b", 5.0, 80, Some(5.0)),
+        ];
+        let order = |sort_by: SortBy| -> Vec<(String, f64)> {
+            let mut sorted = results.clone();
+            sort_results(&mut sorted, sort_by);
+            sorted.into_iter().map(|r| (r.ref_identifier.unwrap(), r.query_start)).collect()
+        };
+        let expected = |pairs: &[(&str, f64)]| -> Vec<(String, f64)> {
+            pairs.iter().map(|&(id, t)| (id.to_string(), t)).collect()
+        };
+
+        assert_eq!(SortBy::default(), SortBy::QueryTime);
+        assert_eq!(
+            order(SortBy::QueryTime),
+            expected(&[("spot_b", 5.0), ("spot_a", 10.0), ("spot_a", 20.0), ("spot_c", 20.0), ("spot_b", 30.0)])
+        );
+        assert_eq!(
+            order(SortBy::Score),
+            expected(&[("spot_b", 30.0), ("spot_b", 5.0), ("spot_a", 10.0), ("spot_a", 20.0), ("spot_c", 20.0)])
+        );
+        assert_eq!(
+            order(SortBy::AbsoluteTime),
+            expected(&[("spot_b", 5.0), ("spot_a", 20.0), ("spot_c", 20.0), ("spot_b", 30.0), ("spot_a", 10.0)])
+        );
+        assert_eq!(
+            order(SortBy::Reference),
+            expected(&[("spot_a", 10.0), ("spot_a", 20.0), ("spot_b", 5.0), ("spot_b", 30.0), ("spot_c", 20.0)])
+        );
+
+        // The input order does not affect the result
+        for sort_by in [SortBy::Score, SortBy::QueryTime, SortBy::AbsoluteTime, SortBy::Reference] {
+            let mut reversed = results.clone();
+            reversed.reverse();
+            sort_results(&mut reversed, sort_by);
+            let reversed: Vec<(String, f64)> =
+                reversed.into_iter().map(|r| (r.ref_identifier.unwrap(), r.query_start)).collect();
+            assert_eq!(reversed, order(sort_by));
+        }
+    }
+
+    #[test]
+    fn test_java_result_line() {
+        let java_result = |query_path: &str, query: (f64, f64), reference: &str, ref_span: (f64, f64), score: i32, factors: (f64, f64, f64)| {
+            let mut result = QueryResult::empty(query_path.to_string(), query.0, query.1);
+            result.ref_path = Some(reference.to_string());
+            result.ref_identifier = Some(reference.to_string());
+            (result.ref_start, result.ref_stop) = ref_span;
+            result.score = score;
+            (result.time_factor, result.frequency_factor, result.percent_seconds_with_match) = factors;
+            result
+        };
+        let result = java_result("queries/ad_break.mp3", (12.4, 42.384), "spot_1234", (0.048, 29.952), 187, (1.0, 0.9987, 0.8667));
+        let ties = java_result("queries/ad_break.mp3", (12.0625, 42.3845), "spot_1234", (0.0625, 2.675), 186, (1.000625, 0.998705, 0.86675));
+        let carries = java_result("q.wav", (0.0004, 999.9996), "r", (-0.0625, 9.9995), 7, (0.5, 2.0, 0.0));
+
+        // Printed by Java (17, Locale.ROOT) with Java Panako's result format
+        // "%d ; %d ; %s ; %.3f ; %.3f ; %s ; %s ; %.3f ; %.3f ; %.0f ; %.3f ; %.3f ; %.2f"
+        assert_eq!(
+            format_java_result(1, 1, &result),
+            "1 ; 1 ; queries/ad_break.mp3 ; 12.400 ; 42.384 ; spot_1234 ; spot_1234 ; 0.048 ; 29.952 ; 187 ; 100.000 ; 99.870 ; 86.67"
+        );
+        assert_eq!(
+            format_java_result(2, 3, &ties),
+            "2 ; 3 ; queries/ad_break.mp3 ; 12.063 ; 42.385 ; spot_1234 ; spot_1234 ; 0.063 ; 2.675 ; 186 ; 100.063 ; 99.870 ; 86.68"
+        );
+        assert_eq!(
+            format_java_result(3, 3, &carries),
+            "3 ; 3 ; q.wav ; 0.000 ; 1000.000 ; r ; r ; -0.063 ; 10.000 ; 7 ; 50.000 ; 200.000 ; 0.00"
+        );
+        assert_eq!(JAVA_RESULT_HEADER.split(" ; ").count(), 13);
+
+        // Lines are numbered with the query's position in the run
+        let mut buffer = Vec::new();
+        let position = QueryPosition { index: 2, total: 3 };
+        write_jsonl_results(&mut buffer, std::slice::from_ref(&result), OutputFormat::Java, position).unwrap();
+        assert!(String::from_utf8(buffer).unwrap().starts_with("2 ; 3 ; queries/ad_break.mp3 ; "));
+        let mut buffer = Vec::new();
+        write_json_results(&mut buffer, "queries/ad_break.mp3", &[result], OutputFormat::Java, SortBy::default(), position)
+            .unwrap();
+        let output = String::from_utf8(buffer).unwrap();
+        assert_eq!(output.lines().nth(1).map(|line| &line[..8]), Some("2 ; 3 ; "));
+    }
+
+    #[test]
+    fn test_timeline_times_consistent_with_segment_offset() {
+        use crate::fingerprinting::offset_segment_fingerprints;
+        use panako_core::{config::PanakoConfig, matching::Matcher, Fingerprint};
+
+        let config = PanakoConfig::default();
+        let fingerprint = |hash: u64, t: i32| Fingerprint {
+            hash,
+            t1: t,
+            f1: 50,
+            m1: 1.0,
+            t2: t + 10,
+            f2: 60,
+            m2: 1.0,
+            t3: t + 20,
+            f3: 70,
+            m3: 1.0,
+        };
+        let tuples = |fps: &[Fingerprint]| -> Vec<(u64, i32, i16, f32)> {
+            fps.iter().map(|fp| (fp.hash, fp.t1, fp.f1, fp.m1)).collect()
+        };
+
+        // 10 s reference; the segment starts 20 s into the query file and
+        // contains the reference 1 s after its own start
+        let reference: Vec<Fingerprint> = (0..40).map(|i| fingerprint(i, i as i32 * 30)).collect();
+        let segment: Vec<Fingerprint> = reference.iter().map(|fp| fingerprint(fp.hash, fp.t1 + 125)).collect();
+        let segment_start = 20.0;
+        let query = offset_segment_fingerprints(&segment, segment_start, &config);
+
+        let mut matcher = Matcher::new();
+        matcher.add_fingerprints("spot".to_string(), &tuples(&reference));
+        matcher.add_duration("spot".to_string(), 10000);
+        let mut result = matcher.query("capture.ts", &tuples(&query), &config).unwrap().remove(0);
+        result.segment_index = Some(4);
+        result.segment_start = Some(segment_start);
+
+        let timeline = TimelineResult::from(&result);
+        let eps = 1e-6;
+        assert_eq!(timeline.segment_start_s, Some(segment_start));
+        assert!((timeline.segment_relative_start_s.unwrap() - 1.0).abs() < eps);
+        assert!((timeline.query_file_start_s - 21.0).abs() < eps);
+        assert!(
+            (timeline.segment_start_s.unwrap() + timeline.segment_relative_start_s.unwrap() - timeline.query_file_start_s).abs() < eps
+        );
+        assert!(
+            (timeline.segment_start_s.unwrap() + timeline.segment_relative_end_s.unwrap() - timeline.query_file_end_s).abs() < eps
+        );
+        assert!((timeline.reference_in_query_start_s - 21.0).abs() < eps);
+        assert!((timeline.reference_in_query_end_s.unwrap() - 31.0).abs() < eps);
+        assert_eq!(Some(timeline.reference_in_query_start_s), result.absolute_start);
+
+        let line = serde_json::to_value(&timeline).unwrap();
+        assert_eq!(line["segment_index"], 4);
+        assert!(line.get("query_start").is_none());
     }
 }
@@ -0,0 +1,170 @@
+//! End-to-end self-test on synthetic audio
+//!
+//! Synthesizes a deterministic multi-tone signal, fingerprints it, indexes it
+//! and queries an excerpt of it, checking that the whole pipeline finds a
+//! strong match at the right offset. Needs no audio files.
+
+use anyhow::Result;
+use panako_core::audio::AudioData;
+use panako_core::{fingerprint_audio_timed, Matcher, PanakoConfig};
+use serde::Serialize;
+
+/// Length of the synthetic reference (seconds)
+const REFERENCE_S: f64 = 12.0;
+/// Start and length of the queried excerpt (seconds)
+const EXCERPT_START_S: f64 = 4.0;
+const EXCERPT_S: f64 = 5.0;
+/// Tolerance on the recovered excerpt offset (seconds)
+const OFFSET_TOLERANCE_S: f64 = 0.1;
+/// Identifier of the synthetic reference in the index
+const REFERENCE_ID: &str = "selftest_reference";
+
+/// A single self-test check
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SelfTestCheck {
+    pub name: String,
+    pub passed: bool,
+    pub detail: String,
+}
+
+/// Outcome of `run_selftest`
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SelfTestReport {
+    pub passed: bool,
+    pub reference_fingerprints: usize,
+    pub query_fingerprints: usize,
+    /// Score of the best match, if any
+    pub score: Option<i32>,
+    pub checks: Vec<SelfTestCheck>,
+}
+
+impl SelfTestReport {
+    fn check(&mut self, name: &str, passed: bool, detail: String) {
+        self.passed &= passed;
+        self.checks.push(SelfTestCheck {
+            name: name.to_string(),
+            passed,
+            detail,
+        });
+    }
+}
+
+/// Deterministic test signal: a three-voice melody over low-level noise
+///
+/// Each voice changes pitch every 250 ms, so the spectrogram has plenty of
+/// distinct peaks to pair into fingerprints.
+pub fn synthesize_signal(sample_rate: u32, duration_s: f64) -> Vec<f32> {
+    const NOTES_HZ: [f32; 12] = [
+        220.0, 277.2, 329.6, 392.0, 466.2, 523.3, 622.3, 740.0, 880.0, 1046.5, 1318.5, 1760.0,
+    ];
+    let num_samples = (duration_s * sample_rate as f64) as usize;
+    let mut seed: u32 = 0x5EED;
+    (0..num_samples)
+        .map(|i| {
+            let t = i as f32 / sample_rate as f32;
+            let step = (t * 4.0) as usize;
+            let tones: f32 = (0..3)
+                .map(|voice| {
+                    let note = NOTES_HZ[(step * (voice * 2 + 5) + voice * 3) % NOTES_HZ.len()];
+                    let freq = note * (voice + 1) as f32;
+                    (2.0 * std::f32::consts::PI * freq * t).sin()
+                })
+                .sum();
+            seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+            let noise = ((seed >> 16) as f32 / 32768.0 - 1.0) * 0.02;
+            tones * 0.25 + noise
+        })
+        .collect()
+}
+
+fn fingerprint_samples(samples: Vec<f32>, config: &PanakoConfig) -> Result<Vec<(u64, i32, i16, f32)>> {
+    let duration_ms = (samples.len() as f64 / config.sample_rate as f64 * 1000.0) as u32;
+    let audio_data = AudioData {
+        samples,
+        sample_rate: config.sample_rate,
+        channels: 1,
+        duration_ms,
+    };
+    let (fingerprints, _) = fingerprint_audio_timed(&audio_data, config)?;
+    Ok(fingerprints.iter().map(|fp| (fp.hash, fp.t1, fp.f1, fp.m1)).collect())
+}
+
+/// Fingerprint, index and query synthetic audio with `config`
+///
+/// Errors are only returned for failures to run the pipeline at all; failed
+/// checks are reported in the returned report.
+pub fn run_selftest(config: &PanakoConfig) -> Result<SelfTestReport> {
+    config.validate()?;
+
+    let signal = synthesize_signal(config.sample_rate, REFERENCE_S);
+    let excerpt_start = (EXCERPT_START_S * config.sample_rate as f64) as usize;
+    let excerpt_len = (EXCERPT_S * config.sample_rate as f64) as usize;
+    let excerpt = signal[excerpt_start..excerpt_start + excerpt_len].to_vec();
+
+    let reference = fingerprint_samples(signal, config)?;
+    let query = fingerprint_samples(excerpt, config)?;
+
+    let mut report = SelfTestReport {
+        passed: true,
+        reference_fingerprints: reference.len(),
+        query_fingerprints: query.len(),
+        score: None,
+        checks: Vec::new(),
+    };
+    report.check(
+        "fingerprints",
+        !reference.is_empty() && !query.is_empty(),
+        format!("{} reference, {} query fingerprints", reference.len(), query.len()),
+    );
+
+    let mut matcher = Matcher::new();
+    matcher.set_hash_width(config.hash_width);
+    matcher.add_fingerprints(REFERENCE_ID.to_string(), &reference);
+    matcher.add_duration(REFERENCE_ID.to_string(), (REFERENCE_S * 1000.0) as u32);
+    let results = matcher.query("selftest_query", &query, config)?;
+
+    let Some(best) = results.first() else {
+        report.check("match", false, "no match found".to_string());
+        return Ok(report);
+    };
+    report.score = Some(best.score);
+    report.check(
+        "match",
+        best.ref_identifier.as_deref() == Some(REFERENCE_ID),
+        format!("best match {:?} with score {}", best.ref_identifier, best.score),
+    );
+
+    // A strong match aligns a good share of the query fingerprints
+    let min_score = (query.len() / 10).max(config.min_hits_filtered) as i32;
+    report.check(
+        "score",
+        best.score >= min_score,
+        format!("score {} (need {})", best.score, min_score),
+    );
+
+    let offset = best.ref_start - best.query_start;
+    report.check(
+        "offset",
+        (offset - EXCERPT_START_S).abs() <= OFFSET_TOLERANCE_S,
+        format!("excerpt found at {:.3} s (expected {:.3} s)", offset, EXCERPT_START_S),
+    );
+
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_selftest_passes_with_default_config() {
+        let report = run_selftest(&PanakoConfig::default()).unwrap();
+        assert!(report.passed, "{:#?}", report);
+        assert_eq!(report.checks.len(), 4);
+    }
+
+    #[test]
+    fn test_synthesized_signal_is_deterministic() {
+        assert_eq!(synthesize_signal(16000, 1.0), synthesize_signal(16000, 1.0));
+    }
+}
@@ -0,0 +1,175 @@
+//! Same-recording verdict for two files, for audio QC
+
+use anyhow::{Context, Result};
+use panako_core::audio::DecodeCache;
+use panako_core::pipeline::{decode_input, open_decode_cache};
+use panako_core::PanakoConfig;
+use panako_fp::{FpJsonFile, FpJsonFingerprint, FpJsonSegment};
+use serde::Serialize;
+use std::path::Path;
+
+use crate::database::is_fingerprint_file;
+use crate::fingerprinting::fingerprint_audio;
+
+/// Default minimum `FpJsonFile::similarity` for a match
+pub const DEFAULT_VERIFY_THRESHOLD: f64 = 0.25;
+
+/// Result of comparing two files
+#[derive(Debug, Clone, Serialize)]
+pub struct VerifyReport {
+    pub file_a: String,
+    pub file_b: String,
+    /// `FpJsonFile::similarity` of the two fingerprint sets
+    pub similarity: f64,
+    pub threshold: f64,
+    /// Similarity reached the threshold
+    pub is_match: bool,
+    pub fingerprints_a: usize,
+    pub fingerprints_b: usize,
+}
+
+impl VerifyReport {
+    /// Process exit code for the verdict: 0 = match, 1 = no match
+    pub fn exit_code(&self) -> i32 {
+        if self.is_match {
+            0
+        } else {
+            1
+        }
+    }
+}
+
+/// Load a fingerprint file, or decode and fingerprint an audio/video file
+///
+/// Fingerprint files made with other analysis parameters than `config` are
+/// rejected, since their hashes cannot be compared.
+pub fn load_or_fingerprint(path: &Path, config: &PanakoConfig, cache: Option<&DecodeCache>) -> Result<FpJsonFile> {
+    if is_fingerprint_file(path) {
+        let file = FpJsonFile::load_auto(path)?;
+        if let Some(params) = &file.metadata.fingerprint_params {
+            let differences = params.differences(&config.fingerprint_params());
+            if !differences.is_empty() {
+                anyhow::bail!(
+                    "{} was fingerprinted with different parameters: {}",
+                    path.display(),
+                    differences.join(", ")
+                );
+            }
+        }
+        return Ok(file);
+    }
+
+    let path_str = path.to_str().context("Path is not valid UTF-8")?;
+    let audio = decode_input(path_str, config, cache)?;
+    let fingerprints: Vec<FpJsonFingerprint> = fingerprint_audio(&audio, config)?
+        .iter()
+        .map(|fp| FpJsonFingerprint { hash: fp.hash, t1: fp.t1, f1: fp.f1, m1: fp.m1 })
+        .collect();
+    let stem = path.file_stem().and_then(|s| s.to_str()).unwrap_or("unknown").to_string();
+    let mut file = FpJsonFile::new(path_str.to_string(), stem, config.sample_rate, audio.duration_ms, 1);
+    file.add_segment(FpJsonSegment {
+        segment_id: 0,
+        start_time_s: 0.0,
+        end_time_s: audio.duration_ms as f64 / 1000.0,
+        num_fingerprints: fingerprints.len(),
+        fingerprints,
+    });
+    Ok(file)
+}
+
+/// Compare two files (audio or fingerprint files) and judge whether they
+/// are the same recording
+pub fn verify_files(path_a: &Path, path_b: &Path, config: &PanakoConfig, threshold: f64) -> Result<VerifyReport> {
+    let cache = open_decode_cache(config)?;
+    let file_a = load_or_fingerprint(path_a, config, cache.as_ref())?;
+    let file_b = load_or_fingerprint(path_b, config, cache.as_ref())?;
+    let similarity = file_a.similarity(&file_b);
+    let count = |file: &FpJsonFile| file.segments.iter().map(|s| s.fingerprints.len()).sum();
+
+    Ok(VerifyReport {
+        file_a: path_a.display().to_string(),
+        file_b: path_b.display().to_string(),
+        similarity,
+        threshold,
+        is_match: similarity >= threshold,
+        fingerprints_a: count(&file_a),
+        fingerprints_b: count(&file_b),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::selftest::synthesize_signal;
+    use panako_core::audio::AudioData;
+
+    fn save_fingerprints(name: &str, samples: Vec<f32>, config: &PanakoConfig) -> std::path::PathBuf {
+        let audio = AudioData {
+            duration_ms: (samples.len() as u64 * 1000 / config.sample_rate as u64) as u32,
+            samples,
+            sample_rate: config.sample_rate,
+            channels: 1,
+        };
+        let fingerprints: Vec<FpJsonFingerprint> = fingerprint_audio(&audio, config)
+            .unwrap()
+            .iter()
+            .map(|fp| FpJsonFingerprint { hash: fp.hash, t1: fp.t1, f1: fp.f1, m1: fp.m1 })
+            .collect();
+        let mut file = FpJsonFile::new(format!("/audio/{}.wav", name), name.to_string(), 16000, audio.duration_ms, 1);
+        file.metadata.fingerprint_params = Some(config.fingerprint_params());
+        file.add_segment(FpJsonSegment {
+            segment_id: 0,
+            start_time_s: 0.0,
+            end_time_s: audio.duration_ms as f64 / 1000.0,
+            num_fingerprints: fingerprints.len(),
+            fingerprints,
+        });
+        let path = std::env::temp_dir().join(format!("panako_verify_{}_{}.json", name, std::process::id()));
+        file.save(&path).unwrap();
+        path
+    }
+
+    #[test]
+    fn test_verify_verdicts() {
+        let config = PanakoConfig::default();
+        let signal = synthesize_signal(config.sample_rate, 10.0);
+        let mut reversed = signal.clone();
+        reversed.reverse();
+        let original = save_fingerprints("original", signal, &config);
+        let unrelated = save_fingerprints("unrelated", reversed, &config);
+
+        let same = verify_files(&original, &original, &config, DEFAULT_VERIFY_THRESHOLD).unwrap();
+        let different = verify_files(&original, &unrelated, &config, DEFAULT_VERIFY_THRESHOLD).unwrap();
+        let strict = PanakoConfig { fp_max_time_dist: 40, ..PanakoConfig::default() };
+        let mismatched_params = verify_files(&original, &original, &strict, DEFAULT_VERIFY_THRESHOLD);
+        std::fs::remove_file(&original).ok();
+        std::fs::remove_file(&unrelated).ok();
+
+        assert!(same.fingerprints_a > 0);
+        assert_eq!(same.similarity, 1.0);
+        assert_eq!(same.exit_code(), 0);
+        assert!(different.similarity < DEFAULT_VERIFY_THRESHOLD, "similarity {}", different.similarity);
+        assert_ne!(different.exit_code(), 0);
+        assert!(mismatched_params.is_err());
+    }
+
+    #[test]
+    fn test_short_excerpt_is_not_a_match() {
+        let config = PanakoConfig::default();
+        let signal = synthesize_signal(config.sample_rate, 10.0);
+        let excerpt_samples = signal[..config.sample_rate as usize].to_vec();
+        let original = save_fingerprints("excerpt_original", signal, &config);
+        let excerpt = save_fingerprints("excerpt", excerpt_samples, &config);
+
+        let forward = verify_files(&excerpt, &original, &config, DEFAULT_VERIFY_THRESHOLD).unwrap();
+        let backward = verify_files(&original, &excerpt, &config, DEFAULT_VERIFY_THRESHOLD).unwrap();
+        std::fs::remove_file(&original).ok();
+        std::fs::remove_file(&excerpt).ok();
+
+        // One second of a ten second recording is not the same recording
+        assert!(forward.fingerprints_a > 0);
+        assert!(forward.similarity < DEFAULT_VERIFY_THRESHOLD, "similarity {}", forward.similarity);
+        assert_eq!(forward.similarity, backward.similarity);
+        assert_ne!(forward.exit_code(), 0);
+    }
+}
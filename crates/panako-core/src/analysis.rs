@@ -0,0 +1,105 @@
+//! Signal analysis that explains fingerprint coverage
+//!
+//! Silent or constant stretches of audio have no spectral peaks, so they
+//! yield no event points and leave gaps in the fingerprints of a file.
+
+use crate::audio::AudioData;
+
+/// Length of the analysis frame used to detect silence (seconds)
+const FRAME_S: f64 = 0.01;
+/// Shortest silent run reported as a region (seconds)
+pub const MIN_SILENCE_S: f64 = 0.25;
+
+/// Time ranges `(start_s, end_s)` of silent or constant audio
+///
+/// The mono downmix is cut into 10 ms frames; a frame counts as silent when
+/// the RMS of its deviation from the frame mean is below `threshold`
+/// (linear, 0..1), so a DC offset counts as silence too. Runs of silent
+/// frames of at least `MIN_SILENCE_S` are returned in time order, clipped to
+/// the end of the audio.
+pub fn silence_regions(audio: &AudioData, threshold: f32) -> Vec<(f64, f64)> {
+    let samples = audio.to_mono();
+    let sample_rate = audio.sample_rate as f64;
+    let frame_len = ((sample_rate * FRAME_S) as usize).max(1);
+    let min_frames = ((MIN_SILENCE_S / FRAME_S).round() as usize).max(1);
+
+    let silent: Vec<bool> = samples
+        .chunks(frame_len)
+        .map(|frame| {
+            let mean = frame.iter().sum::<f32>() / frame.len() as f32;
+            let variance = frame.iter().map(|s| (s - mean) * (s - mean)).sum::<f32>() / frame.len() as f32;
+            variance.sqrt() < threshold
+        })
+        .collect();
+
+    let to_seconds = |frame: usize| (frame * frame_len).min(samples.len()) as f64 / sample_rate;
+    let mut regions = Vec::new();
+    let mut run_start: Option<usize> = None;
+    for (idx, &is_silent) in silent.iter().chain(std::iter::once(&false)).enumerate() {
+        match (is_silent, run_start) {
+            (true, None) => run_start = Some(idx),
+            (false, Some(start)) => {
+                if idx - start >= min_frames {
+                    regions.push((to_seconds(start), to_seconds(idx)));
+                }
+                run_start = None;
+            }
+            _ => {}
+        }
+    }
+    regions
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn audio(samples: Vec<f32>, sample_rate: u32) -> AudioData {
+        let duration_ms = (samples.len() as u64 * 1000 / sample_rate as u64) as u32;
+        AudioData {
+            samples,
+            sample_rate,
+            channels: 1,
+            duration_ms,
+        }
+    }
+
+    fn tone(num_samples: usize) -> impl Iterator<Item = f32> {
+        (0..num_samples).map(|i| (i as f32 * 0.3).sin() * 0.5)
+    }
+
+    #[test]
+    fn test_silent_gap_is_reported_with_bounds() {
+        // 2 s tone, 1.5 s silence, 1 s tone
+        let samples: Vec<f32> = tone(32000)
+            .chain(std::iter::repeat_n(0.0, 24000))
+            .chain(tone(16000))
+            .collect();
+        let regions = silence_regions(&audio(samples, 16000), 0.001);
+        assert_eq!(regions.len(), 1);
+        let (start, end) = regions[0];
+        assert!((start - 2.0).abs() < 1e-9, "start {}", start);
+        assert!((end - 3.5).abs() < 1e-9, "end {}", end);
+    }
+
+    #[test]
+    fn test_constant_and_trailing_regions() {
+        // 1 s tone, 0.5 s DC offset, 0.5 s tone, 0.1 s silence (too short), 1 s tone,
+        // trailing 0.305 s silence
+        let samples: Vec<f32> = tone(16000)
+            .chain(std::iter::repeat_n(0.3, 8000))
+            .chain(tone(8000))
+            .chain(std::iter::repeat_n(0.0, 1600))
+            .chain(tone(16000))
+            .chain(std::iter::repeat_n(0.0, 4880))
+            .collect();
+        let regions = silence_regions(&audio(samples, 16000), 0.001);
+        assert_eq!(regions, vec![(1.0, 1.5), (3.1, 54480.0 / 16000.0)]);
+    }
+
+    #[test]
+    fn test_no_regions_in_continuous_audio() {
+        assert!(silence_regions(&audio(tone(48000).collect(), 16000), 0.001).is_empty());
+        assert!(silence_regions(&audio(Vec::new(), 16000), 0.001).is_empty());
+    }
+}
@@ -0,0 +1,118 @@
+//! Coarse frequency-band energy summary
+//!
+//! A cheap spectral overview of decoded audio, meant for routing decisions
+//! (silence, speech-like, music-like) before running the full pipeline.
+
+use super::AudioData;
+use crate::transform::create_hann_window;
+use rustfft::{num_complex::Complex, FftPlanner};
+
+/// Number of bands returned by `AudioData::band_energy`
+pub const NUM_ENERGY_BANDS: usize = 4;
+
+/// Upper edges (Hz) of all but the last band; the last band extends to Nyquist
+///
+/// Bands: < 250 Hz (bass), 250-1000 Hz, 1-4 kHz (speech formants), >= 4 kHz.
+pub const ENERGY_BAND_EDGES_HZ: [f32; NUM_ENERGY_BANDS - 1] = [250.0, 1000.0, 4000.0];
+
+/// FFT size of the analysis frames
+const FRAME_SIZE: usize = 1024;
+
+/// Maximum number of frames analysed, spread evenly over the audio
+const MAX_FRAMES: usize = 64;
+
+impl AudioData {
+    /// Fraction of the signal energy in each frequency band
+    ///
+    /// Analyses at most `MAX_FRAMES` non-overlapping 1024-sample frames spread
+    /// evenly over the (mono) audio. The result sums to 1.0, or is all zeros
+    /// for silent or empty audio.
+    pub fn band_energy(&self) -> [f32; NUM_ENERGY_BANDS] {
+        let mut energy = [0.0f64; NUM_ENERGY_BANDS];
+        
+        let mono = self.to_mono();
+        let available_frames = mono.len() / FRAME_SIZE;
+        if available_frames == 0 || self.sample_rate == 0 {
+            return [0.0; NUM_ENERGY_BANDS];
+        }
+        
+        let num_frames = available_frames.min(MAX_FRAMES);
+        let frame_step = available_frames / num_frames;
+        
+        let mut planner = FftPlanner::new();
+        let fft = planner.plan_fft_forward(FRAME_SIZE);
+        let window = create_hann_window(FRAME_SIZE);
+        
+        let bin_hz = self.sample_rate as f32 / FRAME_SIZE as f32;
+        let band_of_bin: Vec<usize> = (0..=FRAME_SIZE / 2)
+            .map(|bin| {
+                let freq = bin as f32 * bin_hz;
+                ENERGY_BAND_EDGES_HZ
+                    .iter()
+                    .position(|&edge| freq < edge)
+                    .unwrap_or(NUM_ENERGY_BANDS - 1)
+            })
+            .collect();
+        
+        for frame_idx in 0..num_frames {
+            let start = frame_idx * frame_step * FRAME_SIZE;
+            let mut frame: Vec<Complex<f32>> = mono[start..start + FRAME_SIZE]
+                .iter()
+                .zip(&window)
+                .map(|(&s, &w)| Complex::new(s * w, 0.0))
+                .collect();
+            
+            fft.process(&mut frame);
+            
+            for (bin, &band) in band_of_bin.iter().enumerate() {
+                energy[band] += frame[bin].norm_sqr() as f64;
+            }
+        }
+        
+        let total: f64 = energy.iter().sum();
+        if total <= f64::EPSILON {
+            return [0.0; NUM_ENERGY_BANDS];
+        }
+        
+        energy.map(|e| (e / total) as f32)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tone(freq: f32) -> AudioData {
+        AudioData {
+            samples: (0..16000)
+                .map(|i| (2.0 * std::f32::consts::PI * freq * i as f32 / 16000.0).sin() * 0.5)
+                .collect(),
+            sample_rate: 16000,
+            channels: 1,
+            duration_ms: 1000,
+        }
+    }
+
+    #[test]
+    fn test_band_energy_follows_tone_frequency() {
+        let low = tone(100.0).band_energy();
+        assert!(low[0] > 0.9, "low tone bands {:?}", low);
+
+        let high = tone(6000.0).band_energy();
+        assert!(high[NUM_ENERGY_BANDS - 1] > 0.9, "high tone bands {:?}", high);
+
+        let total: f32 = high.iter().sum();
+        assert!((total - 1.0).abs() < 1e-4);
+    }
+
+    #[test]
+    fn test_band_energy_silence() {
+        let silence = AudioData {
+            samples: vec![0.0; 16000],
+            sample_rate: 16000,
+            channels: 1,
+            duration_ms: 1000,
+        };
+        assert_eq!(silence.band_energy(), [0.0; NUM_ENERGY_BANDS]);
+    }
+}
@@ -0,0 +1,282 @@
+//! On-disk cache of decoded audio
+//!
+//! Decoding large video files dominates repeated runs over the same input
+//! (e.g. `fpgen` with different analysis parameters). `DecodeCache` stores
+//! the decoded, resampled samples in one file per input path and sample
+//! rate, together with the input's modification time and size; an entry
+//! whose input changed since is decoded again and overwritten. Entry layout
+//! (little-endian): magic `PNKPCM01`, mtime seconds u64, mtime nanoseconds
+//! u32, input size u64, sample rate u32, channels u16, padding u16,
+//! duration ms u32, sample count u64, then the samples as f32.
+
+use anyhow::{Context, Result};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::UNIX_EPOCH;
+
+use super::{decode_audio_channels_with_options, decode_audio_with_options, AudioData, FfmpegOptions};
+
+const MAGIC: &[u8; 8] = b"PNKPCM01";
+const HEADER_LEN: usize = 48;
+
+/// Modification time and size identifying one version of an input file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct FileStamp {
+    mtime_s: u64,
+    mtime_ns: u32,
+    size: u64,
+}
+
+impl FileStamp {
+    fn of(path: &Path) -> Result<Self> {
+        let metadata = std::fs::metadata(path)
+            .with_context(|| format!("Audio file not found: {}", path.display()))?;
+        let mtime = metadata.modified()?.duration_since(UNIX_EPOCH).unwrap_or_default();
+        Ok(Self {
+            mtime_s: mtime.as_secs(),
+            mtime_ns: mtime.subsec_nanos(),
+            size: metadata.len(),
+        })
+    }
+}
+
+/// Decodes audio through a cache directory of decoded samples
+pub struct DecodeCache {
+    dir: PathBuf,
+    hits: AtomicUsize,
+    misses: AtomicUsize,
+}
+
+impl DecodeCache {
+    /// Use (and create if needed) the cache directory `dir`
+    pub fn new(dir: impl Into<PathBuf>) -> Result<Self> {
+        let dir = dir.into();
+        std::fs::create_dir_all(&dir)
+            .with_context(|| format!("Failed to create decode cache directory {}", dir.display()))?;
+        Ok(Self {
+            dir,
+            hits: AtomicUsize::new(0),
+            misses: AtomicUsize::new(0),
+        })
+    }
+
+    /// Number of decodes served from the cache
+    pub fn hits(&self) -> usize {
+        self.hits.load(Ordering::Relaxed)
+    }
+
+    /// Number of decodes that had to decode the input
+    pub fn misses(&self) -> usize {
+        self.misses.load(Ordering::Relaxed)
+    }
+
+    /// `decode_audio`, served from the cache while the input is unchanged
+    ///
+    /// Unreadable or corrupt entries are decoded again; failing to write an
+    /// entry only logs a warning.
+    pub fn decode_audio(&self, path: &str, target_sample_rate: u32, ffmpeg: &FfmpegOptions) -> Result<AudioData> {
+        self.decode(path, target_sample_rate, false, || decode_audio_with_options(path, target_sample_rate, ffmpeg))
+    }
+
+    /// `decode_audio_channels`, served from the cache like `decode_audio`
+    ///
+    /// Entries keep every channel and are stored apart from the mono ones.
+    pub fn decode_audio_channels(&self, path: &str, target_sample_rate: u32, ffmpeg: &FfmpegOptions) -> Result<AudioData> {
+        self.decode(path, target_sample_rate, true, || {
+            decode_audio_channels_with_options(path, target_sample_rate, ffmpeg)
+        })
+    }
+
+    /// Serve `path` from its entry, or run `decode` and store the result
+    fn decode(
+        &self,
+        path: &str,
+        target_sample_rate: u32,
+        keep_channels: bool,
+        decode: impl FnOnce() -> Result<AudioData>,
+    ) -> Result<AudioData> {
+        let input = Path::new(path);
+        let stamp = FileStamp::of(input)?;
+        let entry = self.entry_path(input, target_sample_rate, keep_channels);
+
+        if let Some(audio) = read_entry(&entry, stamp) {
+            self.hits.fetch_add(1, Ordering::Relaxed);
+            log::debug!("Decode cache hit for {}", input.display());
+            return Ok(audio);
+        }
+
+        self.misses.fetch_add(1, Ordering::Relaxed);
+        let audio = decode()?;
+        if let Err(e) = write_entry(&entry, stamp, &audio) {
+            log::warn!("Failed to write decode cache entry {}: {:#}", entry.display(), e);
+        }
+        Ok(audio)
+    }
+
+    /// Entry file of an input path at a sample rate, mono or with every channel
+    fn entry_path(&self, input: &Path, sample_rate: u32, keep_channels: bool) -> PathBuf {
+        use sha2::{Digest, Sha256};
+
+        let absolute = std::fs::canonicalize(input).unwrap_or_else(|_| input.to_path_buf());
+        let mut hasher = Sha256::new();
+        hasher.update(absolute.to_string_lossy().as_bytes());
+        hasher.update(sample_rate.to_le_bytes());
+        if keep_channels {
+            hasher.update(b"channels");
+        }
+        let name: String = hasher.finalize()[..16].iter().map(|b| format!("{:02x}", b)).collect();
+        self.dir.join(format!("{}.pcm", name))
+    }
+}
+
+/// Read an entry, if it exists, is intact and was made from `stamp`
+fn read_entry(entry: &Path, stamp: FileStamp) -> Option<AudioData> {
+    let bytes = std::fs::read(entry).ok()?;
+    if bytes.len() < HEADER_LEN || &bytes[..8] != MAGIC {
+        return None;
+    }
+    let u64_at = |pos: usize| u64::from_le_bytes(bytes[pos..pos + 8].try_into().unwrap());
+    let u32_at = |pos: usize| u32::from_le_bytes(bytes[pos..pos + 4].try_into().unwrap());
+
+    let entry_stamp = FileStamp {
+        mtime_s: u64_at(8),
+        mtime_ns: u32_at(16),
+        size: u64_at(20),
+    };
+    if entry_stamp != stamp {
+        return None;
+    }
+    let sample_rate = u32_at(28);
+    let channels = u16::from_le_bytes(bytes[32..34].try_into().unwrap());
+    let duration_ms = u32_at(36);
+    let expected_len = usize::try_from(u64_at(40))
+        .ok()
+        .and_then(|n| n.checked_mul(4))
+        .and_then(|n| n.checked_add(HEADER_LEN))?;
+    if bytes.len() != expected_len {
+        return None;
+    }
+
+    let samples = bytes[HEADER_LEN..]
+        .chunks_exact(4)
+        .map(|b| f32::from_le_bytes(b.try_into().unwrap()))
+        .collect();
+    Some(AudioData {
+        samples,
+        sample_rate,
+        channels,
+        duration_ms,
+    })
+}
+
+/// Write an entry atomically (temporary file renamed into place)
+///
+/// The temporary file name is unique per process and call, so concurrent
+/// writers of the same entry never interleave their bytes.
+fn write_entry(entry: &Path, stamp: FileStamp, audio: &AudioData) -> Result<()> {
+    static NEXT_TMP: AtomicUsize = AtomicUsize::new(0);
+    let tmp_path = entry.with_extension(format!(
+        "pcm.{}.{}.tmp",
+        std::process::id(),
+        NEXT_TMP.fetch_add(1, Ordering::Relaxed)
+    ));
+    let result = write_entry_to(&tmp_path, stamp, audio).and_then(|()| std::fs::rename(&tmp_path, entry).map_err(Into::into));
+    if result.is_err() {
+        std::fs::remove_file(&tmp_path).ok();
+    }
+    result
+}
+
+fn write_entry_to(tmp_path: &Path, stamp: FileStamp, audio: &AudioData) -> Result<()> {
+    let mut writer = std::io::BufWriter::new(std::fs::File::create(tmp_path)?);
+    writer.write_all(MAGIC)?;
+    writer.write_all(&stamp.mtime_s.to_le_bytes())?;
+    writer.write_all(&stamp.mtime_ns.to_le_bytes())?;
+    writer.write_all(&stamp.size.to_le_bytes())?;
+    writer.write_all(&audio.sample_rate.to_le_bytes())?;
+    writer.write_all(&audio.channels.to_le_bytes())?;
+    writer.write_all(&0u16.to_le_bytes())?;
+    writer.write_all(&audio.duration_ms.to_le_bytes())?;
+    writer.write_all(&(audio.samples.len() as u64).to_le_bytes())?;
+    for sample in &audio.samples {
+        writer.write_all(&sample.to_le_bytes())?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write_wav(path: &Path, samples: &[i16]) {
+        let spec = hound::WavSpec {
+            channels: 1,
+            sample_rate: 8000,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        };
+        let mut writer = hound::WavWriter::create(path, spec).unwrap();
+        for &s in samples {
+            writer.write_sample(s).unwrap();
+        }
+        writer.finalize().unwrap();
+    }
+
+    #[test]
+    fn test_unchanged_file_hits_cache() {
+        let dir = std::env::temp_dir().join(format!("panako_decode_cache_{}", std::process::id()));
+        let input = std::env::temp_dir().join(format!("panako_decode_cache_input_{}.wav", std::process::id()));
+        let samples: Vec<i16> = (0..8000).map(|i| ((i * 37) % 2000 - 1000) as i16).collect();
+        write_wav(&input, &samples);
+        let path = input.to_str().unwrap();
+        let ffmpeg = FfmpegOptions::default();
+
+        let cache = DecodeCache::new(&dir).unwrap();
+        let first = cache.decode_audio(path, 16000, &ffmpeg).unwrap();
+        let second = cache.decode_audio(path, 16000, &ffmpeg).unwrap();
+        assert_eq!((cache.hits(), cache.misses()), (1, 1));
+        assert_eq!(second.samples, first.samples);
+        assert_eq!(
+            (second.sample_rate, second.channels, second.duration_ms),
+            (first.sample_rate, first.channels, first.duration_ms)
+        );
+        assert_eq!(first.samples, crate::audio::decode_audio(path, 16000).unwrap().samples);
+
+        // Another sample rate is a separate entry
+        cache.decode_audio(path, 8000, &ffmpeg).unwrap();
+        assert_eq!(cache.misses(), 2);
+
+        // So is the same input decoded with every channel
+        let channels = cache.decode_audio_channels(path, 16000, &ffmpeg).unwrap();
+        cache.decode_audio_channels(path, 16000, &ffmpeg).unwrap();
+        assert_eq!((cache.hits(), cache.misses()), (2, 3));
+        assert_eq!(channels.samples, crate::audio::decode_audio_channels(path, 16000).unwrap().samples);
+
+        // A rewritten input (other size and mtime) is decoded again
+        write_wav(&input, &samples[..4000]);
+        let changed = cache.decode_audio(path, 16000, &ffmpeg).unwrap();
+        assert_eq!(cache.misses(), 4);
+        assert_eq!(changed.duration_ms, 500);
+
+        std::fs::remove_file(&input).ok();
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_overflowing_sample_count_is_a_miss() {
+        let entry = std::env::temp_dir().join(format!("panako_decode_cache_entry_{}.pcm", std::process::id()));
+        let stamp = FileStamp { mtime_s: 1, mtime_ns: 2, size: 3 };
+        let audio = AudioData { samples: vec![0.5; 4], sample_rate: 16000, channels: 1, duration_ms: 0 };
+        write_entry(&entry, stamp, &audio).unwrap();
+        assert_eq!(read_entry(&entry, stamp).unwrap().samples, audio.samples);
+
+        let mut bytes = std::fs::read(&entry).unwrap();
+        bytes[40..48].copy_from_slice(&(u64::MAX / 2).to_le_bytes());
+        std::fs::write(&entry, &bytes).unwrap();
+        assert!(read_entry(&entry, stamp).is_none());
+
+        std::fs::remove_file(&entry).ok();
+    }
+}
@@ -1,6 +1,6 @@
 //! Audio decoding for multiple formats
 
-use super::{resample_to_target, AudioFormat};
+use super::{resample_to_target, AudioFormat, FfmpegOptions};
 use anyhow::{Context, Result};
 use std::path::Path;
 
@@ -27,56 +27,79 @@ impl AudioData {
         }
         mono
     }
-}
-
-/// Decode audio file to target sample rate
-pub fn decode_audio(path: &str, target_sample_rate: u32) -> Result<AudioData> {
-    let path = Path::new(path);
     
-    if !path.exists() {
-        anyhow::bail!("Audio file not found: {}", path.display());
+    /// Samples of one channel (0-based), de-interleaved
+    pub fn channel(&self, channel: u16) -> Vec<f32> {
+        self.samples
+            .iter()
+            .skip(channel as usize)
+            .step_by(self.channels.max(1) as usize)
+            .copied()
+            .collect()
     }
     
-    let format = AudioFormat::from_path(path);
-    
-    // Handle MPEG-TS separately (Symphonia doesn't support TS)
-    if format == AudioFormat::MpegTs {
-        let mut audio_data = super::extract_audio_from_ts(path)?;
+    /// SHA-256 of the decoded samples (hex), identifying the audio content
+    /// independently of file name, path or container
+    pub fn content_hash(&self) -> String {
+        use sha2::{Digest, Sha256};
         
-        // Resample if needed
-        if audio_data.sample_rate != target_sample_rate {
-            let mono = audio_data.to_mono();
-            let resampled = resample_to_target(&mono, audio_data.sample_rate, target_sample_rate)?;
-            audio_data.samples = resampled;
-            audio_data.sample_rate = target_sample_rate;
-            audio_data.channels = 1;
-        } else if audio_data.channels > 1 {
-            audio_data.samples = audio_data.to_mono();
-            audio_data.channels = 1;
+        let mut hasher = Sha256::new();
+        hasher.update(self.sample_rate.to_le_bytes());
+        hasher.update(self.channels.to_le_bytes());
+        for sample in &self.samples {
+            hasher.update(sample.to_le_bytes());
         }
         
-        return Ok(audio_data);
+        hasher
+            .finalize()
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect()
     }
-    
-    // Handle video formats with Symphonia
-    let mut audio_data = if format.is_video_container() {
-        super::extract_audio_from_video(path)?
-    } else {
-        // Handle pure audio formats
-        match format {
-            AudioFormat::Wav => decode_wav(path)?,
-            AudioFormat::Mp3 => decode_mp3(path)?,
-            AudioFormat::Flac => decode_flac(path)?,
-            AudioFormat::Ogg => decode_ogg(path)?,
-            AudioFormat::Unknown => {
-                anyhow::bail!("Unsupported audio format: {}", path.display());
-            }
-            _ => {
-                // Fallback: try Symphonia for any other format
-                super::extract_audio_from_video(path)?
-            }
+}
+
+/// Duration in milliseconds of `num_samples` interleaved samples
+///
+/// Every decoder reports `AudioData::duration_ms` through this function so
+/// the same content decoded from any format gets the same duration. Only
+/// whole frames (`num_samples / channels`) count, and the result is rounded
+/// to the nearest millisecond, halves up, in exact integer arithmetic.
+/// Returns 0 when the rate or channel count is 0 (nothing was decoded).
+pub fn samples_to_duration_ms(num_samples: usize, sample_rate: u32, channels: u16) -> u32 {
+    if sample_rate == 0 || channels == 0 {
+        return 0;
+    }
+    let frames = (num_samples / channels as usize) as u64;
+    let rate = sample_rate as u64;
+    ((frames * 1000 + rate / 2) / rate).min(u32::MAX as u64) as u32
+}
+
+/// Reject audio longer than the configured maximum duration
+///
+/// Called right after decoding so batch jobs fail fast with a clear message
+/// instead of fingerprinting pathological inputs.
+pub fn check_max_duration(audio: &AudioData, max_duration_ms: Option<u32>) -> Result<()> {
+    if let Some(max_ms) = max_duration_ms {
+        if audio.duration_ms > max_ms {
+            anyhow::bail!(
+                "Audio duration {:.1}s exceeds maximum allowed duration of {:.1}s",
+                audio.duration_ms as f64 / 1000.0,
+                max_ms as f64 / 1000.0
+            );
         }
-    };
+    }
+    Ok(())
+}
+
+/// Decode audio file to target sample rate
+pub fn decode_audio(path: &str, target_sample_rate: u32) -> Result<AudioData> {
+    decode_audio_with_options(path, target_sample_rate, &FfmpegOptions::default())
+}
+
+/// Decode audio file to target sample rate, with limits for the FFmpeg
+/// subprocess used for MPEG-TS input
+pub fn decode_audio_with_options(path: &str, target_sample_rate: u32, ffmpeg: &FfmpegOptions) -> Result<AudioData> {
+    let mut audio_data = decode_native(Path::new(path), ffmpeg, true)?;
     
     // Resample if needed
     if audio_data.sample_rate != target_sample_rate {
@@ -94,6 +117,82 @@ pub fn decode_audio(path: &str, target_sample_rate: u32) -> Result<AudioData> {
     Ok(audio_data)
 }
 
+/// Decode audio file to target sample rate, keeping every channel
+///
+/// Samples stay interleaved; each channel is resampled on its own.
+pub fn decode_audio_channels(path: &str, target_sample_rate: u32) -> Result<AudioData> {
+    decode_audio_channels_with_options(path, target_sample_rate, &FfmpegOptions::default())
+}
+
+/// `decode_audio_channels` with limits for the FFmpeg subprocess used for
+/// MPEG-TS input
+///
+/// MPEG-TS input is rejected: FFmpeg extracts it as mono only.
+pub fn decode_audio_channels_with_options(
+    path: &str,
+    target_sample_rate: u32,
+    ffmpeg: &FfmpegOptions,
+) -> Result<AudioData> {
+    let mut audio_data = decode_native(Path::new(path), ffmpeg, false)?;
+    
+    if audio_data.sample_rate != target_sample_rate {
+        let channels: Vec<Vec<f32>> = (0..audio_data.channels)
+            .map(|channel| resample_to_target(&audio_data.channel(channel), audio_data.sample_rate, target_sample_rate))
+            .collect::<Result<_>>()?;
+        let num_frames = channels.iter().map(Vec::len).min().unwrap_or(0);
+        audio_data.samples = (0..num_frames)
+            .flat_map(|i| channels.iter().map(move |channel| channel[i]))
+            .collect();
+        audio_data.sample_rate = target_sample_rate;
+    }
+    
+    Ok(audio_data)
+}
+
+/// Decode a file at its own sample rate and channel count
+///
+/// Video containers (and other Symphonia formats) are downmixed to mono while
+/// decoding when `downmix` is set.
+fn decode_native(path: &Path, ffmpeg: &FfmpegOptions, downmix: bool) -> Result<AudioData> {
+    if !path.exists() {
+        anyhow::bail!("Audio file not found: {}", path.display());
+    }
+    
+    let format = AudioFormat::from_path(path);
+    
+    // Handle MPEG-TS separately (Symphonia doesn't support TS)
+    if format == AudioFormat::MpegTs {
+        // FFmpeg extracts TS audio downmixed to mono
+        if !downmix {
+            anyhow::bail!(
+                "Per-channel decoding is not supported for MPEG-TS input {}; it can only be decoded to mono",
+                path.display()
+            );
+        }
+        return super::extract_audio_from_ts(path, ffmpeg);
+    }
+    
+    // Handle video formats with Symphonia
+    if format.is_video_container() {
+        return super::extract_audio_from_video_with(path, downmix);
+    }
+    
+    // Handle pure audio formats
+    match format {
+        AudioFormat::Wav => decode_wav(path),
+        AudioFormat::Mp3 => decode_mp3(path),
+        AudioFormat::Flac => decode_flac(path),
+        AudioFormat::Ogg => decode_ogg(path),
+        AudioFormat::Unknown => {
+            anyhow::bail!("Unsupported audio format: {}", path.display());
+        }
+        _ => {
+            // Fallback: try Symphonia for any other format
+            super::extract_audio_from_video_with(path, downmix)
+        }
+    }
+}
+
 /// Decode WAV file
 fn decode_wav(path: &Path) -> Result<AudioData> {
     let mut reader = hound::WavReader::open(path)
@@ -108,16 +207,33 @@ fn decode_wav(path: &Path) -> Result<AudioData> {
         hound::SampleFormat::Float => {
             reader.samples::<f32>().collect::<Result<Vec<_>, _>>()?
         }
-        hound::SampleFormat::Int => {
-            let max_val = (1i64 << (spec.bits_per_sample - 1)) as f32;
-            reader
-                .samples::<i32>()
-                .map(|s| s.map(|v| v as f32 / max_val))
-                .collect::<Result<Vec<_>, _>>()?
-        }
+        hound::SampleFormat::Int => match spec.bits_per_sample {
+            // 8-bit WAV is stored unsigned; hound re-centers it around zero
+            // when reading as i8, so scale by the signed 8-bit range.
+            8 => reader
+                .samples::<i8>()
+                .map(|s| s.map(|v| v as f32 / 128.0))
+                .collect::<Result<Vec<_>, _>>()?,
+            16 => reader
+                .samples::<i16>()
+                .map(|s| s.map(|v| v as f32 / 32768.0))
+                .collect::<Result<Vec<_>, _>>()?,
+            bits @ 17..=32 => {
+                let max_val = (1i64 << (bits - 1)) as f32;
+                reader
+                    .samples::<i32>()
+                    .map(|s| s.map(|v| v as f32 / max_val))
+                    .collect::<Result<Vec<_>, _>>()?
+            }
+            bits => anyhow::bail!(
+                "Unsupported WAV bit depth: {} bits ({})",
+                bits,
+                path.display()
+            ),
+        },
     };
     
-    let duration_ms = (samples.len() as f64 / (sample_rate * channels as u32) as f64 * 1000.0) as u32;
+    let duration_ms = samples_to_duration_ms(samples.len(), sample_rate, channels);
     
     Ok(AudioData {
         samples,
@@ -154,7 +270,7 @@ fn decode_mp3(path: &Path) -> Result<AudioData> {
         }
     }
     
-    let duration_ms = (samples.len() as f64 / (sample_rate * channels as u32) as f64 * 1000.0) as u32;
+    let duration_ms = samples_to_duration_ms(samples.len(), sample_rate, channels);
     
     Ok(AudioData {
         samples,
@@ -180,7 +296,7 @@ fn decode_flac(path: &Path) -> Result<AudioData> {
         .map(|s| s.map(|v| v as f32 / max_val))
         .collect::<Result<Vec<_>, _>>()?;
     
-    let duration_ms = (samples.len() as f64 / (sample_rate * channels as u32) as f64 * 1000.0) as u32;
+    let duration_ms = samples_to_duration_ms(samples.len(), sample_rate, channels);
     
     Ok(AudioData {
         samples,
@@ -209,7 +325,7 @@ fn decode_ogg(path: &Path) -> Result<AudioData> {
         }
     }
     
-    let duration_ms = (samples.len() as f64 / (sample_rate * channels as u32) as f64 * 1000.0) as u32;
+    let duration_ms = samples_to_duration_ms(samples.len(), sample_rate, channels);
     
     Ok(AudioData {
         samples,
@@ -219,4 +335,186 @@ fn decode_ogg(path: &Path) -> Result<AudioData> {
     })
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_max_duration_guard() {
+        let audio = AudioData {
+            samples: vec![0.0; 16000 * 20], // 20 seconds
+            sample_rate: 16000,
+            channels: 1,
+            duration_ms: 20000,
+        };
+
+        assert!(check_max_duration(&audio, None).is_ok());
+        assert!(check_max_duration(&audio, Some(20000)).is_ok());
+
+        let err = check_max_duration(&audio, Some(10000)).unwrap_err();
+        assert!(err.to_string().contains("exceeds maximum allowed duration"));
+    }
+
+    fn write_test_wav(name: &str, bits_per_sample: u16, samples: &[i32]) -> std::path::PathBuf {
+        let path = std::env::temp_dir().join(format!(
+            "panako_{}_{}.wav",
+            name,
+            std::process::id()
+        ));
+        let spec = hound::WavSpec {
+            channels: 1,
+            sample_rate: 16000,
+            bits_per_sample,
+            sample_format: hound::SampleFormat::Int,
+        };
+        let mut writer = hound::WavWriter::create(&path, spec).unwrap();
+        for &s in samples {
+            match bits_per_sample {
+                8 => writer.write_sample(s as i8).unwrap(),
+                _ => writer.write_sample(s).unwrap(),
+            }
+        }
+        writer.finalize().unwrap();
+        path
+    }
+
+    #[test]
+    fn test_decode_8bit_wav() {
+        let path = write_test_wav("8bit", 8, &[0, 0, 127, -128, 64, -64]);
+        let audio = decode_wav(&path).unwrap();
+        std::fs::remove_file(&path).ok();
+
+        assert_eq!(audio.samples.len(), 6);
+        assert!(audio.samples.iter().all(|s| (-1.0..=1.0).contains(s)));
+        assert!(audio.samples[0].abs() < 1e-6);
+        assert!(audio.samples[1].abs() < 1e-6);
+        assert!((audio.samples[3] + 1.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_decode_24bit_wav() {
+        let max = (1 << 23) - 1;
+        let min = -(1 << 23);
+        let path = write_test_wav("24bit", 24, &[0, 0, max, min, max / 2]);
+        let audio = decode_wav(&path).unwrap();
+        std::fs::remove_file(&path).ok();
+
+        assert_eq!(audio.samples.len(), 5);
+        assert!(audio.samples.iter().all(|s| (-1.0..=1.0).contains(s)));
+        assert!(audio.samples[0].abs() < 1e-6);
+        assert!((audio.samples[2] - 1.0).abs() < 1e-6);
+        assert!((audio.samples[3] + 1.0).abs() < 1e-6);
+        assert!((audio.samples[4] - 0.5).abs() < 1e-3);
+    }
+
+    #[test]
+    fn test_samples_to_duration_ms() {
+        assert_eq!(samples_to_duration_ms(16000, 16000, 1), 1000);
+        assert_eq!(samples_to_duration_ms(24000, 8000, 2), 1500);
+        // 11520 frames at 44.1 kHz are 261.22 ms, 21 frames at 2 kHz 10.5 ms
+        assert_eq!(samples_to_duration_ms(11520, 44100, 1), 261);
+        assert_eq!(samples_to_duration_ms(21, 2000, 1), 11);
+        // A trailing partial frame does not count
+        assert_eq!(samples_to_duration_ms(43, 2000, 2), 11);
+        assert_eq!(samples_to_duration_ms(100, 0, 1), 0);
+        assert_eq!(samples_to_duration_ms(100, 16000, 0), 0);
+    }
+
+    #[test]
+    fn test_per_channel_decode_rejects_mpeg_ts() {
+        // Rejected before FFmpeg runs, so the content does not matter
+        let path = std::env::temp_dir().join(format!("panako_channels_{}.ts", std::process::id()));
+        std::fs::write(&path, [0u8; 188]).unwrap();
+        let err = decode_audio_channels(path.to_str().unwrap(), 16000).unwrap_err();
+        assert!(err.to_string().contains("Per-channel decoding is not supported"), "{}", err);
+        std::fs::remove_file(&path).ok();
+    }
+
+    /// MPEG-1 Layer III, 128 kbps, 44.1 kHz, mono frames of silence
+    /// (1152 samples each, no padding, empty side information)
+    fn write_silent_mp3(path: &std::path::Path, num_frames: usize) {
+        let mut frame = vec![0u8; 417];
+        frame[..4].copy_from_slice(&[0xFF, 0xFB, 0x90, 0xC0]);
+        std::fs::write(path, frame.repeat(num_frames)).unwrap();
+    }
+
+    /// Single-frame 16-bit mono FLAC with a verbatim subframe
+    fn write_flac(path: &std::path::Path, sample_rate: u32, samples: &[i16]) {
+        fn crc8(data: &[u8]) -> u8 {
+            data.iter().fold(0u8, |crc, &byte| {
+                (0..8).fold(crc ^ byte, |crc, _| if crc & 0x80 != 0 { (crc << 1) ^ 0x07 } else { crc << 1 })
+            })
+        }
+        fn crc16(data: &[u8]) -> u16 {
+            data.iter().fold(0u16, |crc, &byte| {
+                (0..8).fold(crc ^ ((byte as u16) << 8), |crc, _| {
+                    if crc & 0x8000 != 0 { (crc << 1) ^ 0x8005 } else { crc << 1 }
+                })
+            })
+        }
 
+        let block_size = samples.len() as u16;
+        let mut out = b"fLaC".to_vec();
+        // Last metadata block: STREAMINFO (34 bytes)
+        out.extend_from_slice(&[0x80, 0, 0, 34]);
+        out.extend_from_slice(&block_size.to_be_bytes());
+        out.extend_from_slice(&block_size.to_be_bytes());
+        out.extend_from_slice(&[0; 6]);
+        // Sample rate (20 bits), channels - 1 (3), bits per sample - 1 (5), total samples (36)
+        let packed = ((sample_rate as u64) << 44) | (15u64 << 36) | samples.len() as u64;
+        out.extend_from_slice(&packed.to_be_bytes());
+        out.extend_from_slice(&[0; 16]);
+
+        // Frame header: fixed block size, 16-bit block size at the end,
+        // rate from STREAMINFO, mono, 16 bits, frame number 0
+        let mut frame = vec![0xFF, 0xF8, 0x70, 0x08, 0x00];
+        frame.extend_from_slice(&(block_size - 1).to_be_bytes());
+        frame.push(crc8(&frame));
+        frame.push(0x02); // Verbatim subframe
+        for sample in samples {
+            frame.extend_from_slice(&sample.to_be_bytes());
+        }
+        frame.extend_from_slice(&crc16(&frame).to_be_bytes());
+        out.extend_from_slice(&frame);
+        std::fs::write(path, out).unwrap();
+    }
+
+    #[test]
+    fn test_formats_report_same_duration() {
+        // Five MP3 frames of content: 5760 samples at 44.1 kHz (130.61 ms)
+        let num_samples = 5760;
+        let base = std::env::temp_dir().join(format!("panako_duration_{}", std::process::id()));
+        let (wav, mp3, flac) = (base.with_extension("wav"), base.with_extension("mp3"), base.with_extension("flac"));
+        let spec = hound::WavSpec {
+            channels: 1,
+            sample_rate: 44100,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        };
+        let mut writer = hound::WavWriter::create(&wav, spec).unwrap();
+        for _ in 0..num_samples {
+            writer.write_sample(0i16).unwrap();
+        }
+        writer.finalize().unwrap();
+        write_silent_mp3(&mp3, num_samples / 1152);
+        write_flac(&flac, 44100, &vec![0; num_samples]);
+
+        let decoded: Vec<AudioData> = [decode_wav(&wav), decode_mp3(&mp3), decode_flac(&flac)]
+            .into_iter()
+            .map(Result::unwrap)
+            .collect();
+        for path in [&wav, &mp3, &flac] {
+            std::fs::remove_file(path).ok();
+        }
+
+        for audio in &decoded {
+            assert_eq!((audio.sample_rate, audio.channels), (44100, 1));
+            assert_eq!(audio.samples.len(), num_samples);
+        }
+        let durations: Vec<u32> = decoded.iter().map(|audio| audio.duration_ms).collect();
+        let (min, max) = (durations.iter().min().unwrap(), durations.iter().max().unwrap());
+        assert!(max - min <= 1, "durations {:?}", durations);
+        // Rounded, not truncated
+        assert_eq!(durations[0], 131);
+    }
+}
@@ -2,15 +2,26 @@
 //!
 //! Supports WAV, MP3, FLAC, OGG, and video formats (MP4, AVI, TS, etc.) using pure Rust decoders.
 
+mod band_energy;
+mod cache;
 mod decoder;
+mod pre_emphasis;
+mod probe;
 mod resample;
 mod video;
 mod ts;
 
-pub use decoder::{decode_audio, AudioData};
+pub use band_energy::{ENERGY_BAND_EDGES_HZ, NUM_ENERGY_BANDS};
+pub use cache::DecodeCache;
+pub use decoder::{
+    check_max_duration, decode_audio, decode_audio_channels, decode_audio_channels_with_options,
+    decode_audio_with_options, samples_to_duration_ms, AudioData,
+};
+pub use pre_emphasis::apply_pre_emphasis;
+pub use probe::{probe, MediaInfo};
 pub use resample::resample_to_target;
-pub use video::extract_audio_from_video;
-pub use ts::extract_audio_from_ts;
+pub use video::{extract_audio_from_video, extract_audio_from_video_with};
+pub use ts::{extract_audio_from_ts, FfmpegOptions};
 
 use std::path::Path;
 
@@ -0,0 +1,55 @@
+//! First-order pre-emphasis filter
+
+/// Apply `y[n] = x[n] - coefficient * x[n-1]` in place
+///
+/// Boosts high frequencies relative to low ones (typical coefficients are
+/// 0.9-0.97), which keeps high-frequency event points visible after lossy
+/// codecs roll off the top of the spectrum. A coefficient of 0 leaves the
+/// samples unchanged. The first sample is kept as is.
+pub fn apply_pre_emphasis(samples: &mut [f32], coefficient: f32) {
+    if coefficient == 0.0 {
+        return;
+    }
+    
+    // Walk backwards so every output uses the original previous sample
+    for n in (1..samples.len()).rev() {
+        samples[n] -= coefficient * samples[n - 1];
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f32::consts::PI;
+    
+    fn tone(freq: f32) -> Vec<f32> {
+        (0..16000).map(|i| (2.0 * PI * freq * i as f32 / 16000.0).sin()).collect()
+    }
+    
+    fn rms(samples: &[f32]) -> f32 {
+        (samples.iter().map(|s| s * s).sum::<f32>() / samples.len() as f32).sqrt()
+    }
+    
+    #[test]
+    fn test_pre_emphasis_boosts_high_frequencies() {
+        let mut low = tone(200.0);
+        let mut high = tone(6000.0);
+        let (low_before, high_before) = (rms(&low), rms(&high));
+        
+        apply_pre_emphasis(&mut low, 0.95);
+        apply_pre_emphasis(&mut high, 0.95);
+        let low_gain = rms(&low) / low_before;
+        let high_gain = rms(&high) / high_before;
+        
+        assert!(low_gain < 0.2);
+        assert!(high_gain > 1.5);
+    }
+    
+    #[test]
+    fn test_zero_coefficient_is_noop() {
+        let original = tone(1000.0);
+        let mut samples = original.clone();
+        apply_pre_emphasis(&mut samples, 0.0);
+        assert_eq!(samples, original);
+    }
+}
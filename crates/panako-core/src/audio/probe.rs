@@ -0,0 +1,200 @@
+//! Cheap media inspection without decoding audio samples
+
+use super::AudioFormat;
+use anyhow::{Context, Result};
+use serde::Serialize;
+use std::path::Path;
+use std::process::{Command, Stdio};
+use symphonia::core::codecs::CODEC_TYPE_NULL;
+use symphonia::core::formats::FormatOptions;
+use symphonia::core::io::MediaSourceStream;
+use symphonia::core::meta::MetadataOptions;
+use symphonia::core::probe::Hint;
+
+/// Container and stream information of a media file
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct MediaInfo {
+    /// Container format detected from the file extension
+    pub container: String,
+    /// Audio codec short name (e.g. "pcm_s16le", "mp3", "aac")
+    pub codec: Option<String>,
+    /// Sample rate of the audio stream (Hz)
+    pub sample_rate: Option<u32>,
+    /// Number of audio channels
+    pub channels: Option<u16>,
+    /// Duration in milliseconds, when the container reports it
+    pub duration_ms: Option<u32>,
+}
+
+/// Report container, codec, duration, sample rate and channel count of a file
+///
+/// Only headers are read (Symphonia probe, or `ffmpeg -i` for MPEG-TS), so this
+/// is cheap enough for pre-ingestion validation of large batches.
+pub fn probe(path: &Path) -> Result<MediaInfo> {
+    if !path.exists() {
+        anyhow::bail!("Audio file not found: {}", path.display());
+    }
+
+    let format = AudioFormat::from_path(path);
+    if format == AudioFormat::MpegTs {
+        return probe_with_ffmpeg(path, format);
+    }
+
+    let file = std::fs::File::open(path)
+        .with_context(|| format!("Failed to open media file: {}", path.display()))?;
+    let mss = MediaSourceStream::new(Box::new(file), Default::default());
+
+    let mut hint = Hint::new();
+    if let Some(ext) = path.extension().and_then(|e| e.to_str()) {
+        hint.with_extension(ext);
+    }
+
+    let probed = symphonia::default::get_probe()
+        .format(&hint, mss, &FormatOptions::default(), &MetadataOptions::default())
+        .with_context(|| format!("Failed to probe media file: {}", path.display()))?;
+
+    let track = probed
+        .format
+        .tracks()
+        .iter()
+        .find(|t| t.codec_params.codec != CODEC_TYPE_NULL)
+        .ok_or_else(|| anyhow::anyhow!("No audio track found in {}", path.display()))?;
+    let params = &track.codec_params;
+
+    let codec = symphonia::default::get_codecs()
+        .get_codec(params.codec)
+        .map(|descriptor| descriptor.short_name.to_string());
+    let duration_ms = match (params.n_frames, params.sample_rate) {
+        (Some(frames), Some(rate)) if rate > 0 => Some(super::samples_to_duration_ms(frames as usize, rate, 1)),
+        _ => None,
+    };
+
+    Ok(MediaInfo {
+        container: container_name(format),
+        codec,
+        sample_rate: params.sample_rate,
+        channels: params.channels.map(|c| c.count() as u16),
+        duration_ms,
+    })
+}
+
+fn container_name(format: AudioFormat) -> String {
+    format!("{:?}", format).to_lowercase()
+}
+
+/// Probe a file by parsing the stream summary FFmpeg prints for `ffmpeg -i`
+fn probe_with_ffmpeg(path: &Path, format: AudioFormat) -> Result<MediaInfo> {
+    let output = Command::new("ffmpeg")
+        .arg("-hide_banner")
+        .arg("-i")
+        .arg(path)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .output()
+        .with_context(|| "Failed to run FFmpeg (is it installed?)")?;
+
+    // `ffmpeg -i` without an output exits non-zero but still prints stream info
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let mut info = parse_ffmpeg_info(&stderr);
+    info.container = container_name(format);
+    Ok(info)
+}
+
+/// Parse duration and the first audio stream from `ffmpeg -i` output
+fn parse_ffmpeg_info(text: &str) -> MediaInfo {
+    let mut info = MediaInfo {
+        container: String::new(),
+        codec: None,
+        sample_rate: None,
+        channels: None,
+        duration_ms: None,
+    };
+
+    for line in text.lines().map(str::trim) {
+        if let Some(rest) = line.strip_prefix("Duration:") {
+            let timestamp = rest.split(',').next().unwrap_or("").trim();
+            info.duration_ms = parse_timestamp_ms(timestamp);
+        } else if info.codec.is_none() && line.contains("Audio:") {
+            let stream = line.split("Audio:").nth(1).unwrap_or("");
+            let fields: Vec<&str> = stream.split(',').map(str::trim).collect();
+
+            info.codec = fields
+                .first()
+                .and_then(|f| f.split_whitespace().next())
+                .map(str::to_string);
+            info.sample_rate = fields
+                .iter()
+                .find_map(|f| f.strip_suffix(" Hz"))
+                .and_then(|hz| hz.trim().parse().ok());
+            info.channels = fields.get(2).and_then(|layout| parse_channel_layout(layout));
+        }
+    }
+
+    info
+}
+
+/// Map an FFmpeg channel layout name ("stereo", "5.1", "3 channels") to a count
+fn parse_channel_layout(layout: &str) -> Option<u16> {
+    match layout {
+        "mono" => Some(1),
+        "stereo" => Some(2),
+        "5.1" | "5.1(side)" => Some(6),
+        "7.1" => Some(8),
+        other => other.strip_suffix(" channels").and_then(|n| n.parse().ok()),
+    }
+}
+
+/// Parse an `HH:MM:SS.ss` timestamp into milliseconds
+fn parse_timestamp_ms(timestamp: &str) -> Option<u32> {
+    let mut parts = timestamp.split(':');
+    let hours: f64 = parts.next()?.parse().ok()?;
+    let minutes: f64 = parts.next()?.parse().ok()?;
+    let seconds: f64 = parts.next()?.parse().ok()?;
+    Some(((hours * 3600.0 + minutes * 60.0 + seconds) * 1000.0).round() as u32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_probe_wav_header() {
+        let path = std::env::temp_dir().join(format!("panako_probe_{}.wav", std::process::id()));
+        let spec = hound::WavSpec {
+            channels: 2,
+            sample_rate: 22050,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        };
+        let mut writer = hound::WavWriter::create(&path, spec).unwrap();
+        // 1.5 seconds of stereo silence
+        for _ in 0..(22050 * 3 / 2 * 2) {
+            writer.write_sample(0i16).unwrap();
+        }
+        writer.finalize().unwrap();
+
+        let info = probe(&path).unwrap();
+        std::fs::remove_file(&path).ok();
+
+        assert_eq!(info.container, "wav");
+        assert_eq!(info.sample_rate, Some(22050));
+        assert_eq!(info.channels, Some(2));
+        assert_eq!(info.duration_ms, Some(1500));
+        assert!(info.codec.is_some());
+    }
+
+    #[test]
+    fn test_parse_ffmpeg_info() {
+        let stderr = "Input #0, mpegts, from 'capture.ts':\n\
+            \x20 Duration: 00:01:02.50, start: 1.400000, bitrate: 3000 kb/s\n\
+            \x20 Stream #0:0[0x100]: Video: h264 (Main), yuv420p, 1280x720, 25 fps\n\
+            \x20 Stream #0:1[0x101](eng): Audio: aac (LC) ([15][0][0][0] / 0x000F), 48000 Hz, stereo, fltp, 128 kb/s\n";
+
+        let info = parse_ffmpeg_info(stderr);
+        assert_eq!(info.duration_ms, Some(62500));
+        assert_eq!(info.codec.as_deref(), Some("aac"));
+        assert_eq!(info.sample_rate, Some(48000));
+        assert_eq!(info.channels, Some(2));
+    }
+}
@@ -5,12 +5,25 @@ use anyhow::{Context, Result};
 use std::io::Read;
 use std::path::Path;
 use std::process::{Command, Stdio};
+use std::time::{Duration, Instant};
+
+/// How often a running FFmpeg is checked against its timeout
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Limits for the FFmpeg subprocess used to demux MPEG-TS
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct FfmpegOptions {
+    /// Kill FFmpeg and fail if it runs longer than this (None = wait forever)
+    pub timeout: Option<Duration>,
+    /// Extra attempts after a failed or timed out run
+    pub retries: u32,
+}
 
 /// Extract audio from MPEG-TS file using FFmpeg pipe
 /// 
 /// This function spawns FFmpeg as a subprocess and reads the audio
 /// data directly from stdout as WAV format, avoiding temporary files.
-pub fn extract_audio_from_ts(path: &Path) -> Result<AudioData> {
+pub fn extract_audio_from_ts(path: &Path, options: &FfmpegOptions) -> Result<AudioData> {
     // Check if FFmpeg is available
     let ffmpeg_check = Command::new("ffmpeg")
         .arg("-version")
@@ -33,43 +46,91 @@ pub fn extract_audio_from_ts(path: &Path) -> Result<AudioData> {
         );
     }
     
-    // Spawn FFmpeg to extract audio as raw PCM to stdout
-    let mut child = Command::new("ffmpeg")
-        .arg("-i")
-        .arg(path)
-        .arg("-vn")                    // No video
-        .arg("-acodec")
-        .arg("pcm_s16le")              // PCM 16-bit little-endian
-        .arg("-ar")
-        .arg("16000")                  // 16kHz sample rate
-        .arg("-ac")
-        .arg("1")                      // Mono
-        .arg("-f")
-        .arg("s16le")                  // Raw PCM format (no WAV header)
-        .arg("pipe:1")                 // Output to stdout
+    // FFmpeg extracts audio as raw PCM to stdout
+    let pcm_data = run_with_retries(
+        || {
+            let mut command = Command::new("ffmpeg");
+            command
+                .arg("-i")
+                .arg(path)
+                .arg("-vn")                    // No video
+                .arg("-acodec")
+                .arg("pcm_s16le")              // PCM 16-bit little-endian
+                .arg("-ar")
+                .arg("16000")                  // 16kHz sample rate
+                .arg("-ac")
+                .arg("1")                      // Mono
+                .arg("-f")
+                .arg("s16le")                  // Raw PCM format (no WAV header)
+                .arg("pipe:1");                // Output to stdout
+            command
+        },
+        options,
+    )
+    .with_context(|| format!("FFmpeg failed to extract audio from TS file {}", path.display()))?;
+    
+    // Parse raw PCM data
+    parse_pcm_from_memory(&pcm_data)
+}
+
+/// Run the command built by `make_command` until it succeeds or the
+/// attempts allowed by `options.retries` are used up
+fn run_with_retries(make_command: impl Fn() -> Command, options: &FfmpegOptions) -> Result<Vec<u8>> {
+    let mut attempt = 0;
+    loop {
+        match run_with_timeout(make_command(), options.timeout) {
+            Ok(output) => return Ok(output),
+            Err(e) if attempt < options.retries => {
+                attempt += 1;
+                log::warn!("{:#}; retrying ({}/{})", e, attempt, options.retries);
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Run `command` and return its stdout, killing it after `timeout`
+fn run_with_timeout(mut command: Command, timeout: Option<Duration>) -> Result<Vec<u8>> {
+    let mut child = command
         .stdin(Stdio::null())
         .stdout(Stdio::piped())
         .stderr(Stdio::null())         // Suppress FFmpeg output
         .spawn()
         .with_context(|| "Failed to spawn FFmpeg process")?;
     
-    // Read PCM data from FFmpeg stdout
-    let mut pcm_data = Vec::new();
-    if let Some(mut stdout) = child.stdout.take() {
-        stdout.read_to_end(&mut pcm_data)
-            .with_context(|| "Failed to read audio data from FFmpeg")?;
-    }
+    // Read stdout on another thread so a full pipe cannot block the wait below
+    let mut stdout = child.stdout.take().context("FFmpeg stdout is not piped")?;
+    let reader = std::thread::spawn(move || {
+        let mut data = Vec::new();
+        stdout.read_to_end(&mut data).map(|_| data)
+    });
     
-    // Wait for FFmpeg to finish
-    let status = child.wait()
-        .with_context(|| "Failed to wait for FFmpeg process")?;
+    let status = match timeout {
+        None => child.wait().with_context(|| "Failed to wait for FFmpeg process")?,
+        Some(timeout) => {
+            let deadline = Instant::now().checked_add(timeout);
+            loop {
+                if let Some(status) = child.try_wait().with_context(|| "Failed to wait for FFmpeg process")? {
+                    break status;
+                }
+                if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                    child.kill().ok();
+                    child.wait().ok();
+                    anyhow::bail!("FFmpeg timed out after {:.1}s and was killed", timeout.as_secs_f64());
+                }
+                std::thread::sleep(POLL_INTERVAL);
+            }
+        }
+    };
     
+    let data = reader
+        .join()
+        .map_err(|_| anyhow::anyhow!("FFmpeg output reader panicked"))?
+        .with_context(|| "Failed to read audio data from FFmpeg")?;
     if !status.success() {
-        anyhow::bail!("FFmpeg failed to extract audio from TS file");
+        anyhow::bail!("FFmpeg exited with {}", status);
     }
-    
-    // Parse raw PCM data
-    parse_pcm_from_memory(&pcm_data)
+    Ok(data)
 }
 
 /// Parse raw PCM data from memory buffer
@@ -86,7 +147,7 @@ fn parse_pcm_from_memory(data: &[u8]) -> Result<AudioData> {
     // FFmpeg was configured for 16kHz mono
     let sample_rate = 16000;
     let channels = 1;
-    let duration_ms = (samples.len() as f64 / sample_rate as f64 * 1000.0) as u32;
+    let duration_ms = super::samples_to_duration_ms(samples.len(), sample_rate, channels);
     
     Ok(AudioData {
         samples,
@@ -95,3 +156,50 @@ fn parse_pcm_from_memory(data: &[u8]) -> Result<AudioData> {
         duration_ms,
     })
 }
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    fn shell(script: &str) -> Command {
+        let mut command = Command::new("sh");
+        command.arg("-c").arg(script);
+        command
+    }
+
+    #[test]
+    fn test_timeout_kills_hung_process() {
+        let marker = std::env::temp_dir().join(format!("panako_ffmpeg_timeout_{}", std::process::id()));
+        std::fs::remove_file(&marker).ok();
+        // The marker is only written if the process outlives the timeout
+        let script = format!("sleep 2; touch {}", marker.display());
+
+        let start = Instant::now();
+        let err = run_with_timeout(shell(&script), Some(Duration::from_millis(200))).unwrap_err();
+        assert!(err.to_string().contains("timed out"), "{}", err);
+        assert!(start.elapsed() < Duration::from_millis(1500));
+
+        std::thread::sleep(Duration::from_millis(2500));
+        assert!(!marker.exists(), "process was not killed");
+    }
+
+    #[test]
+    fn test_output_is_returned_within_timeout() {
+        let output = run_with_timeout(shell("printf abc"), Some(Duration::from_secs(10))).unwrap();
+        assert_eq!(output, b"abc");
+        assert!(run_with_timeout(shell("exit 3"), None).is_err());
+    }
+
+    #[test]
+    fn test_failed_runs_are_retried() {
+        let counter = std::env::temp_dir().join(format!("panako_ffmpeg_retries_{}", std::process::id()));
+        std::fs::remove_file(&counter).ok();
+        let script = format!("echo run >> {}; exit 1", counter.display());
+        let options = FfmpegOptions { timeout: None, retries: 2 };
+
+        assert!(run_with_retries(|| shell(&script), &options).is_err());
+        let runs = std::fs::read_to_string(&counter).unwrap().lines().count();
+        std::fs::remove_file(&counter).ok();
+        assert_eq!(runs, 3);
+    }
+}
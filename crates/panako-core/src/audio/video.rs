@@ -3,15 +3,42 @@
 use super::AudioData;
 use anyhow::{Context, Result};
 use std::path::Path;
-use symphonia::core::audio::{AudioBufferRef, Signal};
+use symphonia::core::audio::{AudioBuffer, AudioBufferRef, Signal};
 use symphonia::core::codecs::{DecoderOptions, CODEC_TYPE_NULL};
 use symphonia::core::formats::FormatOptions;
 use symphonia::core::io::MediaSourceStream;
 use symphonia::core::meta::MetadataOptions;
 use symphonia::core::probe::Hint;
+use symphonia::core::sample::Sample;
+
+/// Append the frames of a decoded buffer, interleaved or averaged to mono
+fn push_frames<S: Sample>(buf: &AudioBuffer<S>, convert: impl Fn(S) -> f32, downmix: bool, samples: &mut Vec<f32>) {
+    let channels = buf.spec().channels.count();
+    for frame_idx in 0..buf.frames() {
+        if downmix {
+            let sum: f32 = (0..channels).map(|ch| convert(buf.chan(ch)[frame_idx])).sum();
+            samples.push(sum / channels.max(1) as f32);
+        } else {
+            samples.extend((0..channels).map(|ch| convert(buf.chan(ch)[frame_idx])));
+        }
+    }
+}
 
 /// Extract audio from video file using Symphonia
+///
+/// Samples are interleaved with the track's channel count; see
+/// `extract_audio_from_video_with` to downmix while decoding.
 pub fn extract_audio_from_video(path: &Path) -> Result<AudioData> {
+    extract_audio_from_video_with(path, false)
+}
+
+/// Extract audio from video file, averaging all channels of every frame to
+/// mono when `downmix_to_mono` is set
+///
+/// Downmixing uses the channel count of each decoded buffer, so the result
+/// is mono with an exact `duration_ms` even if the stream's channel layout
+/// differs from the track header or changes midway.
+pub fn extract_audio_from_video_with(path: &Path, downmix_to_mono: bool) -> Result<AudioData> {
     // Open the media file
     let file = std::fs::File::open(path)
         .with_context(|| format!("Failed to open video file: {}", path.display()))?;
@@ -85,41 +112,16 @@ pub fn extract_audio_from_video(path: &Path) -> Result<AudioData> {
         
         // Convert decoded audio to f32 samples
         match decoded {
-            AudioBufferRef::F32(buf) => {
-                // Interleave channels
-                for frame_idx in 0..buf.frames() {
-                    for ch in 0..buf.spec().channels.count() {
-                        samples.push(buf.chan(ch)[frame_idx]);
-                    }
-                }
-            }
-            AudioBufferRef::F64(buf) => {
-                for frame_idx in 0..buf.frames() {
-                    for ch in 0..buf.spec().channels.count() {
-                        samples.push(buf.chan(ch)[frame_idx] as f32);
-                    }
-                }
-            }
+            AudioBufferRef::F32(buf) => push_frames(&buf, |s| s, downmix_to_mono, &mut samples),
+            AudioBufferRef::F64(buf) => push_frames(&buf, |s| s as f32, downmix_to_mono, &mut samples),
             AudioBufferRef::S32(buf) => {
-                for frame_idx in 0..buf.frames() {
-                    for ch in 0..buf.spec().channels.count() {
-                        samples.push(buf.chan(ch)[frame_idx] as f32 / i32::MAX as f32);
-                    }
-                }
+                push_frames(&buf, |s| s as f32 / i32::MAX as f32, downmix_to_mono, &mut samples)
             }
             AudioBufferRef::S16(buf) => {
-                for frame_idx in 0..buf.frames() {
-                    for ch in 0..buf.spec().channels.count() {
-                        samples.push(buf.chan(ch)[frame_idx] as f32 / i16::MAX as f32);
-                    }
-                }
+                push_frames(&buf, |s| s as f32 / i16::MAX as f32, downmix_to_mono, &mut samples)
             }
             AudioBufferRef::U8(buf) => {
-                for frame_idx in 0..buf.frames() {
-                    for ch in 0..buf.spec().channels.count() {
-                        samples.push((buf.chan(ch)[frame_idx] as f32 - 128.0) / 128.0);
-                    }
-                }
+                push_frames(&buf, |s| (s as f32 - 128.0) / 128.0, downmix_to_mono, &mut samples)
             }
             _ => {
                 return Err(anyhow::anyhow!("Unsupported audio buffer format"));
@@ -127,7 +129,8 @@ pub fn extract_audio_from_video(path: &Path) -> Result<AudioData> {
         }
     }
     
-    let duration_ms = (samples.len() as f64 / (sample_rate * channels as u32) as f64 * 1000.0) as u32;
+    let channels = if downmix_to_mono { 1 } else { channels };
+    let duration_ms = super::samples_to_duration_ms(samples.len(), sample_rate, channels);
     
     Ok(AudioData {
         samples,
@@ -136,3 +139,39 @@ pub fn extract_audio_from_video(path: &Path) -> Result<AudioData> {
         duration_ms,
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_downmix_during_extraction() {
+        // A stereo stream read through Symphonia: left 0.5, right -0.1
+        let path = std::env::temp_dir().join(format!("panako_video_stereo_{}.wav", std::process::id()));
+        let spec = hound::WavSpec {
+            channels: 2,
+            sample_rate: 8000,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        };
+        let mut writer = hound::WavWriter::create(&path, spec).unwrap();
+        for _ in 0..12000 {
+            writer.write_sample((0.5 * i16::MAX as f32) as i16).unwrap();
+            writer.write_sample((-0.1 * i16::MAX as f32) as i16).unwrap();
+        }
+        writer.finalize().unwrap();
+
+        let interleaved = extract_audio_from_video(&path).unwrap();
+        let mono = extract_audio_from_video_with(&path, true).unwrap();
+        std::fs::remove_file(&path).ok();
+
+        assert_eq!(interleaved.channels, 2);
+        assert_eq!(interleaved.samples.len(), 24000);
+        assert_eq!(mono.channels, 1);
+        assert_eq!(mono.samples.len(), 12000);
+        assert_eq!(mono.duration_ms, 1500);
+        assert_eq!(mono.duration_ms, interleaved.duration_ms);
+        assert!(mono.samples.iter().all(|&s| (s - 0.2).abs() < 1e-3));
+        assert_eq!(mono.samples, interleaved.to_mono());
+    }
+}
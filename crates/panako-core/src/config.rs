@@ -2,6 +2,7 @@
 //!
 //! These values match the Java reference implementation defaults.
 
+use panako_fp::FingerprintParams;
 use serde::{Deserialize, Serialize};
 
 /// Algorithm configuration matching Java Panako defaults
@@ -11,23 +12,71 @@ pub struct PanakoConfig {
     pub sample_rate: u32,
     pub audio_block_size: usize,
     pub audio_block_overlap: usize,
+    /// Maximum accepted audio duration in milliseconds (None = unlimited)
+    #[serde(default)]
+    pub max_duration_ms: Option<u32>,
     
     // Spectral transform (Gabor/Constant-Q)
     pub min_freq: f32,
     pub max_freq: f32,
     pub bands_per_octave: u32,
     pub ref_freq: f32,
+    /// Align the constant-Q bin grid to `ref_freq` instead of `min_freq`
+    ///
+    /// When enabled, one bin is centered exactly on `ref_freq` and all other
+    /// bins sit at whole-band steps from it, so the `f1_range` hash component
+    /// refers to the same musical pitches across files. Setting `ref_freq` to
+    /// 432.0 shifts the whole grid down by ~31.8 cents (about 2.25 bins at 85
+    /// bands per octave) relative to A=440.
+    #[serde(default)]
+    pub pitch_normalized: bool,
     pub time_resolution: usize,
     
     // Event point extraction
     pub freq_max_filter_size: usize,
     pub time_max_filter_size: usize,
+    /// Frequency extent of the max filter in cents, overriding `freq_max_filter_size`
+    ///
+    /// The constant-Q axis is logarithmic, so a fixed width in Hz would span a
+    /// different number of bins at every frequency; a musical interval maps to
+    /// the same number of bins everywhere for a given `bands_per_octave`.
+    /// 1454 cents gives the default 103 bins at 85 bands per octave.
+    #[serde(default)]
+    pub freq_max_filter_cents: Option<f32>,
+    /// Time extent of the max filter in seconds, overriding `time_max_filter_size`
+    ///
+    /// 0.2 s gives the default 25 frames at 16 kHz with a 128-sample hop.
+    #[serde(default)]
+    pub time_max_filter_s: Option<f64>,
     
     // Fingerprint generation
     pub fp_min_freq_dist: i16,
     pub fp_max_freq_dist: i16,
     pub fp_min_time_dist: i32,
     pub fp_max_time_dist: i32,
+    /// Relative tolerance under which two magnitudes count as near-equal
+    /// (None = exact hashing)
+    ///
+    /// When set, each fingerprint whose magnitude comparisons are within this
+    /// tolerance is also emitted with the affected comparison bits flipped, so a
+    /// tiny amount of noise no longer changes the hash. Must be used for both
+    /// reference and query generation. The matcher counts each pair of query
+    /// and reference first points once, however many of their variants match.
+    #[serde(default)]
+    pub fuzzy_hash_epsilon: Option<f32>,
+    /// Compare event point magnitudes in buckets of this many dB when hashing
+    /// (None = exact comparison)
+    ///
+    /// Magnitudes in the same bucket compare as equal, so near-equal peaks no
+    /// longer flip the magnitude bits of the hash under lossy re-encoding.
+    /// Changes every hash: must match between references and queries.
+    #[serde(default)]
+    pub magnitude_bucket_db: Option<f32>,
+    /// Pre-emphasis coefficient `a` in `y[n] = x[n] - a*x[n-1]`, applied to the
+    /// mono samples before the transform (None = off). Must match between
+    /// references and queries.
+    #[serde(default)]
+    pub pre_emphasis: Option<f32>,
     
     // Matching parameters
     pub query_range: i32,
@@ -39,6 +88,185 @@ pub struct PanakoConfig {
     pub max_freq_factor: f64,
     pub min_sec_with_match: f64,
     pub min_match_duration: f64,
+    /// Compute coverage over the full query span instead of the matched span
+    #[serde(default = "default_true")]
+    pub coverage_over_full_query: bool,
+    /// Refine the frame-level alignment to a sub-frame offset (`sub_frame_offset_ms`)
+    #[serde(default)]
+    pub refine_alignment: bool,
+    /// Derive the reference bounds from the query bounds and the aligned
+    /// delta (`ref = query + delta`) instead of the independent min/max of
+    /// the matched reference frames, which a looping reference can stretch
+    #[serde(default)]
+    pub ref_bounds_from_delta: bool,
+    /// Attach the aligned fingerprint pairs behind each result (for auditing)
+    #[serde(default)]
+    pub explain_matches: bool,
+    /// Attach the aligned (query, reference) times in seconds behind each
+    /// result (`QueryResult::aligned_points`), for plotting a match
+    #[serde(default)]
+    pub include_aligned_points: bool,
+    /// Also report the match bounds in frame indices (`QueryResult::frames`),
+    /// so consumers can recompute the times exactly for their own frame length
+    #[serde(default)]
+    pub include_frame_times: bool,
+    /// Skip hash buckets holding more than this many index entries (None = no cap)
+    #[serde(default)]
+    pub max_candidates_per_hash: Option<usize>,
+    /// Match at most this many occurrences of the same hash in a query, so
+    /// looping or sustained content does not dominate the offset histogram
+    /// (1 = collapse duplicates; None = match every occurrence)
+    #[serde(default)]
+    pub max_query_hash_repeats: Option<usize>,
+    /// Drop hashes present in more than this fraction of references (None = keep all)
+    #[serde(default)]
+    pub max_hash_ref_fraction: Option<f64>,
+    /// Pre-filter query hashes with a Bloom filter of this many bits per
+    /// indexed hash (None = plain index lookups)
+    #[serde(default)]
+    pub bloom_filter_bits_per_hash: Option<usize>,
+    /// Only match references registered at or after this Unix time in
+    /// seconds (see `Matcher::add_registration_time`); references without a
+    /// registration time are skipped too. None = all references
+    #[serde(default)]
+    pub registered_after_s: Option<i64>,
+    /// Estimate the duration of references indexed without `add_duration` from
+    /// their latest fingerprint, so absolute positions are always populated
+    #[serde(default = "default_true")]
+    pub estimate_ref_duration: bool,
+    /// Minimum fraction of the reference duration spanned by the match (None = no minimum)
+    #[serde(default)]
+    pub min_ref_coverage: Option<f64>,
+    /// Flag results whose `time_factor` differs from 1.0 by more than this
+    /// fraction as `speed_altered` (e.g. 0.02 = 2%, to surface airings sped
+    /// up to fit a slot); None = never flagged
+    #[serde(default)]
+    pub speed_altered_tolerance: Option<f64>,
+    /// Merge detections of the same reference and offset separated by at most
+    /// this many seconds (e.g. a play interrupted by a dropout); None = no merging
+    #[serde(default)]
+    pub max_detection_gap_s: Option<f64>,
+    /// Report at most one detection of a reference per burst of detections
+    /// separated by less than this many seconds, after merging (None = off)
+    #[serde(default)]
+    pub detection_debounce_s: Option<f64>,
+    /// Rank results by `normalized_score` (aligned matches per indexed
+    /// reference fingerprint) instead of the raw score, so long references
+    /// do not outrank short ones just by being longer
+    #[serde(default)]
+    pub normalize_score_by_ref_length: bool,
+    /// Quantize indexed reference times to multiples of this many frames,
+    /// merging postings that become identical (None = exact times). At most
+    /// `query_range + 1` so alignment stays within the matching tolerance
+    #[serde(default)]
+    pub index_time_step: Option<i32>,
+    /// How to pick the time offset when several have the same support
+    #[serde(default)]
+    pub delta_tie_break: DeltaTieBreak,
+    /// Keep reference magnitudes (`m1`) in the matcher index and report the
+    /// mean matched magnitude (`mean_matched_magnitude`); off keeps the index lean
+    #[serde(default)]
+    pub index_magnitudes: bool,
+    /// Transform audio longer than this many seconds in chunks of this length
+    /// on all cores (None = one sequential pass); the spectrogram is identical
+    #[serde(default)]
+    pub parallel_transform_chunk_s: Option<f64>,
+    /// Divide each spectrogram bin by its running median over this many
+    /// seconds before event point extraction, cancelling EQ and other
+    /// spectral coloration (None = no whitening)
+    #[serde(default)]
+    pub whitening_window_s: Option<f64>,
+    /// Kill the FFmpeg process demuxing MPEG-TS input after this many
+    /// seconds, failing the file (None = wait forever)
+    #[serde(default)]
+    pub ffmpeg_timeout_s: Option<f64>,
+    /// Extra FFmpeg attempts after a failed or timed out run
+    #[serde(default)]
+    pub ffmpeg_retries: u32,
+    /// Directory of a cache of decoded audio, reused while an input file's
+    /// modification time and size are unchanged (None = always decode)
+    #[serde(default)]
+    pub decode_cache_dir: Option<String>,
+    /// Directory holding the reference media files; results then report
+    /// `media_path`, the reference's original file name under it (None = off)
+    #[serde(default)]
+    pub reference_media_root: Option<String>,
+    /// Skip frames whose samples have an RMS (linear, 0..1) below this: no
+    /// FFT is computed and no event points are taken from them (None = off)
+    #[serde(default)]
+    pub frame_energy_gate_rms: Option<f32>,
+    /// Keep only this many event points of a whole file, the highest
+    /// magnitudes, bounding fingerprint generation on pathological input
+    /// (None = no cap). Not applied by `IncrementalFingerprinter`
+    #[serde(default)]
+    pub max_event_points: Option<usize>,
+    /// Keep only this many event points per frame, the highest magnitudes
+    /// (None = no cap)
+    #[serde(default)]
+    pub max_event_points_per_frame: Option<usize>,
+    /// Width of fingerprint hashes and index keys, see `HashWidth`
+    #[serde(default)]
+    pub hash_width: HashWidth,
+}
+
+/// Choice between time offsets (`delta_t`) with equal histogram support
+///
+/// Whatever the rule, remaining ties go to the smallest absolute offset and
+/// then to the negative one, so the chosen offset never depends on hash map
+/// iteration order.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum DeltaTieBreak {
+    /// Prefer the offset closest to zero
+    #[default]
+    SmallestAbsolute,
+    /// Prefer the offset whose matches span the longest stretch of the query
+    WidestSpread,
+}
+
+/// Width of fingerprint hashes and of the index keys built from them
+///
+/// 64-bit hashes use the Java layout (34 significant bits). 32-bit hashes
+/// pack the same fields at lower precision (7 bits of `f1` range and 5 bits
+/// per frequency difference instead of 8 and 6) into 31 bits, halving the
+/// key size in memory-mapped indexes. The price is more collisions: about
+/// eight times more unrelated fingerprints share each hash, so queries see
+/// more candidates and scores of unrelated references rise, which recall
+/// thresholds (`min_hits_*`) may need to compensate for. Fingerprints of
+/// different widths never match.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum HashWidth {
+    #[default]
+    Bits64,
+    Bits32,
+}
+
+impl HashWidth {
+    /// Number of bits of a hash (and index key)
+    pub fn bits(self) -> u32 {
+        match self {
+            Self::Bits64 => 64,
+            Self::Bits32 => 32,
+        }
+    }
+    
+    /// Width with `bits` bits, if supported
+    pub fn from_bits(bits: u32) -> Option<Self> {
+        match bits {
+            64 => Some(Self::Bits64),
+            32 => Some(Self::Bits32),
+            _ => None,
+        }
+    }
+    
+    /// Index key of a hash: the hash limited to this width
+    pub fn key(self, hash: u64) -> u64 {
+        match self {
+            Self::Bits64 => hash,
+            Self::Bits32 => hash & u32::MAX as u64,
+        }
+    }
 }
 
 impl Default for PanakoConfig {
@@ -48,23 +276,30 @@ impl Default for PanakoConfig {
             sample_rate: 16000,
             audio_block_size: 8192,
             audio_block_overlap: 0,
+            max_duration_ms: None,
             
             // Spectral transform
             min_freq: 110.0,
             max_freq: 7040.0,
             bands_per_octave: 85,
             ref_freq: 440.0,
+            pitch_normalized: false,
             time_resolution: 128,
             
             // Event point extraction
             freq_max_filter_size: 103,
             time_max_filter_size: 25,
+            freq_max_filter_cents: None,
+            time_max_filter_s: None,
             
             // Fingerprint generation
             fp_min_freq_dist: 1,
             fp_max_freq_dist: 128,
             fp_min_time_dist: 2,
             fp_max_time_dist: 33,
+            fuzzy_hash_epsilon: None,
+            magnitude_bucket_db: None,
+            pre_emphasis: None,
             
             // Matching parameters
             query_range: 2,
@@ -76,10 +311,53 @@ impl Default for PanakoConfig {
             max_freq_factor: 1.2,
             min_sec_with_match: 0.2,
             min_match_duration: 3.0,
+            coverage_over_full_query: true,
+            refine_alignment: false,
+            ref_bounds_from_delta: false,
+            explain_matches: false,
+            include_aligned_points: false,
+            include_frame_times: false,
+            max_candidates_per_hash: None,
+            max_query_hash_repeats: None,
+            max_hash_ref_fraction: None,
+            bloom_filter_bits_per_hash: None,
+            registered_after_s: None,
+            estimate_ref_duration: true,
+            min_ref_coverage: None,
+            speed_altered_tolerance: None,
+            max_detection_gap_s: None,
+            detection_debounce_s: None,
+            normalize_score_by_ref_length: false,
+            index_time_step: None,
+            delta_tie_break: DeltaTieBreak::default(),
+            index_magnitudes: false,
+            parallel_transform_chunk_s: None,
+            whitening_window_s: None,
+            ffmpeg_timeout_s: None,
+            ffmpeg_retries: 0,
+            decode_cache_dir: None,
+            reference_media_root: None,
+            frame_energy_gate_rms: None,
+            max_event_points: None,
+            max_event_points_per_frame: None,
+            hash_width: HashWidth::default(),
         }
     }
 }
 
+/// Smallest accepted FFT size (`audio_block_size`)
+pub const MIN_FFT_SIZE: usize = 256;
+
+/// Largest accepted FFT size; larger blocks mean multi-MB FFTs per frame
+pub const MAX_FFT_SIZE: usize = 65536;
+
+/// Largest accepted `ffmpeg_timeout_s` (one day)
+pub const MAX_FFMPEG_TIMEOUT_S: f64 = 86_400.0;
+
+fn default_true() -> bool {
+    true
+}
+
 impl PanakoConfig {
     /// Validate configuration parameters
     pub fn validate(&self) -> anyhow::Result<()> {
@@ -89,9 +367,351 @@ impl PanakoConfig {
         if self.min_freq >= self.max_freq {
             anyhow::bail!("min_freq must be < max_freq");
         }
+        if self.max_freq > self.sample_rate as f32 / 2.0 {
+            anyhow::bail!(
+                "max_freq {} Hz exceeds the Nyquist frequency of {} Hz",
+                self.max_freq,
+                self.sample_rate as f32 / 2.0
+            );
+        }
+        if !(MIN_FFT_SIZE..=MAX_FFT_SIZE).contains(&self.audio_block_size) {
+            anyhow::bail!(
+                "audio_block_size (FFT size) must be between {} and {} samples, got {}",
+                MIN_FFT_SIZE,
+                MAX_FFT_SIZE,
+                self.audio_block_size
+            );
+        }
         if self.bands_per_octave == 0 {
             anyhow::bail!("bands_per_octave must be > 0");
         }
+        if self.pitch_normalized && self.ref_freq <= 0.0 {
+            anyhow::bail!("ref_freq must be > 0 when pitch_normalized is enabled");
+        }
+        if let Some(fraction) = self.max_hash_ref_fraction {
+            if !(fraction > 0.0 && fraction <= 1.0) {
+                anyhow::bail!("max_hash_ref_fraction must be in (0, 1]");
+            }
+        }
+        if let Some(coverage) = self.min_ref_coverage {
+            if !(0.0..=1.0).contains(&coverage) {
+                anyhow::bail!("min_ref_coverage must be in [0, 1]");
+            }
+        }
+        if self.speed_altered_tolerance.is_some_and(|tolerance| tolerance.is_nan() || tolerance < 0.0) {
+            anyhow::bail!("speed_altered_tolerance must be >= 0 when set");
+        }
+        if self.max_detection_gap_s.is_some_and(|gap| gap.is_nan() || gap < 0.0) {
+            anyhow::bail!("max_detection_gap_s must be >= 0 when set");
+        }
+        if self.detection_debounce_s.is_some_and(|gap| gap.is_nan() || gap < 0.0) {
+            anyhow::bail!("detection_debounce_s must be >= 0 when set");
+        }
+        if let Some(step) = self.index_time_step {
+            if step <= 0 || step - 1 > self.query_range {
+                anyhow::bail!(
+                    "index_time_step must be in [1, query_range + 1] = [1, {}]",
+                    self.query_range + 1
+                );
+            }
+        }
+        if self.parallel_transform_chunk_s.is_some_and(|chunk| chunk.is_nan() || chunk <= 0.0) {
+            anyhow::bail!("parallel_transform_chunk_s must be > 0 when set");
+        }
+        if self.whitening_window_s.is_some_and(|window| window.is_nan() || window <= 0.0) {
+            anyhow::bail!("whitening_window_s must be > 0 when set");
+        }
+        if self.ffmpeg_timeout_s.is_some_and(|timeout| !(timeout > 0.0 && timeout <= MAX_FFMPEG_TIMEOUT_S)) {
+            anyhow::bail!("ffmpeg_timeout_s must be in (0, {}] when set", MAX_FFMPEG_TIMEOUT_S);
+        }
+        if self.decode_cache_dir.as_deref() == Some("") {
+            anyhow::bail!("decode_cache_dir must not be empty when set");
+        }
+        if self.reference_media_root.as_deref() == Some("") {
+            anyhow::bail!("reference_media_root must not be empty when set");
+        }
+        if self.frame_energy_gate_rms.is_some_and(|rms| !rms.is_finite() || rms <= 0.0) {
+            anyhow::bail!("frame_energy_gate_rms must be > 0 when set");
+        }
+        if self.max_event_points == Some(0) {
+            anyhow::bail!("max_event_points must be > 0 when set");
+        }
+        if self.max_event_points_per_frame == Some(0) {
+            anyhow::bail!("max_event_points_per_frame must be > 0 when set");
+        }
+        if self.bloom_filter_bits_per_hash == Some(0) {
+            anyhow::bail!("bloom_filter_bits_per_hash must be > 0 when set");
+        }
+        if self.max_candidates_per_hash == Some(0) {
+            anyhow::bail!("max_candidates_per_hash must be > 0 when set");
+        }
+        if self.max_query_hash_repeats == Some(0) {
+            anyhow::bail!("max_query_hash_repeats must be > 0 when set");
+        }
+        if self.max_duration_ms == Some(0) {
+            anyhow::bail!("max_duration_ms must be > 0 when set");
+        }
+        if let Some(epsilon) = self.fuzzy_hash_epsilon {
+            if !(0.0..1.0).contains(&epsilon) {
+                anyhow::bail!("fuzzy_hash_epsilon must be in [0, 1)");
+            }
+        }
+        if self.magnitude_bucket_db.is_some_and(|bucket| bucket.is_nan() || bucket <= 0.0) {
+            anyhow::bail!("magnitude_bucket_db must be > 0 when set");
+        }
+        if let Some(coefficient) = self.pre_emphasis {
+            if !(0.0..1.0).contains(&coefficient) {
+                anyhow::bail!("pre_emphasis must be in [0, 1)");
+            }
+        }
+        if self.freq_max_filter_cents.is_some_and(|cents| !(cents.is_finite() && cents > 0.0)) {
+            anyhow::bail!("freq_max_filter_cents must be finite and > 0 when set");
+        }
+        if self.time_max_filter_s.is_some_and(|seconds| !(seconds.is_finite() && seconds > 0.0)) {
+            anyhow::bail!("time_max_filter_s must be finite and > 0 when set");
+        }
         Ok(())
     }
+    
+    /// Parameters that must agree between references and queries, for storage
+    /// in fingerprint file metadata
+    pub fn fingerprint_params(&self) -> FingerprintParams {
+        FingerprintParams {
+            sample_rate: self.sample_rate,
+            audio_block_size: self.audio_block_size,
+            time_resolution: self.time_resolution,
+            min_freq: self.min_freq,
+            max_freq: self.max_freq,
+            bands_per_octave: self.bands_per_octave,
+            pitch_normalized: self.pitch_normalized,
+            ref_freq: self.ref_freq,
+            freq_max_filter_bins: self.freq_max_filter_bins(),
+            time_max_filter_frames: self.time_max_filter_frames(),
+            fp_min_freq_dist: self.fp_min_freq_dist,
+            fp_max_freq_dist: self.fp_max_freq_dist,
+            fp_min_time_dist: self.fp_min_time_dist,
+            fp_max_time_dist: self.fp_max_time_dist,
+            pre_emphasis: self.pre_emphasis,
+            fuzzy_hash_epsilon: self.fuzzy_hash_epsilon,
+            magnitude_bucket_db: self.magnitude_bucket_db,
+            whitening_window_frames: self.whitening_window_frames(),
+            hash_bits: (self.hash_width != HashWidth::Bits64).then(|| self.hash_width.bits()),
+        }
+    }
+    
+    /// This configuration with the fingerprinting parameters of a stored file
+    ///
+    /// Used to query or regenerate fingerprints exactly as a file was made.
+    /// Filter sizes are applied in bins and frames, so `freq_max_filter_cents`
+    /// and `time_max_filter_s` are cleared. Matching parameters are kept.
+    pub fn with_fingerprint_params(self, params: &FingerprintParams) -> Self {
+        Self {
+            sample_rate: params.sample_rate,
+            audio_block_size: params.audio_block_size,
+            time_resolution: params.time_resolution,
+            min_freq: params.min_freq,
+            max_freq: params.max_freq,
+            bands_per_octave: params.bands_per_octave,
+            pitch_normalized: params.pitch_normalized,
+            ref_freq: params.ref_freq,
+            freq_max_filter_size: params.freq_max_filter_bins,
+            time_max_filter_size: params.time_max_filter_frames,
+            freq_max_filter_cents: None,
+            time_max_filter_s: None,
+            fp_min_freq_dist: params.fp_min_freq_dist,
+            fp_max_freq_dist: params.fp_max_freq_dist,
+            fp_min_time_dist: params.fp_min_time_dist,
+            fp_max_time_dist: params.fp_max_time_dist,
+            pre_emphasis: params.pre_emphasis,
+            fuzzy_hash_epsilon: params.fuzzy_hash_epsilon,
+            magnitude_bucket_db: params.magnitude_bucket_db,
+            whitening_window_s: params
+                .whitening_window_frames
+                .map(|frames| frames as f64 * params.time_resolution as f64 / params.sample_rate as f64),
+            hash_width: params.hash_bits.and_then(HashWidth::from_bits).unwrap_or_default(),
+            ..self
+        }
+    }
+    
+    /// Duration of one transform frame in seconds (`time_resolution` samples)
+    pub fn frame_duration_s(&self) -> f64 {
+        self.time_resolution as f64 / self.sample_rate as f64
+    }
+    
+    /// Same configuration at another sample rate
+    ///
+    /// `max_freq` is scaled with the rate so the analysed band keeps the same
+    /// position relative to Nyquist (7040 Hz at 16 kHz becomes ~9.7 kHz at
+    /// 22.05 kHz). References and queries must use the same rate.
+    pub fn with_sample_rate(mut self, sample_rate: u32) -> Self {
+        self.max_freq = self.max_freq * sample_rate as f32 / self.sample_rate as f32;
+        self.sample_rate = sample_rate;
+        self
+    }
+    
+    /// Max filter size in frequency bins, from cents if specified
+    pub fn freq_max_filter_bins(&self) -> usize {
+        match self.freq_max_filter_cents {
+            Some(cents) => {
+                let bins = (cents as f64 / 1200.0 * self.bands_per_octave as f64).round();
+                (bins as usize).max(1)
+            }
+            None => self.freq_max_filter_size,
+        }
+    }
+    
+    /// Max filter size in frames, from seconds if specified
+    pub fn time_max_filter_frames(&self) -> usize {
+        match self.time_max_filter_s {
+            Some(seconds) => {
+                let frames = (seconds * self.sample_rate as f64 / self.time_resolution as f64).round();
+                (frames as usize).max(1)
+            }
+            None => self.time_max_filter_size,
+        }
+    }
+    
+    /// Running median window of spectral whitening in frames, if enabled
+    pub fn whitening_window_frames(&self) -> Option<usize> {
+        self.whitening_window_s
+            .map(|seconds| ((seconds / self.frame_duration_s()).round() as usize).max(1))
+    }
+    
+    /// Limits for the FFmpeg subprocess used when decoding MPEG-TS
+    ///
+    /// A timeout that is not a valid duration (rejected by `validate`) is
+    /// ignored rather than panicking.
+    pub fn ffmpeg_options(&self) -> crate::audio::FfmpegOptions {
+        crate::audio::FfmpegOptions {
+            timeout: self
+                .ffmpeg_timeout_s
+                .and_then(|timeout| std::time::Duration::try_from_secs_f64(timeout).ok()),
+            retries: self.ffmpeg_retries,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default_config_is_valid() {
+        PanakoConfig::default().validate().unwrap();
+    }
+
+    #[test]
+    fn test_with_sample_rate() {
+        let config = PanakoConfig::default().with_sample_rate(22050);
+        assert_eq!(config.sample_rate, 22050);
+        assert!((config.max_freq - 9702.0).abs() < 1.0);
+        assert!((config.frame_duration_s() - 128.0 / 22050.0).abs() < 1e-12);
+        config.validate().unwrap();
+        
+        // Analysing above Nyquist is rejected
+        let config = PanakoConfig {
+            sample_rate: 8000,
+            ..PanakoConfig::default()
+        };
+        assert!(config.validate().unwrap_err().to_string().contains("Nyquist"));
+    }
+
+    #[test]
+    fn test_fingerprint_params_round_trip_through_file() {
+        use panako_fp::FpJsonFile;
+
+        let config = PanakoConfig {
+            bands_per_octave: 36,
+            freq_max_filter_cents: Some(1454.0),
+            pre_emphasis: Some(0.95),
+            magnitude_bucket_db: Some(3.0),
+            hash_width: HashWidth::Bits32,
+            ..PanakoConfig::default()
+        }
+        .with_sample_rate(22050);
+
+        let mut fp_file = FpJsonFile::new("/audio/song.wav".to_string(), "song".to_string(), 22050, 5000, 1);
+        fp_file.metadata.fingerprint_params = Some(config.fingerprint_params());
+
+        let dir = std::env::temp_dir().join(format!("panako_config_params_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        fp_file.save(&dir.join("song.json")).unwrap();
+        fp_file.save_bson(&dir.join("song.bson")).unwrap();
+        let json = std::fs::read_to_string(dir.join("song.json")).unwrap();
+        let from_json = FpJsonFile::load_auto(&dir.join("song.json")).unwrap();
+        let from_bson = FpJsonFile::load_auto(&dir.join("song.bson")).unwrap();
+        std::fs::remove_dir_all(&dir).ok();
+
+        assert!(json.contains("\"fingerprint_params\""));
+        assert!(json.contains("\"bands_per_octave\": 36"));
+        for loaded in [from_json, from_bson] {
+            let params = loaded.metadata.fingerprint_params.unwrap();
+            assert_eq!(params, config.fingerprint_params());
+
+            // The stored parameters reproduce the generating configuration
+            let rebuilt = PanakoConfig::default().with_fingerprint_params(&params);
+            rebuilt.validate().unwrap();
+            assert_eq!(rebuilt.fingerprint_params(), config.fingerprint_params());
+            assert_eq!(rebuilt.freq_max_filter_bins(), config.freq_max_filter_bins());
+            assert_eq!(rebuilt.hash_width, HashWidth::Bits32);
+        }
+        assert!(!PanakoConfig::default().fingerprint_params().differences(&config.fingerprint_params()).is_empty());
+    }
+
+    #[test]
+    fn test_out_of_range_block_size_rejected() {
+        for audio_block_size in [0, 128, 1 << 20] {
+            let config = PanakoConfig {
+                audio_block_size,
+                ..PanakoConfig::default()
+            };
+            let message = config.validate().unwrap_err().to_string();
+            assert!(message.contains("audio_block_size"), "{}", message);
+            assert!(message.contains(&audio_block_size.to_string()), "{}", message);
+        }
+
+        for audio_block_size in [MIN_FFT_SIZE, MAX_FFT_SIZE] {
+            let config = PanakoConfig {
+                audio_block_size,
+                ..PanakoConfig::default()
+            };
+            config.validate().unwrap();
+        }
+    }
+
+    #[test]
+    fn test_non_finite_filter_extents_rejected() {
+        for value in [0.0, -1.0, f64::NAN, f64::INFINITY] {
+            let cents = PanakoConfig {
+                freq_max_filter_cents: Some(value as f32),
+                ..PanakoConfig::default()
+            };
+            assert!(cents.validate().unwrap_err().to_string().contains("freq_max_filter_cents"), "{}", value);
+            let seconds = PanakoConfig {
+                time_max_filter_s: Some(value),
+                ..PanakoConfig::default()
+            };
+            assert!(seconds.validate().unwrap_err().to_string().contains("time_max_filter_s"), "{}", value);
+        }
+    }
+
+    #[test]
+    fn test_out_of_range_ffmpeg_timeout_rejected() {
+        for timeout in [0.0, -1.0, f64::NAN, f64::INFINITY, 1e20] {
+            let config = PanakoConfig {
+                ffmpeg_timeout_s: Some(timeout),
+                ..PanakoConfig::default()
+            };
+            assert!(config.validate().is_err(), "{}", timeout);
+            // Never panics, even unvalidated
+            let _ = config.ffmpeg_options();
+        }
+
+        let config = PanakoConfig {
+            ffmpeg_timeout_s: Some(MAX_FFMPEG_TIMEOUT_S),
+            ..PanakoConfig::default()
+        };
+        config.validate().unwrap();
+        assert_eq!(config.ffmpeg_options().timeout, Some(std::time::Duration::from_secs(86_400)));
+    }
 }
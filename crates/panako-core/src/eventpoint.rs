@@ -24,58 +24,113 @@ impl EventPoint {
     }
 }
 
+/// Keep the `max` highest-magnitude points, in their original order
+///
+/// Equal magnitudes are decided by time, then frequency, so the result does
+/// not depend on the order of `points`. Returns the number of points dropped.
+fn keep_strongest(points: &mut Vec<EventPoint>, max: usize) -> usize {
+    if points.len() <= max {
+        return 0;
+    }
+    let mut order: Vec<usize> = (0..points.len()).collect();
+    let rank = |&a: &usize, &b: &usize| {
+        let (a, b) = (&points[a], &points[b]);
+        b.m.total_cmp(&a.m).then(a.t.cmp(&b.t)).then(a.f.cmp(&b.f))
+    };
+    order.select_nth_unstable_by(max, rank);
+    let mut keep = vec![false; points.len()];
+    for &i in &order[..max] {
+        keep[i] = true;
+    }
+    let dropped = points.len() - max;
+    let mut index = 0;
+    points.retain(|_| {
+        index += 1;
+        keep[index - 1]
+    });
+    dropped
+}
+
 /// Event point extractor
 pub struct EventPointExtractor {
     freq_filter_size: usize,
     time_filter_size: usize,
+    max_points: Option<usize>,
+    max_points_per_frame: Option<usize>,
 }
 
 impl EventPointExtractor {
     pub fn new(config: &PanakoConfig) -> Self {
         Self {
-            freq_filter_size: config.freq_max_filter_size,
-            time_filter_size: config.time_max_filter_size,
+            freq_filter_size: config.freq_max_filter_bins(),
+            time_filter_size: config.time_max_filter_frames(),
+            max_points: config.max_event_points,
+            max_points_per_frame: config.max_event_points_per_frame,
         }
     }
     
     /// Extract event points from spectrogram
+    ///
+    /// Fails on NaN or infinite magnitudes, which would make the max filter
+    /// and the fingerprint hashes nondeterministic; clean such spectrograms
+    /// with `Spectrogram::sanitize` first (`compute_transform` already does).
+    /// At most `max_event_points` (and `max_event_points_per_frame` per
+    /// frame) of the strongest points are returned.
     pub fn extract(&self, spectrogram: &Spectrogram) -> Result<Vec<EventPoint>> {
+        if !spectrogram.is_finite() {
+            anyhow::bail!("Spectrogram contains NaN or infinite magnitudes; sanitize it before extracting event points");
+        }
+        
         // Apply 2D max filtering
         let max_filtered = self.apply_2d_max_filter(spectrogram);
         
         // Find local maxima
-        let event_points = self.find_local_maxima(spectrogram, &max_filtered);
+        let mut event_points = self.find_local_maxima(spectrogram, &max_filtered);
+        
+        if let Some(max) = self.max_points {
+            let found = event_points.len();
+            if keep_strongest(&mut event_points, max) > 0 {
+                log::warn!(
+                    "Found {} event points, keeping the {} strongest (max_event_points)",
+                    found,
+                    max
+                );
+            }
+        }
         
         Ok(event_points)
     }
     
     /// Apply 2D max filter (frequency then time)
+    #[allow(clippy::needless_range_loop)]
     fn apply_2d_max_filter(&self, spectrogram: &Spectrogram) -> Vec<Vec<f32>> {
         let num_frames = spectrogram.num_frames;
         let num_bins = spectrogram.num_bins;
         
-        // First, filter in frequency dimension
-        let mut freq_filtered = vec![vec![0.0; num_bins]; num_frames];
-        
-        for t in 0..num_frames {
-            for f in 0..num_bins {
-                let f_start = f.saturating_sub(self.freq_filter_size / 2);
-                let f_end = (f + self.freq_filter_size / 2 + 1).min(num_bins);
-                
-                let max_val = (f_start..f_end)
-                    .map(|fi| spectrogram.magnitudes[t][fi])
-                    .fold(f32::NEG_INFINITY, f32::max);
-                
-                freq_filtered[t][f] = max_val;
-            }
-        }
+        // First, filter in frequency dimension (gated frames are all zero)
+        let freq_filtered: Vec<Vec<f32>> = spectrogram
+            .magnitudes
+            .iter()
+            .enumerate()
+            .map(|(t, frame)| {
+                if spectrogram.is_gated(t) {
+                    vec![0.0; num_bins]
+                } else {
+                    self.freq_max_filter(frame)
+                }
+            })
+            .collect();
         
         // Then, filter in time dimension
         let mut time_filtered = vec![vec![0.0; num_bins]; num_frames];
         
         for t in 0..num_frames {
-            let t_start = t.saturating_sub(self.time_filter_size / 2);
-            let t_end = (t + self.time_filter_size / 2 + 1).min(num_frames);
+            // Never searched for event points
+            if spectrogram.is_gated(t) {
+                continue;
+            }
+            let t_start = t.saturating_sub(self.time_half_window());
+            let t_end = (t + self.time_half_window() + 1).min(num_frames);
             
             for f in 0..num_bins {
                 let max_val = (t_start..t_end)
@@ -89,30 +144,70 @@ impl EventPointExtractor {
         time_filtered
     }
     
+    /// Max filter of one frame along the frequency axis
+    pub(crate) fn freq_max_filter(&self, frame: &[f32]) -> Vec<f32> {
+        let num_bins = frame.len();
+        (0..num_bins)
+            .map(|f| {
+                let f_start = f.saturating_sub(self.freq_filter_size / 2);
+                let f_end = (f + self.freq_filter_size / 2 + 1).min(num_bins);
+                frame[f_start..f_end].iter().copied().fold(f32::NEG_INFINITY, f32::max)
+            })
+            .collect()
+    }
+    
+    /// Frames on each side of a frame covered by the time max filter
+    pub(crate) fn time_half_window(&self) -> usize {
+        self.time_filter_size / 2
+    }
+    
+    /// Apply `max_event_points_per_frame` to the event points of one frame,
+    /// returning the number dropped
+    pub(crate) fn cap_frame(&self, frame_points: &mut Vec<EventPoint>) -> usize {
+        self.max_points_per_frame.map_or(0, |max| keep_strongest(frame_points, max))
+    }
+    
+    /// Whether a magnitude equal to its max-filtered value is an event point
+    pub(crate) fn is_local_maximum(original: f32, filtered: f32) -> bool {
+        original > 0.0 && (original - filtered).abs() < 1e-6
+    }
+    
     /// Find local maxima by comparing original with max-filtered
+    #[allow(clippy::needless_range_loop)]
     fn find_local_maxima(
         &self,
         spectrogram: &Spectrogram,
         max_filtered: &[Vec<f32>],
     ) -> Vec<EventPoint> {
         let mut event_points = Vec::new();
+        let mut frame_points = Vec::new();
+        let mut dropped = 0;
         
         for t in 0..spectrogram.num_frames {
+            if spectrogram.is_gated(t) {
+                continue;
+            }
+            frame_points.clear();
             for f in 0..spectrogram.num_bins {
                 let original = spectrogram.magnitudes[t][f];
                 let filtered = max_filtered[t][f];
                 
                 // If original equals max-filtered, it's a local maximum
-                if original > 0.0 && (original - filtered).abs() < 1e-6 {
-                    event_points.push(EventPoint::new(
+                if Self::is_local_maximum(original, filtered) {
+                    frame_points.push(EventPoint::new(
                         t as i32,
                         f as i16,
                         original,
                     ));
                 }
             }
+            dropped += self.cap_frame(&mut frame_points);
+            event_points.extend_from_slice(&frame_points);
         }
         
+        if dropped > 0 {
+            log::info!("Dropped {} event points over max_event_points_per_frame", dropped);
+        }
         event_points
     }
 }
@@ -128,4 +223,86 @@ mod tests {
         assert_eq!(ep.f, 50);
         assert!((ep.m - 0.8).abs() < 1e-6);
     }
+    
+    #[test]
+    fn test_filter_sizes_from_cents_and_seconds() {
+        let config = PanakoConfig {
+            freq_max_filter_cents: Some(1454.0),
+            time_max_filter_s: Some(0.2),
+            ..PanakoConfig::default()
+        };
+        let extractor = EventPointExtractor::new(&config);
+        assert_eq!(extractor.freq_filter_size, 103);
+        assert_eq!(extractor.time_filter_size, 25);
+        
+        // The same physical extent scales with the transform resolution
+        let fine = PanakoConfig {
+            bands_per_octave: 170,
+            time_resolution: 64,
+            ..config
+        };
+        let extractor = EventPointExtractor::new(&fine);
+        assert_eq!(extractor.freq_filter_size, 206);
+        assert_eq!(extractor.time_filter_size, 50);
+    }
+    
+    #[test]
+    fn test_event_point_caps_keep_strongest() {
+        // With 1x1 filters every positive magnitude is a local maximum
+        let num_frames = 50;
+        let num_bins = 40;
+        let magnitudes: Vec<Vec<f32>> = (0..num_frames)
+            .map(|t| (0..num_bins).map(|f| (((t * num_bins + f) * 7919) % 2000 + 1) as f32).collect())
+            .collect();
+        let spectrogram = Spectrogram { magnitudes, num_frames, num_bins, gated_frames: Vec::new() };
+        let extractor = |max_points, max_points_per_frame| EventPointExtractor {
+            freq_filter_size: 1,
+            time_filter_size: 1,
+            max_points,
+            max_points_per_frame,
+        };
+        assert_eq!(extractor(None, None).extract(&spectrogram).unwrap().len(), 2000);
+        
+        let capped = extractor(Some(100), None).extract(&spectrogram).unwrap();
+        assert_eq!(capped.len(), 100);
+        // Magnitudes are 1..=2000, each once: the strongest 100 are above 1900
+        assert!(capped.iter().all(|ep| ep.m > 1900.0));
+        assert!(capped.windows(2).all(|w| (w[0].t, w[0].f) < (w[1].t, w[1].f)));
+        
+        let per_frame = extractor(None, Some(3)).extract(&spectrogram).unwrap();
+        assert_eq!(per_frame.len(), 3 * num_frames);
+        for t in 0..num_frames {
+            let mut frame = spectrogram.magnitudes[t].clone();
+            frame.sort_by(|a, b| b.total_cmp(a));
+            let kept: Vec<f32> = per_frame.iter().filter(|ep| ep.t == t as i32).map(|ep| ep.m).collect();
+            assert_eq!(kept.len(), 3);
+            assert!(kept.iter().all(|&m| m >= frame[2]));
+        }
+        
+        let both = extractor(Some(20), Some(3)).extract(&spectrogram).unwrap();
+        assert_eq!(both.len(), 20);
+    }
+    
+    #[test]
+    fn test_non_finite_spectrogram() {
+        let config = PanakoConfig::default();
+        let num_frames = 200;
+        let num_bins = 300;
+        let mut magnitudes: Vec<Vec<f32>> = (0..num_frames)
+            .map(|t| (0..num_bins).map(|f| ((t * 31 + f * 17) % 97) as f32 / 97.0).collect())
+            .collect();
+        magnitudes[50][120] = f32::NAN;
+        magnitudes[120][40] = f32::INFINITY;
+        let mut spectrogram = Spectrogram { magnitudes, num_frames, num_bins, gated_frames: Vec::new() };
+        
+        let extractor = EventPointExtractor::new(&config);
+        let err = extractor.extract(&spectrogram).unwrap_err();
+        assert!(err.to_string().contains("NaN"));
+        
+        assert_eq!(spectrogram.sanitize(), 2);
+        let first = extractor.extract(&spectrogram).unwrap();
+        let second = extractor.extract(&spectrogram).unwrap();
+        assert_eq!(first, second);
+        assert!(first.iter().all(|e| e.m.is_finite()));
+    }
 }
@@ -1,9 +1,9 @@
 //! Fingerprint generation and hashing
 //!
 //! Implements the Panako fingerprint algorithm that connects 3 event points
-//! and computes a 64-bit hash.
+//! and computes a 64-bit (or, with `HashWidth::Bits32`, 32-bit) hash.
 
-use crate::config::PanakoConfig;
+use crate::config::{HashWidth, PanakoConfig};
 use crate::eventpoint::EventPoint;
 use anyhow::Result;
 use serde::{Deserialize, Serialize};
@@ -11,7 +11,7 @@ use serde::{Deserialize, Serialize};
 /// A fingerprint connects three event points
 #[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
 pub struct Fingerprint {
-    /// 64-bit hash of the fingerprint
+    /// Hash of the fingerprint (fits in 32 bits with `HashWidth::Bits32`)
     pub hash: u64,
     /// Time index of first event point
     pub t1: i32,
@@ -32,6 +32,25 @@ pub struct Fingerprint {
 impl Fingerprint {
     /// Create fingerprint from three event points
     pub fn new(e1: &EventPoint, e2: &EventPoint, e3: &EventPoint) -> Self {
+        Self::with_magnitude_buckets(e1, e2, e3, None)
+    }
+    
+    /// Create fingerprint, comparing magnitudes in buckets of `bucket_db` dB
+    ///
+    /// Magnitudes falling in the same bucket compare as equal (see
+    /// `PanakoConfig::magnitude_bucket_db`); `None` compares them exactly.
+    pub fn with_magnitude_buckets(e1: &EventPoint, e2: &EventPoint, e3: &EventPoint, bucket_db: Option<f32>) -> Self {
+        Self::with_hash_options(e1, e2, e3, bucket_db, HashWidth::Bits64)
+    }
+    
+    /// Create fingerprint with magnitude buckets and a hash of `width`
+    pub fn with_hash_options(
+        e1: &EventPoint,
+        e2: &EventPoint,
+        e3: &EventPoint,
+        bucket_db: Option<f32>,
+        width: HashWidth,
+    ) -> Self {
         let mut fp = Self {
             hash: 0,
             t1: e1.t,
@@ -46,19 +65,56 @@ impl Fingerprint {
         };
         
         // Compute hash
-        fp.hash = fp.compute_hash();
+        fp.hash = fp.compute_hash(bucket_db, width);
         fp
     }
     
-    /// Compute 64-bit hash matching Java implementation
-    /// This is the exact algorithm from PanakoFingerprint.java
-    fn compute_hash(&self) -> u64 {
+    /// Alternative hashes for magnitude comparisons near the decision boundary
+    ///
+    /// A magnitude comparison bit is considered ambiguous when the two
+    /// magnitudes differ by at most `epsilon` relative to the larger one. Returns
+    /// the hashes obtained by flipping every combination of ambiguous bits (up
+    /// to 7 variants), excluding the fingerprint's own hash.
+    pub fn hash_variants(&self, epsilon: f32) -> Vec<u64> {
+        let near = |a: f32, b: f32| (a - b).abs() <= epsilon * a.abs().max(b.abs());
+        
+        // Bits 9-11 hold m1 > m2, m2 > m3 and m3 > m1 (in both hash widths)
+        let ambiguous_bits: Vec<u64> = [
+            (near(self.m1, self.m2), 9),
+            (near(self.m2, self.m3), 10),
+            (near(self.m3, self.m1), 11),
+        ]
+        .iter()
+        .filter(|(ambiguous, _)| *ambiguous)
+        .map(|&(_, bit)| 1u64 << bit)
+        .collect();
+        
+        (1..1usize << ambiguous_bits.len())
+            .map(|combination| {
+                ambiguous_bits
+                    .iter()
+                    .enumerate()
+                    .filter(|(i, _)| combination & (1 << i) != 0)
+                    .fold(self.hash, |hash, (_, &mask)| hash ^ mask)
+            })
+            .collect()
+    }
+    
+    /// Compute hash matching Java implementation
+    /// This is the exact algorithm from PanakoFingerprint.java; the 32-bit
+    /// width keeps the same fields with coarser frequency precision
+    #[allow(clippy::identity_op)]
+    fn compute_hash(&self, bucket_db: Option<f32>, width: HashWidth) -> u64 {
         let f1 = self.f1 as i32;
         let f2 = self.f2 as i32;
         let f3 = self.f3 as i32;
-        let m1 = self.m1;
-        let m2 = self.m2;
-        let m3 = self.m3;
+        let quantize = |m: f32| match bucket_db {
+            Some(bucket) => (20.0 * m.abs().max(f32::MIN_POSITIVE).log10() / bucket).floor(),
+            None => m,
+        };
+        let m1 = quantize(self.m1);
+        let m2 = quantize(self.m2);
+        let m3 = quantize(self.m3);
         let t1 = self.t1;
         let t2 = self.t2;
         let t3 = self.t3;
@@ -75,6 +131,28 @@ impl Fingerprint {
         let dt1t2_larger_than_t3t2 = if (t2 - t1) > (t3 - t2) { 1u64 } else { 0u64 };
         let df1f2_larger_than_f3f2 = if (f2 - f1).abs() > (f3 - f2).abs() { 1u64 } else { 0u64 };
         
+        // Time ratio (6 bits)
+        let ratio_t = ((t2 - t1) as f32 / (t3 - t1) as f32 * 64.0) as u64 & 0x3F;
+        
+        if width == HashWidth::Bits32 {
+            // Frequency range (7 bits) and differences (5 bits each): 31 bits
+            let f1_range = ((f1 >> 6) & 0x7F) as u64;
+            let df2f1 = (((f2 - f1).abs() >> 3) & 0x1F) as u64;
+            let df3f2 = (((f3 - f2).abs() >> 3) & 0x1F) as u64;
+            return (ratio_t                    & 0x3F)  << 0  |
+                (f1_larger_than_f2          & 0x1)   << 6  |
+                (f2_larger_than_f3          & 0x1)   << 7  |
+                (f3_larger_than_f1          & 0x1)   << 8  |
+                (m1_larger_than_m2          & 0x1)   << 9  |
+                (m2_larger_than_m3          & 0x1)   << 10 |
+                (m3_larger_than_m1          & 0x1)   << 11 |
+                (dt1t2_larger_than_t3t2     & 0x1)   << 12 |
+                (df1f2_larger_than_f3f2     & 0x1)   << 13 |
+                (f1_range                   & 0x7F)  << 14 |
+                (df2f1                      & 0x1F)  << 21 |
+                (df3f2                      & 0x1F)  << 26;
+        }
+        
         // Frequency range (9 bits -> 8 bits)
         let f1_range = ((f1 >> 5) & 0xFF) as u64;
         
@@ -82,25 +160,19 @@ impl Fingerprint {
         let df2f1 = (((f2 - f1).abs() >> 2) & 0x3F) as u64;
         let df3f2 = (((f3 - f2).abs() >> 2) & 0x3F) as u64;
         
-        // Time ratio (6 bits)
-        let ratio_t = ((t2 - t1) as f32 / (t3 - t1) as f32 * 64.0) as u64 & 0x3F;
-        
         // Combine into 64-bit hash
-        let hash = 
-            (ratio_t                    & 0x3F)  << 0  |
-            (f1_larger_than_f2          & 0x1)   << 6  |
-            (f2_larger_than_f3          & 0x1)   << 7  |
-            (f3_larger_than_f1          & 0x1)   << 8  |
-            (m1_larger_than_m2          & 0x1)   << 9  |
-            (m2_larger_than_m3          & 0x1)   << 10 |
-            (m3_larger_than_m1          & 0x1)   << 11 |
-            (dt1t2_larger_than_t3t2     & 0x1)   << 12 |
-            (df1f2_larger_than_f3f2     & 0x1)   << 13 |
-            (f1_range                   & 0xFF)  << 14 |
-            (df2f1                      & 0x3F)  << 22 |
-            (df3f2                      & 0x3F)  << 28;
-        
-        hash
+        (ratio_t                    & 0x3F)  << 0  |
+        (f1_larger_than_f2          & 0x1)   << 6  |
+        (f2_larger_than_f3          & 0x1)   << 7  |
+        (f3_larger_than_f1          & 0x1)   << 8  |
+        (m1_larger_than_m2          & 0x1)   << 9  |
+        (m2_larger_than_m3          & 0x1)   << 10 |
+        (m3_larger_than_m1          & 0x1)   << 11 |
+        (dt1t2_larger_than_t3t2     & 0x1)   << 12 |
+        (df1f2_larger_than_f3f2     & 0x1)   << 13 |
+        (f1_range                   & 0xFF)  << 14 |
+        (df2f1                      & 0x3F)  << 22 |
+        (df3f2                      & 0x3F)  << 28
     }
 }
 
@@ -110,6 +182,9 @@ pub struct FingerprintGenerator {
     max_freq_dist: i16,
     min_time_dist: i32,
     max_time_dist: i32,
+    fuzzy_hash_epsilon: Option<f32>,
+    magnitude_bucket_db: Option<f32>,
+    hash_width: HashWidth,
 }
 
 impl FingerprintGenerator {
@@ -119,6 +194,9 @@ impl FingerprintGenerator {
             max_freq_dist: config.fp_max_freq_dist,
             min_time_dist: config.fp_min_time_dist,
             max_time_dist: config.fp_max_time_dist,
+            fuzzy_hash_epsilon: config.fuzzy_hash_epsilon,
+            magnitude_bucket_db: config.magnitude_bucket_db,
+            hash_width: config.hash_width,
         }
     }
     
@@ -128,48 +206,67 @@ impl FingerprintGenerator {
         
         // For each event point, find valid pairs to form fingerprints
         for i in 0..event_points.len() {
-            let e1 = &event_points[i];
+            self.generate_from(event_points, i, &mut fingerprints);
+        }
+        
+        // Sort by t1 for deterministic output
+        fingerprints.sort_by_key(|fp| fp.t1);
+        
+        Ok(fingerprints)
+    }
+    
+    /// Append the fingerprints whose first event point is `event_points[i]`
+    ///
+    /// Second and third points are taken from later entries, so the result is
+    /// complete once all points within two `fp_max_time_dist` of it are known.
+    pub(crate) fn generate_from(&self, event_points: &[EventPoint], i: usize, fingerprints: &mut Vec<Fingerprint>) {
+        let e1 = &event_points[i];
+        
+        // Find second event point
+        for j in (i + 1)..event_points.len() {
+            let e2 = &event_points[j];
             
-            // Find second event point
-            for j in (i + 1)..event_points.len() {
-                let e2 = &event_points[j];
-                
-                // Check constraints for e1-e2
-                let dt12 = e2.t - e1.t;
-                let df12 = (e2.f - e1.f).abs();
+            // Check constraints for e1-e2
+            let dt12 = e2.t - e1.t;
+            let df12 = (e2.f - e1.f).abs();
+            
+            if dt12 < self.min_time_dist || dt12 > self.max_time_dist {
+                continue;
+            }
+            if df12 < self.min_freq_dist || df12 > self.max_freq_dist {
+                continue;
+            }
+            
+            // Find third event point
+            for e3 in &event_points[(j + 1)..] {
+                // Check constraints for e2-e3
+                let dt23 = e3.t - e2.t;
+                let df23 = (e3.f - e2.f).abs();
                 
-                if dt12 < self.min_time_dist || dt12 > self.max_time_dist {
+                if dt23 < self.min_time_dist || dt23 > self.max_time_dist {
                     continue;
                 }
-                if df12 < self.min_freq_dist || df12 > self.max_freq_dist {
+                if df23 < self.min_freq_dist || df23 > self.max_freq_dist {
                     continue;
                 }
                 
-                // Find third event point
-                for k in (j + 1)..event_points.len() {
-                    let e3 = &event_points[k];
-                    
-                    // Check constraints for e2-e3
-                    let dt23 = e3.t - e2.t;
-                    let df23 = (e3.f - e2.f).abs();
-                    
-                    if dt23 < self.min_time_dist || dt23 > self.max_time_dist {
-                        continue;
-                    }
-                    if df23 < self.min_freq_dist || df23 > self.max_freq_dist {
-                        continue;
+                // Create fingerprint
+                let fp = Fingerprint::with_hash_options(e1, e2, e3, self.magnitude_bucket_db, self.hash_width);
+                fingerprints.push(fp);
+                
+                // Also emit variants for near-equal magnitude comparisons
+                if let Some(epsilon) = self.fuzzy_hash_epsilon {
+                    for hash in fp.hash_variants(epsilon) {
+                        fingerprints.push(Fingerprint { hash, ..fp });
                     }
-                    
-                    // Create fingerprint
-                    fingerprints.push(Fingerprint::new(e1, e2, e3));
                 }
             }
         }
-        
-        // Sort by t1 for deterministic output
-        fingerprints.sort_by_key(|fp| fp.t1);
-        
-        Ok(fingerprints)
+    }
+    
+    /// Largest time distance between consecutive points of a fingerprint
+    pub(crate) fn max_time_dist(&self) -> i32 {
+        self.max_time_dist
     }
 }
 
@@ -192,4 +289,102 @@ mod tests {
         let fp2 = Fingerprint::new(&e1, &e2, &e3);
         assert_eq!(fp.hash, fp2.hash);
     }
+    
+    #[test]
+    fn test_fuzzy_hash_matches_borderline_triple() {
+        // m1 and m2 are nearly equal; noise flips their order between captures
+        let reference = [
+            EventPoint::new(0, 100, 0.500),
+            EventPoint::new(10, 120, 0.502),
+            EventPoint::new(20, 110, 0.9),
+        ];
+        let query = [
+            EventPoint::new(0, 100, 0.503),
+            EventPoint::new(10, 120, 0.501),
+            EventPoint::new(20, 110, 0.9),
+        ];
+        
+        let exact = FingerprintGenerator::new(&PanakoConfig::default());
+        let ref_hashes: Vec<u64> = exact.generate(&reference).unwrap().iter().map(|fp| fp.hash).collect();
+        let query_hashes: Vec<u64> = exact.generate(&query).unwrap().iter().map(|fp| fp.hash).collect();
+        assert!(query_hashes.iter().all(|h| !ref_hashes.contains(h)));
+        
+        let fuzzy = FingerprintGenerator::new(&PanakoConfig {
+            fuzzy_hash_epsilon: Some(0.02),
+            ..PanakoConfig::default()
+        });
+        let ref_hashes: Vec<u64> = fuzzy.generate(&reference).unwrap().iter().map(|fp| fp.hash).collect();
+        let query_fps = fuzzy.generate(&query).unwrap();
+        assert_eq!(query_fps.len(), 2);
+        assert!(query_fps.iter().any(|fp| ref_hashes.contains(&fp.hash)));
+        assert!(query_fps.iter().all(|fp| fp.t1 == 0 && fp.f1 == 100));
+    }
+    
+    #[test]
+    fn test_hash_variants_only_for_ambiguous_bits() {
+        let distinct = Fingerprint::new(
+            &EventPoint::new(0, 100, 0.2),
+            &EventPoint::new(10, 120, 0.5),
+            &EventPoint::new(20, 110, 0.9),
+        );
+        assert!(distinct.hash_variants(0.05).is_empty());
+        
+        // All three magnitudes near-equal: every combination of the 3 bits
+        let flat = Fingerprint::new(
+            &EventPoint::new(0, 100, 0.5),
+            &EventPoint::new(10, 120, 0.5),
+            &EventPoint::new(20, 110, 0.5),
+        );
+        let variants = flat.hash_variants(0.05);
+        assert_eq!(variants.len(), 7);
+        assert!(variants.iter().all(|&h| h != flat.hash && (h ^ flat.hash) & !(0b111 << 9) == 0));
+    }
+    
+    #[test]
+    fn test_magnitude_buckets_make_hashes_robust_to_noise() {
+        // Peaks of similar strength, as for a steady mix, and a copy with
+        // about 1% of magnitude noise
+        let mut seed: u32 = 7;
+        let mut next = || {
+            seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+            (seed >> 16) as f32 / 65536.0
+        };
+        let original: Vec<EventPoint> = (0..80)
+            .map(|i| EventPoint::new(i * 4, 100 + ((i * 37) % 60) as i16, 0.4 * (1.0 + 0.005 * next())))
+            .collect();
+        let noisy: Vec<EventPoint> = original
+            .iter()
+            .map(|e| EventPoint::new(e.t, e.f, e.m * (1.0 + 0.02 * (next() - 0.5))))
+            .collect();
+        
+        let matching_fraction = |config: &PanakoConfig| -> f64 {
+            // Same peak positions, so fingerprints pair up one to one
+            let generator = FingerprintGenerator::new(config);
+            let reference = generator.generate(&original).unwrap();
+            let query = generator.generate(&noisy).unwrap();
+            assert_eq!(reference.len(), query.len());
+            let same = reference.iter().zip(&query).filter(|(r, q)| r.hash == q.hash).count();
+            same as f64 / query.len() as f64
+        };
+        
+        let exact = matching_fraction(&PanakoConfig::default());
+        let bucketed = matching_fraction(&PanakoConfig {
+            magnitude_bucket_db: Some(3.0),
+            ..PanakoConfig::default()
+        });
+        assert!(bucketed > exact + 0.2, "bucketed {:.2} vs exact {:.2}", bucketed, exact);
+        assert!(bucketed > 0.9);
+    }
+    
+    #[test]
+    fn test_magnitude_buckets_keep_distinct_magnitudes_ordered() {
+        let e1 = EventPoint::new(0, 100, 0.1);
+        let e2 = EventPoint::new(10, 120, 0.5);
+        let e3 = EventPoint::new(20, 110, 0.9);
+        // 0.1, 0.5 and 0.9 are several 3 dB buckets apart
+        assert_eq!(
+            Fingerprint::with_magnitude_buckets(&e1, &e2, &e3, Some(3.0)).hash,
+            Fingerprint::new(&e1, &e2, &e3).hash
+        );
+    }
 }
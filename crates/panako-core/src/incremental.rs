@@ -0,0 +1,355 @@
+//! Incremental fingerprinting of audio that arrives in chunks
+//!
+//! `IncrementalFingerprinter` runs the same transform, event point and
+//! fingerprint stages as `fingerprint_audio_timed`, but keeps only the
+//! trailing window of samples, frames and event points that later output
+//! still depends on. Fingerprints are returned as soon as no later audio can
+//! change them; concatenating everything returned by `push_samples` and
+//! `finish` gives exactly the fingerprints of the whole buffer.
+
+use std::collections::VecDeque;
+
+use anyhow::Result;
+
+use crate::config::PanakoConfig;
+use crate::eventpoint::{EventPoint, EventPointExtractor};
+use crate::fingerprint::{Fingerprint, FingerprintGenerator};
+use crate::transform::{FrameTransform, SpectralWhitener};
+
+/// Spectrogram frame with its frequency max-filtered copy
+struct Frame {
+    magnitudes: Vec<f32>,
+    freq_filtered: Vec<f32>,
+    /// Skipped by the energy gate (all magnitudes zero)
+    gated: bool,
+}
+
+/// Fingerprints mono audio (at `config.sample_rate`) pushed in chunks
+pub struct IncrementalFingerprinter {
+    config: PanakoConfig,
+    frame_transform: FrameTransform,
+    extractor: EventPointExtractor,
+    generator: FingerprintGenerator,
+    whitener: Option<SpectralWhitener>,
+    /// Last raw sample, for pre-emphasis across chunk boundaries
+    prev_sample: Option<f32>,
+    /// Samples from absolute index `buffer_start` on
+    buffer: Vec<f32>,
+    buffer_start: usize,
+    total_samples: usize,
+    /// Index of the next frame to compute
+    next_frame: usize,
+    /// Computed frames from absolute index `frames_start` on
+    frames: VecDeque<Frame>,
+    frames_start: usize,
+    /// Next frame to search for event points
+    next_peak_frame: usize,
+    /// Event points that may still start or take part in a fingerprint
+    event_points: Vec<EventPoint>,
+}
+
+impl IncrementalFingerprinter {
+    pub fn new(config: &PanakoConfig) -> Result<Self> {
+        if config.max_event_points.is_some() {
+            log::warn!("max_event_points needs the whole file and is ignored when fingerprinting incrementally");
+        }
+        Ok(Self {
+            config: config.clone(),
+            frame_transform: FrameTransform::new(config)?,
+            extractor: EventPointExtractor::new(config),
+            generator: FingerprintGenerator::new(config),
+            whitener: config.whitening_window_frames().map(SpectralWhitener::new),
+            prev_sample: None,
+            buffer: Vec::new(),
+            buffer_start: 0,
+            total_samples: 0,
+            next_frame: 0,
+            frames: VecDeque::new(),
+            frames_start: 0,
+            next_peak_frame: 0,
+            event_points: Vec::new(),
+        })
+    }
+
+    /// Number of samples pushed so far
+    pub fn num_samples(&self) -> usize {
+        self.total_samples
+    }
+
+    /// Add mono samples and return the fingerprints they completed, sorted by t1
+    pub fn push_samples(&mut self, samples: &[f32]) -> Result<Vec<Fingerprint>> {
+        self.buffer.reserve(samples.len());
+        for &sample in samples {
+            let emphasized = match (self.config.pre_emphasis, self.prev_sample) {
+                (Some(coefficient), Some(prev)) if coefficient != 0.0 => sample - coefficient * prev,
+                _ => sample,
+            };
+            self.prev_sample = Some(sample);
+            self.buffer.push(emphasized);
+        }
+        self.total_samples += samples.len();
+
+        // A frame is final once its window is full and it is not the last
+        // hop of the stream (which `compute_transform` never includes)
+        let hop = self.config.time_resolution;
+        let fft_size = self.frame_transform.fft_size;
+        while self.next_frame * hop + fft_size <= self.total_samples
+            && (self.next_frame + 2) * hop <= self.total_samples
+        {
+            self.compute_next_frame();
+        }
+
+        // Event points of frame t need the frames up to t + half window
+        let half_window = self.extractor.time_half_window();
+        while self.next_peak_frame + half_window < self.next_frame {
+            self.find_event_points(self.next_peak_frame, self.next_frame);
+        }
+
+        self.trim();
+        Ok(self.emit_fingerprints(false))
+    }
+
+    /// Process the end of the stream and return the remaining fingerprints
+    pub fn finish(mut self) -> Result<Vec<Fingerprint>> {
+        let num_frames = (self.total_samples / self.config.time_resolution).saturating_sub(1);
+        while self.next_frame < num_frames {
+            self.compute_next_frame();
+        }
+        while self.next_peak_frame < num_frames {
+            self.find_event_points(self.next_peak_frame, num_frames);
+        }
+        Ok(self.emit_fingerprints(true))
+    }
+
+    fn compute_next_frame(&mut self) {
+        let start = self.next_frame * self.config.time_resolution - self.buffer_start;
+        let end = (start + self.frame_transform.fft_size).min(self.buffer.len());
+        let samples = &self.buffer[start..end];
+        if self.frame_transform.is_gated(samples, &self.config) {
+            let num_bins = self.frame_transform.num_bins;
+            self.frames.push_back(Frame {
+                magnitudes: vec![0.0; num_bins],
+                freq_filtered: vec![0.0; num_bins],
+                gated: true,
+            });
+            self.next_frame += 1;
+            return;
+        }
+        let mut magnitudes = self.frame_transform.magnitudes(samples, &self.config);
+
+        let mut replaced = 0;
+        for value in magnitudes.iter_mut().filter(|m| !m.is_finite()) {
+            *value = 0.0;
+            replaced += 1;
+        }
+        if replaced > 0 {
+            log::warn!(
+                "Replaced {} non-finite magnitudes with 0 in frame {} (corrupt or badly resampled audio?)",
+                replaced,
+                self.next_frame
+            );
+        }
+
+        if let Some(whitener) = &mut self.whitener {
+            whitener.whiten_frame(&mut magnitudes);
+        }
+
+        let freq_filtered = self.extractor.freq_max_filter(&magnitudes);
+        self.frames.push_back(Frame { magnitudes, freq_filtered, gated: false });
+        self.next_frame += 1;
+    }
+
+    /// Find the event points of frame `t`, with frames below `num_frames` known
+    fn find_event_points(&mut self, t: usize, num_frames: usize) {
+        let half_window = self.extractor.time_half_window();
+        let t_start = t.saturating_sub(half_window);
+        let t_end = (t + half_window + 1).min(num_frames);
+        let frame = &self.frames[t - self.frames_start];
+        if frame.gated {
+            self.next_peak_frame += 1;
+            return;
+        }
+
+        let mut frame_points = Vec::new();
+        for (f, &original) in frame.magnitudes.iter().enumerate() {
+            let filtered = (t_start..t_end)
+                .map(|ti| self.frames[ti - self.frames_start].freq_filtered[f])
+                .fold(f32::NEG_INFINITY, f32::max);
+            if EventPointExtractor::is_local_maximum(original, filtered) {
+                frame_points.push(EventPoint::new(t as i32, f as i16, original));
+            }
+        }
+        let dropped = self.extractor.cap_frame(&mut frame_points);
+        if dropped > 0 {
+            log::debug!("Dropped {} event points of frame {} over max_event_points_per_frame", dropped, t);
+        }
+        self.event_points.extend(frame_points);
+        self.next_peak_frame += 1;
+    }
+
+    /// Drop samples and frames no later frame or event point depends on
+    fn trim(&mut self) {
+        let keep_from = self.next_frame * self.config.time_resolution;
+        self.buffer.drain(..keep_from - self.buffer_start);
+        self.buffer_start = keep_from;
+
+        let keep_from = self.next_peak_frame.saturating_sub(self.extractor.time_half_window());
+        while self.frames_start < keep_from {
+            self.frames.pop_front();
+            self.frames_start += 1;
+        }
+    }
+
+    /// Generate the fingerprints of every event point whose later partners
+    /// are all known (or all of them at the end of the stream)
+    fn emit_fingerprints(&mut self, end_of_stream: bool) -> Vec<Fingerprint> {
+        let reach = 2 * self.generator.max_time_dist() as i64;
+        let complete = if end_of_stream {
+            self.event_points.len()
+        } else {
+            self.event_points
+                .iter()
+                .take_while(|ep| (ep.t as i64) + reach < self.next_peak_frame as i64)
+                .count()
+        };
+
+        let mut fingerprints = Vec::new();
+        for i in 0..complete {
+            self.generator.generate_from(&self.event_points, i, &mut fingerprints);
+        }
+        self.event_points.drain(..complete);
+        fingerprints
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audio::AudioData;
+    use crate::fingerprint_audio_timed;
+
+    fn test_signal(num_samples: usize) -> Vec<f32> {
+        let mut seed: u32 = 777;
+        (0..num_samples)
+            .map(|i| {
+                seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+                let noise = ((seed >> 16) as f32 / 32768.0 - 1.0) * 0.1;
+                let t = i as f32 / 16000.0;
+                let tone = (2.0 * std::f32::consts::PI * (600.0 + 300.0 * (t * 2.0).sin()) * t).sin();
+                tone * 0.4 + noise
+            })
+            .collect()
+    }
+
+    fn batch_fingerprints(samples: &[f32], config: &PanakoConfig) -> Vec<Fingerprint> {
+        let audio_data = AudioData {
+            samples: samples.to_vec(),
+            sample_rate: config.sample_rate,
+            channels: 1,
+            duration_ms: (samples.len() as u64 * 1000 / config.sample_rate as u64) as u32,
+        };
+        fingerprint_audio_timed(&audio_data, config).unwrap().0
+    }
+
+    fn incremental_fingerprints(samples: &[f32], chunk_sizes: &[usize], config: &PanakoConfig) -> Vec<Fingerprint> {
+        let mut fingerprinter = IncrementalFingerprinter::new(config).unwrap();
+        let mut fingerprints = Vec::new();
+        let mut pos = 0;
+        for &size in chunk_sizes.iter().cycle() {
+            if pos >= samples.len() {
+                break;
+            }
+            let end = (pos + size).min(samples.len());
+            fingerprints.extend(fingerprinter.push_samples(&samples[pos..end]).unwrap());
+            pos = end;
+        }
+        assert_eq!(fingerprinter.num_samples(), samples.len());
+        fingerprints.extend(fingerprinter.finish().unwrap());
+        fingerprints
+    }
+
+    #[test]
+    fn test_chunked_input_matches_whole_buffer() {
+        let config = PanakoConfig::default();
+        // Length deliberately not a multiple of the hop size
+        let samples = test_signal(16000 * 4 + 77);
+        let expected = batch_fingerprints(&samples, &config);
+        assert!(!expected.is_empty());
+
+        for chunk_sizes in [&[samples.len()][..], &[1000], &[1, 4097, 333, 128], &[50]] {
+            assert_eq!(incremental_fingerprints(&samples, chunk_sizes, &config), expected);
+        }
+    }
+
+    #[test]
+    fn test_chunked_input_matches_whole_buffer_with_pre_emphasis() {
+        let config = PanakoConfig {
+            pre_emphasis: Some(0.95),
+            ..PanakoConfig::default()
+        };
+        let samples = test_signal(16000 * 4);
+        let expected = batch_fingerprints(&samples, &config);
+        assert!(!expected.is_empty());
+        assert_eq!(incremental_fingerprints(&samples, &[1, 777, 2048], &config), expected);
+    }
+
+    #[test]
+    fn test_chunked_input_matches_whole_buffer_with_whitening() {
+        let config = PanakoConfig {
+            whitening_window_s: Some(0.5),
+            ..PanakoConfig::default()
+        };
+        let samples = test_signal(16000 * 4);
+        let expected = batch_fingerprints(&samples, &config);
+        assert!(!expected.is_empty());
+        assert_eq!(incremental_fingerprints(&samples, &[999, 3000], &config), expected);
+    }
+
+    #[test]
+    fn test_chunked_input_matches_whole_buffer_with_energy_gate() {
+        let config = PanakoConfig {
+            frame_energy_gate_rms: Some(0.01),
+            whitening_window_s: Some(0.5),
+            ..PanakoConfig::default()
+        };
+        // Loud and silent stretches, so some frames are gated
+        let mut samples = test_signal(16000 * 5);
+        samples[16000..40000].iter_mut().for_each(|s| *s = 0.0);
+        let expected = batch_fingerprints(&samples, &config);
+        assert!(!expected.is_empty());
+        assert_eq!(incremental_fingerprints(&samples, &[1234, 5000], &config), expected);
+    }
+
+    #[test]
+    fn test_chunked_input_matches_whole_buffer_with_frame_cap() {
+        let config = PanakoConfig {
+            max_event_points_per_frame: Some(1),
+            ..PanakoConfig::default()
+        };
+        let samples = test_signal(16000 * 4);
+        let expected = batch_fingerprints(&samples, &config);
+        assert!(!expected.is_empty());
+        assert!(expected.len() < batch_fingerprints(&samples, &PanakoConfig::default()).len());
+        assert_eq!(incremental_fingerprints(&samples, &[1500, 4096], &config), expected);
+    }
+
+    #[test]
+    fn test_fingerprints_are_emitted_before_finish() {
+        let config = PanakoConfig::default();
+        let samples = test_signal(16000 * 6);
+        let mut fingerprinter = IncrementalFingerprinter::new(&config).unwrap();
+        let early: usize = samples
+            .chunks(4000)
+            .map(|chunk| fingerprinter.push_samples(chunk).unwrap().len())
+            .sum();
+        assert!(early > 0);
+    }
+
+    #[test]
+    fn test_short_input() {
+        let config = PanakoConfig::default();
+        let mut fingerprinter = IncrementalFingerprinter::new(&config).unwrap();
+        assert!(fingerprinter.push_samples(&[0.1; 100]).unwrap().is_empty());
+        assert!(fingerprinter.finish().unwrap().is_empty());
+    }
+}
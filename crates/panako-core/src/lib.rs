@@ -3,29 +3,42 @@
 //! This crate implements the Panako acoustic fingerprinting algorithm,
 //! ported from the Java reference implementation.
 
+pub mod analysis;
 pub mod audio;
 pub mod config;
 pub mod eventpoint;
 pub mod fingerprint;
+pub mod incremental;
 pub mod matching;
+pub mod pipeline;
 pub mod transform;
 pub mod segmentation;
 pub mod storage_config;
 pub mod storage_backend;
 
-pub use config::PanakoConfig;
+pub use config::{DeltaTieBreak, HashWidth, PanakoConfig};
 pub use eventpoint::{EventPoint, EventPointExtractor};
 pub use fingerprint::{Fingerprint, FingerprintGenerator};
-pub use matching::{Matcher, QueryResult};
-pub use segmentation::{segment_audio, should_segment, AudioSegment, SegmentationConfig};
+pub use incremental::IncrementalFingerprinter;
+pub use matching::{DirectoryIndex, FrameTimes, IndexStats, LazyMatcher, MatchedFingerprint, Matcher, MmapIndexBuilder, ProgressiveMatch, QueryResult};
+pub use pipeline::{
+    decode_input, decode_input_channels, fingerprint_audio_timed, fingerprint_channels_timed,
+    generate_channel_fingerprints_timed, generate_fingerprints_timed, open_decode_cache, ChannelFingerprints,
+    PipelineTimings,
+};
+pub use segmentation::{
+    segment_audio, should_segment, AudioSegment, SegmentationConfig, SegmentationMode,
+};
 pub use storage_config::{
     PanakoStorageConfig, StorageBackend, StorageConfig, 
-    FilesystemConfig, FileFormat, PostgresqlConfig,
-    MatchingConfig, SegmentationConfig as StorageSegmentationConfig,
+    FilesystemConfig, FileFormat, PostgresqlConfig, CorruptFilePolicy, IngestTransaction,
+    MatchingConfig, SegmentationConfig as StorageSegmentationConfig, ConfigSource,
 };
 pub use storage_backend::{
     StorageBackend as StorageBackendTrait, FilesystemBackend, PostgresqlBackend,
-    FingerprintMetadata, QueryCriteria,
+    FingerprintMetadata, QueryCriteria, DuplicatePolicy, IngestOutcome, ingest_fingerprints, resolve_duplicate,
+    load_fingerprint_files, load_fingerprint_dir, load_reference_dir, resolve_identifier_collisions,
+    LoadedFiles, LoadedReferences, PackSummary, IngestProgress, DirectoryIngestSummary, DirectoryIngestError,
 };
 
 /// Generate fingerprints from audio file
@@ -33,20 +46,16 @@ pub fn generate_fingerprints(
     audio_path: &str,
     config: &PanakoConfig,
 ) -> anyhow::Result<Vec<Fingerprint>> {
-    // Decode audio
-    let audio_data = audio::decode_audio(audio_path, config.sample_rate)?;
-    
-    // Convert to mono samples
-    let mono_samples = audio_data.to_mono();
-    
-    // Extract spectral representation
-    let spectrogram = transform::compute_transform(&mono_samples, config)?;
-    
-    // Extract event points
-    let event_points = EventPointExtractor::new(config).extract(&spectrogram)?;
-    
-    // Generate fingerprints
-    let fingerprints = FingerprintGenerator::new(config).generate(&event_points)?;
-    
+    let (fingerprints, _timings) = generate_fingerprints_timed(audio_path, config)?;
     Ok(fingerprints)
 }
+
+/// Generate fingerprints of each channel of an audio file separately
+/// (experimental stereo-sensitive mode, see `fingerprint_channels_timed`)
+pub fn generate_channel_fingerprints(
+    audio_path: &str,
+    config: &PanakoConfig,
+) -> anyhow::Result<Vec<ChannelFingerprints>> {
+    let (sets, _timings) = generate_channel_fingerprints_timed(audio_path, config)?;
+    Ok(sets)
+}
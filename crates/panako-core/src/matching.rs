@@ -2,26 +2,47 @@
 //!
 //! Implements the Panako matching algorithm with JSON output support.
 
-use crate::config::PanakoConfig;
+use crate::config::{DeltaTieBreak, HashWidth, PanakoConfig};
 use anyhow::Result;
+use panako_fp::FingerprintParams;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
+mod bloom;
+mod directory;
+mod frozen;
+mod lazy;
+mod mmap_index;
+mod progressive;
+mod stats;
 #[cfg(test)]
 mod tests;
 
+use bloom::HashBloomFilter;
+use frozen::FrozenIndex;
+use mmap_index::MappedIndex;
+pub use directory::DirectoryIndex;
+pub use lazy::LazyMatcher;
+pub use mmap_index::MmapIndexBuilder;
+pub use progressive::ProgressiveMatch;
+pub use stats::IndexStats;
+
+/// Tracks of a multi-track reference: (track name, fingerprints)
+type ReferenceTracks = [(String, Vec<(u64, i32, i16, f32)>)];
+
 /// Query result matching Java QueryResult structure
 /// Output format: JSON for easy parsing
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct QueryResult {
     /// Path of the query file
     pub query_path: String,
-    /// Start of match in query (seconds)
+    /// Start of match in query (seconds from the start of the query file,
+    /// also for segmented queries)
     pub query_start: f64,
-    /// End of match in query (seconds)
+    /// End of match in query (seconds from the start of the query file)
     pub query_stop: f64,
     
-    /// Path of the reference file
+    /// Original path of the reference file (the identifier when unknown)
     pub ref_path: Option<String>,
     /// Internal identifier of the reference
     pub ref_identifier: Option<String>,
@@ -32,24 +53,128 @@ pub struct QueryResult {
     
     /// Match score (number of matching fingerprints)
     pub score: i32,
+    /// Score divided by the number of indexed fingerprints of the reference
+    /// (only filled when `PanakoConfig::normalize_score_by_ref_length` is enabled)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub normalized_score: Option<f64>,
     /// Time factor (percentage)
     pub time_factor: f64,
+    /// `time_factor` deviates from 1.0 by more than
+    /// `PanakoConfig::speed_altered_tolerance`: the query plays the
+    /// reference at an altered speed
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub speed_altered: bool,
     /// Frequency factor (percentage)
     pub frequency_factor: f64,
     /// Percentage of seconds with matches
     pub percent_seconds_with_match: f64,
+    /// Fraction of the reference's seconds that contain matched fingerprints
+    /// (0..1), against the known or estimated reference duration; a clip of
+    /// a long reference scores low even when the clip itself matches fully
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub percent_ref_covered: Option<f64>,
     
     // NEW: Reference duration and absolute positions
     #[serde(skip_serializing_if = "Option::is_none")]
     pub ref_duration_ms: Option<u32>,
+    /// Query file time (seconds) at which the whole reference would start,
+    /// i.e. `query_start - ref_start`
     #[serde(skip_serializing_if = "Option::is_none")]
     pub absolute_start: Option<f64>,
+    /// Query file time (seconds) at which the whole reference would end
     #[serde(skip_serializing_if = "Option::is_none")]
     pub absolute_end: Option<f64>,
     
     // NEW: Segment information
     #[serde(skip_serializing_if = "Option::is_none")]
     pub segment_index: Option<usize>,
+    /// Start of the query segment in the query file (seconds)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub segment_start: Option<f64>,
+    /// True when the result comes from matching one segment of a segmented
+    /// query, so the query times refer to that segment's window
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub from_segmented: bool,
+    
+    /// Number of consecutive segments supporting the detection
+    /// (only filled by temporal aggregation in monitoring)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub segment_count: Option<usize>,
+    /// Confidence in [0, 1] from evidence accumulated across segments
+    /// (only filled by temporal aggregation in monitoring)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub confidence: Option<f64>,
+    
+    /// Sub-frame correction (ms) to the frame-level query/reference offset
+    /// (only filled when `PanakoConfig::refine_alignment` is enabled)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sub_frame_offset_ms: Option<f64>,
+    
+    /// Custom tags of the matched reference (artist, ISRC, campaign id, ...)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tags: Option<HashMap<String, String>>,
+    /// External content ID of the matched reference (ISRC, EIDR, ...)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content_id: Option<String>,
+    
+    /// Track of a multi-track reference in which the match starts
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub track: Option<String>,
+    /// Start of match within `track` (seconds)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub track_start: Option<f64>,
+    /// Mean reference magnitude (`m1`) of the aligned matches; only set
+    /// when the matcher retains magnitudes
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mean_matched_magnitude: Option<f64>,
+    
+    /// Aligned fingerprint pairs that contributed to the score
+    /// (only filled when `PanakoConfig::explain_matches` is enabled)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub explanation: Option<Vec<MatchedFingerprint>>,
+    /// `(query_s, ref_s)` times of the aligned fingerprints, in query time
+    /// order; the slope of the points is `time_factor` (only filled when
+    /// `PanakoConfig::include_aligned_points` is enabled)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub aligned_points: Option<Vec<(f64, f64)>>,
+    /// The reference's media file under `PanakoConfig::reference_media_root`
+    /// (see `resolve_media_path`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub media_path: Option<String>,
+    /// Match bounds in frame indices (only filled when
+    /// `PanakoConfig::include_frame_times` is enabled)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub frames: Option<FrameTimes>,
+}
+
+/// Match bounds in transform frames, independent of the configuration
+///
+/// The second fields of `QueryResult` are these indices multiplied by
+/// `seconds_per_frame`, so results produced with different sample rates or
+/// hop sizes can be recomputed exactly instead of compared in seconds.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct FrameTimes {
+    /// First aligned query frame (`query_start`)
+    pub query_start: i32,
+    /// Last aligned query frame (`query_stop`)
+    pub query_stop: i32,
+    /// First aligned reference frame (`ref_start`)
+    pub ref_start: i32,
+    /// Last aligned reference frame (`ref_stop`)
+    pub ref_stop: i32,
+    /// Duration of one frame in seconds (`PanakoConfig::frame_duration_s`)
+    pub seconds_per_frame: f64,
+}
+
+/// A query/reference fingerprint pair supporting a match
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct MatchedFingerprint {
+    /// Time index (frame) of the fingerprint in the query
+    pub query_time: i32,
+    /// Time index (frame) of the fingerprint in the reference
+    pub match_time: i32,
+    /// Shared fingerprint hash
+    pub hash: u64,
 }
 
 impl QueryResult {
@@ -64,25 +189,63 @@ impl QueryResult {
             ref_start: -1.0,
             ref_stop: -1.0,
             score: -1,
+            normalized_score: None,
             time_factor: -1.0,
+            speed_altered: false,
             frequency_factor: -1.0,
             percent_seconds_with_match: 0.0,
+            percent_ref_covered: None,
             ref_duration_ms: None,
             absolute_start: None,
             absolute_end: None,
             segment_index: None,
+            segment_start: None,
+            from_segmented: false,
+            segment_count: None,
+            confidence: None,
+            sub_frame_offset_ms: None,
+            tags: None,
+            content_id: None,
+            track: None,
+            track_start: None,
+            mean_matched_magnitude: None,
+            explanation: None,
+            aligned_points: None,
+            media_path: None,
+            frames: None,
         }
     }
+
+    /// Path of the matched reference's media file under `root`
+    ///
+    /// Joins `root` with the file name (including extension) of the original
+    /// path stored for the reference (`ref_path`), so references fingerprinted
+    /// on another machine or from another directory resolve to the copy
+    /// under `root`. Both `/` and `\` separate directories in the stored
+    /// path. None when the result has no reference path, or its last
+    /// component is `.` or `..` (which would resolve outside a file under
+    /// `root`).
+    pub fn resolve_media_path(&self, root: impl AsRef<std::path::Path>) -> Option<std::path::PathBuf> {
+        let ref_path = self.ref_path.as_deref()?;
+        let file_name = ref_path
+            .rsplit(['/', '\\'])
+            .next()
+            .filter(|name| !name.is_empty() && *name != "." && *name != "..")?;
+        Some(root.as_ref().join(file_name))
+    }
 }
 
 /// Match between query and reference
 #[derive(Debug, Clone)]
 struct Match {
     identifier: String,
+    hash: u64,
     query_time: i32,
     match_time: i32,
     query_f1: i16,
     match_f1: i16,
+    /// Reference magnitude, when retained
+    match_m1: Option<f32>,
 }
 
 impl Match {
@@ -91,30 +254,294 @@ impl Match {
     }
 }
 
+/// State of `find_matches` carried across consecutive parts of one query
+///
+/// Matching a query in parts with one state finds the same matches as
+/// matching it at once (see `Matcher::query_progressive`).
+#[derive(Default)]
+struct MatchState<'a> {
+    /// Occurrences of each hash key counted so far, with `max_query_hash_repeats`
+    repeats: HashMap<u64, usize>,
+    /// (query t1, reference, reference t1) triples matched so far, with
+    /// `fuzzy_hash_epsilon`
+    matched_pairs: HashSet<(i32, &'a str, i32)>,
+}
+
 /// Matcher for finding/// Matcher for fingerprints
 pub struct Matcher {
     /// Inverted index: hash -> Vec<(identifier, t1, f1)>
     index: HashMap<u64, Vec<(String, i32, i16)>>,
+    /// Reference magnitudes parallel to the postings in `index`, when retained
+    magnitudes: Option<HashMap<u64, Vec<f32>>>,
     /// Reference durations: identifier -> duration_ms
     ref_durations: HashMap<String, u32>,
+    /// Original reference paths: identifier -> path
+    ref_paths: HashMap<String, String>,
+    /// Reference tags: identifier -> key/value tags
+    ref_tags: HashMap<String, HashMap<String, String>>,
+    /// External content IDs: identifier -> ISRC, EIDR, ...
+    ref_content_ids: HashMap<String, String>,
+    /// Latest indexed fingerprint time per reference: identifier -> max t1
+    ref_extents: HashMap<String, i32>,
+    /// Minimum score required for a match, per reference
+    ref_min_scores: HashMap<String, usize>,
+    /// Number of fingerprints added per reference: identifier -> count
+    ref_fingerprint_counts: HashMap<String, usize>,
+    /// Track names and start frames of multi-track references, sorted by start
+    ref_tracks: HashMap<String, Vec<(String, i32)>>,
+    /// Fingerprinting parameters per reference, when known
+    ref_params: HashMap<String, FingerprintParams>,
+    /// Registration time per reference: identifier -> Unix seconds
+    ref_registered_at: HashMap<String, i64>,
+    /// Hashes excluded from indexing and queries (too common to discriminate)
+    stop_hashes: HashSet<u64>,
+    /// Optional pre-filter of indexed hashes, checked before the index lookup
+    bloom: Option<HashBloomFilter>,
+    /// Memory-mapped index file, searched in addition to `index`
+    mapped: Option<MappedIndex>,
+    /// Postings moved out of `index` by `freeze`, searched in addition to it
+    frozen: Option<FrozenIndex>,
+    /// Indexed times are rounded down to multiples of this many frames
+    time_step: i32,
+    /// Width of the index keys; hashes are limited to it
+    hash_width: HashWidth,
 }
 
 impl Matcher {
     pub fn new() -> Self {
         Self {
             index: HashMap::new(),
+            magnitudes: None,
             ref_durations: HashMap::new(),
+            ref_paths: HashMap::new(),
+            ref_tags: HashMap::new(),
+            ref_content_ids: HashMap::new(),
+            ref_extents: HashMap::new(),
+            ref_min_scores: HashMap::new(),
+            ref_fingerprint_counts: HashMap::new(),
+            ref_tracks: HashMap::new(),
+            ref_params: HashMap::new(),
+            ref_registered_at: HashMap::new(),
+            stop_hashes: HashSet::new(),
+            bloom: None,
+            mapped: None,
+            frozen: None,
+            time_step: 1,
+            hash_width: HashWidth::default(),
         }
     }
+    
+    /// Open an index file written by `MmapIndexBuilder` without loading it
+    ///
+    /// Hash postings stay on disk and are found by binary search, so the
+    /// index may be larger than RAM; only reference names, durations, tags
+    /// and extents are loaded. Fingerprints added afterwards are kept in
+    /// memory and queried together with the mapped index.
+    pub fn open_mmap(path: &std::path::Path) -> Result<Self> {
+        let (mapped, metadata) = MappedIndex::open(path)?;
+        Ok(Self {
+            hash_width: mapped.hash_width(),
+            ref_durations: metadata.durations,
+            ref_paths: metadata.paths,
+            ref_tags: metadata.tags,
+            ref_content_ids: metadata.content_ids,
+            ref_extents: metadata.extents,
+            ref_fingerprint_counts: metadata.fingerprint_counts,
+            ref_registered_at: metadata.registered_at,
+            mapped: Some(mapped),
+            ..Self::new()
+        })
+    }
 
     /// Add fingerprints to the index
     pub fn add_fingerprints(&mut self, identifier: String, fingerprints: &[(u64, i32, i16, f32)]) {
-        for (hash, t1, f1, _m1) in fingerprints {
-            self.index
-                .entry(*hash)
-                .or_insert_with(Vec::new)
-                .push((identifier.clone(), *t1, *f1));
+        if let Some(max_t1) = fingerprints.iter().map(|fp| fp.1).max() {
+            let extent = self.ref_extents.entry(identifier.clone()).or_insert(max_t1);
+            *extent = (*extent).max(max_t1);
+        }
+        *self.ref_fingerprint_counts.entry(identifier.clone()).or_insert(0) += fingerprints.len();
+        
+        for (hash, t1, f1, m1) in fingerprints {
+            let hash = self.hash_width.key(*hash);
+            if self.stop_hashes.contains(&hash) {
+                continue;
+            }
+            if let Some(bloom) = &mut self.bloom {
+                bloom.insert(hash);
+            }
+            let posting = (identifier.clone(), t1.div_euclid(self.time_step) * self.time_step, *f1);
+            let postings = self.index.entry(hash).or_default();
+            if postings.last() != Some(&posting) {
+                postings.push(posting);
+                if let Some(magnitudes) = &mut self.magnitudes {
+                    magnitudes.entry(hash).or_default().push(*m1);
+                }
+            }
+        }
+    }
+    
+    /// Width of the index keys (see `HashWidth`)
+    pub fn hash_width(&self) -> HashWidth {
+        self.hash_width
+    }
+    
+    /// Key the index by hashes of `width` (`PanakoConfig::hash_width`)
+    ///
+    /// Hashes added and queried afterwards are limited to the width, so a
+    /// 32-bit index only ever holds 32-bit keys. Postings already indexed
+    /// are re-keyed (with their magnitudes) and the Bloom filter, if any, is
+    /// rebuilt; set it before adding fingerprints. In memory a 32-bit key
+    /// saves nothing (buckets are padded to the posting list's alignment).
+    pub fn set_hash_width(&mut self, width: HashWidth) {
+        if width == self.hash_width {
+            return;
+        }
+        self.thaw();
+        self.hash_width = width;
+        // One pass over the postings, so merged buckets keep their postings
+        // and magnitudes in the same order
+        let mut magnitudes = self.magnitudes.take();
+        let mut rekeyed_magnitudes = magnitudes.as_ref().map(|_| HashMap::new());
+        for (hash, postings) in std::mem::take(&mut self.index) {
+            let key = width.key(hash);
+            if let (Some(magnitudes), Some(rekeyed)) = (magnitudes.as_mut(), rekeyed_magnitudes.as_mut()) {
+                let mut values = magnitudes.remove(&hash).unwrap_or_default();
+                values.resize(postings.len(), f32::NAN);
+                rekeyed.entry(key).or_insert_with(Vec::new).extend(values);
+            }
+            self.index.entry(key).or_default().extend(postings);
+        }
+        self.magnitudes = rekeyed_magnitudes;
+        self.stop_hashes = self.stop_hashes.iter().map(|&hash| width.key(hash)).collect();
+        if let Some(bits_per_hash) = self.bloom.as_ref().map(HashBloomFilter::bits_per_item) {
+            self.enable_bloom_filter(bits_per_hash);
+        }
+    }
+    
+    /// Keep the magnitude (`m1`) of every posting in the index
+    ///
+    /// Matches then report `mean_matched_magnitude`. Costs one `f32` per
+    /// posting, so it is off by default. Postings indexed before this call
+    /// (and those of a memory-mapped index) have no magnitude and are left
+    /// out of the mean, so call it before adding fingerprints.
+    pub fn retain_magnitudes(&mut self) {
+        if self.magnitudes.is_none() {
+            self.thaw();
+            self.magnitudes = Some(
+                self.index
+                    .iter()
+                    .map(|(&hash, postings)| (hash, vec![f32::NAN; postings.len()]))
+                    .collect(),
+            );
+        }
+    }
+    
+    /// Quantize indexed times to multiples of `step` frames
+    ///
+    /// The matcher only aligns on `delta_t` with a tolerance of `query_range`,
+    /// so exact reference times are not needed. Postings of a reference that
+    /// become identical after rounding (a hash repeated on adjacent frames, as
+    /// for sustained tones) are stored once, shrinking the index. Applies to
+    /// postings already indexed and to fingerprints added later; set it before
+    /// adding fingerprints to keep peak memory low.
+    pub fn quantize_times(&mut self, step: i32) {
+        self.thaw();
+        self.time_step = step.max(1);
+        let step = self.time_step;
+        for (hash, postings) in self.index.iter_mut() {
+            for posting in postings.iter_mut() {
+                posting.1 = posting.1.div_euclid(step) * step;
+            }
+            if let Some(magnitudes) = self.magnitudes.as_mut().and_then(|m| m.get_mut(hash)) {
+                // Keep each magnitude with the first of its merged postings
+                let mut keep = Vec::with_capacity(postings.len());
+                keep.extend((0..postings.len()).map(|i| i == 0 || postings[i] != postings[i - 1]));
+                let mut keep_iter = keep.iter();
+                magnitudes.retain(|_| *keep_iter.next().unwrap());
+            }
+            postings.dedup();
+        }
+    }
+    
+    /// Number of postings held in memory (one per indexed fingerprint)
+    pub fn num_postings(&self) -> usize {
+        self.index.values().map(Vec::len).sum::<usize>() + self.frozen.as_ref().map_or(0, FrozenIndex::num_postings)
+    }
+    
+    /// Convert the index into a compact immutable form for a matcher that
+    /// is built once and then only queried
+    ///
+    /// The postings are stored in one flat array grouped by hash and found
+    /// by binary search over the sorted hashes, which takes far less memory
+    /// than the hash map and keeps each bucket contiguous. Query results are
+    /// unchanged. Fingerprints added afterwards go to a regular in-memory
+    /// index queried alongside; methods that rewrite the index
+    /// (`filter_common_hashes`, `quantize_times`, `set_hash_width`,
+    /// `retain_magnitudes`) convert it back first, so call them before.
+    pub fn freeze(mut self) -> Self {
+        self.thaw();
+        let index = std::mem::take(&mut self.index);
+        let magnitudes = self.magnitudes.as_mut().map(std::mem::take);
+        self.frozen = Some(FrozenIndex::new(index, magnitudes));
+        self
+    }
+    
+    /// Whether the postings indexed so far are in the frozen form
+    pub fn is_frozen(&self) -> bool {
+        self.frozen.is_some()
+    }
+    
+    /// Move frozen postings back into the hash map index, ahead of those
+    /// added after freezing
+    fn thaw(&mut self) {
+        let Some(frozen) = self.frozen.take() else {
+            return;
+        };
+        let (mut index, magnitudes) = frozen.into_index();
+        for (hash, postings) in std::mem::take(&mut self.index) {
+            index.entry(hash).or_default().extend(postings);
+        }
+        self.index = index;
+        if let (Some(current), Some(mut thawed)) = (self.magnitudes.as_mut(), magnitudes) {
+            for (hash, values) in std::mem::take(current) {
+                thawed.entry(hash).or_default().extend(values);
+            }
+            *current = thawed;
+        }
+    }
+    
+    /// Add separately fingerprinted tracks as one logical reference
+    ///
+    /// Tracks (e.g. the per-track files of an album) are laid out back to
+    /// back in the given order: each track's fingerprints are shifted to
+    /// start one frame after the latest fingerprint of the previous track.
+    /// Matches report the logical reference as usual, plus the track in
+    /// which the match starts and the offset within it.
+    pub fn add_tracks(&mut self, identifier: String, tracks: &ReferenceTracks) {
+        let mut track_start = 0;
+        let mut boundaries = Vec::with_capacity(tracks.len());
+        for (name, fingerprints) in tracks {
+            let shifted: Vec<(u64, i32, i16, f32)> = fingerprints
+                .iter()
+                .map(|&(hash, t1, f1, m1)| (hash, t1 + track_start, f1, m1))
+                .collect();
+            self.add_fingerprints(identifier.clone(), &shifted);
+            boundaries.push((name.clone(), track_start));
+            if let Some(max_t1) = shifted.iter().map(|fp| fp.1).max() {
+                track_start = max_t1 + 1;
+            }
         }
+        self.ref_tracks.insert(identifier, boundaries);
+    }
+    
+    /// Track containing reference frame `t1` and the frame offset within it
+    fn track_at(&self, identifier: &str, t1: i32) -> Option<(&str, i32)> {
+        self.ref_tracks
+            .get(identifier)?
+            .iter()
+            .rev()
+            .find(|(_, start)| *start <= t1)
+            .map(|(name, start)| (name.as_str(), t1 - start))
     }
     
     /// Add reference duration
@@ -122,35 +549,269 @@ impl Matcher {
         self.ref_durations.insert(identifier, duration_ms);
     }
     
+    /// Add the original path of the reference's source file, reported as
+    /// `ref_path` so operators can locate it
+    pub fn add_path(&mut self, identifier: String, original_path: String) {
+        self.ref_paths.insert(identifier, original_path);
+    }
+    
+    /// Add the reference's external content ID (ISRC, EIDR, ...), reported
+    /// as `content_id` so matches map directly to the rights catalog
+    pub fn add_content_id(&mut self, identifier: String, content_id: String) {
+        self.ref_content_ids.insert(identifier, content_id);
+    }
+    
+    /// Reference duration in milliseconds, estimated from the fingerprint extent
+    /// (latest indexed `t1`) when no duration was added
+    fn effective_duration_ms(&self, identifier: &str, config: &PanakoConfig) -> Option<u32> {
+        if let Some(&duration_ms) = self.ref_durations.get(identifier) {
+            return Some(duration_ms);
+        }
+        if !config.estimate_ref_duration {
+            return None;
+        }
+        self.ref_extents
+            .get(identifier)
+            .map(|&max_t1| (max_t1.max(0) as f64 * config.frame_duration_s() * 1000.0).round() as u32)
+    }
+    
+    /// Record the parameters a reference was fingerprinted with
+    pub fn add_fingerprint_params(&mut self, identifier: String, params: FingerprintParams) {
+        self.ref_params.insert(identifier, params);
+    }
+    
+    /// Check that query fingerprints were made with the same parameters as
+    /// the indexed references
+    ///
+    /// Fingerprints made with different parameters (`bands_per_octave`,
+    /// time/frequency distances, filter sizes, ...) cannot match even for
+    /// identical audio. Fails, naming the references and differing
+    /// parameters, if any reference with known parameters is incompatible.
+    pub fn check_query_params(&self, query_params: &FingerprintParams) -> Result<()> {
+        let mut incompatible: Vec<(&String, Vec<String>)> = self
+            .ref_params
+            .iter()
+            .map(|(identifier, params)| (identifier, query_params.differences(params)))
+            .filter(|(_, differences)| !differences.is_empty())
+            .collect();
+        
+        if incompatible.is_empty() {
+            return Ok(());
+        }
+        
+        incompatible.sort();
+        let examples: Vec<String> = incompatible
+            .iter()
+            .take(3)
+            .map(|(identifier, differences)| format!("{} ({})", identifier, differences.join(", ")))
+            .collect();
+        anyhow::bail!(
+            "Query fingerprint parameters are incompatible with {} of {} references (query vs reference): {}",
+            incompatible.len(),
+            self.ref_params.len(),
+            examples.join("; ")
+        )
+    }
+    
+    /// Require at least `min_score` aligned matches for this reference
+    ///
+    /// Applied on top of `PanakoConfig::min_hits_filtered`, for references
+    /// prone to false positives such as short idents or jingles.
+    pub fn add_min_score(&mut self, identifier: String, min_score: usize) {
+        self.ref_min_scores.insert(identifier, min_score);
+    }
+    
+    /// Record when a reference was added to the catalog (Unix seconds)
+    ///
+    /// Queries with `PanakoConfig::registered_after_s` only consider
+    /// references registered at or after that time, e.g. this week's new
+    /// ads in a monitoring service.
+    pub fn add_registration_time(&mut self, identifier: String, registered_at_s: i64) {
+        self.ref_registered_at.insert(identifier, registered_at_s);
+    }
+    
+    /// Reference is a candidate under `config.registered_after_s`
+    fn is_registered_in_window(&self, identifier: &str, config: &PanakoConfig) -> bool {
+        match config.registered_after_s {
+            None => true,
+            Some(after) => self.ref_registered_at.get(identifier).is_some_and(|&at| at >= after),
+        }
+    }
+    
+    /// Add reference tags, reported with every match of the reference
+    pub fn add_tags(&mut self, identifier: String, tags: HashMap<String, String>) {
+        if !tags.is_empty() {
+            self.ref_tags.insert(identifier, tags);
+        }
+    }
+    
+    /// Remove hashes that occur in more than `max_ref_fraction` of the indexed references
+    ///
+    /// Such hashes (structural or noise patterns) carry almost no information but
+    /// inflate candidate lists. Removed hashes are also ignored by later calls to
+    /// `add_fingerprints` and `query`. Only in-memory postings are counted;
+    /// a memory-mapped index is read-only. Returns the number of hashes removed.
+    pub fn filter_common_hashes(&mut self, max_ref_fraction: f64) -> usize {
+        self.thaw();
+        let num_refs = self
+            .index
+            .values()
+            .flat_map(|postings| postings.iter().map(|(id, _, _)| id.as_str()))
+            .collect::<HashSet<_>>()
+            .len();
+        
+        if num_refs == 0 {
+            return 0;
+        }
+        
+        let max_refs = max_ref_fraction * num_refs as f64;
+        let common: Vec<u64> = self
+            .index
+            .iter()
+            .filter(|(_, postings)| {
+                let refs: HashSet<&str> = postings.iter().map(|(id, _, _)| id.as_str()).collect();
+                refs.len() as f64 > max_refs
+            })
+            .map(|(&hash, _)| hash)
+            .collect();
+        
+        for hash in &common {
+            self.index.remove(hash);
+            if let Some(magnitudes) = &mut self.magnitudes {
+                magnitudes.remove(hash);
+            }
+            self.stop_hashes.insert(*hash);
+        }
+        
+        log::debug!(
+            "Filtered {} common hashes (present in > {:.0}% of {} references)",
+            common.len(),
+            max_ref_fraction * 100.0,
+            num_refs
+        );
+        
+        common.len()
+    }
+    
+    /// Build a Bloom filter over the indexed hashes to pre-filter queries
+    ///
+    /// On large indexes most query hashes are absent; the filter rejects
+    /// them without probing the index map. `bits_per_hash` trades memory for
+    /// the false positive rate (10 bits per hash is about 1%). Fingerprints
+    /// added later are inserted into the filter as well, so results are
+    /// identical to unfiltered queries.
+    pub fn enable_bloom_filter(&mut self, bits_per_hash: usize) {
+        let num_mapped = self.mapped.as_ref().map_or(0, |mapped| mapped.num_hashes());
+        let num_frozen = self.frozen.as_ref().map_or(0, FrozenIndex::num_hashes);
+        let mut bloom = HashBloomFilter::new(self.index.len() + num_mapped + num_frozen, bits_per_hash);
+        for &hash in self.index.keys() {
+            bloom.insert(hash);
+        }
+        for hash in self.mapped.iter().flat_map(|mapped| mapped.hashes()) {
+            bloom.insert(hash);
+        }
+        for hash in self.frozen.iter().flat_map(FrozenIndex::hashes) {
+            bloom.insert(hash);
+        }
+        self.bloom = Some(bloom);
+    }
+    
+    /// Fingerprints of a reference whose hashes no other reference contains
+    ///
+    /// Only these fingerprints can tell the reference apart from the rest of
+    /// the index, so storage-constrained catalogs can keep just this subset.
+    /// Results are sorted by `t1`. Postings in memory and in a memory-mapped
+    /// index are considered; `m1` is the retained magnitude (see
+    /// `retain_magnitudes`), or 0.0 when it is not known. Hashes removed by
+    /// `filter_common_hashes` are not returned.
+    pub fn minimal_distinguishing_set(&self, identifier: &str) -> Vec<(u64, i32, i16, f32)> {
+        let mut subset: Vec<(u64, i32, i16, f32)> = Vec::new();
+        for hash in self.indexed_hashes() {
+            if !self.all_postings(hash).all(|(id, _, _)| id == identifier) {
+                continue;
+            }
+            let in_memory_magnitudes = self
+                .magnitudes
+                .as_ref()
+                .and_then(|magnitudes| magnitudes.get(&hash))
+                .map_or(&[][..], Vec::as_slice);
+            let frozen = self
+                .frozen
+                .iter()
+                .flat_map(move |frozen| frozen.postings(hash))
+                .map(|(_, t1, f1, m1)| (t1, f1, m1));
+            let in_memory = self.index.get(&hash).into_iter().flatten().enumerate().map(|(i, (_, t1, f1))| {
+                (*t1, *f1, in_memory_magnitudes.get(i).copied().filter(|m| !m.is_nan()))
+            });
+            let mapped = self
+                .mapped
+                .iter()
+                .flat_map(move |mapped| mapped.postings(hash))
+                .map(|(_, t1, f1)| (t1, f1, None));
+            subset.extend(frozen.chain(in_memory).chain(mapped).map(|(t1, f1, m1)| (hash, t1, f1, m1.unwrap_or(0.0))));
+        }
+        
+        subset.sort_by_key(|&(hash, t1, f1, _)| (t1, f1, hash));
+        subset
+    }
+    
+    /// Postings `(identifier, t1, f1)` of a hash, frozen and in the hash map
+    fn in_memory_postings(&self, hash: u64) -> impl Iterator<Item = (&str, i32, i16)> + '_ {
+        let frozen = self.frozen.iter().flat_map(move |frozen| frozen.postings(hash));
+        frozen
+            .map(|(id, t1, f1, _)| (id, t1, f1))
+            .chain(self.index.get(&hash).into_iter().flatten().map(|(id, t1, f1)| (id.as_str(), *t1, *f1)))
+    }
+    
     /// Query the index with fingerprints
+    ///
+    /// Results are sorted by score descending (normalized score when
+    /// `normalize_score_by_ref_length` is enabled), equal scores by identifier.
     pub fn query(
         &self,
         query_path: &str,
         query_fingerprints: &[(u64, i32, i16, f32)],
         config: &PanakoConfig,
     ) -> Result<Vec<QueryResult>> {
-        // Find all matches
-        let mut matches: Vec<Match> = Vec::new();
-
-        // Find matches
-        for (hash, t1, f1, _m1) in query_fingerprints {
-            if let Some(candidates) = self.index.get(hash) {
-                for (identifier, ref_t1, ref_f1) in candidates {
-                    matches.push(Match {
-                        identifier: identifier.clone(),
-                        query_time: *t1,
-                        match_time: *ref_t1,
-                        query_f1: *f1,
-                        match_f1: *ref_f1,
-                    });
-                }
-            }
-        }
-        
+        let mut results = self.query_unsorted(query_path, query_fingerprints, config)?;
+        sort_by_score(&mut results, config);
+        Ok(results)
+    }
+    
+    /// Like `query`, without sorting the results
+    ///
+    /// Results come in discovery order, which is unspecified and may change
+    /// between calls. For latency-sensitive callers that only check whether
+    /// anything matched or sort the results themselves.
+    pub fn query_unsorted(
+        &self,
+        query_path: &str,
+        query_fingerprints: &[(u64, i32, i16, f32)],
+        config: &PanakoConfig,
+    ) -> Result<Vec<QueryResult>> {
+        let matches = self.find_matches(query_fingerprints, config, &mut MatchState::default());
+        self.results_from_matches(query_path, matches, query_fingerprints, config)
+    }
+    
+    /// Unsorted results of the matches found for `query_fingerprints`
+    fn results_from_matches(
+        &self,
+        query_path: &str,
+        matches: Vec<Match>,
+        query_fingerprints: &[(u64, i32, i16, f32)],
+        config: &PanakoConfig,
+    ) -> Result<Vec<QueryResult>> {
         if matches.is_empty() {
             return Ok(vec![]);  // Return empty array instead of empty result
         }
         
+        // Duration of one frame in seconds
+        let frame_s = config.frame_duration_s();
+        
+        // Full frame span of the query, used as the coverage denominator
+        let full_query_start = query_fingerprints.iter().map(|fp| fp.1).min().unwrap_or(0);
+        let full_query_stop = query_fingerprints.iter().map(|fp| fp.1).max().unwrap_or(0);
+        
         // Group by identifier and find most common delta_t
         let mut results = Vec::new();
         let mut by_identifier: HashMap<String, Vec<Match>> = HashMap::new();
@@ -158,7 +819,7 @@ impl Matcher {
         for m in matches {
             by_identifier
                 .entry(m.identifier.clone())
-                .or_insert_with(Vec::new)
+                .or_default()
                 .push(m);
         }
         
@@ -184,11 +845,7 @@ impl Matcher {
                 *delta_histogram.entry(m.delta_t()).or_insert(0) += 1;
             }
             
-            let (best_delta, count) = delta_histogram
-                .iter()
-                .max_by_key(|(_, &count)| count)
-                .map(|(&delta, &count)| (delta, count))
-                .unwrap_or((0, 0));
+            let (best_delta, count) = best_delta(&delta_histogram, &id_matches, config.delta_tie_break);
             
             // Skip if best delta doesn't have enough support
             if count < min_aligned_threshold {
@@ -211,6 +868,18 @@ impl Matcher {
                 continue;
             }
             
+            if let Some(&min_score) = self.ref_min_scores.get(&identifier) {
+                if aligned_matches.len() < min_score {
+                    log::trace!(
+                        "Skipping {}: score {} below its minimum {}",
+                        identifier,
+                        aligned_matches.len(),
+                        min_score
+                    );
+                    continue;
+                }
+            }
+            
             log::debug!(
                 "Identifier: {}, raw matches: {}, aligned: {}, best_delta: {}",
                 identifier,
@@ -225,15 +894,36 @@ impl Matcher {
             
             let query_start_frame = *query_times.iter().min().unwrap();
             let query_stop_frame = *query_times.iter().max().unwrap();
-            let query_start = query_start_frame as f64 * 0.008; // ~8ms per frame
-            let query_stop = query_stop_frame as f64 * 0.008;
-            let ref_start = *match_times.iter().min().unwrap() as f64 * 0.008;
-            let ref_stop = *match_times.iter().max().unwrap() as f64 * 0.008;
+            let query_start = query_start_frame as f64 * frame_s; // 8ms per frame at 16 kHz
+            let query_stop = query_stop_frame as f64 * frame_s;
             
             // Calculate factors using helper functions
             let time_factor = calculate_time_factor(&aligned_matches);
+            let (ref_start_frame, ref_stop_frame) = if config.ref_bounds_from_delta {
+                // A reference repeating within `query_range` frames contributes
+                // aligned matches from several loop iterations, whose extreme
+                // frames do not correspond to the query bounds. The query span
+                // is scaled by the playback speed and both ends are kept
+                // within the reference.
+                let start = query_start_frame + best_delta;
+                let span = ((query_stop_frame - query_start_frame) as f64 * time_factor).round() as i32;
+                let extent = self.ref_extents.get(&identifier).copied().unwrap_or(i32::MAX);
+                let clamp = |frame: i32| frame.clamp(0, extent.max(0));
+                (clamp(start), clamp(start.saturating_add(span)))
+            } else {
+                (*match_times.iter().min().unwrap(), *match_times.iter().max().unwrap())
+            };
+            let ref_start = ref_start_frame as f64 * frame_s;
+            let ref_stop = ref_stop_frame as f64 * frame_s;
+            let speed_altered = config
+                .speed_altered_tolerance
+                .is_some_and(|tolerance| (time_factor - 1.0).abs() > tolerance);
             let frequency_factor = calculate_frequency_factor(&aligned_matches);
-            let coverage = calculate_coverage(&aligned_matches, query_start_frame, query_stop_frame);
+            let coverage = if config.coverage_over_full_query {
+                calculate_coverage(&aligned_matches, full_query_start, full_query_stop, frame_s)
+            } else {
+                calculate_coverage(&aligned_matches, query_start_frame, query_stop_frame, frame_s)
+            };
 
             log::debug!(
                 "Identifier: {}, raw matches: {}, aligned: {}, best_delta: {}",
@@ -249,11 +939,69 @@ impl Matcher {
                 coverage * 100.0
             );
 
-            // Get reference duration if available
+            let sub_frame_offset_ms = if config.refine_alignment {
+                Some(estimate_sub_frame_offset(&aligned_matches, best_delta) * frame_s * 1000.0)
+            } else {
+                None
+            };
+
+            let explanation = if config.explain_matches {
+                Some(
+                    aligned_matches
+                        .iter()
+                        .map(|m| MatchedFingerprint {
+                            query_time: m.query_time,
+                            match_time: m.match_time,
+                            hash: m.hash,
+                        })
+                        .collect(),
+                )
+            } else {
+                None
+            };
+
+            let aligned_points = if config.include_aligned_points {
+                let mut points: Vec<(f64, f64)> = aligned_matches
+                    .iter()
+                    .map(|m| (m.query_time as f64 * frame_s, m.match_time as f64 * frame_s))
+                    .collect();
+                points.sort_by(|a, b| a.0.total_cmp(&b.0).then(a.1.total_cmp(&b.1)));
+                Some(points)
+            } else {
+                None
+            };
+
+            let frames = config.include_frame_times.then_some(FrameTimes {
+                query_start: query_start_frame,
+                query_stop: query_stop_frame,
+                ref_start: ref_start_frame,
+                ref_stop: ref_stop_frame,
+                seconds_per_frame: frame_s,
+            });
+
+            // Get reference duration if available, else estimate it for position math
             let ref_duration_ms = self.ref_durations.get(&identifier).copied();
+            let effective_duration_ms = self.effective_duration_ms(&identifier, config);
+            
+            // Require the match to span enough of the reference (e.g. the full ad played)
+            if let (Some(min_ref_coverage), Some(duration_ms)) = (config.min_ref_coverage, effective_duration_ms) {
+                let ref_coverage = calculate_ref_coverage(ref_start, ref_stop, duration_ms);
+                if ref_coverage < min_ref_coverage {
+                    log::debug!(
+                        "Skipping {}: matched {:.1}% of reference (need {:.1}%)",
+                        identifier,
+                        ref_coverage * 100.0,
+                        min_ref_coverage * 100.0
+                    );
+                    continue;
+                }
+            }
             
+            let percent_ref_covered = effective_duration_ms
+                .map(|duration_ms| calculate_ref_seconds_covered(&aligned_matches, duration_ms, frame_s));
+
             // Calculate absolute positions
-            let (absolute_start, absolute_end) = if let Some(duration_ms) = ref_duration_ms {
+            let (absolute_start, absolute_end) = if let Some(duration_ms) = effective_duration_ms {
                 let abs_start = query_start - ref_start;
                 let abs_end = abs_start + (duration_ms as f64 / 1000.0);
                 (Some(abs_start), Some(abs_end))
@@ -261,31 +1009,185 @@ impl Matcher {
                 (None, None)
             };
 
+            let normalized_score = if config.normalize_score_by_ref_length {
+                let num_fingerprints = self.ref_fingerprint_counts.get(&identifier).copied().unwrap_or(0);
+                Some(aligned_matches.len() as f64 / num_fingerprints.max(1) as f64)
+            } else {
+                None
+            };
+
+            let mean_matched_magnitude = if self.magnitudes.is_some() {
+                mean_magnitude(&aligned_matches)
+            } else {
+                None
+            };
+
+            let (track, track_start) = match self.track_at(&identifier, ref_start_frame) {
+                Some((name, offset)) => (Some(name.to_string()), Some(offset as f64 * frame_s)),
+                None => (None, None),
+            };
+
             results.push(QueryResult {
                 query_path: query_path.to_string(),
                 query_start,
                 query_stop,
-                ref_path: Some(identifier.clone()), // Kept as Some()
+                ref_path: Some(self.ref_paths.get(&identifier).unwrap_or(&identifier).clone()),
                 ref_identifier: Some(identifier.clone()),
                 ref_start,
                 ref_stop,
                 score: aligned_matches.len() as i32,
+                normalized_score,
                 time_factor,
+                speed_altered,
                 frequency_factor,
                 percent_seconds_with_match: coverage,
+                percent_ref_covered,
                 ref_duration_ms,
                 absolute_start,
                 absolute_end,
                 segment_index: None, // Filled by caller if applicable
+                segment_start: None,
+                from_segmented: false,
+                segment_count: None,
+                confidence: None,
+                sub_frame_offset_ms,
+                tags: self.ref_tags.get(&identifier).cloned(),
+                content_id: self.ref_content_ids.get(&identifier).cloned(),
+                track,
+                track_start,
+                mean_matched_magnitude,
+                explanation,
+                aligned_points,
+                media_path: None,
+                frames,
             });
         }
         
-        // Sort by score descending
-        results.sort_by(|a, b| b.score.cmp(&a.score));
+        if let Some(root) = &config.reference_media_root {
+            for result in &mut results {
+                result.media_path = result.resolve_media_path(root).map(|path| path.to_string_lossy().into_owned());
+            }
+        }
         
         // Return all results (no max_results limit)
         Ok(results)
     }
+    
+    /// Index postings sharing a hash with the query fingerprints
+    ///
+    /// With `max_query_hash_repeats`, occurrences of a hash past the cap are
+    /// not looked up: the earliest (by `t1`, equal times in input order) are
+    /// kept, whatever the input order, and calls sharing `state` count
+    /// together. With `fuzzy_hash_epsilon`, the hash
+    /// variants of one triple would match the variants of the reference triple
+    /// several times at the same offset, so each (query t1, reference, reference
+    /// t1) pair is kept once, also across calls sharing `state`.
+    fn find_matches<'a>(
+        &'a self,
+        query_fingerprints: &[(u64, i32, i16, f32)],
+        config: &PanakoConfig,
+        state: &mut MatchState<'a>,
+    ) -> Vec<Match> {
+        let mut matches: Vec<Match> = Vec::new();
+        let capped: HashSet<usize> = match config.max_query_hash_repeats {
+            Some(max_repeats) => {
+                let mut by_time: Vec<usize> = (0..query_fingerprints.len()).collect();
+                by_time.sort_by_key(|&i| query_fingerprints[i].1);
+                by_time
+                    .into_iter()
+                    .filter(|&i| {
+                        let seen = state.repeats.entry(self.hash_width.key(query_fingerprints[i].0)).or_default();
+                        *seen += 1;
+                        *seen > max_repeats
+                    })
+                    .collect()
+            }
+            None => HashSet::new(),
+        };
+        for (i, (hash, t1, f1, _m1)) in query_fingerprints.iter().enumerate() {
+            let hash = &self.hash_width.key(*hash);
+            if capped.contains(&i) {
+                continue;
+            }
+            if self.bloom.as_ref().is_some_and(|bloom| !bloom.may_contain(*hash)) {
+                continue;
+            }
+            if self.stop_hashes.contains(hash) {
+                continue;
+            }
+            let in_memory = self.index.get(hash).map_or(&[][..], Vec::as_slice);
+            let in_memory_magnitudes = self
+                .magnitudes
+                .as_ref()
+                .and_then(|magnitudes| magnitudes.get(hash))
+                .map_or(&[][..], Vec::as_slice);
+            let mapped = self.mapped.as_ref().map(|mapped| mapped.postings(*hash));
+            let frozen = self.frozen.as_ref().map(|frozen| frozen.postings(*hash));
+            let num_candidates = in_memory.len()
+                + mapped.as_ref().map_or(0, |postings| postings.len())
+                + frozen.as_ref().map_or(0, |postings| postings.len());
+            if num_candidates == 0 {
+                continue;
+            }
+            // Oversized buckets are non-discriminative and dominate query time
+            if let Some(cap) = config.max_candidates_per_hash {
+                if num_candidates > cap {
+                    log::trace!(
+                        "Skipping hash {}: {} candidates (cap {})",
+                        hash,
+                        num_candidates,
+                        cap
+                    );
+                    continue;
+                }
+            }
+            let candidates = mapped
+                .into_iter()
+                .flatten()
+                .map(|(identifier, ref_t1, ref_f1)| (identifier, ref_t1, ref_f1, None))
+                .chain(frozen.into_iter().flatten())
+                .chain(in_memory.iter().enumerate().map(|(i, (identifier, ref_t1, ref_f1))| {
+                    let ref_m1 = in_memory_magnitudes.get(i).copied().filter(|m| !m.is_nan());
+                    (identifier.as_str(), *ref_t1, *ref_f1, ref_m1)
+                }));
+            for (identifier, ref_t1, ref_f1, ref_m1) in candidates {
+                if !self.is_registered_in_window(identifier, config) {
+                    continue;
+                }
+                if config.fuzzy_hash_epsilon.is_some() && !state.matched_pairs.insert((*t1, identifier, ref_t1)) {
+                    continue;
+                }
+                matches.push(Match {
+                    identifier: identifier.to_string(),
+                    hash: *hash,
+                    query_time: *t1,
+                    match_time: ref_t1,
+                    query_f1: *f1,
+                    match_f1: ref_f1,
+                    match_m1: ref_m1,
+                });
+            }
+        }
+        
+        matches
+    }
+    
+    /// The `n` best references for a query, e.g. candidates for display
+    ///
+    /// Same results and ranking as `query` (one result per reference, with
+    /// all statistics), truncated to at most `n`; references with equal
+    /// scores are ranked by identifier.
+    pub fn query_top_n(
+        &self,
+        query_path: &str,
+        query_fingerprints: &[(u64, i32, i16, f32)],
+        n: usize,
+        config: &PanakoConfig,
+    ) -> Result<Vec<QueryResult>> {
+        let mut results = self.query(query_path, query_fingerprints, config)?;
+        results.truncate(n);
+        Ok(results)
+    }
 }
 
 impl Default for Matcher {
@@ -294,6 +1196,61 @@ impl Default for Matcher {
     }
 }
 
+/// Sort by (normalized) score descending, equal scores by identifier
+fn sort_by_score(results: &mut [QueryResult], config: &PanakoConfig) {
+    results.sort_by(|a, b| a.ref_identifier.cmp(&b.ref_identifier));
+    if config.normalize_score_by_ref_length {
+        results.sort_by(|a, b| {
+            b.normalized_score
+                .partial_cmp(&a.normalized_score)
+                .unwrap_or(std::cmp::Ordering::Equal)
+        });
+    } else {
+        results.sort_by_key(|r| std::cmp::Reverse(r.score));
+    }
+}
+
+/// Mean of the known reference magnitudes of `matches`
+fn mean_magnitude(matches: &[&Match]) -> Option<f64> {
+    let magnitudes: Vec<f64> = matches.iter().filter_map(|m| m.match_m1).map(f64::from).collect();
+    if magnitudes.is_empty() {
+        None
+    } else {
+        Some(magnitudes.iter().sum::<f64>() / magnitudes.len() as f64)
+    }
+}
+
+/// Most supported time offset and its count, ties broken by `tie_break`
+fn best_delta(histogram: &HashMap<i32, usize>, matches: &[Match], tie_break: DeltaTieBreak) -> (i32, usize) {
+    let Some(max_count) = histogram.values().copied().max() else {
+        return (0, 0);
+    };
+    let mut tied: Vec<i32> = histogram
+        .iter()
+        .filter(|(_, &count)| count == max_count)
+        .map(|(&delta, _)| delta)
+        .collect();
+    tied.sort_by_key(|&delta| (delta.abs(), delta));
+    
+    let best = match tie_break {
+        DeltaTieBreak::SmallestAbsolute => tied[0],
+        DeltaTieBreak::WidestSpread => {
+            let spread = |delta: i32| {
+                let times = matches.iter().filter(|m| m.delta_t() == delta).map(|m| m.query_time);
+                let (min, max) = times.fold((i32::MAX, i32::MIN), |(min, max), t| (min.min(t), max.max(t)));
+                max - min
+            };
+            // Stable max: the first (smallest absolute) of the widest wins
+            tied.iter()
+                .copied()
+                .rev()
+                .max_by_key(|&delta| spread(delta))
+                .unwrap_or(tied[0])
+        }
+    };
+    (best, max_count)
+}
+
 /// Calculate time factor (speed ratio) using linear regression
 /// Returns the slope of query_time vs match_time
 /// 1.0 = normal speed, > 1.0 = sped up, < 1.0 = slowed down
@@ -317,7 +1274,28 @@ fn calculate_time_factor(matches: &[&Match]) -> f64 {
     let slope = (n * sum_xy - sum_x * sum_y) / denominator;
     
     // Clamp to reasonable range (0.5x to 2.0x speed)
-    slope.max(0.5).min(2.0)
+    slope.clamp(0.5, 2.0)
+}
+
+/// Estimate the sub-frame part of the time offset between query and reference
+///
+/// When the true offset is not a whole number of frames, the quantized
+/// per-fingerprint deltas split between the two neighboring frames in
+/// proportion to the fractional part. The centroid of the delta distribution
+/// around the correlation peak (`best_delta` +/- 1) recovers that fraction.
+/// Returns the correction in frames relative to `best_delta`.
+fn estimate_sub_frame_offset(matches: &[&Match], best_delta: i32) -> f64 {
+    let (weighted_sum, count) = matches
+        .iter()
+        .map(|m| m.delta_t() - best_delta)
+        .filter(|d| d.abs() <= 1)
+        .fold((0i64, 0i64), |(sum, n), d| (sum + d as i64, n + 1));
+    
+    if count == 0 {
+        return 0.0;
+    }
+    
+    weighted_sum as f64 / count as f64
 }
 
 /// Calculate frequency factor (pitch ratio)
@@ -333,7 +1311,7 @@ fn calculate_frequency_factor(matches: &[&Match]) -> f64 {
         if m.query_f1 > 0 && m.match_f1 > 0 {
             let ratio = m.match_f1 as f64 / m.query_f1 as f64;
             // Only include reasonable ratios (within 2 octaves)
-            if ratio >= 0.25 && ratio <= 4.0 {
+            if (0.25..=4.0).contains(&ratio) {
                 ratios.push(ratio);
             }
         }
@@ -345,18 +1323,43 @@ fn calculate_frequency_factor(matches: &[&Match]) -> f64 {
     
     // Use median instead of mean to be robust to outliers
     ratios.sort_by(|a, b| a.partial_cmp(b).unwrap());
-    let median = if ratios.len() % 2 == 0 {
+    if ratios.len() % 2 == 0 {
         (ratios[ratios.len() / 2 - 1] + ratios[ratios.len() / 2]) / 2.0
     } else {
         ratios[ratios.len() / 2]
-    };
+    }
+}
+
+/// Fraction of the reference duration spanned by the matched reference times
+/// Returns value between 0.0 and 1.0
+fn calculate_ref_coverage(ref_start: f64, ref_stop: f64, ref_duration_ms: u32) -> f64 {
+    if ref_duration_ms == 0 {
+        return 0.0;
+    }
+    
+    let ref_duration_s = ref_duration_ms as f64 / 1000.0;
+    ((ref_stop - ref_start) / ref_duration_s).clamp(0.0, 1.0)
+}
+
+/// Fraction of the reference's seconds that contain a matched fingerprint
+/// Returns value between 0.0 and 1.0
+fn calculate_ref_seconds_covered(matches: &[&Match], ref_duration_ms: u32, frame_s: f64) -> f64 {
+    let total_seconds = (ref_duration_ms as f64 / 1000.0).ceil();
+    if total_seconds <= 0.0 {
+        return 0.0;
+    }
     
-    median
+    let covered_seconds: HashSet<i32> = matches
+        .iter()
+        .map(|m| (m.match_time as f64 * frame_s).floor() as i32)
+        .collect();
+    (covered_seconds.len() as f64 / total_seconds).min(1.0)
 }
 
 /// Calculate percentage of query seconds that have matches
+/// `query_start`/`query_stop` are the frame bounds of the span to cover
 /// Returns value between 0.0 and 1.0
-fn calculate_coverage(matches: &[&Match], query_start: i32, query_stop: i32) -> f64 {
+fn calculate_coverage(matches: &[&Match], query_start: i32, query_stop: i32, frame_s: f64) -> f64 {
     if matches.is_empty() || query_stop <= query_start {
         return 0.0;
     }
@@ -364,12 +1367,12 @@ fn calculate_coverage(matches: &[&Match], query_start: i32, query_stop: i32) ->
     // Count unique seconds that have matches
     let mut covered_seconds = std::collections::HashSet::new();
     for m in matches {
-        // Convert frame index to seconds (assuming ~8ms per frame)
-        let second = (m.query_time as f64 * 0.008).floor() as i32;
+        // Convert frame index to seconds
+        let second = (m.query_time as f64 * frame_s).floor() as i32;
         covered_seconds.insert(second);
     }
     
-    let total_seconds = ((query_stop - query_start) as f64 * 0.008).ceil() as i32;
+    let total_seconds = ((query_stop - query_start) as f64 * frame_s).ceil() as i32;
     if total_seconds <= 0 {
         return 0.0;
     }
@@ -0,0 +1,63 @@
+//! Bloom filter over indexed fingerprint hashes
+
+/// Probabilistic set of `u64` hashes with no false negatives
+///
+/// Uses double hashing over a 64-bit mix of the key to derive the probe
+/// positions, so it needs no external hashing state.
+#[derive(Debug, Clone)]
+pub(crate) struct HashBloomFilter {
+    bits: Vec<u64>,
+    num_bits: u64,
+    num_probes: u32,
+    bits_per_item: usize,
+}
+
+impl HashBloomFilter {
+    /// Create a filter sized for `expected_items` at `bits_per_item` bits each
+    ///
+    /// 10 bits per item gives roughly a 1% false positive rate.
+    pub(crate) fn new(expected_items: usize, bits_per_item: usize) -> Self {
+        let num_bits = (expected_items.max(1) * bits_per_item.max(1)).next_power_of_two().max(64) as u64;
+        let num_probes = ((bits_per_item as f64 * std::f64::consts::LN_2).round() as u32).clamp(1, 16);
+        Self {
+            bits: vec![0; (num_bits / 64) as usize],
+            num_bits,
+            num_probes,
+            bits_per_item,
+        }
+    }
+
+    /// Bits per item the filter was sized with
+    pub(crate) fn bits_per_item(&self) -> usize {
+        self.bits_per_item
+    }
+
+    pub(crate) fn insert(&mut self, hash: u64) {
+        for bit in probes(hash, self.num_bits, self.num_probes) {
+            self.bits[(bit / 64) as usize] |= 1 << (bit % 64);
+        }
+    }
+
+    /// False means the hash was never inserted; true means it probably was
+    pub(crate) fn may_contain(&self, hash: u64) -> bool {
+        probes(hash, self.num_bits, self.num_probes)
+            .all(|bit| self.bits[(bit / 64) as usize] & (1 << (bit % 64)) != 0)
+    }
+}
+
+/// Bit positions probed for a hash (double hashing)
+fn probes(hash: u64, num_bits: u64, num_probes: u32) -> impl Iterator<Item = u64> {
+    let h1 = splitmix64(hash);
+    let h2 = h1.rotate_left(32) | 1;
+    // num_bits is a power of two, so masking is a cheap modulo
+    let mask = num_bits - 1;
+    (0..num_probes as u64).map(move |i| h1.wrapping_add(i.wrapping_mul(h2)) & mask)
+}
+
+/// SplitMix64 finalizer: spreads structured fingerprint hashes over all bits
+fn splitmix64(mut x: u64) -> u64 {
+    x = x.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    x = (x ^ (x >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    x ^ (x >> 31)
+}
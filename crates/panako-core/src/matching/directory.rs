@@ -0,0 +1,126 @@
+//! Building a matcher from a fingerprint database directory
+//!
+//! `Matcher::from_directory` is the one place that turns a directory of
+//! fingerprint files (JSON, BSON and packs, auto-detected and loaded in
+//! parallel) into an index, so every tool indexes a database the same way.
+
+use anyhow::Result;
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+
+use super::Matcher;
+use crate::config::PanakoConfig;
+use crate::storage_backend::load_reference_dir;
+use crate::storage_config::CorruptFilePolicy;
+
+/// A matcher built from a database directory, with its load report
+pub struct DirectoryIndex {
+    /// Index of every reference that loaded
+    pub matcher: Matcher,
+    /// The configuration to query with: the requested one, switched to the
+    /// database's sample rate if it differs
+    pub config: PanakoConfig,
+    /// Number of references indexed
+    pub loaded: usize,
+    /// Files that failed to load and were skipped
+    /// (only filled with `CorruptFilePolicy::SkipWithWarning`)
+    pub skipped: Vec<PathBuf>,
+    /// Files that failed to load, left for the caller to report
+    /// (only filled with `CorruptFilePolicy::Collect`)
+    pub failed: Vec<PathBuf>,
+}
+
+impl Matcher {
+    /// Index every fingerprint file of a database directory
+    ///
+    /// Unreadable files are skipped with a warning; see `index_directory`
+    /// for the other policies and the effective configuration.
+    pub fn from_directory(dir: &Path, config: &PanakoConfig) -> Result<Matcher> {
+        let index = Self::index_directory(dir, config.clone(), CorruptFilePolicy::SkipWithWarning)?;
+        Ok(index.matcher)
+    }
+
+    /// Index a database directory with an explicit policy for corrupt files
+    ///
+    /// Indexes fingerprints, tags, content IDs, durations, paths,
+    /// registration times and fingerprint parameters of every reference,
+    /// then applies `max_hash_ref_fraction` and `bloom_filter_bits_per_hash`
+    /// from the config if set, and freezes the index (see `Matcher::freeze`).
+    /// All references must share one sample rate; if it differs from
+    /// `config.sample_rate`, the returned config is switched to it (see
+    /// `PanakoConfig::with_sample_rate`) so queries are analysed the same
+    /// way. With `CorruptFilePolicy::FailFast` the first unreadable file
+    /// fails the load; with `CorruptFilePolicy::Collect` the references that
+    /// loaded are indexed and the unreadable files are returned in `failed`.
+    pub fn index_directory(dir: &Path, config: PanakoConfig, policy: CorruptFilePolicy) -> Result<DirectoryIndex> {
+        let loaded = load_reference_dir(dir, policy)?;
+
+        let rates: BTreeSet<u32> = loaded.references.iter().map(|(_, f)| f.metadata.sample_rate).collect();
+        if rates.len() > 1 {
+            anyhow::bail!(
+                "Database {} mixes sample rates {:?}; rebuild it at a single rate",
+                dir.display(),
+                rates
+            );
+        }
+        let config = match rates.into_iter().next() {
+            Some(rate) if rate != config.sample_rate => {
+                log::info!("Database was built at {} Hz, matching at that rate", rate);
+                config.with_sample_rate(rate)
+            }
+            _ => config,
+        };
+        config.validate()?;
+
+        let mut matcher = Matcher::new();
+        matcher.set_hash_width(config.hash_width);
+        if let Some(step) = config.index_time_step {
+            matcher.quantize_times(step);
+        }
+        if config.index_magnitudes {
+            matcher.retain_magnitudes();
+        }
+        let count = loaded.references.len();
+        for (identifier, fp_file) in loaded.references {
+            let all_fps = fp_file.get_all_fingerprints();
+            matcher.add_fingerprints(identifier.clone(), &all_fps);
+            matcher.add_tags(identifier.clone(), fp_file.metadata.tags.clone());
+            if let Some(created_at) = fp_file.metadata.created_at_unix_s() {
+                matcher.add_registration_time(identifier.clone(), created_at);
+            }
+            if let Some(params) = fp_file.metadata.fingerprint_params {
+                matcher.add_fingerprint_params(identifier.clone(), params);
+            }
+            matcher.add_path(identifier.clone(), fp_file.metadata.original_path.clone());
+            if let Some(content_id) = fp_file.metadata.content_id {
+                matcher.add_content_id(identifier.clone(), content_id);
+            }
+            matcher.add_duration(identifier, fp_file.metadata.duration_ms);
+        }
+
+        if let Some(fraction) = config.max_hash_ref_fraction {
+            let removed = matcher.filter_common_hashes(fraction);
+            log::info!("Removed {} overly common hashes from the index", removed);
+        }
+        let mut matcher = matcher.freeze();
+        if let Some(bits_per_hash) = config.bloom_filter_bits_per_hash {
+            matcher.enable_bloom_filter(bits_per_hash);
+        }
+
+        log::info!(
+            "Indexed {} references from {} ({} files skipped, {} failed)",
+            count,
+            dir.display(),
+            loaded.skipped.len(),
+            loaded.failed.len()
+        );
+
+        Ok(DirectoryIndex {
+            matcher,
+            config,
+            loaded: count,
+            skipped: loaded.skipped,
+            failed: loaded.failed,
+        })
+    }
+}
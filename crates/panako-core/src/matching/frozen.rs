@@ -0,0 +1,122 @@
+//! Compact read-only in-memory index
+//!
+//! `Matcher::freeze` moves the postings of the hash map index into one
+//! flat array grouped by hash, plus a sorted array of `(hash, offset)` keys
+//! searched by binary search. Identifiers are stored once and referenced by
+//! index, so a posting takes 12 bytes instead of a `String` and a slot in a
+//! per-hash `Vec`, and the postings of a bucket are contiguous.
+
+use std::collections::HashMap;
+
+/// Postings of the hash map index: hash -> (identifier, t1, f1)
+pub(crate) type PostingMap = HashMap<u64, Vec<(String, i32, i16)>>;
+
+/// Magnitudes parallel to a `PostingMap`
+pub(crate) type MagnitudeMap = HashMap<u64, Vec<f32>>;
+
+/// Immutable index with the postings of each hash in one contiguous run
+pub(crate) struct FrozenIndex {
+    /// Hashes in ascending order with the offset of their first posting
+    keys: Vec<(u64, usize)>,
+    /// (reference index, t1, f1), grouped by hash in `keys` order
+    postings: Vec<(u32, i32, i16)>,
+    /// Magnitudes parallel to `postings`, when retained (NaN = unknown)
+    magnitudes: Option<Vec<f32>>,
+    identifiers: Vec<String>,
+}
+
+impl FrozenIndex {
+    /// Build from the hash map index, keeping the posting order of each hash
+    pub(crate) fn new(mut index: PostingMap, mut magnitudes: Option<MagnitudeMap>) -> Self {
+        let mut hashes: Vec<u64> = index.keys().copied().collect();
+        hashes.sort_unstable();
+
+        let mut ref_ids: HashMap<String, u32> = HashMap::new();
+        let mut identifiers = Vec::new();
+        let mut keys = Vec::with_capacity(hashes.len());
+        let mut postings = Vec::with_capacity(index.values().map(Vec::len).sum());
+        let mut flat_magnitudes = magnitudes.as_ref().map(|_| Vec::with_capacity(postings.capacity()));
+
+        for hash in hashes {
+            let bucket = index.remove(&hash).unwrap_or_default();
+            keys.push((hash, postings.len()));
+            if let Some(flat) = flat_magnitudes.as_mut() {
+                let mut values = magnitudes.as_mut().and_then(|m| m.remove(&hash)).unwrap_or_default();
+                values.resize(bucket.len(), f32::NAN);
+                flat.extend(values);
+            }
+            for (identifier, t1, f1) in bucket {
+                let id = match ref_ids.get(&identifier) {
+                    Some(&id) => id,
+                    None => {
+                        let id = identifiers.len() as u32;
+                        identifiers.push(identifier.clone());
+                        ref_ids.insert(identifier, id);
+                        id
+                    }
+                };
+                postings.push((id, t1, f1));
+            }
+        }
+
+        Self {
+            keys,
+            postings,
+            magnitudes: flat_magnitudes,
+            identifiers,
+        }
+    }
+
+    /// Back to the hash map index (and magnitudes, when retained)
+    pub(crate) fn into_index(self) -> (PostingMap, Option<MagnitudeMap>) {
+        let mut index = PostingMap::with_capacity(self.keys.len());
+        let mut magnitudes = self.magnitudes.as_ref().map(|_| MagnitudeMap::with_capacity(self.keys.len()));
+        for i in 0..self.keys.len() {
+            let (hash, range) = (self.keys[i].0, self.range(i));
+            let bucket = self.postings[range.clone()]
+                .iter()
+                .map(|&(id, t1, f1)| (self.identifiers[id as usize].clone(), t1, f1))
+                .collect();
+            index.insert(hash, bucket);
+            if let (Some(magnitudes), Some(flat)) = (magnitudes.as_mut(), self.magnitudes.as_ref()) {
+                magnitudes.insert(hash, flat[range].to_vec());
+            }
+        }
+        (index, magnitudes)
+    }
+
+    /// Posting range of the `i`-th key
+    fn range(&self, i: usize) -> std::ops::Range<usize> {
+        let end = self.keys.get(i + 1).map_or(self.postings.len(), |&(_, offset)| offset);
+        self.keys[i].1..end
+    }
+
+    fn find(&self, hash: u64) -> Option<std::ops::Range<usize>> {
+        self.keys
+            .binary_search_by_key(&hash, |&(key, _)| key)
+            .ok()
+            .map(|i| self.range(i))
+    }
+
+    /// All indexed hashes in ascending order
+    pub(crate) fn hashes(&self) -> impl Iterator<Item = u64> + '_ {
+        self.keys.iter().map(|&(hash, _)| hash)
+    }
+
+    pub(crate) fn num_hashes(&self) -> usize {
+        self.keys.len()
+    }
+
+    pub(crate) fn num_postings(&self) -> usize {
+        self.postings.len()
+    }
+
+    /// Postings of `hash`: (identifier, t1, f1, magnitude if known)
+    pub(crate) fn postings(&self, hash: u64) -> impl ExactSizeIterator<Item = (&str, i32, i16, Option<f32>)> + '_ {
+        self.find(hash).unwrap_or(0..0).map(move |p| {
+            let (id, t1, f1) = self.postings[p];
+            let m1 = self.magnitudes.as_ref().map(|flat| flat[p]).filter(|m| !m.is_nan());
+            (self.identifiers[id as usize].as_str(), t1, f1, m1)
+        })
+    }
+}
@@ -0,0 +1,142 @@
+//! Matching against a storage backend without loading the whole catalog
+//!
+//! `LazyMatcher` fetches the postings of a query's hashes from a
+//! `StorageBackend` (e.g. an indexed PostgreSQL hash lookup) on first use and
+//! caches them, together with the metadata of the references they belong to.
+//! Only the part of the catalog the session's queries touch is ever indexed,
+//! which suits huge catalogs that are rarely queried in full.
+//!
+//! The index is set up like `Matcher::index_directory` (hash width, time
+//! quantization, magnitudes) and every fetched reference must share the
+//! configured sample rate. Options that need the whole catalog up front
+//! (`max_hash_ref_fraction`, `bloom_filter_bits_per_hash`) are rejected.
+
+use anyhow::Result;
+use std::collections::{BTreeSet, HashSet};
+
+use super::{Matcher, QueryResult};
+use crate::config::PanakoConfig;
+use crate::storage_backend::StorageBackend;
+
+/// A matcher whose index is filled from a storage backend on demand
+///
+/// Results are the same as those of a `Matcher` holding the whole catalog,
+/// except `normalized_score`, which is relative to the fingerprints fetched
+/// so far rather than to all fingerprints of the reference.
+pub struct LazyMatcher<'b> {
+    backend: &'b dyn StorageBackend,
+    matcher: Matcher,
+    /// Hashes whose postings are in `matcher`
+    fetched_hashes: HashSet<u64>,
+    /// References whose metadata is in `matcher`
+    known_references: HashSet<String>,
+    /// Sample rate every reference must have been fingerprinted at
+    sample_rate: u32,
+}
+
+impl<'b> LazyMatcher<'b> {
+    /// An empty matcher over `backend`, indexing with `config`'s settings
+    ///
+    /// Fails if the config is invalid or sets an option that needs the
+    /// whole catalog to be indexed first.
+    pub fn new(backend: &'b dyn StorageBackend, config: &PanakoConfig) -> Result<Self> {
+        config.validate()?;
+        if config.max_hash_ref_fraction.is_some() {
+            anyhow::bail!("max_hash_ref_fraction is not supported with lazy matching: it needs the whole catalog indexed");
+        }
+        if config.bloom_filter_bits_per_hash.is_some() {
+            anyhow::bail!("bloom_filter_bits_per_hash is not supported with lazy matching: it needs the whole catalog indexed");
+        }
+
+        let mut matcher = Matcher::new();
+        matcher.set_hash_width(config.hash_width);
+        if let Some(step) = config.index_time_step {
+            matcher.quantize_times(step);
+        }
+        if config.index_magnitudes {
+            matcher.retain_magnitudes();
+        }
+        Ok(Self {
+            backend,
+            matcher,
+            fetched_hashes: HashSet::new(),
+            known_references: HashSet::new(),
+            sample_rate: config.sample_rate,
+        })
+    }
+
+    /// Number of hashes fetched from the backend so far
+    pub fn fetched_hashes(&self) -> usize {
+        self.fetched_hashes.len()
+    }
+
+    /// Number of references seen in fetched postings so far
+    pub fn known_references(&self) -> usize {
+        self.known_references.len()
+    }
+
+    /// Match query fingerprints, fetching the postings of unseen hashes first
+    ///
+    /// All hashes of the query not fetched by an earlier query are looked up
+    /// in one backend call, limited to the index's hash width; references
+    /// appearing for the first time also get their duration, path, tags,
+    /// content ID and registration time from the backend. Fails if such a
+    /// reference was fingerprinted at another sample rate than the config's.
+    pub async fn query(
+        &mut self,
+        query_path: &str,
+        query_fingerprints: &[(u64, i32, i16, f32)],
+        config: &PanakoConfig,
+    ) -> Result<Vec<QueryResult>> {
+        let missing: Vec<u64> = query_fingerprints
+            .iter()
+            .map(|fp| self.matcher.hash_width().key(fp.0))
+            .filter(|hash| !self.fetched_hashes.contains(hash))
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .collect();
+
+        if !missing.is_empty() {
+            let references = self.backend.fingerprints_by_hashes(&missing).await?;
+            log::debug!(
+                "Fetched {} hashes: postings in {} references",
+                missing.len(),
+                references.len()
+            );
+            for (identifier, fingerprints) in references {
+                if self.known_references.insert(identifier.clone()) {
+                    self.add_metadata(&identifier).await?;
+                }
+                self.matcher.add_fingerprints(identifier, &fingerprints);
+            }
+            self.fetched_hashes.extend(missing);
+        }
+
+        self.matcher.query(query_path, query_fingerprints, config)
+    }
+
+    /// Index the metadata of a newly seen reference
+    async fn add_metadata(&mut self, identifier: &str) -> Result<()> {
+        let Some(metadata) = self.backend.get_metadata(identifier).await? else {
+            return Ok(());
+        };
+        if metadata.sample_rate != self.sample_rate {
+            anyhow::bail!(
+                "Reference {} was fingerprinted at {} Hz but matching uses {} Hz",
+                identifier,
+                metadata.sample_rate,
+                self.sample_rate
+            );
+        }
+        if let Some(created_at) = metadata.created_at_unix_s() {
+            self.matcher.add_registration_time(identifier.to_string(), created_at);
+        }
+        if let Some(content_id) = metadata.content_id {
+            self.matcher.add_content_id(identifier.to_string(), content_id);
+        }
+        self.matcher.add_tags(identifier.to_string(), metadata.tags);
+        self.matcher.add_path(identifier.to_string(), metadata.original_path);
+        self.matcher.add_duration(identifier.to_string(), metadata.duration_ms);
+        Ok(())
+    }
+}
@@ -0,0 +1,336 @@
+//! Compact on-disk index that can be memory-mapped by the `Matcher`
+//!
+//! Layout (all integers little-endian):
+//!
+//! | section  | content                                                      |
+//! |----------|--------------------------------------------------------------|
+//! | header   | magic `PNKIDX01`, version u32, reference count u32,          |
+//! |          | hash count u64, posting count u64, metadata length u64,      |
+//! |          | key size u32 (8; 4-byte keys are also read), reserved u32   |
+//! | hashes   | distinct hashes as u32 or u64, sorted ascending              |
+//! | offsets  | hash count + 1 posting offsets as u64                        |
+//! | postings | reference index u32, t1 i32, f1 i16, padding u16             |
+//! | metadata | JSON with names, paths, durations, tags, extents, counts    |
+//! |          | registration times and content IDs                           |
+//!
+//! The postings of `hashes[i]` are `postings[offsets[i]..offsets[i + 1]]`,
+//! in the order they were added. Version 1 files have no key size field
+//! (40-byte header) and always store u64 hashes. Only the (small) metadata is loaded into
+//! memory; hashes are found by binary search over the mapped file.
+
+use anyhow::{Context, Result};
+use memmap2::Mmap;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use crate::config::HashWidth;
+
+const MAGIC: &[u8; 8] = b"PNKIDX01";
+const VERSION: u32 = 2;
+const HEADER_LEN: usize = 48;
+const HEADER_LEN_V1: usize = 40;
+const POSTING_LEN: usize = 12;
+
+/// Per-reference data kept in the metadata section
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub(crate) struct IndexMetadata {
+    /// Reference identifiers, indexed by the postings' reference index
+    pub(crate) identifiers: Vec<String>,
+    pub(crate) durations: HashMap<String, u32>,
+    pub(crate) tags: HashMap<String, HashMap<String, String>>,
+    pub(crate) extents: HashMap<String, i32>,
+    #[serde(default)]
+    pub(crate) fingerprint_counts: HashMap<String, usize>,
+    #[serde(default)]
+    pub(crate) paths: HashMap<String, String>,
+    #[serde(default)]
+    pub(crate) registered_at: HashMap<String, i64>,
+    #[serde(default)]
+    pub(crate) content_ids: HashMap<String, String>,
+}
+
+/// Writes fingerprints in the memory-mappable index format
+///
+/// Postings are kept as compact fixed-size records until `write`, so
+/// building needs far less memory than an in-memory `Matcher`.
+#[derive(Debug, Default)]
+pub struct MmapIndexBuilder {
+    /// (hash, reference index, t1, f1)
+    postings: Vec<(u64, u32, i32, i16)>,
+    ref_ids: HashMap<String, u32>,
+    metadata: IndexMetadata,
+}
+
+impl MmapIndexBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn ref_index(&mut self, identifier: &str) -> u32 {
+        if let Some(&index) = self.ref_ids.get(identifier) {
+            return index;
+        }
+        let index = self.metadata.identifiers.len() as u32;
+        self.metadata.identifiers.push(identifier.to_string());
+        self.ref_ids.insert(identifier.to_string(), index);
+        index
+    }
+
+    /// Add fingerprints of a reference, as `Matcher::add_fingerprints`
+    pub fn add_fingerprints(&mut self, identifier: String, fingerprints: &[(u64, i32, i16, f32)]) {
+        let index = self.ref_index(&identifier);
+        if let Some(max_t1) = fingerprints.iter().map(|fp| fp.1).max() {
+            let extent = self.metadata.extents.entry(identifier.clone()).or_insert(max_t1);
+            *extent = (*extent).max(max_t1);
+        }
+        *self.metadata.fingerprint_counts.entry(identifier).or_insert(0) += fingerprints.len();
+        self.postings
+            .extend(fingerprints.iter().map(|&(hash, t1, f1, _m1)| (hash, index, t1, f1)));
+    }
+
+    /// Add reference duration
+    pub fn add_duration(&mut self, identifier: String, duration_ms: u32) {
+        self.metadata.durations.insert(identifier, duration_ms);
+    }
+
+    /// Add the original path of the reference's source file
+    pub fn add_path(&mut self, identifier: String, original_path: String) {
+        self.metadata.paths.insert(identifier, original_path);
+    }
+
+    /// Add the Unix time (seconds) the reference was registered at
+    pub fn add_registration_time(&mut self, identifier: String, registered_at_s: i64) {
+        self.metadata.registered_at.insert(identifier, registered_at_s);
+    }
+
+    /// Add the reference's external content ID
+    pub fn add_content_id(&mut self, identifier: String, content_id: String) {
+        self.metadata.content_ids.insert(identifier, content_id);
+    }
+
+    /// Add reference tags
+    pub fn add_tags(&mut self, identifier: String, tags: HashMap<String, String>) {
+        if !tags.is_empty() {
+            self.metadata.tags.insert(identifier, tags);
+        }
+    }
+
+    /// Sort the postings by hash and write the index file
+    pub fn write(mut self, path: &Path) -> Result<()> {
+        // Stable sort keeps the insertion order within a hash, as in `Matcher`
+        self.postings.sort_by_key(|posting| posting.0);
+
+        let mut hashes: Vec<u64> = Vec::new();
+        let mut offsets: Vec<u64> = Vec::new();
+        for (i, posting) in self.postings.iter().enumerate() {
+            if hashes.last() != Some(&posting.0) {
+                hashes.push(posting.0);
+                offsets.push(i as u64);
+            }
+        }
+        offsets.push(self.postings.len() as u64);
+
+        let metadata = serde_json::to_vec(&self.metadata)?;
+
+        let file = File::create(path)
+            .with_context(|| format!("Failed to create index file {}", path.display()))?;
+        let mut writer = BufWriter::new(file);
+        writer.write_all(MAGIC)?;
+        writer.write_all(&VERSION.to_le_bytes())?;
+        writer.write_all(&(self.metadata.identifiers.len() as u32).to_le_bytes())?;
+        writer.write_all(&(hashes.len() as u64).to_le_bytes())?;
+        writer.write_all(&(self.postings.len() as u64).to_le_bytes())?;
+        writer.write_all(&(metadata.len() as u64).to_le_bytes())?;
+        writer.write_all(&8u32.to_le_bytes())?;
+        writer.write_all(&0u32.to_le_bytes())?;
+        for &hash in &hashes {
+            writer.write_all(&hash.to_le_bytes())?;
+        }
+        for offset in &offsets {
+            writer.write_all(&offset.to_le_bytes())?;
+        }
+        for &(_, index, t1, f1) in &self.postings {
+            writer.write_all(&index.to_le_bytes())?;
+            writer.write_all(&t1.to_le_bytes())?;
+            writer.write_all(&f1.to_le_bytes())?;
+            writer.write_all(&[0, 0])?;
+        }
+        writer.write_all(&metadata)?;
+        writer.flush()?;
+
+        log::debug!(
+            "Wrote index {}: {} references, {} hashes, {} postings",
+            path.display(),
+            self.metadata.identifiers.len(),
+            hashes.len(),
+            self.postings.len()
+        );
+        Ok(())
+    }
+}
+
+/// Read-only view of a memory-mapped index file
+pub(crate) struct MappedIndex {
+    mmap: Mmap,
+    num_hashes: usize,
+    hashes_start: usize,
+    key_len: usize,
+    offsets_start: usize,
+    postings_start: usize,
+    identifiers: Vec<String>,
+}
+
+impl MappedIndex {
+    /// Map an index file, returning it with its metadata
+    pub(crate) fn open(path: &Path) -> Result<(Self, IndexMetadata)> {
+        let file = File::open(path)
+            .with_context(|| format!("Failed to open index file {}", path.display()))?;
+        // SAFETY: the index file is treated as immutable while mapped; it is
+        // only ever written whole by `MmapIndexBuilder::write`.
+        let mmap = unsafe { Mmap::map(&file) }
+            .with_context(|| format!("Failed to map index file {}", path.display()))?;
+
+        if mmap.len() < HEADER_LEN_V1 || &mmap[..8] != MAGIC {
+            anyhow::bail!("{} is not a Panako index file", path.display());
+        }
+        let version = u32::from_le_bytes(mmap[8..12].try_into().unwrap());
+        let (hashes_start, key_len) = match version {
+            1 => (HEADER_LEN_V1, 8),
+            VERSION if mmap.len() >= HEADER_LEN => {
+                (HEADER_LEN, u32::from_le_bytes(mmap[40..44].try_into().unwrap()) as usize)
+            }
+            _ => anyhow::bail!("Unsupported index version {} in {}", version, path.display()),
+        };
+        if key_len != 4 && key_len != 8 {
+            anyhow::bail!("Unsupported key size {} in index file {}", key_len, path.display());
+        }
+        let num_refs = u32::from_le_bytes(mmap[12..16].try_into().unwrap()) as usize;
+        let num_hashes = u64::from_le_bytes(mmap[16..24].try_into().unwrap()) as usize;
+        let num_postings = u64::from_le_bytes(mmap[24..32].try_into().unwrap()) as usize;
+        let metadata_len = u64::from_le_bytes(mmap[32..40].try_into().unwrap()) as usize;
+
+        let sections = (|| {
+            let offsets_start = hashes_start.checked_add(num_hashes.checked_mul(key_len)?)?;
+            let postings_start = offsets_start.checked_add(num_hashes.checked_add(1)?.checked_mul(8)?)?;
+            let metadata_start = postings_start.checked_add(num_postings.checked_mul(POSTING_LEN)?)?;
+            let end = metadata_start.checked_add(metadata_len)?;
+            Some((offsets_start, postings_start, metadata_start, end))
+        })();
+        let (offsets_start, postings_start, metadata_start) = match sections {
+            Some((offsets_start, postings_start, metadata_start, end)) if end == mmap.len() => {
+                (offsets_start, postings_start, metadata_start)
+            }
+            _ => anyhow::bail!(
+                "Index file {} is truncated or corrupt ({} bytes, header declares {} hashes, {} postings)",
+                path.display(),
+                mmap.len(),
+                num_hashes,
+                num_postings
+            ),
+        };
+
+        let metadata: IndexMetadata = serde_json::from_slice(&mmap[metadata_start..])
+            .with_context(|| format!("Invalid metadata in index file {}", path.display()))?;
+        if metadata.identifiers.len() != num_refs {
+            anyhow::bail!("Index file {} has inconsistent reference count", path.display());
+        }
+
+        let index = Self {
+            mmap,
+            num_hashes,
+            hashes_start,
+            key_len,
+            offsets_start,
+            postings_start,
+            identifiers: metadata.identifiers.clone(),
+        };
+        index
+            .validate(num_postings)
+            .with_context(|| format!("Index file {} is corrupt", path.display()))?;
+        Ok((index, metadata))
+    }
+
+    /// Check the bucket offsets and posting reference indexes, so lookups
+    /// can slice the mapped file without bounds failures
+    fn validate(&self, num_postings: usize) -> Result<()> {
+        let mut previous = 0;
+        for i in 0..=self.num_hashes {
+            let offset = self.read_u64(self.offsets_start + i * 8);
+            if offset < previous || offset > num_postings as u64 {
+                anyhow::bail!("bucket offset {} of hash {} is out of order or past {} postings", offset, i, num_postings);
+            }
+            previous = offset;
+        }
+        for p in 0..num_postings {
+            let pos = self.postings_start + p * POSTING_LEN;
+            let index = u32::from_le_bytes(self.mmap[pos..pos + 4].try_into().unwrap()) as usize;
+            if index >= self.identifiers.len() {
+                anyhow::bail!("posting {} refers to reference {} of {}", p, index, self.identifiers.len());
+            }
+        }
+        Ok(())
+    }
+
+    fn read_u64(&self, pos: usize) -> u64 {
+        u64::from_le_bytes(self.mmap[pos..pos + 8].try_into().unwrap())
+    }
+
+    fn hash_at(&self, i: usize) -> u64 {
+        let pos = self.hashes_start + i * self.key_len;
+        match self.key_len {
+            4 => u32::from_le_bytes(self.mmap[pos..pos + 4].try_into().unwrap()) as u64,
+            _ => self.read_u64(pos),
+        }
+    }
+
+    /// Width of the stored keys
+    pub(crate) fn hash_width(&self) -> HashWidth {
+        if self.key_len == 4 {
+            HashWidth::Bits32
+        } else {
+            HashWidth::Bits64
+        }
+    }
+
+    /// All indexed hashes in ascending order
+    pub(crate) fn hashes(&self) -> impl Iterator<Item = u64> + '_ {
+        (0..self.num_hashes).map(|i| self.hash_at(i))
+    }
+
+    pub(crate) fn num_hashes(&self) -> usize {
+        self.num_hashes
+    }
+
+    /// Postings of `hash`: (identifier, t1, f1)
+    pub(crate) fn postings(&self, hash: u64) -> impl ExactSizeIterator<Item = (&str, i32, i16)> + '_ {
+        let range = self.find(hash).map_or(0..0, |i| {
+            self.read_u64(self.offsets_start + i * 8) as usize
+                ..self.read_u64(self.offsets_start + (i + 1) * 8) as usize
+        });
+        range.map(move |p| {
+            let pos = self.postings_start + p * POSTING_LEN;
+            let bytes = &self.mmap[pos..pos + POSTING_LEN];
+            let index = u32::from_le_bytes(bytes[0..4].try_into().unwrap()) as usize;
+            let t1 = i32::from_le_bytes(bytes[4..8].try_into().unwrap());
+            let f1 = i16::from_le_bytes(bytes[8..10].try_into().unwrap());
+            (self.identifiers[index].as_str(), t1, f1)
+        })
+    }
+
+    /// Binary search for the position of `hash` in the sorted hash array
+    fn find(&self, hash: u64) -> Option<usize> {
+        let (mut low, mut high) = (0, self.num_hashes);
+        while low < high {
+            let mid = low + (high - low) / 2;
+            match self.hash_at(mid).cmp(&hash) {
+                std::cmp::Ordering::Less => low = mid + 1,
+                std::cmp::Ordering::Greater => high = mid,
+                std::cmp::Ordering::Equal => return Some(mid),
+            }
+        }
+        None
+    }
+}
@@ -0,0 +1,108 @@
+//! Matching that stops reading the query once a match is certain
+//!
+//! To tell whether a long recording contains known content, the whole query
+//! rarely needs matching: `Matcher::query_progressive` feeds the query
+//! fingerprints in time order and stops as soon as a reference has enough
+//! aligned matches.
+
+use anyhow::Result;
+use std::collections::HashMap;
+
+use super::{sort_by_score, MatchState, Matcher, QueryResult};
+use crate::config::PanakoConfig;
+
+/// Result of `Matcher::query_progressive`
+#[derive(Debug, Clone)]
+pub struct ProgressiveMatch {
+    /// Results of matching the consumed part of the query, as `Matcher::query`
+    pub results: Vec<QueryResult>,
+    /// Query fingerprints matched before stopping, earliest first
+    pub consumed_fingerprints: usize,
+    /// All query fingerprints
+    pub total_fingerprints: usize,
+    /// Query time (seconds) of the last consumed fingerprint
+    pub consumed_until_s: f64,
+    /// Stopped on a strong match before the end of the query
+    pub early_exit: bool,
+}
+
+impl Matcher {
+    /// Match a query progressively, stopping on the first strong match
+    ///
+    /// Fingerprints are consumed in time order while the aligned matches of
+    /// every reference (same offset within `query_range`) are counted. Once
+    /// a reference reaches `min_score`, the matches found so far are scored
+    /// as `query` would score the consumed part (at most once per second of
+    /// query) and returned if a result scores at least `min_score`.
+    /// Otherwise the results are those of `query` on the whole query. Index
+    /// lookups are made once per fingerprint.
+    pub fn query_progressive(
+        &self,
+        query_path: &str,
+        query_fingerprints: &[(u64, i32, i16, f32)],
+        min_score: usize,
+        config: &PanakoConfig,
+    ) -> Result<ProgressiveMatch> {
+        let mut ordered = query_fingerprints.to_vec();
+        ordered.sort_by_key(|fp| fp.1);
+        let frame_s = config.frame_duration_s();
+        let frames_per_check = (1.0 / frame_s).ceil() as i32;
+
+        // identifier -> delta_t -> number of matches
+        let mut deltas: HashMap<String, HashMap<i32, usize>> = HashMap::new();
+        let mut matches = Vec::new();
+        let mut state = MatchState::default();
+        let mut next_check = i32::MIN;
+        for consumed in 1..=ordered.len() {
+            let fingerprint = ordered[consumed - 1];
+            let mut strong = false;
+            let new_matches = self.find_matches(std::slice::from_ref(&fingerprint), config, &mut state);
+            for m in &new_matches {
+                let delta = m.delta_t();
+                if let Some(histogram) = deltas.get_mut(&m.identifier) {
+                    *histogram.entry(delta).or_default() += 1;
+                } else {
+                    deltas.insert(m.identifier.clone(), HashMap::from([(delta, 1)]));
+                }
+                let histogram = &deltas[&m.identifier];
+                let aligned: usize = (delta - config.query_range..=delta + config.query_range)
+                    .filter_map(|d| histogram.get(&d))
+                    .sum();
+                strong |= aligned >= min_score;
+            }
+            matches.extend(new_matches);
+            if !strong || fingerprint.1 < next_check {
+                continue;
+            }
+            next_check = fingerprint.1 + frames_per_check;
+
+            let mut results = self.results_from_matches(query_path, matches.clone(), &ordered[..consumed], config)?;
+            sort_by_score(&mut results, config);
+            if results.iter().any(|result| result.score.max(0) as usize >= min_score) {
+                log::debug!(
+                    "Strong match after {} of {} query fingerprints ({:.1}s)",
+                    consumed,
+                    ordered.len(),
+                    fingerprint.1 as f64 * frame_s
+                );
+                return Ok(ProgressiveMatch {
+                    results,
+                    consumed_fingerprints: consumed,
+                    total_fingerprints: ordered.len(),
+                    consumed_until_s: fingerprint.1 as f64 * frame_s,
+                    early_exit: consumed < ordered.len(),
+                });
+            }
+        }
+
+        let mut results = self.results_from_matches(query_path, matches, &ordered, config)?;
+        sort_by_score(&mut results, config);
+        Ok(ProgressiveMatch {
+            results,
+            consumed_fingerprints: ordered.len(),
+            total_fingerprints: ordered.len(),
+            consumed_until_s: ordered.last().map_or(0.0, |fp| fp.1 as f64 * frame_s),
+            early_exit: false,
+        })
+    }
+}
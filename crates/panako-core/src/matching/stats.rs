@@ -0,0 +1,118 @@
+//! Inverted index statistics and export for offline analysis
+//!
+//! `Matcher::export_index_stats` summarizes how postings are spread over the
+//! hash buckets: long buckets are hashes many fingerprints share, which
+//! inflate candidate lists and point at over-matching content, while a
+//! reference whose hashes all sit in singleton buckets can only match itself.
+//! `Matcher::write_index_csv` dumps every posting as a CSV row for tools such
+//! as pandas or DuckDB.
+
+use anyhow::Result;
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::io::Write;
+
+use super::Matcher;
+
+/// Bucket statistics of a matcher's inverted index
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct IndexStats {
+    /// Number of distinct indexed hashes (buckets)
+    pub total_hashes: usize,
+    /// Number of postings over all buckets
+    pub total_postings: usize,
+    /// Mean number of postings per bucket (0 for an empty index)
+    pub mean_bucket_size: f64,
+    /// Largest number of postings in one bucket
+    pub max_bucket_size: usize,
+    /// Number of buckets per bucket size (postings per hash)
+    pub bucket_size_histogram: BTreeMap<usize, usize>,
+    /// Number of buckets per number of distinct references in the bucket;
+    /// every entry above 1 is a hash shared by several references
+    pub references_per_hash_histogram: BTreeMap<usize, usize>,
+    /// Number of hashes indexed for more than one reference
+    pub colliding_hashes: usize,
+}
+
+impl Matcher {
+    /// Indexed hashes in ascending order, in memory (frozen or not) and
+    /// memory-mapped
+    pub(super) fn indexed_hashes(&self) -> BTreeSet<u64> {
+        self.index
+            .keys()
+            .copied()
+            .chain(self.frozen.iter().flat_map(|frozen| frozen.hashes()))
+            .chain(self.mapped.iter().flat_map(|mapped| mapped.hashes()))
+            .collect()
+    }
+
+    /// Postings `(identifier, t1, f1)` of a hash, in memory and memory-mapped
+    pub(super) fn all_postings(&self, hash: u64) -> impl Iterator<Item = (&str, i32, i16)> + '_ {
+        self.in_memory_postings(hash)
+            .chain(self.mapped.iter().flat_map(move |mapped| mapped.postings(hash)))
+    }
+
+    /// Bucket statistics of the index (in-memory and memory-mapped postings)
+    pub fn export_index_stats(&self) -> IndexStats {
+        let mut stats = IndexStats {
+            total_hashes: 0,
+            total_postings: 0,
+            mean_bucket_size: 0.0,
+            max_bucket_size: 0,
+            bucket_size_histogram: BTreeMap::new(),
+            references_per_hash_histogram: BTreeMap::new(),
+            colliding_hashes: 0,
+        };
+
+        for hash in self.indexed_hashes() {
+            let mut size = 0;
+            let mut references = HashSet::new();
+            for (id, _, _) in self.all_postings(hash) {
+                size += 1;
+                references.insert(id);
+            }
+            stats.total_hashes += 1;
+            stats.total_postings += size;
+            stats.max_bucket_size = stats.max_bucket_size.max(size);
+            *stats.bucket_size_histogram.entry(size).or_default() += 1;
+            *stats.references_per_hash_histogram.entry(references.len()).or_default() += 1;
+            if references.len() > 1 {
+                stats.colliding_hashes += 1;
+            }
+        }
+        if stats.total_hashes > 0 {
+            stats.mean_bucket_size = stats.total_postings as f64 / stats.total_hashes as f64;
+        }
+
+        stats
+    }
+
+    /// Write every posting as a CSV row `hash,identifier,t1,f1`
+    ///
+    /// Rows are ordered by hash, then reference, then time, so dumps of the
+    /// same index are identical. Identifiers are quoted when they contain a
+    /// comma, quote or line break. Returns the number of rows written.
+    pub fn write_index_csv<W: Write>(&self, writer: &mut W) -> Result<usize> {
+        writeln!(writer, "hash,identifier,t1,f1")?;
+        let mut rows = 0;
+        for hash in self.indexed_hashes() {
+            let mut postings: Vec<_> = self.all_postings(hash).collect();
+            postings.sort_unstable();
+            for (id, t1, f1) in postings {
+                writeln!(writer, "{},{},{},{}", hash, csv_field(id), t1, f1)?;
+                rows += 1;
+            }
+        }
+        writer.flush()?;
+        Ok(rows)
+    }
+}
+
+/// Quote a CSV field if it needs it
+fn csv_field(value: &str) -> std::borrow::Cow<'_, str> {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\"")).into()
+    } else {
+        value.into()
+    }
+}
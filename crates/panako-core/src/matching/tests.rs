@@ -1,7 +1,7 @@
 //! Tests for matching algorithm
 
 use super::*;
-use crate::config::PanakoConfig;
+use crate::config::{DeltaTieBreak, HashWidth, PanakoConfig};
 
 #[test]
 fn test_matcher_basic() {
@@ -16,6 +16,12 @@ fn test_matcher_basic() {
         (22222, 400, 80, 1.0),
         (33333, 500, 90, 1.0),
         (44444, 600, 100, 1.0),
+        (55555, 700, 110, 1.0),
+        (66666, 800, 120, 1.0),
+        (77777, 900, 130, 1.0),
+        (88888, 1000, 140, 1.0),
+        (99999, 1100, 150, 1.0),
+        (10101, 1200, 160, 1.0),
     ];
     
     matcher.add_fingerprints("test_ref".to_string(), &ref_fps);
@@ -28,11 +34,601 @@ fn test_matcher_basic() {
         .query("test_query", &query_fps, &config)
         .unwrap();
     
-    // Should find a match (we have 6 aligned matches)
+    // Should find a match (we have 12 aligned matches)
     assert!(!results.is_empty());
     assert_eq!(results[0].ref_identifier, Some("test_ref".to_string()));
 }
 
+#[test]
+fn test_reference_tags_in_result() {
+    let mut matcher = Matcher::new();
+    let config = PanakoConfig::default();
+    
+    let ref_fps: Vec<(u64, i32, i16, f32)> = (0..12)
+        .map(|i| (100 + i as u64, i * 100, 50, 1.0))
+        .collect();
+    matcher.add_fingerprints("tagged".to_string(), &ref_fps);
+    
+    let mut tags = HashMap::new();
+    tags.insert("campaign".to_string(), "spring-2025".to_string());
+    matcher.add_tags("tagged".to_string(), tags.clone());
+    
+    let results = matcher.query("query", &ref_fps, &config).unwrap();
+    assert_eq!(results[0].tags, Some(tags));
+    
+    let json = serde_json::to_string(&results[0]).unwrap();
+    assert!(json.contains("spring-2025"));
+}
+
+#[test]
+fn test_pruned_reference_still_matches() {
+    use panako_fp::{FpJsonFile, FpJsonFingerprint, FpJsonSegment};
+    
+    let fingerprints: Vec<FpJsonFingerprint> = (0..60)
+        .map(|i| FpJsonFingerprint { hash: 500 + i, t1: i as i32 * 40, f1: 50, m1: 1.0 })
+        .collect();
+    let mut fp_file = FpJsonFile::new("/ref.wav".to_string(), "ref".to_string(), 16000, 20000, 1);
+    fp_file.add_segment(FpJsonSegment {
+        segment_id: 0,
+        start_time_s: 0.0,
+        end_time_s: 20.0,
+        num_fingerprints: fingerprints.len(),
+        fingerprints,
+    });
+    let query_fps = fp_file.get_all_fingerprints();
+    
+    fp_file.prune(0.5).unwrap();
+    
+    let mut matcher = Matcher::new();
+    matcher.add_fingerprints("ref".to_string(), &fp_file.get_all_fingerprints());
+    
+    let results = matcher.query("query", &query_fps, &PanakoConfig::default()).unwrap();
+    assert_eq!(results.len(), 1);
+    assert_eq!(results[0].ref_identifier, Some("ref".to_string()));
+    assert_eq!(results[0].score, 30);
+}
+
+#[test]
+fn test_min_ref_coverage() {
+    let mut matcher = Matcher::new();
+    
+    // 25 second reference; the query contains only its first 5 seconds (20%)
+    let ref_fps: Vec<(u64, i32, i16, f32)> = (0..63)
+        .map(|i| (900 + i as u64, i * 50, 50, 1.0))
+        .collect();
+    matcher.add_fingerprints("ad".to_string(), &ref_fps);
+    matcher.add_duration("ad".to_string(), 25000);
+    let query_fps: Vec<(u64, i32, i16, f32)> = ref_fps[..13].to_vec();
+    
+    let results = matcher.query("clip", &query_fps, &PanakoConfig::default()).unwrap();
+    assert_eq!(results.len(), 1);
+    
+    let config = PanakoConfig {
+        min_ref_coverage: Some(0.8),
+        ..PanakoConfig::default()
+    };
+    assert!(matcher.query("clip", &query_fps, &config).unwrap().is_empty());
+    
+    // The full reference passes
+    let results = matcher.query("full", &ref_fps, &config).unwrap();
+    assert_eq!(results.len(), 1);
+}
+
+#[test]
+fn test_absolute_positions_without_duration() {
+    let mut matcher = Matcher::new();
+    
+    // Reference fingerprints up to frame 1100 (8.8 s); no add_duration call
+    let ref_fps: Vec<(u64, i32, i16, f32)> = (0..12)
+        .map(|i| (300 + i as u64, i * 100, 50, 1.0))
+        .collect();
+    matcher.add_fingerprints("undated".to_string(), &ref_fps);
+    
+    // Query contains the reference starting 20 s in
+    let query_fps: Vec<(u64, i32, i16, f32)> = ref_fps
+        .iter()
+        .map(|&(hash, t1, f1, m1)| (hash, t1 + 2500, f1, m1))
+        .collect();
+    
+    let results = matcher.query("capture", &query_fps, &PanakoConfig::default()).unwrap();
+    assert_eq!(results.len(), 1);
+    assert_eq!(results[0].ref_duration_ms, None);
+    let absolute_start = results[0].absolute_start.unwrap();
+    let absolute_end = results[0].absolute_end.unwrap();
+    assert!((absolute_start - 20.0).abs() < 1e-6);
+    assert!((absolute_end - 28.8).abs() < 1e-6);
+    
+    // Previous behavior when estimation is disabled
+    let config = PanakoConfig {
+        estimate_ref_duration: false,
+        ..PanakoConfig::default()
+    };
+    let results = matcher.query("capture", &query_fps, &config).unwrap();
+    assert!(results[0].absolute_start.is_none());
+    assert!(results[0].absolute_end.is_none());
+}
+
+#[test]
+fn test_sub_frame_alignment() {
+    let mut matcher = Matcher::new();
+    let config = PanakoConfig {
+        refine_alignment: true,
+        ..PanakoConfig::default()
+    };
+    
+    // Events at continuous times; the query is the reference shifted by 10.4 frames,
+    // so quantized deltas are 10 (60%) or 11 (40%)
+    let events: Vec<f64> = (0..100).map(|i| 100.0 + i as f64 * 7.13).collect();
+    let ref_fps: Vec<(u64, i32, i16, f32)> = events
+        .iter()
+        .enumerate()
+        .map(|(i, &x)| (700 + i as u64, x.round() as i32, 50, 1.0))
+        .collect();
+    let query_fps: Vec<(u64, i32, i16, f32)> = events
+        .iter()
+        .enumerate()
+        .map(|(i, &x)| (700 + i as u64, (x - 10.4).round() as i32, 50, 1.0))
+        .collect();
+    matcher.add_fingerprints("ref".to_string(), &ref_fps);
+    
+    let results = matcher.query("query", &query_fps, &config).unwrap();
+    assert_eq!(results.len(), 1);
+    
+    // Frame-level offset is 10 frames (80 ms); the refinement recovers the extra 0.4 frame
+    let sub_frame_ms = results[0].sub_frame_offset_ms.unwrap();
+    assert!((sub_frame_ms - 3.2).abs() < 2.0, "sub-frame offset {} ms", sub_frame_ms);
+    
+    // Not computed unless requested
+    let results = matcher.query("query", &query_fps, &PanakoConfig::default()).unwrap();
+    assert!(results[0].sub_frame_offset_ms.is_none());
+}
+
+#[test]
+fn test_match_explanation() {
+    let mut matcher = Matcher::new();
+    let config = PanakoConfig {
+        explain_matches: true,
+        ..PanakoConfig::default()
+    };
+    
+    let ref_fps: Vec<(u64, i32, i16, f32)> = (0..12)
+        .map(|i| (100 + i as u64, i * 100, 50 + i as i16, 1.0))
+        .collect();
+    matcher.add_fingerprints("test_ref".to_string(), &ref_fps);
+    
+    let results = matcher.query("test_query", &ref_fps, &config).unwrap();
+    assert_eq!(results.len(), 1);
+    
+    let explanation = results[0].explanation.as_ref().unwrap();
+    assert_eq!(explanation.len(), results[0].score as usize);
+    assert!(explanation.iter().all(|p| p.query_time == p.match_time));
+    
+    // Explanations are opt-in
+    let results = matcher
+        .query("test_query", &ref_fps, &PanakoConfig::default())
+        .unwrap();
+    assert!(results[0].explanation.is_none());
+}
+
+#[test]
+fn test_frame_times_agree_with_seconds() {
+    let mut matcher = Matcher::new();
+    let ref_fps: Vec<(u64, i32, i16, f32)> = (0..40)
+        .map(|i| (700 + i as u64, 100 + i * 25, 50 + (i % 7) as i16, 1.0))
+        .collect();
+    let query_fps: Vec<(u64, i32, i16, f32)> = ref_fps.iter().map(|&(h, t, f, m)| (h, t + 300, f, m)).collect();
+    matcher.add_fingerprints("test_ref".to_string(), &ref_fps);
+
+    let config = PanakoConfig {
+        include_frame_times: true,
+        ..PanakoConfig::default()
+    };
+    let other_rate = PanakoConfig {
+        include_frame_times: true,
+        ..PanakoConfig::default().with_sample_rate(22050)
+    };
+    let mut frames_by_config = Vec::new();
+    for config in [&config, &other_rate] {
+        let results = matcher.query("test_query", &query_fps, config).unwrap();
+        assert_eq!(results.len(), 1);
+        let result = &results[0];
+        let frames = result.frames.unwrap();
+        assert_eq!(frames.seconds_per_frame, config.frame_duration_s());
+        assert_eq!((frames.query_start, frames.query_stop), (400, 400 + 39 * 25));
+        assert_eq!((frames.ref_start, frames.ref_stop), (100, 100 + 39 * 25));
+        assert_eq!(result.query_start, frames.query_start as f64 * frames.seconds_per_frame);
+        assert_eq!(result.query_stop, frames.query_stop as f64 * frames.seconds_per_frame);
+        assert_eq!(result.ref_start, frames.ref_start as f64 * frames.seconds_per_frame);
+        assert_eq!(result.ref_stop, frames.ref_stop as f64 * frames.seconds_per_frame);
+        frames_by_config.push(frames);
+    }
+    // Seconds differ between the configurations, frame indices do not
+    assert_ne!(frames_by_config[0].seconds_per_frame, frames_by_config[1].seconds_per_frame);
+    assert_eq!(frames_by_config[0].query_start, frames_by_config[1].query_start);
+
+    // Frame times are opt-in and not serialized when absent
+    let results = matcher.query("test_query", &query_fps, &PanakoConfig::default()).unwrap();
+    assert!(results[0].frames.is_none());
+    assert!(serde_json::to_value(&results[0]).unwrap().get("frames").is_none());
+}
+
+#[test]
+fn test_looping_reference_reports_coherent_span() {
+    // The reference repeats every 3 fingerprints (30 frames); the query holds
+    // it from fingerprint 7 on, so every query hash recurs along the reference
+    let mut matcher = Matcher::new();
+    let ref_fps: Vec<(u64, i32, i16, f32)> = (0..200).map(|k| (500 + (k % 3) as u64, k * 10, 60, 1.0)).collect();
+    let query_fps: Vec<(u64, i32, i16, f32)> = (0..=100).map(|j| (500 + ((j + 7) % 3) as u64, j * 10, 60, 1.0)).collect();
+    matcher.add_fingerprints("loop".to_string(), &ref_fps);
+
+    // A tolerance spanning one loop period aligns neighbouring iterations too
+    let config = PanakoConfig {
+        query_range: 30,
+        ..PanakoConfig::default()
+    };
+    let frame_s = config.frame_duration_s();
+    let independent = &matcher.query("query", &query_fps, &config).unwrap()[0];
+    let query_span = independent.query_stop - independent.query_start;
+    assert!(independent.ref_stop - independent.ref_start > query_span + 1e-9);
+
+    let config = PanakoConfig {
+        ref_bounds_from_delta: true,
+        ..config
+    };
+    let result = &matcher.query("query", &query_fps, &config).unwrap()[0];
+    assert_eq!((result.query_start, result.query_stop), (0.0, 1000.0 * frame_s));
+    // The query span at the (loop-blurred) time factor, rounded to a frame
+    let scaled_span = query_span * result.time_factor;
+    assert!((result.ref_stop - result.ref_start - scaled_span).abs() <= frame_s / 2.0);
+    assert!((result.time_factor - 1.0).abs() < 0.01);
+    // Smallest of the equally supported loop offsets
+    assert!((result.ref_start - 10.0 * frame_s).abs() < 1e-9);
+    assert!((result.absolute_start.unwrap() + 10.0 * frame_s).abs() < 1e-9);
+}
+
+#[test]
+fn test_unsorted_query_returns_same_results() {
+    // Five references sharing the query's hashes with different support
+    let mut matcher = Matcher::new();
+    let query_fps: Vec<(u64, i32, i16, f32)> = (0..60).map(|i| (900 + i as u64, i * 20, 70, 1.0)).collect();
+    for r in 0..5 {
+        let shared = 12 + r * 10;
+        let ref_fps: Vec<(u64, i32, i16, f32)> = query_fps[..shared].iter().map(|&(h, t, f, m)| (h, t + 100 * r as i32, f, m)).collect();
+        matcher.add_fingerprints(format!("ref_{}", r), &ref_fps);
+    }
+    let config = PanakoConfig::default();
+
+    let sorted = matcher.query("query", &query_fps, &config).unwrap();
+    let mut unsorted = matcher.query_unsorted("query", &query_fps, &config).unwrap();
+    assert_eq!(sorted.len(), 5);
+    assert!(sorted.windows(2).all(|w| w[0].score >= w[1].score));
+
+    unsorted.sort_by(|a, b| a.ref_identifier.cmp(&b.ref_identifier));
+    let mut by_identifier = sorted.clone();
+    by_identifier.sort_by(|a, b| a.ref_identifier.cmp(&b.ref_identifier));
+    assert_eq!(unsorted, by_identifier);
+}
+
+#[test]
+fn test_aligned_points_follow_time_factor() {
+    let mut matcher = Matcher::new();
+    let config = PanakoConfig {
+        include_aligned_points: true,
+        ..PanakoConfig::default()
+    };
+
+    // The query holds the reference 50 frames after its own start
+    let ref_fps: Vec<(u64, i32, i16, f32)> = (0..40)
+        .map(|i| (500 + i as u64, i * 25, 50 + (i % 7) as i16, 1.0))
+        .collect();
+    let query_fps: Vec<(u64, i32, i16, f32)> = ref_fps.iter().map(|&(h, t, f, m)| (h, t + 50, f, m)).collect();
+    matcher.add_fingerprints("test_ref".to_string(), &ref_fps);
+
+    let results = matcher.query("test_query", &query_fps, &config).unwrap();
+    assert_eq!(results.len(), 1);
+    let result = &results[0];
+    let points = result.aligned_points.as_ref().unwrap();
+    assert_eq!(points.len(), result.score as usize);
+    assert!(points.windows(2).all(|w| w[0].0 <= w[1].0));
+
+    // Least-squares line through the points
+    let n = points.len() as f64;
+    let (sum_x, sum_y) = points.iter().fold((0.0, 0.0), |(sx, sy), &(x, y)| (sx + x, sy + y));
+    let (mean_x, mean_y) = (sum_x / n, sum_y / n);
+    let sxy: f64 = points.iter().map(|&(x, y)| (x - mean_x) * (y - mean_y)).sum();
+    let sxx: f64 = points.iter().map(|&(x, _)| (x - mean_x).powi(2)).sum();
+    let slope = sxy / sxx;
+    assert!((slope - result.time_factor).abs() < 1e-6, "slope {} vs time factor {}", slope, result.time_factor);
+    assert!((mean_y - slope * mean_x - (result.ref_start - result.query_start)).abs() < 1e-6);
+    assert_eq!(points[0], (result.query_start, result.ref_start));
+
+    // Points are opt-in and not serialized when absent
+    let results = matcher.query("test_query", &query_fps, &PanakoConfig::default()).unwrap();
+    assert!(results[0].aligned_points.is_none());
+    assert!(serde_json::to_value(&results[0]).unwrap().get("aligned_points").is_none());
+}
+
+#[test]
+fn test_filter_common_hashes() {
+    let mut matcher = Matcher::new();
+    let config = PanakoConfig::default();
+    
+    // Hash 1 is present in every reference; the others are discriminative
+    for (r, id) in ["ref_a", "ref_b", "ref_c"].iter().enumerate() {
+        let fps: Vec<(u64, i32, i16, f32)> = (0..12)
+            .map(|i| {
+                let hash = if i == 0 { 1 } else { 1000 * (r as u64 + 1) + i as u64 };
+                (hash, i * 100, 50 + i as i16, 1.0)
+            })
+            .collect();
+        matcher.add_fingerprints(id.to_string(), &fps);
+    }
+    
+    let removed = matcher.filter_common_hashes(0.5);
+    assert_eq!(removed, 1);
+    
+    // A query made only of the common hash finds nothing
+    let common_query: Vec<(u64, i32, i16, f32)> = (0..12).map(|i| (1, i * 100, 50, 1.0)).collect();
+    let results = matcher.query("common", &common_query, &config).unwrap();
+    assert!(results.is_empty());
+    
+    // Discriminative hashes still match their reference
+    let query: Vec<(u64, i32, i16, f32)> = (0..12)
+        .map(|i| {
+            let hash = if i == 0 { 1 } else { 2000 + i as u64 };
+            (hash, i * 100, 50 + i as i16, 1.0)
+        })
+        .collect();
+    let results = matcher.query("query", &query, &config).unwrap();
+    assert_eq!(results.len(), 1);
+    assert_eq!(results[0].ref_identifier, Some("ref_b".to_string()));
+    // The common hash no longer contributes to the score
+    assert_eq!(results[0].score, 11);
+}
+
+#[test]
+fn test_minimal_distinguishing_set() {
+    let mut matcher = Matcher::new();
+    let config = PanakoConfig::default();
+    
+    // ref_a shares hashes 100-104 with ref_b (e.g. a common jingle); 105-119 are its own
+    let ref_a: Vec<(u64, i32, i16, f32)> = (0..20).map(|i| (100 + i as u64, i * 50, 40, 1.0)).collect();
+    let ref_b: Vec<(u64, i32, i16, f32)> = (0..10).map(|i| (95 + i as u64, i * 50, 60, 1.0)).collect();
+    matcher.add_fingerprints("ref_a".to_string(), &ref_a);
+    matcher.add_fingerprints("ref_b".to_string(), &ref_b);
+    
+    let subset = matcher.minimal_distinguishing_set("ref_a");
+    let hashes: Vec<u64> = subset.iter().map(|fp| fp.0).collect();
+    assert_eq!(hashes, (105..120).collect::<Vec<u64>>());
+    assert!(subset.windows(2).all(|w| w[0].1 <= w[1].1));
+    assert!(matcher.minimal_distinguishing_set("unknown").is_empty());
+    
+    // The subset alone still identifies ref_a
+    let mut compact = Matcher::new();
+    compact.add_fingerprints("ref_a".to_string(), &subset);
+    compact.add_fingerprints("ref_b".to_string(), &matcher.minimal_distinguishing_set("ref_b"));
+    let results = compact.query("query", &ref_a, &config).unwrap();
+    assert_eq!(results[0].ref_identifier, Some("ref_a".to_string()));
+    assert_eq!(results[0].score, 15);
+}
+
+#[test]
+fn test_minimal_distinguishing_set_includes_mapped_index_and_magnitudes() {
+    // ref_a (memory-mapped) shares hashes 100-104 with ref_b (in memory)
+    let ref_a: Vec<(u64, i32, i16, f32)> = (0..20).map(|i| (100 + i as u64, i * 50, 40, 1.0)).collect();
+    let ref_b: Vec<(u64, i32, i16, f32)> = (0..10).map(|i| (95 + i as u64, i * 50, 60, 2.5)).collect();
+    let mut builder = MmapIndexBuilder::new();
+    builder.add_fingerprints("ref_a".to_string(), &ref_a);
+    let path = std::env::temp_dir().join(format!("panako_distinguishing_{}.idx", std::process::id()));
+    builder.write(&path).unwrap();
+    let mut matcher = Matcher::open_mmap(&path).unwrap();
+    matcher.retain_magnitudes();
+    matcher.add_fingerprints("ref_b".to_string(), &ref_b);
+    
+    let subset = matcher.minimal_distinguishing_set("ref_b");
+    assert_eq!(subset.iter().map(|fp| fp.0).collect::<Vec<u64>>(), (95..100).collect::<Vec<u64>>());
+    assert!(subset.iter().all(|fp| fp.3 == 2.5));
+    
+    // The mapped index keeps no magnitudes
+    let subset = matcher.minimal_distinguishing_set("ref_a");
+    assert_eq!(subset.iter().map(|fp| fp.0).collect::<Vec<u64>>(), (105..120).collect::<Vec<u64>>());
+    assert!(subset.iter().all(|fp| fp.3 == 0.0));
+    
+    std::fs::remove_file(&path).ok();
+}
+
+#[test]
+fn test_incompatible_query_params_detected() {
+    let reference_config = PanakoConfig {
+        bands_per_octave: 85,
+        ..PanakoConfig::default()
+    };
+    let query_config = PanakoConfig {
+        bands_per_octave: 36,
+        ..PanakoConfig::default()
+    };
+    
+    let mut matcher = Matcher::new();
+    let fps: Vec<(u64, i32, i16, f32)> = (0..12).map(|i| (500 + i as u64, i * 100, 50, 1.0)).collect();
+    matcher.add_fingerprints("ref_85".to_string(), &fps);
+    matcher.add_fingerprint_params("ref_85".to_string(), reference_config.fingerprint_params());
+    
+    assert!(matcher.check_query_params(&reference_config.fingerprint_params()).is_ok());
+    let err = matcher.check_query_params(&query_config.fingerprint_params()).unwrap_err();
+    let message = err.to_string();
+    assert!(message.contains("ref_85"));
+    assert!(message.contains("bands_per_octave 36 vs 85"));
+}
+
+#[test]
+fn test_bloom_filter_query_matches_plain_query() {
+    let config = PanakoConfig::default();
+    let reference = |r: u64| -> Vec<(u64, i32, i16, f32)> {
+        (0..200)
+            .map(|i| (r * 1_000_003 + i as u64 * 7919, i * 20, (i % 100) as i16, 1.0))
+            .collect()
+    };
+    
+    // 50 references of 200 fingerprints each
+    let mut plain = Matcher::new();
+    let mut filtered = Matcher::new();
+    for r in 0..50u64 {
+        plain.add_fingerprints(format!("ref_{}", r), &reference(r));
+        filtered.add_fingerprints(format!("ref_{}", r), &reference(r));
+        if r == 24 {
+            // References added after building the filter must still be found
+            filtered.enable_bloom_filter(10);
+        }
+    }
+    
+    // Mostly absent hashes, with stretches of ref_7 and ref_42 in between
+    let query: Vec<(u64, i32, i16, f32)> = (0..2000)
+        .map(|i| {
+            let hash = match i {
+                100..=159 => 7 * 1_000_003 + (i as u64 - 100) * 7919,
+                1000..=1059 => 42 * 1_000_003 + (i as u64 - 1000) * 7919,
+                _ => 0xDEAD_0000_0000 + i as u64 * 31,
+            };
+            (hash, i * 20, (i % 100) as i16, 1.0)
+        })
+        .collect();
+    
+    let expected = plain.query("query", &query, &config).unwrap();
+    let actual = filtered.query("query", &query, &config).unwrap();
+    assert_eq!(expected.len(), 2);
+    let key = |r: &QueryResult| (r.ref_identifier.clone(), r.score, r.query_start.to_bits(), r.ref_start.to_bits());
+    let mut expected: Vec<_> = expected.iter().map(key).collect();
+    let mut actual: Vec<_> = actual.iter().map(key).collect();
+    expected.sort();
+    actual.sort();
+    assert_eq!(actual, expected);
+}
+
+#[test]
+fn test_bloom_filter_has_no_false_negatives() {
+    let mut bloom = bloom::HashBloomFilter::new(10_000, 10);
+    for hash in 0..10_000u64 {
+        bloom.insert(hash * 0x9E37);
+    }
+    assert!((0..10_000u64).all(|hash| bloom.may_contain(hash * 0x9E37)));
+    let false_positives = (0..10_000u64).filter(|&hash| bloom.may_contain(hash * 0x9E37 + 1)).count();
+    assert!(false_positives < 300, "{} false positives", false_positives);
+}
+
+#[test]
+fn test_candidate_cap_per_hash() {
+    let mut matcher = Matcher::new();
+    let config = PanakoConfig {
+        max_candidates_per_hash: Some(100),
+        ..PanakoConfig::default()
+    };
+    
+    // Hash 7 appears many times in each of 500 references
+    for r in 0..500 {
+        let fps: Vec<(u64, i32, i16, f32)> = (0..20).map(|i| (7, i * 10, 50, 1.0)).collect();
+        matcher.add_fingerprints(format!("noise_{}", r), &fps);
+    }
+    
+    let target: Vec<(u64, i32, i16, f32)> = (0..12)
+        .map(|i| (9000 + i as u64, i * 100, 50 + i as i16, 1.0))
+        .collect();
+    matcher.add_fingerprints("target".to_string(), &target);
+    
+    let mut query = target.clone();
+    query.extend((0..50).map(|i| (7, i * 10, 50, 1.0)));
+    
+    let start = std::time::Instant::now();
+    let results = matcher.query("query", &query, &config).unwrap();
+    let capped_time = start.elapsed();
+    
+    // Without the cap this query would produce 50 * 500 * 20 raw matches
+    assert!(capped_time < std::time::Duration::from_secs(5));
+    assert_eq!(results.len(), 1);
+    assert_eq!(results[0].ref_identifier, Some("target".to_string()));
+    assert_eq!(results[0].score, 12);
+}
+
+#[test]
+fn test_query_top_n() {
+    let mut matcher = Matcher::new();
+    let config = PanakoConfig::default();
+    
+    // The query contains 40 fingerprints; reference k shares 8 * k of them
+    let query: Vec<(u64, i32, i16, f32)> = (0..40)
+        .map(|i| (700 + i as u64, i * 50, 40 + i as i16, 1.0))
+        .collect();
+    for k in 1..=5 {
+        matcher.add_fingerprints(format!("ref_{}", k), &query[..8 * k]);
+    }
+    // Ties with ref_4 and must rank after it
+    matcher.add_fingerprints("ref_4b".to_string(), &query[..32]);
+    
+    let top = matcher.query_top_n("query", &query, 3, &config).unwrap();
+    let ranked: Vec<(String, i32)> = top
+        .iter()
+        .map(|r| (r.ref_identifier.clone().unwrap(), r.score))
+        .collect();
+    assert_eq!(
+        ranked,
+        [("ref_5".to_string(), 40), ("ref_4".to_string(), 32), ("ref_4b".to_string(), 32)]
+    );
+    assert!(top.iter().all(|r| r.time_factor > 0.0 && r.percent_seconds_with_match > 0.0));
+    
+    // ref_1 has too few matches to be reported at all
+    let all = matcher.query("query", &query, &config).unwrap();
+    assert_eq!(all.len(), 5);
+    assert_eq!(top, all[..3].to_vec());
+    assert_eq!(matcher.query_top_n("query", &query, 10, &config).unwrap().len(), 5);
+    assert!(matcher.query_top_n("query", &query, 0, &config).unwrap().is_empty());
+}
+
+#[test]
+fn test_query_restricted_to_recent_references() {
+    let fps: Vec<(u64, i32, i16, f32)> = (0..12)
+        .map(|i| (500 + i as u64, i * 100, 50 + i as i16, 1.0))
+        .collect();
+    let registered = [("old", Some(1_000)), ("boundary", Some(2_000)), ("new", Some(3_000)), ("unknown", None)];
+    
+    let mut matcher = Matcher::new();
+    let mut builder = MmapIndexBuilder::new();
+    for (identifier, registered_at) in registered {
+        matcher.add_fingerprints(identifier.to_string(), &fps);
+        builder.add_fingerprints(identifier.to_string(), &fps);
+        if let Some(registered_at) = registered_at {
+            matcher.add_registration_time(identifier.to_string(), registered_at);
+            builder.add_registration_time(identifier.to_string(), registered_at);
+        }
+    }
+    let path = std::env::temp_dir().join(format!("panako_mmap_registered_{}.idx", std::process::id()));
+    builder.write(&path).unwrap();
+    let mapped = Matcher::open_mmap(&path).unwrap();
+    std::fs::remove_file(&path).ok();
+    
+    let identifiers = |matcher: &Matcher, config: &PanakoConfig| -> Vec<String> {
+        let mut identifiers: Vec<String> = matcher
+            .query("query", &fps, config)
+            .unwrap()
+            .into_iter()
+            .filter_map(|result| result.ref_identifier)
+            .collect();
+        identifiers.sort();
+        identifiers
+    };
+    let all = PanakoConfig::default();
+    let recent = PanakoConfig {
+        registered_after_s: Some(2_000),
+        ..PanakoConfig::default()
+    };
+    let future = PanakoConfig {
+        registered_after_s: Some(10_000),
+        ..PanakoConfig::default()
+    };
+    for matcher in [&matcher, &mapped] {
+        assert_eq!(identifiers(matcher, &all), ["boundary", "new", "old", "unknown"]);
+        assert_eq!(identifiers(matcher, &recent), ["boundary", "new"]);
+        assert!(identifiers(matcher, &future).is_empty());
+    }
+}
 
 #[test]
 fn test_time_factor_calculation() {
@@ -40,24 +636,30 @@ fn test_time_factor_calculation() {
     let matches: Vec<Match> = vec![
         Match {
             identifier: "test".to_string(),
+            hash: 0,
             query_time: 100,
             match_time: 100,
             query_f1: 50,
             match_f1: 50,
+            match_m1: None,
         },
         Match {
             identifier: "test".to_string(),
+            hash: 0,
             query_time: 200,
             match_time: 200,
             query_f1: 50,
             match_f1: 50,
+            match_m1: None,
         },
         Match {
             identifier: "test".to_string(),
+            hash: 0,
             query_time: 300,
             match_time: 300,
             query_f1: 50,
             match_f1: 50,
+            match_m1: None,
         },
     ];
     
@@ -74,17 +676,21 @@ fn test_frequency_factor_calculation() {
     let matches: Vec<Match> = vec![
         Match {
             identifier: "test".to_string(),
+            hash: 0,
             query_time: 100,
             match_time: 100,
             query_f1: 50,
             match_f1: 50,
+            match_m1: None,
         },
         Match {
             identifier: "test".to_string(),
+            hash: 0,
             query_time: 200,
             match_time: 200,
             query_f1: 60,
             match_f1: 60,
+            match_m1: None,
         },
     ];
     
@@ -101,30 +707,1124 @@ fn test_coverage_calculation() {
     let matches: Vec<Match> = vec![
         Match {
             identifier: "test".to_string(),
+            hash: 0,
             query_time: 0,     // 0 seconds
             match_time: 0,
             query_f1: 50,
             match_f1: 50,
+            match_m1: None,
         },
         Match {
             identifier: "test".to_string(),
+            hash: 0,
             query_time: 125,   // ~1 second
             match_time: 125,
             query_f1: 50,
             match_f1: 50,
+            match_m1: None,
         },
         Match {
             identifier: "test".to_string(),
+            hash: 0,
             query_time: 250,   // ~2 seconds
             match_time: 250,
             query_f1: 50,
             match_f1: 50,
+            match_m1: None,
         },
     ];
     
     let match_refs: Vec<&Match> = matches.iter().collect();
-    let coverage = calculate_coverage(&match_refs, 0, 375); // 0-3 seconds
+    let coverage = calculate_coverage(&match_refs, 0, 375, 0.008); // 0-3 seconds
     
     // Should cover all 3 seconds
     assert!(coverage > 0.9); // Allow some rounding
 }
+
+#[test]
+fn test_coverage_half_of_query() {
+    // Matches in seconds 0-2 of a 6 second query
+    let matches: Vec<Match> = [0, 125, 250]
+        .iter()
+        .map(|&t| Match {
+            identifier: "test".to_string(),
+            hash: 0,
+            query_time: t,
+            match_time: t,
+            query_f1: 50,
+            match_f1: 50,
+            match_m1: None,
+        })
+        .collect();
+    
+    let match_refs: Vec<&Match> = matches.iter().collect();
+    let coverage = calculate_coverage(&match_refs, 0, 750, 0.008);
+    
+    assert!((coverage - 0.5).abs() < 0.01);
+}
+
+#[test]
+fn test_query_coverage_uses_full_query_span() {
+    let mut matcher = Matcher::new();
+    let config = PanakoConfig::default();
+    
+    // Reference matches only the first half (0-6s) of a 12 second query
+    let ref_fps: Vec<(u64, i32, i16, f32)> = (0..12)
+        .map(|i| (100 + i as u64, i * 62, 50, 1.0))
+        .collect();
+    matcher.add_fingerprints("test_ref".to_string(), &ref_fps);
+    
+    let mut query_fps = ref_fps.clone();
+    query_fps.extend((0..12).map(|i| (5000 + i as u64, 750 + i * 62, 50, 1.0)));
+    
+    let results = matcher.query("test_query", &query_fps, &config).unwrap();
+    assert_eq!(results.len(), 1);
+    assert!((results[0].percent_seconds_with_match - 0.5).abs() < 0.1);
+    
+    // Legacy behavior measures coverage over the matched span only
+    let legacy = PanakoConfig {
+        coverage_over_full_query: false,
+        ..PanakoConfig::default()
+    };
+    let results = matcher.query("test_query", &query_fps, &legacy).unwrap();
+    assert!(results[0].percent_seconds_with_match > 0.9);
+}
+
+#[test]
+fn test_mmap_index_query_matches_in_memory_query() {
+    let config = PanakoConfig {
+        estimate_ref_duration: true,
+        ..PanakoConfig::default()
+    };
+    // Hashes repeat within and across references, so buckets hold several postings
+    let reference = |r: u64| -> Vec<(u64, i32, i16, f32)> {
+        (0..300)
+            .map(|i| ((r * 37 + i as u64 * 11) % 900, i * 15, ((i * 7) % 120) as i16, 1.0))
+            .collect()
+    };
+    
+    let mut in_memory = Matcher::new();
+    let mut builder = MmapIndexBuilder::new();
+    for r in 0..20u64 {
+        let identifier = format!("ref_{}", r);
+        in_memory.add_fingerprints(identifier.clone(), &reference(r));
+        builder.add_fingerprints(identifier.clone(), &reference(r));
+        if r % 2 == 0 {
+            in_memory.add_duration(identifier.clone(), 60_000 + r as u32);
+            builder.add_duration(identifier.clone(), 60_000 + r as u32);
+        }
+        if r == 3 {
+            let tags: HashMap<String, String> = [("label".to_string(), "three".to_string())].into();
+            in_memory.add_tags(identifier.clone(), tags.clone());
+            builder.add_tags(identifier, tags);
+        }
+    }
+    
+    let path = std::env::temp_dir().join(format!("panako_mmap_index_{}.idx", std::process::id()));
+    builder.write(&path).unwrap();
+    let mapped = Matcher::open_mmap(&path).unwrap();
+    
+    let sorted = |mut results: Vec<QueryResult>| {
+        results.sort_by(|a, b| a.ref_identifier.cmp(&b.ref_identifier));
+        results
+    };
+    for (r, start) in [(3u64, 0usize), (7, 120), (19, 250)] {
+        let query: Vec<(u64, i32, i16, f32)> = reference(r)[start..start + 50]
+            .iter()
+            .map(|&(hash, t1, f1, m1)| (hash, t1 - 500, f1, m1))
+            .collect();
+        let expected = sorted(in_memory.query("query", &query, &config).unwrap());
+        let actual = sorted(mapped.query("query", &query, &config).unwrap());
+        assert!(expected.iter().any(|result| result.ref_identifier == Some(format!("ref_{}", r))));
+        assert_eq!(actual, expected);
+    }
+    
+    // Hashes that were never indexed find nothing
+    let absent: Vec<(u64, i32, i16, f32)> = (0..50).map(|i| (10_000 + i, i as i32, 0, 1.0)).collect();
+    assert!(mapped.query("query", &absent, &config).unwrap().is_empty());
+    
+    std::fs::remove_file(&path).ok();
+}
+
+#[test]
+fn test_32_bit_hashes_match_through_mmap_index() {
+    let config = PanakoConfig::default();
+    // Keys wider than 32 bits are limited to the configured width
+    let reference = |r: u64| -> Vec<(u64, i32, i16, f32)> {
+        (0..200)
+            .map(|i| ((1u64 << 40) | (r * 1000 + i as u64), i * 15, (i % 90) as i16, 1.0))
+            .collect()
+    };
+    // What 32-bit fingerprinting stores
+    let narrow = |fps: Vec<(u64, i32, i16, f32)>| -> Vec<(u64, i32, i16, f32)> {
+        fps.into_iter().map(|(hash, t1, f1, m1)| (HashWidth::Bits32.key(hash), t1, f1, m1)).collect()
+    };
+    
+    let mut in_memory = Matcher::new();
+    in_memory.set_hash_width(HashWidth::Bits32);
+    let mut builder = MmapIndexBuilder::new();
+    for r in 0..10u64 {
+        in_memory.add_fingerprints(format!("ref_{}", r), &reference(r));
+        builder.add_fingerprints(format!("ref_{}", r), &narrow(reference(r)));
+    }
+    
+    let path = std::env::temp_dir().join(format!("panako_mmap_narrow_{}.idx", std::process::id()));
+    builder.write(&path).unwrap();
+    let mapped = Matcher::open_mmap(&path).unwrap();
+    std::fs::remove_file(&path).ok();
+    
+    assert_eq!(in_memory.hash_width(), HashWidth::Bits32);
+    assert!(in_memory.index.keys().all(|&key| key <= u32::MAX as u64));
+    
+    let query: Vec<(u64, i32, i16, f32)> = narrow(reference(4)[50..120].to_vec());
+    let expected = in_memory.query("query", &query, &config).unwrap();
+    assert_eq!(expected[0].ref_identifier, Some("ref_4".to_string()));
+    assert_eq!(mapped.query("query", &query, &config).unwrap(), expected);
+}
+
+#[test]
+fn test_narrowing_hash_width_keeps_magnitudes_and_bloom_filter() {
+    let config = PanakoConfig::default();
+    // The hashes of both references collapse onto the same 32-bit keys
+    let reference = |r: u64, magnitude: f32| -> Vec<(u64, i32, i16, f32)> {
+        (0..40)
+            .map(|i| (((r + 1) << 40) | i as u64, i * 10 + r as i32 * 5000, 50, magnitude))
+            .collect()
+    };
+    let mut matcher = Matcher::new();
+    matcher.retain_magnitudes();
+    matcher.add_fingerprints("quiet".to_string(), &reference(0, 0.25));
+    matcher.add_fingerprints("loud".to_string(), &reference(1, 0.75));
+    matcher.enable_bloom_filter(10);
+    matcher.set_hash_width(HashWidth::Bits32);
+    
+    for (r, name, magnitude) in [(0, "quiet", 0.25), (1, "loud", 0.75)] {
+        let query: Vec<(u64, i32, i16, f32)> = reference(r, 1.0)
+            .into_iter()
+            .map(|(hash, t1, f1, m1)| (HashWidth::Bits32.key(hash), t1, f1, m1))
+            .collect();
+        let results = matcher.query("query", &query, &config).unwrap();
+        let result = results.iter().find(|result| result.ref_identifier.as_deref() == Some(name)).unwrap();
+        assert_eq!(result.score, 40);
+        assert_eq!(result.mean_matched_magnitude, Some(magnitude));
+    }
+}
+
+#[test]
+fn test_open_mmap_rejects_other_files() {
+    let path = std::env::temp_dir().join(format!("panako_mmap_invalid_{}.idx", std::process::id()));
+    std::fs::write(&path, b"not an index file at all, just some bytes here").unwrap();
+    assert!(Matcher::open_mmap(&path).is_err());
+    std::fs::remove_file(&path).ok();
+}
+
+#[test]
+fn test_open_mmap_rejects_corrupt_index() {
+    let mut builder = MmapIndexBuilder::new();
+    builder.add_fingerprints("a".to_string(), &[(1, 10, 5, 1.0), (2, 20, 5, 1.0)]);
+    builder.add_fingerprints("b".to_string(), &[(2, 30, 5, 1.0), (3, 40, 5, 1.0)]);
+    let path = std::env::temp_dir().join(format!("panako_mmap_corrupt_{}.idx", std::process::id()));
+    builder.write(&path).unwrap();
+    let valid = std::fs::read(&path).unwrap();
+    assert!(Matcher::open_mmap(&path).is_ok());
+
+    let num_hashes = u64::from_le_bytes(valid[16..24].try_into().unwrap()) as usize;
+    let offsets_start = 48 + num_hashes * 8;
+    let postings_start = offsets_start + (num_hashes + 1) * 8;
+    let patched = |pos: usize, bytes: &[u8]| {
+        let mut file = valid.clone();
+        file[pos..pos + bytes.len()].copy_from_slice(bytes);
+        file
+    };
+    let corrupt = [
+        // Posting count overflowing the section sizes
+        patched(24, &(u64::MAX / 2).to_le_bytes()),
+        // Hash count overflowing them
+        patched(16, &u64::MAX.to_le_bytes()),
+        // Bucket offset past the postings
+        patched(offsets_start + 8, &100u64.to_le_bytes()),
+        // Bucket offsets out of order
+        patched(offsets_start + 16, &0u64.to_le_bytes()),
+        // Posting referring to a missing reference
+        patched(postings_start + 12, &7u32.to_le_bytes()),
+        // Truncated file
+        valid[..postings_start].to_vec(),
+    ];
+    for bytes in corrupt {
+        std::fs::write(&path, &bytes).unwrap();
+        assert!(Matcher::open_mmap(&path).is_err());
+    }
+    std::fs::remove_file(&path).ok();
+}
+
+#[test]
+fn test_multi_track_reference_reports_track_and_offset() {
+    let config = PanakoConfig::default();
+    let track = |n: u64| -> Vec<(u64, i32, i16, f32)> {
+        (0..400)
+            .map(|i| (n * 1_000_003 + i as u64 * 7919, i * 10, (i % 90) as i16, 1.0))
+            .collect()
+    };
+    
+    let mut matcher = Matcher::new();
+    matcher.add_tracks(
+        "album".to_string(),
+        &[
+            ("01 Intro.flac".to_string(), track(1)),
+            ("02 Song.flac".to_string(), track(2)),
+            ("03 Outro.flac".to_string(), track(3)),
+        ],
+    );
+    
+    // Clip taken from track 2 starting at its 1000th frame
+    let query: Vec<(u64, i32, i16, f32)> = track(2)[100..160]
+        .iter()
+        .map(|&(hash, t1, f1, m1)| (hash, t1 - 1000 + 25, f1, m1))
+        .collect();
+    let results = matcher.query("clip", &query, &config).unwrap();
+    
+    assert_eq!(results.len(), 1);
+    let result = &results[0];
+    assert_eq!(result.ref_identifier.as_deref(), Some("album"));
+    assert_eq!(result.track.as_deref(), Some("02 Song.flac"));
+    let frame_s = config.frame_duration_s();
+    assert!((result.track_start.unwrap() - 1000.0 * frame_s).abs() < 1e-9);
+    // Track 2 starts right after the last fingerprint of track 1 (frame 3990)
+    assert!((result.ref_start - (3991.0 + 1000.0) * frame_s).abs() < 1e-9);
+}
+
+#[test]
+fn test_score_normalization_by_reference_length() {
+    let fingerprints = |r: u64, count: i32| -> Vec<(u64, i32, i16, f32)> {
+        (0..count)
+            .map(|i| (r * 1_000_003 + i as u64 * 7919, i * 10, (i % 90) as i16, 1.0))
+            .collect()
+    };
+    
+    let mut matcher = Matcher::new();
+    // Short reference matched completely, long reference matched in part
+    matcher.add_fingerprints("short".to_string(), &fingerprints(1, 40));
+    matcher.add_fingerprints("long".to_string(), &fingerprints(2, 2000));
+    let mut query = fingerprints(1, 40);
+    query.extend(fingerprints(2, 2000)[500..700].iter().map(|&(hash, t1, f1, m1)| (hash, t1 - 4000, f1, m1)));
+    
+    let raw = matcher.query("query", &query, &PanakoConfig::default()).unwrap();
+    let ranking: Vec<_> = raw.iter().map(|r| r.ref_identifier.clone().unwrap()).collect();
+    assert_eq!(ranking, ["long", "short"]);
+    assert!(raw.iter().all(|r| r.normalized_score.is_none()));
+    
+    let config = PanakoConfig {
+        normalize_score_by_ref_length: true,
+        ..PanakoConfig::default()
+    };
+    let normalized = matcher.query("query", &query, &config).unwrap();
+    let ranking: Vec<_> = normalized.iter().map(|r| r.ref_identifier.clone().unwrap()).collect();
+    assert_eq!(ranking, ["short", "long"]);
+    // Raw scores are kept
+    assert_eq!(normalized[0].score, 40);
+    assert_eq!(normalized[1].score, 200);
+    assert!((normalized[0].normalized_score.unwrap() - 1.0).abs() < 1e-9);
+    assert!((normalized[1].normalized_score.unwrap() - 0.1).abs() < 1e-9);
+}
+
+#[test]
+fn test_time_quantization_keeps_recall_and_shrinks_index() {
+    // Every hash is held for two frames, as for sustained tones
+    let reference = |r: u64| -> Vec<(u64, i32, i16, f32)> {
+        (0..600)
+            .map(|i| (r * 1_000_003 + (i / 2) as u64 * 7919, (i / 2) * 3 + i % 2, ((i / 2) % 100) as i16, 1.0))
+            .collect()
+    };
+    let config = PanakoConfig {
+        index_time_step: Some(3),
+        ..PanakoConfig::default()
+    };
+    config.validate().unwrap();
+    
+    let mut exact = Matcher::new();
+    let mut quantized = Matcher::new();
+    quantized.quantize_times(config.index_time_step.unwrap());
+    for r in 0..10u64 {
+        exact.add_fingerprints(format!("ref_{}", r), &reference(r));
+        quantized.add_fingerprints(format!("ref_{}", r), &reference(r));
+    }
+    assert!(quantized.num_postings() * 2 <= exact.num_postings());
+    
+    for r in 0..10u64 {
+        let start = (r as usize * 37) % 400;
+        let query: Vec<(u64, i32, i16, f32)> = reference(r)[start..start + 120]
+            .iter()
+            .map(|&(hash, t1, f1, m1)| (hash, t1 + 5_000 + r as i32, f1, m1))
+            .collect();
+        let found = |matcher: &Matcher| -> Vec<String> {
+            matcher
+                .query("query", &query, &config)
+                .unwrap()
+                .into_iter()
+                .filter_map(|result| result.ref_identifier)
+                .collect()
+        };
+        let expected = found(&exact);
+        assert_eq!(expected, [format!("ref_{}", r)]);
+        assert_eq!(found(&quantized), expected);
+    }
+    
+    // Quantizing an existing index gives the same postings
+    let mut late = Matcher::new();
+    late.add_fingerprints("ref_0".to_string(), &reference(0));
+    late.quantize_times(3);
+    let mut early = Matcher::new();
+    early.quantize_times(3);
+    early.add_fingerprints("ref_0".to_string(), &reference(0));
+    assert_eq!(late.num_postings(), early.num_postings());
+}
+
+#[test]
+fn test_delta_ties_are_broken_deterministically() {
+    // Two clusters of 6 matches each: offset 100 spanning 200 frames of the
+    // reference, offset -40 spanning 50 frames
+    let mut ref_fps: Vec<(u64, i32, i16, f32)> = (0..6).map(|i| (1000 + i as u64, 1000 + i * 40, 50, 1.0)).collect();
+    ref_fps.extend((0..6).map(|i| (2000 + i as u64, 2000 + i * 10, 50, 1.0)));
+    let query: Vec<(u64, i32, i16, f32)> = ref_fps
+        .iter()
+        .map(|&(hash, t1, f1, m1)| (hash, if hash < 2000 { t1 - 100 } else { t1 + 40 }, f1, m1))
+        .collect();
+    
+    let chosen_ref_start = |tie_break: DeltaTieBreak| -> Vec<f64> {
+        let config = PanakoConfig {
+            delta_tie_break: tie_break,
+            ..PanakoConfig::default()
+        };
+        // Fresh matchers get fresh hash map seeds
+        (0..20)
+            .map(|_| {
+                let mut matcher = Matcher::new();
+                matcher.add_fingerprints("ref".to_string(), &ref_fps);
+                let results = matcher.query("query", &query, &config).unwrap();
+                assert_eq!(results.len(), 1);
+                assert_eq!(results[0].score, 6);
+                results[0].ref_start
+            })
+            .collect()
+    };
+    
+    let frame_s = PanakoConfig::default().frame_duration_s();
+    let smallest = chosen_ref_start(DeltaTieBreak::SmallestAbsolute);
+    assert!(smallest.iter().all(|&start| start == 2000.0 * frame_s));
+    let widest = chosen_ref_start(DeltaTieBreak::WidestSpread);
+    assert!(widest.iter().all(|&start| start == 1000.0 * frame_s));
+}
+
+#[test]
+fn test_per_reference_min_score() {
+    let config = PanakoConfig::default();
+    let fingerprints = |r: u64| -> Vec<(u64, i32, i16, f32)> {
+        (0..30)
+            .map(|i| (r * 1_000_003 + i as u64 * 7919, i * 10, (i % 90) as i16, 1.0))
+            .collect()
+    };
+    
+    let mut matcher = Matcher::new();
+    matcher.add_fingerprints("jingle".to_string(), &fingerprints(1));
+    matcher.add_fingerprints("song".to_string(), &fingerprints(2));
+    matcher.add_min_score("jingle".to_string(), 25);
+    matcher.add_min_score("song".to_string(), 15);
+    
+    let found = |count: usize| -> Vec<String> {
+        let mut query = fingerprints(1)[..count].to_vec();
+        query.extend_from_slice(&fingerprints(2)[..count]);
+        let mut found: Vec<String> = matcher
+            .query("query", &query, &config)
+            .unwrap()
+            .into_iter()
+            .filter_map(|result| result.ref_identifier)
+            .collect();
+        found.sort();
+        found
+    };
+    
+    // 20 aligned matches pass the song's threshold but not the jingle's
+    assert_eq!(found(20), ["song"]);
+    assert_eq!(found(25), ["jingle", "song"]);
+    assert!(found(12).is_empty());
+}
+
+#[test]
+fn test_ref_path_from_original_path() {
+    let config = PanakoConfig::default();
+    let fingerprints = |r: u64| -> Vec<(u64, i32, i16, f32)> {
+        (0..20).map(|i| (r * 1_000_003 + i as u64 * 7919, i * 10, 50, 1.0)).collect()
+    };
+    
+    let mut matcher = Matcher::new();
+    matcher.add_fingerprints("spot_1234".to_string(), &fingerprints(1));
+    matcher.add_path("spot_1234".to_string(), "/media/ads/2025/spot_1234.wav".to_string());
+    matcher.add_fingerprints("no_path".to_string(), &fingerprints(2));
+    
+    let result = &matcher.query("query", &fingerprints(1), &config).unwrap()[0];
+    assert_eq!(result.ref_identifier.as_deref(), Some("spot_1234"));
+    assert_eq!(result.ref_path.as_deref(), Some("/media/ads/2025/spot_1234.wav"));
+    
+    // Without a stored path the identifier is kept
+    let result = &matcher.query("query", &fingerprints(2), &config).unwrap()[0];
+    assert_eq!(result.ref_path.as_deref(), Some("no_path"));
+}
+
+#[test]
+fn test_resolve_media_path() {
+    let fingerprints = |r: u64| -> Vec<(u64, i32, i16, f32)> {
+        (0..20).map(|i| (r * 1_000_003 + i as u64 * 7919, i * 10, 50, 1.0)).collect()
+    };
+    let mut matcher = Matcher::new();
+    matcher.add_fingerprints("spot_1234".to_string(), &fingerprints(1));
+    matcher.add_path("spot_1234".to_string(), "/media/ads/2025/spot_1234.mp4".to_string());
+    matcher.add_fingerprints("jingle".to_string(), &fingerprints(2));
+    matcher.add_path("jingle".to_string(), "D:\\ingest\\jingle.final.wav".to_string());
+
+    let config = PanakoConfig {
+        reference_media_root: Some("/srv/media".to_string()),
+        ..PanakoConfig::default()
+    };
+    let result = &matcher.query("query", &fingerprints(1), &config).unwrap()[0];
+    assert_eq!(
+        result.resolve_media_path("/mnt/archive"),
+        Some(std::path::PathBuf::from("/mnt/archive/spot_1234.mp4"))
+    );
+    assert_eq!(result.media_path.as_deref(), Some("/srv/media/spot_1234.mp4"));
+
+    let result = &matcher.query("query", &fingerprints(2), &config).unwrap()[0];
+    assert_eq!(result.media_path.as_deref(), Some("/srv/media/jingle.final.wav"));
+
+    // Not resolved unless a root is configured, nor without a reference
+    let result = &matcher.query("query", &fingerprints(1), &PanakoConfig::default()).unwrap()[0];
+    assert!(result.media_path.is_none());
+    assert!(QueryResult::empty("query".to_string(), 0.0, 1.0).resolve_media_path("/srv/media").is_none());
+    // A path ending in `..` does not escape the root
+    let mut escaping = result.clone();
+    escaping.ref_path = Some("/media/ads/..".to_string());
+    assert!(escaping.resolve_media_path("/srv/media").is_none());
+    escaping.ref_path = Some("..".to_string());
+    assert!(escaping.resolve_media_path("/srv/media").is_none());
+    assert!(PanakoConfig { reference_media_root: Some(String::new()), ..PanakoConfig::default() }
+        .validate()
+        .is_err());
+}
+
+#[test]
+fn test_mean_matched_magnitude_with_retained_magnitudes() {
+    let reference: Vec<(u64, i32, i16, f32)> = (0..40)
+        .map(|i| (5000 + i as u64 * 7919, i * 10, (i % 50) as i16, 0.5 + i as f32 * 0.1))
+        .collect();
+    // Query matches the second half only, with unrelated magnitudes
+    let query: Vec<(u64, i32, i16, f32)> = reference[20..]
+        .iter()
+        .map(|&(hash, t1, f1, _)| (hash, t1 + 300, f1, 9.0))
+        .collect();
+    let expected = reference[20..].iter().map(|fp| fp.3 as f64).sum::<f64>() / 20.0;
+    
+    let mut lean = Matcher::new();
+    lean.add_fingerprints("ref".to_string(), &reference);
+    let results = lean.query("query", &query, &PanakoConfig::default()).unwrap();
+    assert_eq!(results[0].score, 20);
+    assert_eq!(results[0].mean_matched_magnitude, None);
+    
+    let mut matcher = Matcher::new();
+    matcher.retain_magnitudes();
+    matcher.add_fingerprints("ref".to_string(), &reference);
+    let results = matcher.query("query", &query, &PanakoConfig::default()).unwrap();
+    assert_eq!(results[0].score, 20);
+    assert!((results[0].mean_matched_magnitude.unwrap() - expected).abs() < 1e-6);
+    
+    // Merged postings keep the magnitude of the first one
+    let mut doubled = reference.clone();
+    doubled.extend(reference.iter().map(|&(hash, t1, f1, m1)| (hash, t1 + 1, f1, m1 + 100.0)));
+    doubled.sort_by_key(|fp| fp.1);
+    let mut quantized = Matcher::new();
+    quantized.retain_magnitudes();
+    quantized.add_fingerprints("ref".to_string(), &doubled);
+    quantized.quantize_times(2);
+    assert_eq!(quantized.num_postings(), reference.len());
+    let results = quantized.query("query", &query, &PanakoConfig::default()).unwrap();
+    assert!((results[0].mean_matched_magnitude.unwrap() - expected).abs() < 1e-6);
+}
+
+#[test]
+fn test_index_stats_of_hand_built_index() {
+    // Buckets: hash 1 -> 3 postings from 2 references, hash 2 -> 1 posting,
+    // hash 3 -> 2 postings of one reference, hash 4 -> 2 postings from 2 references
+    let mut matcher = Matcher::new();
+    matcher.add_fingerprints("a,1".to_string(), &[(1, 0, 10, 1.0), (1, 50, 10, 1.0), (2, 10, 20, 1.0), (4, 5, 1, 1.0)]);
+    matcher.add_fingerprints("b".to_string(), &[(1, 7, 11, 1.0), (3, 0, 30, 1.0), (3, 90, 30, 1.0), (4, 9, 2, 1.0)]);
+
+    let stats = matcher.export_index_stats();
+    assert_eq!(stats.total_hashes, 4);
+    assert_eq!(stats.total_postings, 8);
+    assert_eq!(stats.mean_bucket_size, 2.0);
+    assert_eq!(stats.max_bucket_size, 3);
+    assert_eq!(stats.bucket_size_histogram, std::collections::BTreeMap::from([(1, 1), (2, 2), (3, 1)]));
+    assert_eq!(stats.references_per_hash_histogram, std::collections::BTreeMap::from([(1, 2), (2, 2)]));
+    assert_eq!(stats.colliding_hashes, 2);
+
+    let mut csv = Vec::new();
+    assert_eq!(matcher.write_index_csv(&mut csv).unwrap(), 8);
+    let csv = String::from_utf8(csv).unwrap();
+    let lines: Vec<&str> = csv.lines().collect();
+    assert_eq!(lines[0], "hash,identifier,t1,f1");
+    assert_eq!(&lines[1..4], ["1,\"a,1\",0,10", "1,\"a,1\",50,10", "1,b,7,11"]);
+    assert_eq!(lines.len(), 9);
+
+    // Memory-mapped postings count as well
+    let mut builder = MmapIndexBuilder::new();
+    builder.add_fingerprints("c".to_string(), &[(1, 3, 5, 1.0), (9, 4, 5, 1.0)]);
+    let path = std::env::temp_dir().join(format!("panako_index_stats_{}.idx", std::process::id()));
+    builder.write(&path).unwrap();
+    let mut mapped = Matcher::open_mmap(&path).unwrap();
+    mapped.add_fingerprints("d".to_string(), &[(1, 8, 5, 1.0)]);
+    let stats = mapped.export_index_stats();
+    std::fs::remove_file(&path).ok();
+    assert_eq!((stats.total_hashes, stats.total_postings, stats.colliding_hashes), (2, 3, 1));
+
+    let empty = Matcher::new().export_index_stats();
+    assert_eq!((empty.total_hashes, empty.mean_bucket_size, empty.max_bucket_size), (0, 0.0, 0));
+}
+
+#[test]
+fn test_ref_coverage_of_clip_from_long_reference() {
+    let config = PanakoConfig::default();
+    let frames_per_s = (1.0 / config.frame_duration_s()).round() as i32;
+    // 200 s reference with 4 fingerprints per second
+    let reference: Vec<(u64, i32, i16, f32)> = (0..800)
+        .map(|i| (7_000_000 + i as u64 * 7919, i * frames_per_s / 4, 40 + (i % 30) as i16, 1.0))
+        .collect();
+    let mut matcher = Matcher::new();
+    matcher.add_fingerprints("song".to_string(), &reference);
+    matcher.add_duration("song".to_string(), 200_000);
+
+    // 10 s clip taken 60 s into the song
+    let clip: Vec<(u64, i32, i16, f32)> = reference[240..280]
+        .iter()
+        .map(|&(hash, t1, f1, m1)| (hash, t1 - 60 * frames_per_s, f1, m1))
+        .collect();
+    let result = &matcher.query("clip", &clip, &config).unwrap()[0];
+    let ref_covered = result.percent_ref_covered.unwrap();
+    assert!(result.percent_seconds_with_match > 0.9, "query coverage {}", result.percent_seconds_with_match);
+    assert!((ref_covered - 0.05).abs() < 0.011, "reference coverage {}", ref_covered);
+
+    // The full song played covers (almost) all of it
+    let result = &matcher.query("full", &reference, &config).unwrap()[0];
+    assert!(result.percent_ref_covered.unwrap() > 0.99);
+
+    // Unknown when the duration is neither known nor estimated
+    let mut unknown = Matcher::new();
+    unknown.add_fingerprints("song".to_string(), &reference);
+    let no_estimate = PanakoConfig { estimate_ref_duration: false, ..PanakoConfig::default() };
+    assert!(unknown.query("clip", &clip, &no_estimate).unwrap()[0].percent_ref_covered.is_none());
+}
+
+#[test]
+fn test_from_directory_indexes_mixed_formats() {
+    use panako_fp::{FpJsonFile, FpJsonFingerprint, FpJsonSegment};
+
+    let dir = std::env::temp_dir().join(format!("panako_from_directory_{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let names = ["json_ref", "bson_ref", "upper_ref"];
+    for (r, name) in names.iter().enumerate() {
+        let mut fp_file = FpJsonFile::new(format!("/audio/{}.wav", name), name.to_string(), 16000, 5000, 1);
+        let fingerprints: Vec<FpJsonFingerprint> = (0..20)
+            .map(|i| FpJsonFingerprint { hash: 1000 * (r as u64 + 1) + i, t1: i as i32 * 10, f1: 40, m1: 1.0 })
+            .collect();
+        fp_file.add_segment(FpJsonSegment {
+            segment_id: 0,
+            start_time_s: 0.0,
+            end_time_s: 5.0,
+            num_fingerprints: fingerprints.len(),
+            fingerprints,
+        });
+        match r {
+            0 => fp_file.save(&dir.join("json_ref.json")).unwrap(),
+            1 => fp_file.save_bson(&dir.join("bson_ref.bson")).unwrap(),
+            _ => fp_file.save_bson(&dir.join("upper_ref.BSON")).unwrap(),
+        }
+    }
+    std::fs::write(dir.join("notes.txt"), "not a fingerprint").unwrap();
+    std::fs::write(dir.join("broken.json"), "{ not json").unwrap();
+
+    let config = PanakoConfig::default();
+    let index = Matcher::index_directory(&dir, config.clone(), crate::CorruptFilePolicy::SkipWithWarning).unwrap();
+    let collected = Matcher::index_directory(&dir, config.clone(), crate::CorruptFilePolicy::Collect).unwrap();
+    let fail_fast = Matcher::index_directory(&dir, config.clone(), crate::CorruptFilePolicy::FailFast);
+    let matcher = Matcher::from_directory(&dir, &config).unwrap();
+    std::fs::remove_dir_all(&dir).ok();
+
+    assert_eq!(index.loaded, names.len());
+    assert_eq!(index.skipped, vec![dir.join("broken.json")]);
+    assert!(index.failed.is_empty());
+    // Collect still indexes the readable references and hands back the rest
+    assert_eq!(collected.loaded, names.len());
+    assert_eq!(collected.failed, vec![dir.join("broken.json")]);
+    assert_eq!(collected.matcher.export_index_stats().total_hashes, 60);
+    assert!(fail_fast.is_err());
+    assert_eq!(matcher.export_index_stats().total_hashes, 60);
+
+    // Every reference is indexed, whatever its format
+    for (r, name) in names.iter().enumerate() {
+        let query: Vec<(u64, i32, i16, f32)> = (0..20).map(|i| (1000 * (r as u64 + 1) + i, i as i32 * 10, 40, 1.0)).collect();
+        let results = matcher.query("query", &query, &config).unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].ref_identifier.as_deref(), Some(*name));
+        assert_eq!(results[0].ref_duration_ms, Some(5000));
+    }
+}
+
+#[tokio::test]
+async fn test_match_reports_content_id() {
+    use crate::storage_backend::{FilesystemBackend, FingerprintMetadata, StorageBackend};
+    use crate::storage_config::FileFormat;
+
+    let dir = std::env::temp_dir().join(format!("panako_content_id_{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    let backend = FilesystemBackend::from_path(dir.to_str().unwrap(), FileFormat::Json);
+    for (r, (name, content_id)) in [("spot_1234", Some("USRC17607839")), ("jingle", None)].into_iter().enumerate() {
+        let metadata = FingerprintMetadata {
+            filename: name.to_string(),
+            original_path: format!("/audio/{}.wav", name),
+            algorithm: "panako".to_string(),
+            sample_rate: 16000,
+            duration_ms: 5000,
+            channels: 1,
+            created_at: String::new(),
+            tags: HashMap::new(),
+            content_hash: None,
+            content_id: content_id.map(str::to_string),
+        };
+        let fingerprints: Vec<(u64, i32, i16, f32)> =
+            (0..20).map(|i| (1000 * (r as u64 + 1) + i, i as i32 * 10, 40, 1.0)).collect();
+        backend.save_fingerprints(name, &fingerprints, &metadata).await.unwrap();
+    }
+
+    let config = PanakoConfig::default();
+    let matcher = Matcher::from_directory(&dir, &config).unwrap();
+    let found = backend.get_metadata_by_content_id("USRC17607839").await.unwrap();
+    let missing = backend.get_metadata_by_content_id("GBAYE0601498").await.unwrap();
+    std::fs::remove_dir_all(&dir).ok();
+
+    let query: Vec<(u64, i32, i16, f32)> = (0..20).map(|i| (1000 + i, i as i32 * 10, 40, 1.0)).collect();
+    let results = matcher.query("query", &query, &config).unwrap();
+    assert_eq!(results.len(), 1);
+    assert_eq!(results[0].ref_identifier.as_deref(), Some("spot_1234"));
+    assert_eq!(results[0].content_id.as_deref(), Some("USRC17607839"));
+
+    let query: Vec<(u64, i32, i16, f32)> = (0..20).map(|i| (2000 + i, i as i32 * 10, 40, 1.0)).collect();
+    let results = matcher.query("query", &query, &config).unwrap();
+    assert_eq!(results[0].ref_identifier.as_deref(), Some("jingle"));
+    assert_eq!(results[0].content_id, None);
+
+    assert_eq!(found.map(|metadata| metadata.filename).as_deref(), Some("spot_1234"));
+    assert!(missing.is_none());
+}
+
+#[tokio::test]
+#[ignore] // Requires PostgreSQL with migrations applied
+async fn test_lazy_matching_equals_eager_matching() {
+    use crate::storage_backend::{FingerprintMetadata, PostgresqlBackend, StorageBackend};
+    use crate::storage_config::PostgresqlConfig;
+
+    let config = PostgresqlConfig {
+        database: "panako".to_string(),
+        user: "panako_user".to_string(),
+        password: "panako_pass".to_string(),
+        ..PostgresqlConfig::default()
+    };
+    let backend = PostgresqlBackend::new(&config).await.unwrap();
+
+    // Hashes unique to this run, so other stored references cannot match
+    let pid = std::process::id() as u64;
+    let names: Vec<String> = (0..3).map(|r| format!("lazy_ref_{}_{}", r, pid)).collect();
+    let references: Vec<Vec<(u64, i32, i16, f32)>> = (0..3u64)
+        .map(|r| {
+            (0..200u64)
+                .map(|i| ((pid << 24) + (r << 16) + i * 7919 % 65536, i as i32 * 12, (40 + i % 60) as i16, 1.0))
+                .collect()
+        })
+        .collect();
+    for (name, fingerprints) in names.iter().zip(&references) {
+        let metadata = FingerprintMetadata {
+            filename: name.clone(),
+            original_path: format!("/audio/{}.wav", name),
+            algorithm: "panako".to_string(),
+            sample_rate: 16000,
+            duration_ms: 20_000,
+            channels: 1,
+            created_at: String::new(),
+            tags: HashMap::from([("campaign".to_string(), name.clone())]),
+            content_hash: None,
+            content_id: None,
+        };
+        backend.save_fingerprints(name, fingerprints, &metadata).await.unwrap();
+    }
+
+    // Eager: the whole catalog in memory
+    let mut eager = Matcher::new();
+    for (identifier, fingerprints) in backend.load_all_fingerprints().await.unwrap() {
+        let metadata = backend.get_metadata(&identifier).await.unwrap().unwrap();
+        eager.add_fingerprints(identifier.clone(), &fingerprints);
+        eager.add_registration_time(identifier.clone(), metadata.created_at_unix_s().unwrap());
+        eager.add_tags(identifier.clone(), metadata.tags);
+        eager.add_path(identifier.clone(), metadata.original_path);
+        eager.add_duration(identifier, metadata.duration_ms);
+    }
+
+    // Clips of two references, the second queried twice to use the cache
+    let clip = |r: usize, offset: i32| -> Vec<(u64, i32, i16, f32)> {
+        references[r][50..150].iter().map(|&(h, t, f, m)| (h, t + offset, f, m)).collect()
+    };
+    let queries = [clip(0, 300), clip(2, -200), clip(2, 40)];
+    let match_config = PanakoConfig::default();
+    let mut lazy = LazyMatcher::new(&backend, &match_config).unwrap();
+    let mut results = Vec::new();
+    for (q, query) in queries.iter().enumerate() {
+        let path = format!("query_{}", q);
+        results.push((
+            lazy.query(&path, query, &match_config).await,
+            eager.query(&path, query, &match_config).unwrap(),
+        ));
+    }
+    let (fetched, known) = (lazy.fetched_hashes(), lazy.known_references());
+    for name in &names {
+        backend.delete_fingerprints(name).await.unwrap();
+    }
+
+    for (lazy_results, eager_results) in results {
+        let lazy_results = lazy_results.unwrap();
+        assert_eq!(lazy_results.len(), 1);
+        assert_eq!(lazy_results, eager_results);
+    }
+    // Only the queried hashes and references were fetched
+    assert_eq!(fetched, 200);
+    assert_eq!(known, 2);
+}
+
+#[tokio::test]
+async fn test_lazy_matcher_follows_config() {
+    use crate::storage_backend::{FilesystemBackend, FingerprintMetadata, StorageBackend};
+    use crate::storage_config::FileFormat;
+
+    let dir = std::env::temp_dir().join(format!("panako_lazy_config_{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    let backend = FilesystemBackend::from_path(dir.to_str().unwrap(), FileFormat::Json);
+    let metadata = |name: &str, sample_rate: u32| FingerprintMetadata {
+        filename: name.to_string(),
+        original_path: format!("/audio/{}.wav", name),
+        algorithm: "panako".to_string(),
+        sample_rate,
+        duration_ms: 10_000,
+        channels: 1,
+        created_at: String::new(),
+        tags: HashMap::new(),
+        content_hash: None,
+        content_id: None,
+    };
+    // 32-bit hashes, as fingerprinted with `hash_width = "bits32"`
+    let narrow: Vec<(u64, i32, i16, f32)> = (0..40).map(|i| (7000 + i, i as i32 * 10, 50, 1.0)).collect();
+    let other: Vec<(u64, i32, i16, f32)> = (0..40).map(|i| (9000 + i, i as i32 * 10, 50, 1.0)).collect();
+    backend.save_fingerprints("narrow", &narrow, &metadata("narrow", 16000)).await.unwrap();
+    backend.save_fingerprints("other_rate", &other, &metadata("other_rate", 8000)).await.unwrap();
+
+    let config = PanakoConfig { hash_width: HashWidth::Bits32, ..PanakoConfig::default() };
+    let whole_catalog = PanakoConfig { max_hash_ref_fraction: Some(0.5), ..config.clone() };
+    let bloom = PanakoConfig { bloom_filter_bits_per_hash: Some(10), ..config.clone() };
+    let rejected = [
+        LazyMatcher::new(&backend, &whole_catalog).is_err(),
+        LazyMatcher::new(&backend, &bloom).is_err(),
+    ];
+
+    // Query hashes carry high bits the 32-bit index ignores
+    let mut lazy = LazyMatcher::new(&backend, &config).unwrap();
+    let query: Vec<(u64, i32, i16, f32)> = narrow.iter().map(|&(h, t, f, m)| ((1 << 40) | h, t + 50, f, m)).collect();
+    let results = lazy.query("query", &query, &config).await;
+    let mismatched = lazy.query("query", &other, &config).await;
+    std::fs::remove_dir_all(&dir).ok();
+
+    assert_eq!(rejected, [true, true]);
+    let results = results.unwrap();
+    assert_eq!(results.len(), 1);
+    assert_eq!(results[0].ref_identifier.as_deref(), Some("narrow"));
+    let message = mismatched.unwrap_err().to_string();
+    assert!(message.contains("8000 Hz"), "{}", message);
+}
+
+#[test]
+fn test_speed_altered_flag_follows_tolerance() {
+    let mut matcher = Matcher::new();
+    let ref_fps: Vec<(u64, i32, i16, f32)> = (0..40).map(|i| (500 + i as u64, i * 3, 50, 1.0)).collect();
+    matcher.add_fingerprints("spot".to_string(), &ref_fps);
+
+    // Aired 5% faster: the query reaches each reference frame earlier
+    let sped_up: Vec<(u64, i32, i16, f32)> = ref_fps
+        .iter()
+        .map(|&(hash, t1, f1, m1)| (hash, (t1 as f64 / 1.05).round() as i32, f1, m1))
+        .collect();
+    let config = |tolerance: Option<f64>| PanakoConfig {
+        // Wide enough for the offset drift over the spot
+        query_range: 6,
+        speed_altered_tolerance: tolerance,
+        ..PanakoConfig::default()
+    };
+
+    let strict = matcher.query("query", &sped_up, &config(Some(0.02))).unwrap();
+    assert_eq!(strict.len(), 1);
+    assert!((strict[0].time_factor - 1.05).abs() < 0.01, "time_factor {}", strict[0].time_factor);
+    assert!(strict[0].speed_altered);
+
+    let lenient = matcher.query("query", &sped_up, &config(Some(0.10))).unwrap();
+    assert!(!lenient[0].speed_altered);
+
+    let unset = matcher.query("query", &sped_up, &config(None)).unwrap();
+    assert!(!unset[0].speed_altered);
+
+    let normal = matcher.query("query", &ref_fps, &config(Some(0.02))).unwrap();
+    assert_eq!(normal[0].time_factor, 1.0);
+    assert!(!normal[0].speed_altered);
+}
+
+#[test]
+fn test_frozen_matcher_returns_same_results() {
+    // Three references sharing some hashes, with magnitudes
+    let build = || {
+        let mut matcher = Matcher::new();
+        matcher.retain_magnitudes();
+        for r in 0..3u64 {
+            let fps: Vec<(u64, i32, i16, f32)> = (0..60)
+                .map(|i| ((i % 25) * 7 + r * (i % 3), (i * 4 + r * 11) as i32, (40 + i) as i16, 0.1 * (r + 1) as f32))
+                .collect();
+            matcher.add_fingerprints(format!("ref_{}", r), &fps);
+            matcher.add_duration(format!("ref_{}", r), 8000);
+        }
+        matcher
+    };
+    let queries: Vec<Vec<(u64, i32, i16, f32)>> = (0..3u64)
+        .map(|r| {
+            (10..50)
+                .map(|i| ((i % 25) * 7 + r * (i % 3), (i * 4 + 100) as i32, (40 + i) as i16, 1.0))
+                .collect()
+        })
+        .collect();
+    let config = PanakoConfig::default();
+
+    let mut plain = build();
+    let mut frozen = build().freeze();
+    assert!(frozen.is_frozen());
+    assert_eq!(frozen.num_postings(), plain.num_postings());
+    assert_eq!(frozen.export_index_stats(), plain.export_index_stats());
+    assert_eq!(frozen.minimal_distinguishing_set("ref_1"), plain.minimal_distinguishing_set("ref_1"));
+    for query in &queries {
+        let expected = plain.query("query", query, &config).unwrap();
+        assert!(expected[0].mean_matched_magnitude.is_some());
+        assert_eq!(frozen.query("query", query, &config).unwrap(), expected);
+    }
+
+    // Fingerprints added after freezing are queried alongside the frozen ones
+    let late: Vec<(u64, i32, i16, f32)> = (0..30).map(|i| (7 * i, i as i32 * 5, 60, 0.5)).collect();
+    plain.add_fingerprints("late".to_string(), &late);
+    frozen.add_fingerprints("late".to_string(), &late);
+    for query in queries.iter().chain([&late]) {
+        assert_eq!(
+            frozen.query("query", query, &config).unwrap(),
+            plain.query("query", query, &config).unwrap()
+        );
+    }
+
+    // Rewriting the index converts it back without losing postings
+    assert_eq!(frozen.filter_common_hashes(0.5), plain.filter_common_hashes(0.5));
+    assert!(!frozen.is_frozen());
+    for query in &queries {
+        assert_eq!(
+            frozen.query("query", query, &config).unwrap(),
+            plain.query("query", query, &config).unwrap()
+        );
+    }
+}
+
+#[test]
+fn test_progressive_query_stops_on_early_match() {
+    let config = PanakoConfig::default();
+    let frames = |seconds: f64| (seconds / config.frame_duration_s()) as i32;
+
+    let mut matcher = Matcher::new();
+    let ref_fps: Vec<(u64, i32, i16, f32)> = (0..frames(30.0) / 20)
+        .map(|i| (1000 + i as u64, i * 20, 50, 1.0))
+        .collect();
+    matcher.add_fingerprints("jingle".to_string(), &ref_fps);
+
+    // 60s query: the reference in the first 5s, then unindexed hashes
+    let mut query_fps: Vec<(u64, i32, i16, f32)> = ref_fps
+        .iter()
+        .copied()
+        .filter(|fp| fp.1 < frames(5.0))
+        .collect();
+    query_fps.extend((frames(5.0) / 20..frames(60.0) / 20).map(|i| (900_000 + i as u64, i * 20, 50, 1.0)));
+    // Progressive matching must not rely on the input order
+    query_fps.reverse();
+
+    let progressive = matcher.query_progressive("query", &query_fps, 20, &config).unwrap();
+    assert!(progressive.early_exit);
+    assert_eq!(progressive.total_fingerprints, query_fps.len());
+    assert!(progressive.consumed_fingerprints < query_fps.len() / 10);
+    assert!(progressive.consumed_until_s <= 5.0, "consumed {}s", progressive.consumed_until_s);
+    assert_eq!(progressive.results[0].ref_identifier.as_deref(), Some("jingle"));
+    assert!(progressive.results[0].score >= 20);
+
+    // Never strong enough: the whole query is matched, as with `query`
+    let full = matcher.query_progressive("query", &query_fps, 1000, &config).unwrap();
+    assert!(!full.early_exit);
+    assert_eq!(full.consumed_fingerprints, query_fps.len());
+    let plain = matcher.query("query", &query_fps, &config).unwrap();
+    assert_eq!(full.results.len(), plain.len());
+    assert_eq!(full.results[0].score, plain[0].score);
+}
+
+#[test]
+fn test_progressive_match_on_last_fingerprint() {
+    let config = PanakoConfig {
+        min_hits_unfiltered: 1,
+        min_hits_filtered: 1,
+        ..PanakoConfig::default()
+    };
+    let mut matcher = Matcher::new();
+    let ref_fps: Vec<(u64, i32, i16, f32)> = (0..5).map(|i| (3000 + i as u64, i * 20, 50, 1.0)).collect();
+    matcher.add_fingerprints("chime".to_string(), &ref_fps);
+
+    let single = matcher.query_progressive("query", &ref_fps[2..3], 1, &config).unwrap();
+    assert_eq!((single.consumed_fingerprints, single.total_fingerprints), (1, 1));
+    assert!(!single.early_exit);
+    assert_eq!(single.results[0].ref_identifier.as_deref(), Some("chime"));
+
+    // Strong only once the last fingerprint is consumed
+    let whole = matcher.query_progressive("query", &ref_fps, 5, &config).unwrap();
+    assert!(!whole.early_exit);
+    assert_eq!(whole.consumed_fingerprints, 5);
+    assert_eq!(whole.results, matcher.query("query", &ref_fps, &config).unwrap());
+}
+
+#[test]
+fn test_repeated_query_hash_collapsed() {
+    let mut matcher = Matcher::new();
+    // A sustained tone: the same hash every 10 frames
+    let drone: Vec<(u64, i32, i16, f32)> = (0..20).map(|i| (7, i * 10, 50, 1.0)).collect();
+    matcher.add_fingerprints("drone".to_string(), &drone);
+    let target: Vec<(u64, i32, i16, f32)> = (0..12)
+        .map(|i| (9000 + i as u64, i * 100, 50 + i as i16, 1.0))
+        .collect();
+    matcher.add_fingerprints("target".to_string(), &target);
+
+    // The query loops the tone under the target
+    let mut query = target.clone();
+    query.extend((0..50).map(|i| (7, i * 10, 50, 1.0)));
+
+    let repeated = matcher.query("query", &query, &PanakoConfig::default()).unwrap();
+    let drone_score = repeated
+        .iter()
+        .find(|r| r.ref_identifier.as_deref() == Some("drone"))
+        .map(|r| r.score);
+    assert!(drone_score.is_some_and(|score| score > 12), "drone score {:?}", drone_score);
+
+    let config = PanakoConfig {
+        max_query_hash_repeats: Some(1),
+        ..PanakoConfig::default()
+    };
+    let collapsed = matcher.query("query", &query, &config).unwrap();
+    assert_eq!(collapsed.len(), 1);
+    assert_eq!(collapsed[0].ref_identifier.as_deref(), Some("target"));
+    assert_eq!(collapsed[0].score, 12);
+}
+
+#[test]
+fn test_repeat_cap_keeps_earliest_occurrences() {
+    let config = PanakoConfig {
+        max_query_hash_repeats: Some(2),
+        min_hits_unfiltered: 1,
+        min_hits_filtered: 1,
+        query_range: 100,
+        ..PanakoConfig::default()
+    };
+    let frame_s = config.frame_duration_s();
+    let mut matcher = Matcher::new();
+    matcher.add_fingerprints("tone".to_string(), &[(7, 0, 50, 1.0)]);
+
+    // Occurrences listed latest first: the cap still keeps t1 = 20 and 40
+    let query: Vec<(u64, i32, i16, f32)> = [80, 60, 40, 20].iter().map(|&t1| (7, t1, 50, 1.0)).collect();
+    let results = matcher.query("query", &query, &config).unwrap();
+    assert_eq!(results[0].score, 2);
+    assert_eq!((results[0].query_start, results[0].query_stop), (20.0 * frame_s, 40.0 * frame_s));
+
+    // Progressive matching counts the same occurrences
+    let progressive = matcher.query_progressive("query", &query, 10, &config).unwrap();
+    assert_eq!(progressive.results, results);
+}
+
+#[test]
+fn test_fuzzy_triple_counts_as_one_aligned_match() {
+    use crate::eventpoint::EventPoint;
+    use crate::fingerprint::FingerprintGenerator;
+
+    let config = PanakoConfig {
+        fuzzy_hash_epsilon: Some(0.05),
+        min_hits_unfiltered: 1,
+        min_hits_filtered: 1,
+        ..PanakoConfig::default()
+    };
+    // Near-equal magnitudes: every one of the 8 hash variants is emitted on both sides
+    let triple = [
+        EventPoint::new(0, 100, 0.5),
+        EventPoint::new(10, 120, 0.5),
+        EventPoint::new(20, 110, 0.5),
+    ];
+    let fps: Vec<(u64, i32, i16, f32)> = FingerprintGenerator::new(&config)
+        .generate(&triple)
+        .unwrap()
+        .iter()
+        .map(|fp| (fp.hash, fp.t1, fp.f1, fp.m1))
+        .collect();
+    assert_eq!(fps.len(), 8);
+
+    let mut matcher = Matcher::new();
+    matcher.add_fingerprints("ref".to_string(), &fps);
+    let results = matcher.query("query", &fps, &config).unwrap();
+    assert_eq!(results.len(), 1);
+    assert_eq!(results[0].score, 1);
+}
+
+#[test]
+fn test_delta_bounds_follow_time_factor_within_reference() {
+    let mut matcher = Matcher::new();
+    let ref_fps: Vec<(u64, i32, i16, f32)> = (0..40).map(|i| (500 + i as u64, i * 3, 50, 1.0)).collect();
+    matcher.add_fingerprints("spot".to_string(), &ref_fps);
+    let config = PanakoConfig {
+        query_range: 6,
+        ref_bounds_from_delta: true,
+        ..PanakoConfig::default()
+    };
+    let frame_s = config.frame_duration_s();
+
+    // Aired 5% faster: the scaled span would run past the reference end
+    let sped_up: Vec<(u64, i32, i16, f32)> = ref_fps
+        .iter()
+        .map(|&(hash, t1, f1, m1)| (hash, (t1 as f64 / 1.05).round() as i32, f1, m1))
+        .collect();
+    let result = &matcher.query("query", &sped_up, &config).unwrap()[0];
+    assert!((result.time_factor - 1.05).abs() < 0.01, "time_factor {}", result.time_factor);
+    assert_eq!((result.query_start, result.query_stop), (0.0, 111.0 * frame_s));
+    assert_eq!((result.ref_start, result.ref_stop), (frame_s, 117.0 * frame_s));
+
+    // Aired 5% slower from the middle on: the reference span is the query
+    // span (60 frames) times the factor
+    let slowed: Vec<(u64, i32, i16, f32)> = ref_fps[20..]
+        .iter()
+        .map(|&(hash, t1, f1, m1)| (hash, (t1 as f64 * 1.05).round() as i32, f1, m1))
+        .collect();
+    let result = &matcher.query("query", &slowed, &config).unwrap()[0];
+    assert!((result.time_factor - 0.95).abs() < 0.01, "time_factor {}", result.time_factor);
+    assert_eq!((result.query_start, result.query_stop), (63.0 * frame_s, 123.0 * frame_s));
+    assert_eq!((result.ref_start, result.ref_stop), (58.0 * frame_s, 115.0 * frame_s));
+}
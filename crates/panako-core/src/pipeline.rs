@@ -0,0 +1,388 @@
+//! End-to-end fingerprinting pipeline with per-stage timing
+//!
+//! Records how long each stage (decode, transform, event point extraction,
+//! fingerprint generation) takes, for profiling and regression tracking.
+
+use std::time::{Duration, Instant};
+
+use crate::audio::{self, AudioData, DecodeCache};
+use crate::config::PanakoConfig;
+use crate::eventpoint::EventPointExtractor;
+use crate::fingerprint::{Fingerprint, FingerprintGenerator};
+use crate::transform;
+
+/// Elapsed time per pipeline stage
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct PipelineTimings {
+    /// Decoding and resampling the input file
+    pub decode: Duration,
+    /// Mono downmix, optional pre-emphasis and constant-Q transform
+    pub transform: Duration,
+    /// Event point extraction
+    pub event_points: Duration,
+    /// Fingerprint generation from event points
+    pub fingerprints: Duration,
+}
+
+impl PipelineTimings {
+    /// Sum of all stage durations
+    pub fn total(&self) -> Duration {
+        self.decode + self.transform + self.event_points + self.fingerprints
+    }
+}
+
+impl std::ops::AddAssign for PipelineTimings {
+    fn add_assign(&mut self, other: Self) {
+        self.decode += other.decode;
+        self.transform += other.transform;
+        self.event_points += other.event_points;
+        self.fingerprints += other.fingerprints;
+    }
+}
+
+/// Fingerprint already decoded audio, timing each stage (decode is left at zero)
+pub fn fingerprint_audio_timed(
+    audio_data: &AudioData,
+    config: &PanakoConfig,
+) -> anyhow::Result<(Vec<Fingerprint>, PipelineTimings)> {
+    let mut timings = PipelineTimings::default();
+    
+    let start = Instant::now();
+    let mut mono_samples = audio_data.to_mono();
+    if let Some(coefficient) = config.pre_emphasis {
+        audio::apply_pre_emphasis(&mut mono_samples, coefficient);
+    }
+    let spectrogram = transform::compute_transform(&mono_samples, config)?;
+    timings.transform = start.elapsed();
+    
+    let start = Instant::now();
+    let event_points = EventPointExtractor::new(config).extract(&spectrogram)?;
+    timings.event_points = start.elapsed();
+    
+    let start = Instant::now();
+    let fingerprints = FingerprintGenerator::new(config).generate(&event_points)?;
+    timings.fingerprints = start.elapsed();
+    
+    Ok((fingerprints, timings))
+}
+
+/// Decode cache in `config.decode_cache_dir`, if one is set
+///
+/// Callers decoding many files should open it once and pass it to
+/// `decode_input` for every file.
+pub fn open_decode_cache(config: &PanakoConfig) -> anyhow::Result<Option<DecodeCache>> {
+    config.decode_cache_dir.as_ref().map(DecodeCache::new).transpose()
+}
+
+/// Decode an input file at `config.sample_rate`, through `cache` when given
+pub fn decode_input(path: &str, config: &PanakoConfig, cache: Option<&DecodeCache>) -> anyhow::Result<AudioData> {
+    match cache {
+        Some(cache) => cache.decode_audio(path, config.sample_rate, &config.ffmpeg_options()),
+        None => audio::decode_audio_with_options(path, config.sample_rate, &config.ffmpeg_options()),
+    }
+}
+
+/// Decode every channel of an input file at `config.sample_rate`, through
+/// `cache` when given
+pub fn decode_input_channels(
+    path: &str,
+    config: &PanakoConfig,
+    cache: Option<&DecodeCache>,
+) -> anyhow::Result<AudioData> {
+    match cache {
+        Some(cache) => cache.decode_audio_channels(path, config.sample_rate, &config.ffmpeg_options()),
+        None => audio::decode_audio_channels_with_options(path, config.sample_rate, &config.ffmpeg_options()),
+    }
+}
+
+/// Generate fingerprints from an audio file, timing each stage
+pub fn generate_fingerprints_timed(
+    audio_path: &str,
+    config: &PanakoConfig,
+) -> anyhow::Result<(Vec<Fingerprint>, PipelineTimings)> {
+    let start = Instant::now();
+    let audio_data = decode_input(audio_path, config, open_decode_cache(config)?.as_ref())?;
+    audio::check_max_duration(&audio_data, config.max_duration_ms)?;
+    let decode = start.elapsed();
+    
+    let (fingerprints, mut timings) = fingerprint_audio_timed(&audio_data, config)?;
+    timings.decode = decode;
+    
+    Ok((fingerprints, timings))
+}
+
+/// Fingerprints of one channel of a multichannel input
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChannelFingerprints {
+    /// 0-based channel index (0 = left, 1 = right for stereo)
+    pub channel: u16,
+    pub fingerprints: Vec<Fingerprint>,
+}
+
+/// Fingerprint every channel of decoded audio separately (experimental)
+///
+/// Unlike `fingerprint_audio_timed` nothing is downmixed, so stereo mixes,
+/// mono fold-downs and swapped channels yield different sets. Timings are
+/// summed over the channels.
+pub fn fingerprint_channels_timed(
+    audio_data: &AudioData,
+    config: &PanakoConfig,
+) -> anyhow::Result<(Vec<ChannelFingerprints>, PipelineTimings)> {
+    let mut timings = PipelineTimings::default();
+    let mut sets = Vec::with_capacity(audio_data.channels as usize);
+    for channel in 0..audio_data.channels {
+        let channel_audio = AudioData {
+            samples: audio_data.channel(channel),
+            sample_rate: audio_data.sample_rate,
+            channels: 1,
+            duration_ms: audio_data.duration_ms,
+        };
+        let (fingerprints, channel_timings) = fingerprint_audio_timed(&channel_audio, config)?;
+        timings += channel_timings;
+        sets.push(ChannelFingerprints { channel, fingerprints });
+    }
+    Ok((sets, timings))
+}
+
+/// Generate per-channel fingerprints from an audio file, timing each stage
+pub fn generate_channel_fingerprints_timed(
+    audio_path: &str,
+    config: &PanakoConfig,
+) -> anyhow::Result<(Vec<ChannelFingerprints>, PipelineTimings)> {
+    let start = Instant::now();
+    let audio_data = decode_input_channels(audio_path, config, open_decode_cache(config)?.as_ref())?;
+    audio::check_max_duration(&audio_data, config.max_duration_ms)?;
+    let decode = start.elapsed();
+    
+    let (sets, mut timings) = fingerprint_channels_timed(&audio_data, config)?;
+    timings.decode = decode;
+    
+    Ok((sets, timings))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    fn write_test_file(path: &std::path::Path) {
+        let spec = hound::WavSpec {
+            channels: 1,
+            sample_rate: 16000,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        };
+        let mut writer = hound::WavWriter::create(path, spec).unwrap();
+        let mut seed: u32 = 12345;
+        for i in 0..16000 * 5 {
+            seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+            let noise = ((seed >> 16) as f32 / 32768.0 - 1.0) * 0.2;
+            let t = i as f32 / 16000.0;
+            let tone = (2.0 * std::f32::consts::PI * (440.0 + 200.0 * (t * 3.0).sin()) * t).sin() * 0.5;
+            writer.write_sample(((tone + noise) * 16000.0) as i16).unwrap();
+        }
+        writer.finalize().unwrap();
+    }
+
+    #[test]
+    fn test_stage_timings_cover_pipeline() {
+        let path = std::env::temp_dir().join(format!("panako_pipeline_{}.wav", std::process::id()));
+        write_test_file(&path);
+        let config = PanakoConfig::default();
+
+        let start = Instant::now();
+        let (fingerprints, timings) =
+            generate_fingerprints_timed(path.to_str().unwrap(), &config).unwrap();
+        let total = start.elapsed();
+        std::fs::remove_file(&path).ok();
+
+        assert!(!fingerprints.is_empty());
+        assert!(timings.decode > Duration::ZERO);
+        assert!(timings.transform > Duration::ZERO);
+        assert!(timings.event_points > Duration::ZERO);
+        assert!(timings.fingerprints > Duration::ZERO);
+
+        // Stages are timed within the call
+        assert!(timings.total() <= total);
+    }
+
+    #[test]
+    fn test_high_sample_rate_keeps_content_above_7khz() {
+        // Noise plus a tone sweeping around 9 kHz, only representable above 16 kHz
+        let sample_rate = 22050;
+        let mut seed: u32 = 4242;
+        let samples: Vec<f32> = (0..sample_rate * 5)
+            .map(|i| {
+                seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+                let noise = ((seed >> 16) as f32 / 32768.0 - 1.0) * 0.1;
+                let t = i as f32 / sample_rate as f32;
+                let tone = (2.0 * std::f32::consts::PI * (9000.0 + 300.0 * (t * 2.0).sin()) * t).sin() * 0.5;
+                tone + noise
+            })
+            .collect();
+        let audio_data = AudioData {
+            samples,
+            sample_rate,
+            channels: 1,
+            duration_ms: 5000,
+        };
+        let config = PanakoConfig::default().with_sample_rate(sample_rate);
+        config.validate().unwrap();
+
+        let spectrogram = transform::compute_transform(&audio_data.to_mono(), &config).unwrap();
+        let event_points = EventPointExtractor::new(&config).extract(&spectrogram).unwrap();
+        assert!(event_points
+            .iter()
+            .any(|e| transform::bin_center_frequency(e.f as usize, &config) > 7000.0));
+
+        let (fingerprints, _) = fingerprint_audio_timed(&audio_data, &config).unwrap();
+        assert!(!fingerprints.is_empty());
+        let tuples: Vec<(u64, i32, i16, f32)> =
+            fingerprints.iter().map(|fp| (fp.hash, fp.t1, fp.f1, fp.m1)).collect();
+
+        let mut matcher = crate::matching::Matcher::new();
+        matcher.add_fingerprints("hi_res".to_string(), &tuples);
+        let results = matcher.query("query", &tuples, &config).unwrap();
+        assert_eq!(results[0].ref_identifier, Some("hi_res".to_string()));
+    }
+
+    #[test]
+    fn test_whitening_aligns_event_points_of_equalized_copy() {
+        // Melody over noise, and a copy through a strong low-frequency shelf
+        let sample_rate = 16000;
+        let mut seed: u32 = 2024;
+        let original: Vec<f32> = (0..sample_rate * 8)
+            .map(|i| {
+                seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+                let noise = ((seed >> 16) as f32 / 32768.0 - 1.0) * 0.05;
+                let t = i as f32 / sample_rate as f32;
+                let step = (t * 5.0) as usize;
+                let tones: f32 = [300.0, 700.0, 1500.0, 3100.0]
+                    .iter()
+                    .enumerate()
+                    .map(|(k, &base)| {
+                        let freq = base * (1.0 + ((step * (k + 3)) % 7) as f32 / 12.0);
+                        (2.0 * std::f32::consts::PI * freq * t).sin() * 0.15
+                    })
+                    .sum();
+                tones + noise
+            })
+            .collect();
+        let mut state = 0.0;
+        let equalized: Vec<f32> = original
+            .iter()
+            .map(|&x| {
+                state = x + 0.9 * state;
+                state * 0.2
+            })
+            .collect();
+        
+        let event_points = |samples: &[f32], config: &PanakoConfig| -> HashSet<(i32, i16)> {
+            let spectrogram = transform::compute_transform(samples, config).unwrap();
+            EventPointExtractor::new(config)
+                .extract(&spectrogram)
+                .unwrap()
+                .into_iter()
+                .map(|ep| (ep.t, ep.f))
+                .collect()
+        };
+        let overlap = |config: &PanakoConfig| -> f64 {
+            let a = event_points(&original, config);
+            let b = event_points(&equalized, config);
+            a.intersection(&b).count() as f64 / a.union(&b).count() as f64
+        };
+        
+        let plain = overlap(&PanakoConfig::default());
+        let whitened = overlap(&PanakoConfig {
+            whitening_window_s: Some(1.0),
+            ..PanakoConfig::default()
+        });
+        assert!(whitened > plain, "whitened {:.3} vs plain {:.3}", whitened, plain);
+    }
+
+    #[test]
+    fn test_stereo_channels_are_fingerprinted_separately() {
+        // 22.05 kHz stereo: a sweep on the left, a stepped melody on the right
+        let path = std::env::temp_dir().join(format!("panako_stereo_{}.wav", std::process::id()));
+        let spec = hound::WavSpec {
+            channels: 2,
+            sample_rate: 22050,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        };
+        let mut writer = hound::WavWriter::create(&path, spec).unwrap();
+        let mut seed: u32 = 99;
+        let mut noise = || {
+            seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+            ((seed >> 16) as f32 / 32768.0 - 1.0) * 0.1
+        };
+        for i in 0..22050 * 5 {
+            let t = i as f32 / 22050.0;
+            let left = (2.0 * std::f32::consts::PI * (500.0 + 250.0 * (t * 3.0).sin()) * t).sin() * 0.5 + noise();
+            let freq = [900.0, 1300.0, 2100.0, 1700.0][(t * 4.0) as usize % 4];
+            let right = (2.0 * std::f32::consts::PI * freq * t).sin() * 0.5 + noise();
+            writer.write_sample((left * 16000.0) as i16).unwrap();
+            writer.write_sample((right * 16000.0) as i16).unwrap();
+        }
+        writer.finalize().unwrap();
+
+        let config = PanakoConfig::default();
+        let sets = crate::generate_channel_fingerprints(path.to_str().unwrap(), &config).unwrap();
+        let downmix = crate::generate_fingerprints(path.to_str().unwrap(), &config).unwrap();
+        std::fs::remove_file(&path).ok();
+
+        assert_eq!(sets.iter().map(|set| set.channel).collect::<Vec<_>>(), vec![0, 1]);
+        let hashes: Vec<HashSet<u64>> = sets
+            .iter()
+            .map(|set| set.fingerprints.iter().map(|fp| fp.hash).collect())
+            .collect();
+        assert!(hashes.iter().all(|h| !h.is_empty()));
+        let shared = hashes[0].intersection(&hashes[1]).count() as f64;
+        assert!(shared / (hashes[0].len().min(hashes[1].len()) as f64) < 0.1);
+        assert_ne!(sets[0].fingerprints, downmix);
+        assert_ne!(sets[1].fingerprints, downmix);
+    }
+
+    #[test]
+    fn test_32_bit_hashes_self_match() {
+        let mut seed: u32 = 31;
+        let samples: Vec<f32> = (0..16000 * 12)
+            .map(|i| {
+                seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+                let noise = ((seed >> 16) as f32 / 32768.0 - 1.0) * 0.05;
+                let t = i as f32 / 16000.0;
+                let freq = [330.0, 495.0, 742.0, 1113.0, 880.0][(t * 3.0) as usize % 5];
+                (2.0 * std::f32::consts::PI * freq * t).sin() * 0.4 + noise
+            })
+            .collect();
+        let audio_data = AudioData {
+            samples,
+            sample_rate: 16000,
+            channels: 1,
+            duration_ms: 12000,
+        };
+        let config = PanakoConfig {
+            hash_width: crate::config::HashWidth::Bits32,
+            ..PanakoConfig::default()
+        };
+        
+        let (fingerprints, _) = fingerprint_audio_timed(&audio_data, &config).unwrap();
+        assert!(!fingerprints.is_empty());
+        assert!(fingerprints.iter().all(|fp| fp.hash <= u32::MAX as u64));
+        let tuples: Vec<(u64, i32, i16, f32)> =
+            fingerprints.iter().map(|fp| (fp.hash, fp.t1, fp.f1, fp.m1)).collect();
+        
+        let mut matcher = crate::matching::Matcher::new();
+        matcher.set_hash_width(config.hash_width);
+        assert_eq!(matcher.hash_width(), crate::config::HashWidth::Bits32);
+        matcher.add_fingerprints("narrow".to_string(), &tuples);
+        let excerpt: Vec<(u64, i32, i16, f32)> = tuples.iter().filter(|fp| (300..800).contains(&fp.1)).copied().collect();
+        let results = matcher.query("query", &excerpt, &config).unwrap();
+        assert_eq!(results[0].ref_identifier, Some("narrow".to_string()));
+        
+        // The two widths give different hashes for the same audio
+        let (wide, _) = fingerprint_audio_timed(&audio_data, &PanakoConfig::default()).unwrap();
+        assert_eq!(wide.len(), fingerprints.len());
+        assert!(wide.iter().zip(&fingerprints).any(|(w, n)| w.hash != n.hash));
+    }
+}
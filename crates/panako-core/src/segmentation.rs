@@ -3,8 +3,26 @@
 //! Implements automatic segmentation of long audio files into overlapping chunks
 //! using Java Panako's default parameters (25s segments with 5s overlap).
 
+use anyhow::Result;
+
 use crate::audio::AudioData;
 
+/// How audio is split into segments
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum SegmentationMode {
+    /// Fixed-duration overlapping windows using `segment_duration_s` and
+    /// `overlap_duration_s` (Java Panako behavior)
+    #[default]
+    Fixed,
+    /// Split at silent gaps so segments align to content boundaries
+    Silence {
+        /// RMS level (linear, 0..1) below which a frame counts as silent
+        threshold: f32,
+        /// Minimum silent gap in seconds that separates two segments
+        min_gap_s: f64,
+    },
+}
+
 /// Configuration for audio segmentation
 #[derive(Debug, Clone)]
 pub struct SegmentationConfig {
@@ -14,6 +32,8 @@ pub struct SegmentationConfig {
     pub overlap_duration_s: f64,
     /// Minimum duration for the last segment
     pub min_segment_duration_s: f64,
+    /// Segmentation strategy
+    pub mode: SegmentationMode,
 }
 
 impl Default for SegmentationConfig {
@@ -22,10 +42,36 @@ impl Default for SegmentationConfig {
             segment_duration_s: 25.0,  // Java Panako default
             overlap_duration_s: 5.0,   // Java Panako default
             min_segment_duration_s: 10.0,
+            mode: SegmentationMode::Fixed,
+        }
+    }
+}
+
+impl SegmentationConfig {
+    /// Check that fixed windows advance: a step of `segment_duration_s -
+    /// overlap_duration_s` that is not positive would never reach the end
+    pub fn validate(&self) -> Result<()> {
+        if self.segment_duration_s.is_nan() || self.segment_duration_s <= 0.0 {
+            anyhow::bail!("segment_duration_s must be > 0, got {}", self.segment_duration_s);
+        }
+        if self.overlap_duration_s.is_nan() || self.overlap_duration_s < 0.0 {
+            anyhow::bail!("overlap_duration_s must be >= 0, got {}", self.overlap_duration_s);
+        }
+        if self.overlap_duration_s >= self.segment_duration_s {
+            anyhow::bail!(
+                "overlap_duration_s ({}s) must be shorter than segment_duration_s ({}s), \
+                 otherwise segments never advance",
+                self.overlap_duration_s,
+                self.segment_duration_s
+            );
         }
+        Ok(())
     }
 }
 
+/// Length of the analysis frame used to detect silence (seconds)
+const SILENCE_FRAME_S: f64 = 0.01;
+
 /// Represents a segment of audio
 #[derive(Debug, Clone)]
 pub struct AudioSegment {
@@ -47,10 +93,26 @@ pub fn should_segment(audio: &AudioData, config: &SegmentationConfig) -> bool {
     duration_s > config.segment_duration_s
 }
 
-/// Segment audio into overlapping chunks
+/// Segment audio according to the configured segmentation mode
+///
+/// Fails if the config does not pass `SegmentationConfig::validate`.
 pub fn segment_audio(
     audio: &AudioData,
     config: &SegmentationConfig,
+) -> Result<Vec<AudioSegment>> {
+    config.validate()?;
+    Ok(match config.mode {
+        SegmentationMode::Fixed => segment_fixed(audio, config),
+        SegmentationMode::Silence { threshold, min_gap_s } => {
+            segment_at_silence(audio, threshold, min_gap_s)
+        }
+    })
+}
+
+/// Segment audio into fixed-duration overlapping chunks
+fn segment_fixed(
+    audio: &AudioData,
+    config: &SegmentationConfig,
 ) -> Vec<AudioSegment> {
     let duration_s = audio.duration_ms as f64 / 1000.0;
     
@@ -68,11 +130,13 @@ pub fn segment_audio(
     let mut segments = Vec::new();
     let samples_per_second = audio.sample_rate as f64;
     let step_duration_s = config.segment_duration_s - config.overlap_duration_s;
+    // Upper bound on the window count, in case the step rounds to nothing
+    let max_segments = (duration_s / step_duration_s).ceil() as usize + 1;
     
     let mut current_start_s = 0.0;
     let mut segment_id = 0;
     
-    while current_start_s < duration_s {
+    while current_start_s < duration_s && segment_id < max_segments {
         let current_end_s = (current_start_s + config.segment_duration_s).min(duration_s);
         
         // Check if last segment would be too short
@@ -111,6 +175,67 @@ pub fn segment_audio(
     segments
 }
 
+/// Segment audio into the non-silent regions separated by gaps of at least `min_gap_s`
+///
+/// Leading and trailing silence is dropped. Audio without qualifying gaps is
+/// returned as a single segment; fully silent audio yields no segments.
+fn segment_at_silence(audio: &AudioData, threshold: f32, min_gap_s: f64) -> Vec<AudioSegment> {
+    let sample_rate = audio.sample_rate as f64;
+    let frame_len = ((sample_rate * SILENCE_FRAME_S) as usize).max(1);
+    let min_gap_frames = ((min_gap_s / SILENCE_FRAME_S).ceil() as usize).max(1);
+    
+    // Classify frames as silent or not by RMS level
+    let silent: Vec<bool> = audio
+        .samples
+        .chunks(frame_len)
+        .map(|frame| {
+            let energy: f32 = frame.iter().map(|s| s * s).sum::<f32>() / frame.len() as f32;
+            energy.sqrt() < threshold
+        })
+        .collect();
+    
+    // Collect content regions [start_frame, end_frame) split by long silent runs
+    let mut regions = Vec::new();
+    let mut region_start: Option<usize> = None;
+    let mut silent_run = 0;
+    
+    for (idx, &is_silent) in silent.iter().enumerate() {
+        if is_silent {
+            silent_run += 1;
+            if silent_run == min_gap_frames {
+                if let Some(start) = region_start.take() {
+                    regions.push((start, idx + 1 - silent_run));
+                }
+            }
+        } else {
+            if region_start.is_none() {
+                region_start = Some(idx);
+            }
+            silent_run = 0;
+        }
+    }
+    if let Some(start) = region_start {
+        // Trailing silence shorter than a gap is still trimmed
+        regions.push((start, silent.len() - silent_run));
+    }
+    
+    regions
+        .into_iter()
+        .enumerate()
+        .map(|(segment_id, (start_frame, end_frame))| {
+            let start_sample = start_frame * frame_len;
+            let end_sample = (end_frame * frame_len).min(audio.samples.len());
+            AudioSegment {
+                segment_id,
+                start_time_s: start_sample as f64 / sample_rate,
+                end_time_s: end_sample as f64 / sample_rate,
+                samples: audio.samples[start_sample..end_sample].to_vec(),
+                sample_rate: audio.sample_rate,
+            }
+        })
+        .collect()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -127,7 +252,7 @@ mod tests {
         let config = SegmentationConfig::default();
         assert!(!should_segment(&audio, &config));
         
-        let segments = segment_audio(&audio, &config);
+        let segments = segment_audio(&audio, &config).unwrap();
         assert_eq!(segments.len(), 1);
         assert_eq!(segments[0].segment_id, 0);
     }
@@ -144,7 +269,7 @@ mod tests {
         let config = SegmentationConfig::default();
         assert!(should_segment(&audio, &config));
         
-        let segments = segment_audio(&audio, &config);
+        let segments = segment_audio(&audio, &config).unwrap();
         
         // 60s with 25s segments and 20s step = 3 segments
         // Seg 0: 0-25, Seg 1: 20-45, Seg 2: 40-60
@@ -153,4 +278,61 @@ mod tests {
         // Check overlap
         assert!((segments[0].end_time_s - segments[1].start_time_s - 5.0).abs() < 0.1);
     }
+    
+    #[test]
+    fn test_silence_segmentation_aligns_to_content() {
+        let sample_rate = 16000;
+        let tone = |seconds: usize| -> Vec<f32> {
+            (0..sample_rate * seconds)
+                .map(|i| 0.5 * (2.0 * std::f32::consts::PI * 440.0 * i as f32 / sample_rate as f32).sin())
+                .collect()
+        };
+        
+        // 3s tone, 2s silence, 3s tone, 1s silence
+        let mut samples = tone(3);
+        samples.extend(vec![0.0; sample_rate * 2]);
+        samples.extend(tone(3));
+        samples.extend(vec![0.0; sample_rate]);
+        
+        let audio = AudioData {
+            samples,
+            sample_rate: sample_rate as u32,
+            channels: 1,
+            duration_ms: 9000,
+        };
+        
+        let config = SegmentationConfig {
+            mode: SegmentationMode::Silence { threshold: 0.01, min_gap_s: 0.5 },
+            ..SegmentationConfig::default()
+        };
+        let segments = segment_audio(&audio, &config).unwrap();
+        
+        assert_eq!(segments.len(), 2);
+        assert!((segments[0].start_time_s - 0.0).abs() < 0.02);
+        assert!((segments[0].end_time_s - 3.0).abs() < 0.02);
+        assert!((segments[1].start_time_s - 5.0).abs() < 0.02);
+        assert!((segments[1].end_time_s - 8.0).abs() < 0.02);
+        assert_eq!(segments[1].segment_id, 1);
+    }
+    
+    #[test]
+    fn test_overlap_not_shorter_than_segment_is_an_error() {
+        let audio = AudioData {
+            samples: vec![0.0; 16000 * 60],
+            sample_rate: 16000,
+            channels: 1,
+            duration_ms: 60000,
+        };
+        
+        let config = SegmentationConfig {
+            segment_duration_s: 25.0,
+            overlap_duration_s: 25.0,
+            ..SegmentationConfig::default()
+        };
+        let err = segment_audio(&audio, &config).unwrap_err().to_string();
+        assert!(err.contains("overlap_duration_s (25s) must be shorter than segment_duration_s (25s)"), "{}", err);
+        
+        let config = SegmentationConfig { overlap_duration_s: 30.0, ..config };
+        assert!(segment_audio(&audio, &config).is_err());
+    }
 }
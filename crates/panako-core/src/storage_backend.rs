@@ -4,9 +4,12 @@
 
 use anyhow::Result;
 use async_trait::async_trait;
-use std::path::PathBuf;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
 
-use crate::storage_config::{FileFormat, FilesystemConfig, PostgresqlConfig};
+use crate::storage_config::{CorruptFilePolicy, FileFormat, FilesystemConfig, IngestTransaction, PostgresqlConfig};
 
 /// Metadata for fingerprint storage
 #[derive(Debug, Clone)]
@@ -18,6 +21,41 @@ pub struct FingerprintMetadata {
     pub duration_ms: u32,
     pub channels: u16,
     pub created_at: String,
+    pub tags: HashMap<String, String>,
+    /// SHA-256 of the decoded audio samples (see `AudioData::content_hash`)
+    pub content_hash: Option<String>,
+    /// External content ID such as an ISRC or EIDR
+    pub content_id: Option<String>,
+}
+
+impl FingerprintMetadata {
+    /// `created_at` as Unix seconds, or None if it is not an RFC 3339 time
+    pub fn created_at_unix_s(&self) -> Option<i64> {
+        chrono::DateTime::parse_from_rfc3339(&self.created_at)
+            .ok()
+            .map(|time| time.timestamp())
+    }
+}
+
+/// What to do when ingesting audio whose content hash is already stored
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DuplicatePolicy {
+    /// Keep the existing entry and do not store the new one
+    #[default]
+    Skip,
+    /// Replace the existing entry with the new one
+    Replace,
+}
+
+/// Result of an idempotent ingestion
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum IngestOutcome {
+    /// No entry with the same content existed; the fingerprints were stored
+    Inserted,
+    /// The same content is already stored under `existing`; nothing was written
+    Skipped { existing: String },
+    /// The entry stored under `existing` was replaced
+    Replaced { existing: String },
 }
 
 /// Query criteria for fingerprint retrieval
@@ -55,12 +93,493 @@ pub trait StorageBackend: Send + Sync {
     
     /// Get metadata for a fingerprint
     async fn get_metadata(&self, identifier: &str) -> Result<Option<FingerprintMetadata>>;
+    
+    /// Identifiers of all stored entries, sorted
+    ///
+    /// The default loads every reference; backends should list them directly.
+    async fn identifiers(&self) -> Result<Vec<String>> {
+        let mut identifiers: Vec<String> =
+            self.load_all_fingerprints().await?.into_iter().map(|(identifier, _)| identifier).collect();
+        identifiers.sort();
+        Ok(identifiers)
+    }
+    
+    /// Find the stored entry whose audio has the given content hash
+    ///
+    /// The default reads the metadata of every entry in identifier order;
+    /// backends should look the hash up in an index.
+    async fn find_by_content_hash(&self, content_hash: &str) -> Result<Option<FingerprintMetadata>> {
+        scan_metadata(self, |metadata| metadata.content_hash.as_deref() == Some(content_hash)).await
+    }
+    
+    /// Find the stored entry registered under an external content ID
+    ///
+    /// If several entries share the ID, which one is returned is up to the
+    /// backend but stable. The default reads the metadata of every entry and
+    /// returns the first by identifier.
+    async fn get_metadata_by_content_id(&self, content_id: &str) -> Result<Option<FingerprintMetadata>> {
+        scan_metadata(self, |metadata| metadata.content_id.as_deref() == Some(content_id)).await
+    }
+    
+    /// Delete fingerprints and metadata by identifier
+    async fn delete_fingerprints(&self, identifier: &str) -> Result<()>;
+    
+    /// Fingerprints with one of the given hashes, grouped by identifier
+    ///
+    /// Used to match against a catalog without loading it (see
+    /// `LazyMatcher`). The default scans every reference; backends with a
+    /// hash index should look the hashes up directly.
+    async fn fingerprints_by_hashes(&self, hashes: &[u64]) -> Result<Vec<(String, Vec<(u64, i32, i16, f32)>)>> {
+        let wanted: HashSet<u64> = hashes.iter().copied().collect();
+        let references = self
+            .load_all_fingerprints()
+            .await?
+            .into_iter()
+            .filter_map(|(identifier, fingerprints)| {
+                let matching: Vec<_> = fingerprints.into_iter().filter(|fp| wanted.contains(&fp.0)).collect();
+                (!matching.is_empty()).then_some((identifier, matching))
+            })
+            .collect();
+        Ok(references)
+    }
+}
+
+/// Metadata of the first entry, in identifier order, matching a predicate
+async fn scan_metadata<B: StorageBackend + ?Sized>(
+    backend: &B,
+    predicate: impl Fn(&FingerprintMetadata) -> bool + Send,
+) -> Result<Option<FingerprintMetadata>> {
+    for identifier in backend.identifiers().await? {
+        if let Some(metadata) = backend.get_metadata(&identifier).await? {
+            if predicate(&metadata) {
+                return Ok(Some(metadata));
+            }
+        }
+    }
+    Ok(None)
+}
+
+/// Save fingerprints unless the same audio content is already stored
+///
+/// The lookup is keyed by `metadata.content_hash`, so re-running a batch
+/// ingestion (e.g. after a partial failure) does not create duplicates.
+/// Metadata without a content hash is always inserted.
+pub async fn ingest_fingerprints(
+    backend: &dyn StorageBackend,
+    identifier: &str,
+    fingerprints: &[(u64, i32, i16, f32)],
+    metadata: &FingerprintMetadata,
+    policy: DuplicatePolicy,
+) -> Result<IngestOutcome> {
+    let outcome = resolve_duplicate(backend, identifier, metadata.content_hash.as_deref(), policy).await?;
+    if !matches!(outcome, IngestOutcome::Skipped { .. }) {
+        backend.save_fingerprints(identifier, fingerprints, metadata).await?;
+    }
+    
+    Ok(outcome)
+}
+
+/// Apply a duplicate policy before storing an entry with this content hash
+///
+/// Returns `IngestOutcome::Skipped` if the entry must not be stored.
+/// Otherwise any entry it replaces has been deleted, and the outcome holds
+/// once the caller stores the entry. For callers that store more than
+/// `save_fingerprints` keeps (e.g. segmented files); `ingest_fingerprints`
+/// does both steps.
+pub async fn resolve_duplicate(
+    backend: &dyn StorageBackend,
+    identifier: &str,
+    content_hash: Option<&str>,
+    policy: DuplicatePolicy,
+) -> Result<IngestOutcome> {
+    let existing = match content_hash {
+        Some(hash) => backend.find_by_content_hash(hash).await?,
+        None => None,
+    };
+    
+    match existing {
+        None => Ok(IngestOutcome::Inserted),
+        Some(existing) => match policy {
+            DuplicatePolicy::Skip => {
+                log::info!(
+                    "Skipping {}: same content already stored as {}",
+                    identifier,
+                    existing.filename
+                );
+                Ok(IngestOutcome::Skipped { existing: existing.filename })
+            }
+            DuplicatePolicy::Replace => {
+                backend.delete_fingerprints(&existing.filename).await?;
+                Ok(IngestOutcome::Replaced { existing: existing.filename })
+            }
+        },
+    }
+}
+
+/// Convert file metadata to storage metadata
+fn metadata_from_fp_file(meta: panako_fp::FpJsonMetadata) -> FingerprintMetadata {
+    FingerprintMetadata {
+        filename: meta.filename,
+        original_path: meta.original_path,
+        algorithm: meta.algorithm,
+        sample_rate: meta.sample_rate,
+        duration_ms: meta.duration_ms,
+        channels: meta.channels,
+        created_at: meta.created_at,
+        tags: meta.tags,
+        content_hash: meta.content_hash,
+        content_id: meta.content_id,
+    }
+}
+
+/// Rows fetched per round trip when streaming fingerprints from PostgreSQL
+const STREAM_BATCH_SIZE: i32 = 10_000;
+
+/// `(identifier, fingerprints)` pairs for a set of references
+type ReferenceFingerprints = Vec<(String, Vec<(u64, i32, i16, f32)>)>;
+
+/// Fingerprint files loaded from disk
+#[derive(Debug, Default)]
+pub struct LoadedFiles {
+    /// Successfully loaded `(path, file)` pairs, in input order
+    pub files: Vec<(PathBuf, panako_fp::FpJsonFile)>,
+    /// Paths that failed to load (only filled with `CorruptFilePolicy::Collect`)
+    pub failed: Vec<PathBuf>,
+    /// Paths that failed to load and were skipped
+    /// (only filled with `CorruptFilePolicy::SkipWithWarning`)
+    pub skipped: Vec<PathBuf>,
+}
+
+/// Load fingerprint files (JSON or BSON, auto-detected) in parallel
+///
+/// With `FailFast` the first failure (in input order) is returned as an
+/// error; with `SkipWithWarning` failures are only logged; with `Collect`
+/// they are returned in `LoadedFiles::failed`.
+pub fn load_fingerprint_files(paths: &[PathBuf], policy: CorruptFilePolicy) -> Result<LoadedFiles> {
+    use panako_fp::FpJsonFile;
+    use rayon::prelude::*;
+    
+    let results: Vec<(&PathBuf, Result<FpJsonFile>)> = paths
+        .par_iter()
+        .map(|path| {
+            log::debug!("Loading: {}", path.display());
+            (path, FpJsonFile::load_auto(path))
+        })
+        .collect();
+    
+    let mut loaded = LoadedFiles::default();
+    for (path, result) in results {
+        match result {
+            Ok(fp_file) => loaded.files.push((path.clone(), fp_file)),
+            Err(e) => match policy {
+                CorruptFilePolicy::FailFast => {
+                    return Err(e.context(format!("Failed to load {}", path.display())));
+                }
+                CorruptFilePolicy::SkipWithWarning => {
+                    log::warn!("Failed to load {}: {}", path.display(), e);
+                    loaded.skipped.push(path.clone());
+                }
+                CorruptFilePolicy::Collect => {
+                    log::debug!("Failed to load {}: {}", path.display(), e);
+                    loaded.failed.push(path.clone());
+                }
+            },
+        }
+    }
+    
+    Ok(loaded)
+}
+
+/// Fingerprint files (.json and .bson) directly inside `dir`, unsorted
+fn list_fingerprint_files(dir: &Path) -> Result<Vec<PathBuf>> {
+    let entries = std::fs::read_dir(dir)?;
+    let files = entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| {
+            path.is_file()
+                && path
+                    .extension()
+                    .and_then(|s| s.to_str())
+                    .map(|ext| ext.eq_ignore_ascii_case("json") || ext.eq_ignore_ascii_case("bson"))
+                    .unwrap_or(false)
+        })
+        .collect();
+    
+    Ok(files)
+}
+
+/// `(source file name, file)` entries of a pack
+type PackEntries = Vec<(String, panako_fp::FpJsonFile)>;
+
+/// Entries of the pack in `dir`, or none if it has no pack
+fn read_dir_pack(dir: &Path) -> Result<PackEntries> {
+    let pack_path = dir.join(panako_fp::PACK_FILE_NAME);
+    if !pack_path.exists() {
+        return Ok(Vec::new());
+    }
+    panako_fp::read_pack(&pack_path)
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|metadata| metadata.modified()).ok()
+}
+
+/// Sidecar file of a fingerprint directory caching each loose file's content
+/// hash (see `FilesystemBackend::find_by_content_hash`)
+pub const CONTENT_HASH_INDEX_FILE_NAME: &str = "content_hashes.idx";
+
+/// Modification time and size identifying one version of a loose file
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+struct FileStamp {
+    modified_s: u64,
+    modified_ns: u32,
+    size: u64,
+}
+
+impl FileStamp {
+    fn of(path: &Path) -> Option<Self> {
+        let metadata = std::fs::metadata(path).ok()?;
+        let modified = metadata.modified().ok()?.duration_since(std::time::UNIX_EPOCH).unwrap_or_default();
+        Some(Self {
+            modified_s: modified.as_secs(),
+            modified_ns: modified.subsec_nanos(),
+            size: metadata.len(),
+        })
+    }
+}
+
+/// Content hash of a loose file, valid while its stamp is unchanged
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+struct ContentHashEntry {
+    stamp: FileStamp,
+    content_hash: Option<String>,
+}
+
+/// Write the content hash sidecar atomically (temporary file renamed into place)
+fn write_content_hash_index(path: &Path, entries: &HashMap<String, ContentHashEntry>) -> Result<()> {
+    let tmp_path = path.with_extension(format!("idx.{}.tmp", std::process::id()));
+    std::fs::write(&tmp_path, serde_json::to_vec(entries)?)?;
+    std::fs::rename(&tmp_path, path).inspect_err(|_| {
+        std::fs::remove_file(&tmp_path).ok();
+    })?;
+    Ok(())
+}
+
+/// Whether a loose file replaces its pack entry: it was written at or after
+/// the pack (e.g. a reference re-ingested after packing)
+fn shadows_pack_entry(loose: &Path, pack_modified: Option<SystemTime>) -> bool {
+    match (modified(loose), pack_modified) {
+        (Some(loose_modified), Some(pack_modified)) => loose_modified >= pack_modified,
+        _ => true,
+    }
+}
+
+/// Split a directory into the loose files to load and the pack entries to
+/// use, each source file being taken from exactly one of them
+///
+/// A pack entry wins over the loose file it was packed from, unless that
+/// file was written again since (see `shadows_pack_entry`).
+fn resolve_pack_entries(dir: &Path, packed: PackEntries) -> Result<(Vec<PathBuf>, PackEntries)> {
+    let pack_modified = modified(&dir.join(panako_fp::PACK_FILE_NAME));
+    let mut shadowed: HashSet<String> = HashSet::new();
+    let packed_sources: HashSet<&str> = packed.iter().map(|(source, _)| source.as_str()).collect();
+    let mut files: Vec<PathBuf> = list_fingerprint_files(dir)?
+        .into_iter()
+        .filter(|path| {
+            let source = path.file_name().and_then(|name| name.to_str()).unwrap_or_default();
+            if !packed_sources.contains(source) {
+                return true;
+            }
+            let newer = shadows_pack_entry(path, pack_modified);
+            if newer {
+                shadowed.insert(source.to_string());
+            }
+            newer
+        })
+        .collect();
+    files.sort();
+    let packed = packed.into_iter().filter(|(source, _)| !shadowed.contains(source)).collect();
+    Ok((files, packed))
+}
+
+/// Load every fingerprint file of a directory, using its pack if present
+///
+/// Entries of the pack (see `FilesystemBackend::pack`) are loaded from it and
+/// the loose files they were packed from are skipped, unless such a file was
+/// written again after packing; loose files added since are loaded with
+/// `load_fingerprint_files`. Pack entries are reported under the path of
+/// their source file, and results are sorted by path. An unreadable pack is
+/// handled like a corrupt file, according to `policy`.
+pub fn load_fingerprint_dir(dir: &Path, policy: CorruptFilePolicy) -> Result<LoadedFiles> {
+    let pack_path = dir.join(panako_fp::PACK_FILE_NAME);
+    let mut pack_failed = false;
+    let mut pack_skipped = false;
+    let packed = match read_dir_pack(dir) {
+        Ok(packed) => packed,
+        Err(e) => match policy {
+            CorruptFilePolicy::FailFast => return Err(e),
+            CorruptFilePolicy::SkipWithWarning => {
+                log::warn!("Failed to load {}: {}", pack_path.display(), e);
+                pack_skipped = true;
+                Vec::new()
+            }
+            CorruptFilePolicy::Collect => {
+                log::debug!("Failed to load {}: {}", pack_path.display(), e);
+                pack_failed = true;
+                Vec::new()
+            }
+        },
+    };
+    
+    let (files, packed) = resolve_pack_entries(dir, packed)?;
+    let mut loaded = load_fingerprint_files(&files, policy)?;
+    if pack_failed {
+        loaded.failed.push(pack_path);
+    } else if pack_skipped {
+        loaded.skipped.push(pack_path);
+    }
+    loaded
+        .files
+        .extend(packed.into_iter().map(|(source, file)| (dir.join(source), file)));
+    loaded.files.sort_by(|a, b| a.0.cmp(&b.0));
+    
+    Ok(loaded)
+}
+
+/// Fingerprint files of a directory keyed by reference identifier
+#[derive(Debug, Default)]
+pub struct LoadedReferences {
+    /// `(identifier, file)` pairs sorted by source path; identifiers are unique
+    pub references: Vec<(String, panako_fp::FpJsonFile)>,
+    /// Paths that failed to load (only filled with `CorruptFilePolicy::Collect`)
+    pub failed: Vec<PathBuf>,
+    /// Paths that failed to load and were skipped
+    /// (only filled with `CorruptFilePolicy::SkipWithWarning`)
+    pub skipped: Vec<PathBuf>,
+}
+
+/// Load a fingerprint database directory keyed by reference identifier
+///
+/// Files are loaded with `load_fingerprint_dir`. The identifier of a
+/// reference is the `filename` stored in its metadata, made unique with
+/// `resolve_identifier_collisions` (collisions are logged as warnings).
+pub fn load_reference_dir(dir: &Path, policy: CorruptFilePolicy) -> Result<LoadedReferences> {
+    if !dir.exists() {
+        anyhow::bail!("Database directory not found: {}", dir.display());
+    }
+    
+    // Sorted by path, so disambiguated identifiers are stable
+    let load_start = std::time::Instant::now();
+    let loaded = load_fingerprint_dir(dir, policy)?;
+    let mut references: Vec<(String, panako_fp::FpJsonFile)> = loaded
+        .files
+        .into_iter()
+        .map(|(_, fp_file)| (fp_file.metadata.filename.clone(), fp_file))
+        .collect();
+    
+    let load_duration = load_start.elapsed();
+    log::info!(
+        "Loaded {} files in {:.2}s ({:.0} files/sec)",
+        references.len(),
+        load_duration.as_secs_f64(),
+        references.len() as f64 / load_duration.as_secs_f64()
+    );
+    
+    for identifier in resolve_identifier_collisions(&mut references) {
+        log::warn!(
+            "Several fingerprint files in {} use the identifier '{}'; disambiguated by source file extension",
+            dir.display(),
+            identifier
+        );
+    }
+    
+    Ok(LoadedReferences {
+        references,
+        failed: loaded.failed,
+        skipped: loaded.skipped,
+    })
+}
+
+/// Make reference identifiers unique
+///
+/// Identifiers default to the source file stem, so `song.mp3` and `song.wav`
+/// would otherwise be merged into one reference by the matcher. Colliding
+/// identifiers get the extension of their `original_path` appended
+/// (`song.mp3`, `song.wav`) and, if still not unique, a `#n` counter.
+/// Returns the identifiers that collided, sorted.
+pub fn resolve_identifier_collisions(files: &mut [(String, panako_fp::FpJsonFile)]) -> Vec<String> {
+    let mut counts: HashMap<String, usize> = HashMap::new();
+    for (identifier, _) in files.iter() {
+        *counts.entry(identifier.clone()).or_default() += 1;
+    }
+    
+    let mut collided: Vec<String> = counts
+        .iter()
+        .filter(|(_, &count)| count > 1)
+        .map(|(identifier, _)| identifier.clone())
+        .collect();
+    collided.sort();
+    if collided.is_empty() {
+        return collided;
+    }
+    
+    let mut used: HashSet<String> = files
+        .iter()
+        .filter(|(identifier, _)| counts[identifier] == 1)
+        .map(|(identifier, _)| identifier.clone())
+        .collect();
+    
+    for (identifier, fp_file) in files.iter_mut() {
+        if counts[identifier.as_str()] == 1 {
+            continue;
+        }
+        
+        let base = match Path::new(&fp_file.metadata.original_path).extension().and_then(|e| e.to_str()) {
+            Some(ext) => format!("{}.{}", identifier, ext),
+            None => identifier.clone(),
+        };
+        let mut candidate = base.clone();
+        let mut n = 2;
+        while used.contains(&candidate) {
+            candidate = format!("{}#{}", base, n);
+            n += 1;
+        }
+        used.insert(candidate.clone());
+        *identifier = candidate;
+    }
+    
+    collided
+}
+
+/// Outcome of `FilesystemBackend::pack`
+#[derive(Debug, Clone, PartialEq)]
+pub struct PackSummary {
+    /// Files in the pack
+    pub references: usize,
+    /// Fingerprints in the pack
+    pub fingerprints: usize,
+    /// Loose files now also held by the pack (safe to delete)
+    pub loose_files: Vec<PathBuf>,
 }
 
 /// Filesystem-based storage backend
 pub struct FilesystemBackend {
     base_dir: PathBuf,
     format: FileFormat,
+    on_corrupt_file: CorruptFilePolicy,
+    /// Index of the pack, with the modification time it was read at
+    pack_index: Mutex<Option<(Option<SystemTime>, Arc<panako_fp::PackIndex>)>>,
+    /// Content hash -> identifier, built on the first lookup and kept up to
+    /// date by this backend's writes
+    content_hashes: Mutex<Option<HashMap<String, String>>>,
+}
+
+/// Where the current file of an identifier is stored
+enum StoredFile {
+    Loose(PathBuf),
+    /// Position of the entry in the pack index
+    Packed(Arc<panako_fp::PackIndex>, usize),
 }
 
 impl FilesystemBackend {
@@ -69,6 +588,9 @@ impl FilesystemBackend {
         Self {
             base_dir: PathBuf::from(&config.base_directory),
             format: config.format.clone(),
+            on_corrupt_file: config.on_corrupt_file,
+            pack_index: Mutex::new(None),
+            content_hashes: Mutex::new(None),
         }
     }
     
@@ -77,9 +599,33 @@ impl FilesystemBackend {
         Self {
             base_dir: PathBuf::from(base_dir),
             format,
+            on_corrupt_file: CorruptFilePolicy::default(),
+            pack_index: Mutex::new(None),
+            content_hashes: Mutex::new(None),
         }
     }
     
+    /// Set how files that fail to load are handled
+    pub fn with_corrupt_file_policy(mut self, policy: CorruptFilePolicy) -> Self {
+        self.on_corrupt_file = policy;
+        self
+    }
+    
+    /// Load all fingerprints, also returning the paths that failed to load
+    ///
+    /// Failed paths are only reported with `CorruptFilePolicy::Collect`; see
+    /// `load_fingerprint_files`.
+    pub fn load_all_fingerprints_with_failures(&self) -> Result<(ReferenceFingerprints, Vec<PathBuf>)> {
+        let loaded = load_fingerprint_dir(&self.base_dir, self.on_corrupt_file)?;
+        let fingerprints = loaded
+            .files
+            .into_iter()
+            .map(|(_, fp_file)| (fp_file.metadata.filename.clone(), fp_file.get_all_fingerprints()))
+            .collect();
+        
+        Ok((fingerprints, loaded.failed))
+    }
+    
     /// Determine file extension based on format
     fn get_extension(&self, format: &FileFormat) -> &str {
         match format {
@@ -90,82 +636,373 @@ impl FilesystemBackend {
     }
     
     /// Find fingerprint file for identifier
+    ///
+    /// Extensions match case-insensitively (`song.JSON` is found for `song`);
+    /// with `FileFormat::Auto`, JSON is preferred over BSON.
     fn find_file(&self, identifier: &str) -> Result<PathBuf> {
-        match self.format {
-            FileFormat::Auto => {
-                // Try both extensions
-                let json_path = self.base_dir.join(format!("{}.json", identifier));
-                let bson_path = self.base_dir.join(format!("{}.bson", identifier));
-                
-                if json_path.exists() {
-                    Ok(json_path)
-                } else if bson_path.exists() {
-                    Ok(bson_path)
-                } else {
-                    anyhow::bail!("Fingerprint file not found for identifier: {}", identifier)
+        let extensions: &[&str] = match self.format {
+            FileFormat::Auto => &["json", "bson"],
+            _ => &[self.get_extension(&self.format)],
+        };
+        // The common lower-case names first, to avoid listing the directory
+        if let Some(path) = extensions
+            .iter()
+            .map(|ext| self.base_dir.join(format!("{}.{}", identifier, ext)))
+            .find(|path| path.is_file())
+        {
+            return Ok(path);
+        }
+        let loose = self.loose_files(identifier)?;
+        let found = extensions.iter().find_map(|wanted| {
+            loose.iter().find(|path| {
+                path.extension().and_then(|s| s.to_str()).is_some_and(|ext| ext.eq_ignore_ascii_case(wanted))
+            })
+        });
+        match found {
+            Some(path) => Ok(path.clone()),
+            None => anyhow::bail!("Fingerprint file not found for identifier: {}", identifier),
+        }
+    }
+    
+    /// Loose fingerprint files (.json or .bson, in any case) of an identifier
+    fn loose_files(&self, identifier: &str) -> Result<Vec<PathBuf>> {
+        if !self.base_dir.exists() {
+            return Ok(Vec::new());
+        }
+        let mut files: Vec<PathBuf> = self
+            .list_files()?
+            .into_iter()
+            .filter(|path| path.file_stem().and_then(|s| s.to_str()) == Some(identifier))
+            .collect();
+        files.sort();
+        Ok(files)
+    }
+    
+    /// Identifiers (file stems) of all fingerprint files, sorted
+    ///
+    /// Returns an empty list if the base directory does not exist yet.
+    pub fn identifiers(&self) -> Result<Vec<String>> {
+        if !self.base_dir.exists() {
+            return Ok(Vec::new());
+        }
+        
+        let packed: Vec<PathBuf> = match self.pack_index()? {
+            Some(index) => index.entries().map(|(source, _)| PathBuf::from(source)).collect(),
+            None => Vec::new(),
+        };
+        let mut identifiers: Vec<String> = self
+            .list_files()?
+            .into_iter()
+            .chain(packed)
+            .filter_map(|path| path.file_stem().and_then(|s| s.to_str()).map(str::to_string))
+            .collect();
+        identifiers.sort();
+        identifiers.dedup();
+        
+        Ok(identifiers)
+    }
+    
+    /// List all fingerprint files in the base directory
+    fn list_files(&self) -> Result<Vec<PathBuf>> {
+        list_fingerprint_files(&self.base_dir)
+    }
+    
+    /// Index of the base directory's pack, read again only when the pack changes
+    fn pack_index(&self) -> Result<Option<Arc<panako_fp::PackIndex>>> {
+        let pack_path = self.base_dir.join(panako_fp::PACK_FILE_NAME);
+        let mut cached = self.pack_index.lock().unwrap_or_else(|e| e.into_inner());
+        if !pack_path.exists() {
+            *cached = None;
+            return Ok(None);
+        }
+        let pack_modified = modified(&pack_path);
+        if let Some((read_at, index)) = cached.as_ref() {
+            if *read_at == pack_modified && pack_modified.is_some() {
+                return Ok(Some(index.clone()));
+            }
+        }
+        let index = Arc::new(panako_fp::PackIndex::open(&pack_path)?);
+        *cached = Some((pack_modified, index.clone()));
+        Ok(Some(index))
+    }
+    
+    /// Drop the cached pack index after rewriting the pack
+    fn invalidate_pack_index(&self) {
+        *self.pack_index.lock().unwrap_or_else(|e| e.into_inner()) = None;
+    }
+    
+    /// Identifier stored with a content hash, from the content hash index
+    ///
+    /// The index is built on the first lookup from `build_content_hash_index`;
+    /// when several entries share a hash, the first by source path is kept.
+    fn identifier_by_content_hash(&self, content_hash: &str) -> Result<Option<String>> {
+        let mut index = self.content_hashes.lock().unwrap_or_else(|e| e.into_inner());
+        if index.is_none() {
+            *index = Some(self.build_content_hash_index()?);
+        }
+        Ok(index.as_ref().and_then(|hashes| hashes.get(content_hash).cloned()))
+    }
+    
+    /// Content hash -> identifier of every entry in the directory
+    ///
+    /// Loose files are only parsed when `CONTENT_HASH_INDEX_FILE_NAME` has no
+    /// entry for their current modification time and size, so repeated
+    /// ingestion runs (one process per input) read just the files written
+    /// since; the sidecar is then rewritten. Pack entries come from the pack
+    /// index.
+    fn build_content_hash_index(&self) -> Result<HashMap<String, String>> {
+        use rayon::prelude::*;
+        
+        if !self.base_dir.exists() {
+            return Ok(HashMap::new());
+        }
+        
+        let index_path = self.base_dir.join(CONTENT_HASH_INDEX_FILE_NAME);
+        let cached: HashMap<String, ContentHashEntry> = std::fs::read(&index_path)
+            .ok()
+            .and_then(|bytes| serde_json::from_slice(&bytes).ok())
+            .unwrap_or_default();
+        
+        let pack = match self.pack_index() {
+            Ok(pack) => pack,
+            Err(e) if self.on_corrupt_file == CorruptFilePolicy::FailFast => return Err(e),
+            Err(e) => {
+                log::warn!("Failed to load {}: {}", self.base_dir.join(panako_fp::PACK_FILE_NAME).display(), e);
+                None
+            }
+        };
+        let pack_modified = modified(&self.base_dir.join(panako_fp::PACK_FILE_NAME));
+        let packed_sources: HashSet<&str> = pack.iter().flat_map(|pack| pack.entries().map(|(source, _)| source)).collect();
+        
+        // Loose files, except those whose pack entry is current
+        let mut shadowed: HashSet<String> = HashSet::new();
+        let loose: Vec<(String, PathBuf)> = self
+            .list_files()?
+            .into_iter()
+            .filter_map(|path| Some((path.file_name()?.to_str()?.to_string(), path)))
+            .filter(|(source, path)| {
+                if !packed_sources.contains(source.as_str()) {
+                    return true;
+                }
+                let newer = shadows_pack_entry(path, pack_modified);
+                if newer {
+                    shadowed.insert(source.clone());
+                }
+                newer
+            })
+            .collect();
+        
+        let entries: Vec<(String, Result<ContentHashEntry>)> = loose
+            .into_par_iter()
+            .map(|(source, path)| {
+                let Some(stamp) = FileStamp::of(&path) else {
+                    return (source, Err(anyhow::anyhow!("Failed to read {}", path.display())));
+                };
+                if let Some(entry) = cached.get(&source).filter(|entry| entry.stamp == stamp) {
+                    return (source, Ok(entry.clone()));
                 }
+                let entry = panako_fp::FpJsonFile::load_auto(&path).map(|file| ContentHashEntry {
+                    stamp,
+                    content_hash: file.metadata.content_hash,
+                });
+                (source, entry)
+            })
+            .collect();
+        
+        let mut current: HashMap<String, ContentHashEntry> = HashMap::with_capacity(entries.len());
+        for (source, entry) in entries {
+            match entry {
+                Ok(entry) => {
+                    current.insert(source, entry);
+                }
+                Err(e) => match self.on_corrupt_file {
+                    CorruptFilePolicy::FailFast => return Err(e),
+                    CorruptFilePolicy::SkipWithWarning => log::warn!("Failed to load {}: {}", source, e),
+                    CorruptFilePolicy::Collect => log::debug!("Failed to load {}: {}", source, e),
+                },
             }
-            _ => {
-                let ext = self.get_extension(&self.format);
-                let path = self.base_dir.join(format!("{}.{}", identifier, ext));
-                if path.exists() {
-                    Ok(path)
-                } else {
-                    anyhow::bail!("Fingerprint file not found: {}", path.display())
+        }
+        if current != cached {
+            if let Err(e) = write_content_hash_index(&index_path, &current) {
+                log::warn!("Failed to write content hash index {}: {:#}", index_path.display(), e);
+            }
+        }
+        
+        let mut sources: Vec<(&str, &str)> = current
+            .iter()
+            .filter_map(|(source, entry)| Some((source.as_str(), entry.content_hash.as_deref()?)))
+            .chain(pack.iter().flat_map(|pack| pack.entries()).filter_map(|(source, file)| {
+                if shadowed.contains(source) {
+                    return None;
                 }
+                Some((source, file.metadata.content_hash.as_deref()?))
+            }))
+            .collect();
+        sources.sort();
+        let mut hashes = HashMap::new();
+        for (source, hash) in sources {
+            if let Some(identifier) = Path::new(source).file_stem().and_then(|s| s.to_str()) {
+                hashes.entry(hash.to_string()).or_insert_with(|| identifier.to_string());
             }
         }
+        Ok(hashes)
     }
-}
-
-#[async_trait]
-impl StorageBackend for FilesystemBackend {
-    async fn load_fingerprints(&self, identifier: &str) -> Result<Vec<(u64, i32, i16, f32)>> {
+    
+    /// Update the content hash index (if built) for a written or deleted entry
+    fn update_content_hash_index(&self, identifier: &str, content_hash: Option<&str>) {
+        let mut index = self.content_hashes.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(hashes) = index.as_mut() {
+            hashes.retain(|_, stored| stored != identifier);
+            if let Some(hash) = content_hash {
+                hashes.entry(hash.to_string()).or_insert_with(|| identifier.to_string());
+            }
+        }
+    }
+    
+    /// Locate the current file of an identifier: its loose file, unless the
+    /// pack holds an entry the loose file does not shadow
+    fn locate(&self, identifier: &str) -> Result<StoredFile> {
+        let loose = self.find_file(identifier);
+        let Some(index) = self.pack_index()? else {
+            return loose.map(StoredFile::Loose);
+        };
+        let position = index
+            .entries()
+            .position(|(source, _)| Path::new(source).file_stem().and_then(|s| s.to_str()) == Some(identifier));
+        let pack_modified = modified(&self.base_dir.join(panako_fp::PACK_FILE_NAME));
+        match (loose, position) {
+            (Ok(path), Some(_)) if shadows_pack_entry(&path, pack_modified) => Ok(StoredFile::Loose(path)),
+            (Ok(path), None) => Ok(StoredFile::Loose(path)),
+            (_, Some(i)) => Ok(StoredFile::Packed(index, i)),
+            (Err(e), None) => Err(e),
+        }
+    }
+    
+    /// Load the complete fingerprint file of an identifier (all segments and
+    /// metadata), falling back to the pack
+    pub fn load_file(&self, identifier: &str) -> Result<panako_fp::FpJsonFile> {
+        match self.locate(identifier)? {
+            StoredFile::Loose(path) => panako_fp::FpJsonFile::load_auto(&path),
+            StoredFile::Packed(index, i) => index.load(i),
+        }
+    }
+    
+    /// Metadata of the first file (loose files, then the pack) matching a predicate
+    fn find_metadata(
+        &self,
+        predicate: impl Fn(&panako_fp::FpJsonMetadata) -> bool + Sync,
+    ) -> Result<Option<FingerprintMetadata>> {
         use panako_fp::FpJsonFile;
+        use rayon::prelude::*;
         
-        let file_path = self.find_file(identifier)?;
+        if !self.base_dir.exists() {
+            return Ok(None);
+        }
         
-        // Auto-detect format (JSON or BSON)
-        let fp_file = FpJsonFile::load_auto(&file_path)?;
-        let fingerprints = fp_file.get_all_fingerprints();
+        let found = self.list_files()?
+            .par_iter()
+            .filter_map(|path| FpJsonFile::load_auto(path).ok())
+            .find_first(|fp_file| predicate(&fp_file.metadata))
+            .map(|fp_file| fp_file.metadata);
+        if found.is_some() {
+            return Ok(found.map(metadata_from_fp_file));
+        }
         
-        Ok(fingerprints)
+        // Pack entries whose loose file was written again were searched above
+        let Some(index) = self.pack_index().ok().flatten() else {
+            return Ok(None);
+        };
+        let pack_modified = modified(&self.base_dir.join(panako_fp::PACK_FILE_NAME));
+        let found = index
+            .entries()
+            .filter(|(source, _)| {
+                let loose = self.base_dir.join(source);
+                !loose.is_file() || !shadows_pack_entry(&loose, pack_modified)
+            })
+            .map(|(_, fp_file)| &fp_file.metadata)
+            .find(|metadata| predicate(metadata))
+            .map(|metadata| metadata_from_fp_file(metadata.clone()));
+        
+        Ok(found)
     }
     
-    async fn load_all_fingerprints(&self) -> Result<Vec<(String, Vec<(u64, i32, i16, f32)>)>> {
+    /// Store a complete fingerprint file under an identifier, in the
+    /// configured format
+    pub fn save_file(&self, identifier: &str, fp_file: &panako_fp::FpJsonFile) -> Result<()> {
         use panako_fp::FpJsonFile;
-        use rayon::prelude::*;
+        type SaveFn = fn(&FpJsonFile, &Path) -> Result<()>;
         
-        // Find all fingerprint files
-        let entries = std::fs::read_dir(&self.base_dir)?;
-        let files: Vec<PathBuf> = entries
-            .filter_map(|entry| entry.ok())
-            .map(|entry| entry.path())
-            .filter(|path| {
-                path.extension()
-                    .and_then(|s| s.to_str())
-                    .map(|ext| ext == "json" || ext == "bson")
-                    .unwrap_or(false)
+        // Determine file extension and save method based on format
+        let (ext, save_fn): (&str, SaveFn) = 
+            match &self.format {
+                FileFormat::Bson => ("bson", FpJsonFile::save_bson),
+                FileFormat::Json => ("json", FpJsonFile::save),
+                FileFormat::Auto => ("json", FpJsonFile::save), // Default to JSON
+            };
+        
+        let file_path = self.base_dir.join(format!("{}.{}", identifier, ext));
+        
+        // Create directory if it doesn't exist
+        if let Some(parent) = file_path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        
+        save_fn(fp_file, &file_path)?;
+        self.update_content_hash_index(identifier, fp_file.metadata.content_hash.as_deref());
+        Ok(())
+    }
+    
+    /// Consolidate the directory into a single pack file
+    ///
+    /// The pack (`panako_fp::PACK_FILE_NAME` in the base directory) holds the
+    /// existing pack's entries plus all loose files, and loads much faster
+    /// than many small files. Loose files are left in place; the pack takes
+    /// precedence over them, so they can be deleted. A loose file written
+    /// after packing (e.g. a re-ingested reference) takes precedence over its
+    /// entry until the next pack. Files that fail to load are handled by the
+    /// corrupt file policy and stay out of the pack.
+    pub fn pack(&self) -> Result<PackSummary> {
+        let loaded = load_fingerprint_dir(&self.base_dir, self.on_corrupt_file)?;
+        let entries: Vec<(String, panako_fp::FpJsonFile)> = loaded
+            .files
+            .into_iter()
+            .map(|(path, file)| {
+                let source = path.file_name().and_then(|name| name.to_str()).unwrap_or_default().to_string();
+                (source, file)
             })
             .collect();
+        panako_fp::write_pack(&self.base_dir.join(panako_fp::PACK_FILE_NAME), &entries)?;
+        self.invalidate_pack_index();
         
-        // Load all files in parallel (auto-detect format)
-        let results: Vec<(String, Vec<(u64, i32, i16, f32)>)> = files
-            .par_iter()
-            .filter_map(|path| {
-                match FpJsonFile::load_auto(path) {
-                    Ok(fp_file) => {
-                        let identifier = fp_file.metadata.filename.clone();
-                        let fingerprints = fp_file.get_all_fingerprints();
-                        Some((identifier, fingerprints))
-                    }
-                    Err(e) => {
-                        log::warn!("Failed to load {}: {}", path.display(), e);
-                        None
-                    }
-                }
-            })
+        let loose_files = entries
+            .iter()
+            .map(|(source, _)| self.base_dir.join(source))
+            .filter(|path| path.is_file())
             .collect();
+        Ok(PackSummary {
+            references: entries.len(),
+            fingerprints: entries.iter().map(|(_, file)| file.get_all_fingerprints().len()).sum(),
+            loose_files,
+        })
+    }
+}
+
+#[async_trait]
+impl StorageBackend for FilesystemBackend {
+    async fn load_fingerprints(&self, identifier: &str) -> Result<Vec<(u64, i32, i16, f32)>> {
+        // Auto-detect format (JSON or BSON)
+        let fp_file = self.load_file(identifier)?;
+        let fingerprints = fp_file.get_all_fingerprints();
+        
+        Ok(fingerprints)
+    }
+    
+    async fn load_all_fingerprints(&self) -> Result<Vec<(String, Vec<(u64, i32, i16, f32)>)>> {
+        // The trait has no room for failures; use `load_all_fingerprints_with_failures` to get them
+        let (results, failed) = self.load_all_fingerprints_with_failures()?;
+        if !failed.is_empty() {
+            log::warn!("{} fingerprint files in {} failed to load", failed.len(), self.base_dir.display());
+        }
         
         Ok(results)
     }
@@ -186,6 +1023,15 @@ impl StorageBackend for FilesystemBackend {
             metadata.duration_ms,
             metadata.channels,
         );
+        fp_file.metadata.tags = metadata.tags.clone();
+        fp_file.metadata.content_hash = metadata.content_hash.clone();
+        fp_file.metadata.content_id = metadata.content_id.clone();
+        if !metadata.created_at.is_empty() {
+            fp_file.metadata.created_at = metadata.created_at.clone();
+        }
+        if !metadata.algorithm.is_empty() {
+            fp_file.metadata.algorithm = metadata.algorithm.clone();
+        }
         
         // Create a single segment with all fingerprints
         let fps: Vec<FpJsonFingerprint> = fingerprints
@@ -208,24 +1054,7 @@ impl StorageBackend for FilesystemBackend {
         
         fp_file.add_segment(segment);
         
-        // Determine file extension and save method based on format
-        let (ext, save_fn): (&str, fn(&FpJsonFile, &std::path::Path) -> anyhow::Result<()>) = 
-            match &self.format {
-                FileFormat::Bson => ("bson", FpJsonFile::save_bson),
-                FileFormat::Json => ("json", FpJsonFile::save),
-                FileFormat::Auto => ("json", FpJsonFile::save), // Default to JSON
-            };
-        
-        let file_path = self.base_dir.join(format!("{}.{}", identifier, ext));
-        
-        // Create directory if it doesn't exist
-        if let Some(parent) = file_path.parent() {
-            std::fs::create_dir_all(parent)?;
-        }
-        
-        save_fn(&fp_file, &file_path)?;
-        
-        Ok(())
+        self.save_file(identifier, &fp_file)
     }
     
     async fn query_fingerprints(
@@ -256,28 +1085,116 @@ impl StorageBackend for FilesystemBackend {
     }
     
     async fn get_metadata(&self, identifier: &str) -> Result<Option<FingerprintMetadata>> {
-        use panako_fp::FpJsonFile;
+        let metadata = match self.locate(identifier)? {
+            StoredFile::Loose(path) => panako_fp::FpJsonFile::load_auto(&path)?.metadata,
+            StoredFile::Packed(index, i) => match index.entries().nth(i) {
+                Some((_, file)) => file.metadata.clone(),
+                None => return Ok(None),
+            },
+        };
         
-        let file_path = self.find_file(identifier)?;
-        let fp_file = FpJsonFile::load(&file_path)?;
+        Ok(Some(metadata_from_fp_file(metadata)))
+    }
+    
+    async fn identifiers(&self) -> Result<Vec<String>> {
+        FilesystemBackend::identifiers(self)
+    }
+    
+    async fn find_by_content_hash(&self, content_hash: &str) -> Result<Option<FingerprintMetadata>> {
+        match self.identifier_by_content_hash(content_hash)? {
+            Some(identifier) => self.get_metadata(&identifier).await,
+            None => Ok(None),
+        }
+    }
+    
+    async fn get_metadata_by_content_id(&self, content_id: &str) -> Result<Option<FingerprintMetadata>> {
+        self.find_metadata(|metadata| metadata.content_id.as_deref() == Some(content_id))
+    }
+    
+    async fn delete_fingerprints(&self, identifier: &str) -> Result<()> {
+        // Remove the entry in either format, regardless of the configured one
+        let mut removed = false;
+        for path in self.loose_files(identifier)? {
+            std::fs::remove_file(&path)?;
+            removed = true;
+        }
         
-        let metadata = FingerprintMetadata {
-            filename: fp_file.metadata.filename,
-            original_path: fp_file.metadata.original_path,
-            algorithm: fp_file.metadata.algorithm,
-            sample_rate: fp_file.metadata.sample_rate,
-            duration_ms: fp_file.metadata.duration_ms,
-            channels: fp_file.metadata.channels,
-            created_at: fp_file.metadata.created_at,
-        };
+        // Rewrite the pack without the entry. Entries shadowed by newer loose
+        // files are dropped too: the rewritten pack is newer than those files
+        // and would otherwise take precedence over them again.
+        let packed = read_dir_pack(&self.base_dir)?;
+        let is_entry = |source: &str| Path::new(source).file_stem().and_then(|s| s.to_str()) == Some(identifier);
+        if packed.iter().any(|(source, _)| is_entry(source)) {
+            let (_, current) = resolve_pack_entries(&self.base_dir, packed)?;
+            let kept: Vec<_> = current.into_iter().filter(|(source, _)| !is_entry(source)).collect();
+            panako_fp::write_pack(&self.base_dir.join(panako_fp::PACK_FILE_NAME), &kept)?;
+            self.invalidate_pack_index();
+            removed = true;
+        }
         
-        Ok(Some(metadata))
+        if !removed {
+            anyhow::bail!("Fingerprint file not found for identifier: {}", identifier);
+        }
+        self.update_content_hash_index(identifier, None);
+        Ok(())
+    }
+}
+
+/// Progress of `PostgresqlBackend::ingest_directory`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IngestProgress<'a> {
+    /// A file was inserted; it is stored once its transaction commits
+    Ingested { identifier: &'a str, fingerprints: usize },
+    /// A file was skipped because its identifier is already stored
+    Skipped { identifier: &'a str },
+    /// A transaction holding this many inserted files was committed
+    Committed { files: usize },
+}
+
+/// Outcome of `PostgresqlBackend::ingest_directory`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DirectoryIngestSummary {
+    /// Files inserted and committed
+    pub ingested: usize,
+    /// Files skipped because their identifier is already stored
+    pub skipped: usize,
+    /// Fingerprints inserted and committed
+    pub fingerprints: usize,
+}
+
+/// Error of `PostgresqlBackend::ingest_directory`, with what was stored
+/// before the failure
+#[derive(Debug)]
+pub struct DirectoryIngestError {
+    /// Files committed (and skipped) before the failure
+    pub summary: DirectoryIngestSummary,
+    /// Files inserted in the transaction that was rolled back
+    pub rolled_back: usize,
+    /// Cause of the failure
+    pub source: anyhow::Error,
+}
+
+impl std::fmt::Display for DirectoryIngestError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Ingestion aborted after committing {} files ({} rolled back)",
+            self.summary.ingested, self.rolled_back
+        )
+    }
+}
+
+impl std::error::Error for DirectoryIngestError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(self.source.as_ref())
     }
 }
 
 /// PostgreSQL-based storage backend
 pub struct PostgresqlBackend {
     pool: deadpool_postgres::Pool,
+    ingest_transaction: IngestTransaction,
+    ingest_batch_size: usize,
 }
 
 impl PostgresqlBackend {
@@ -295,26 +1212,187 @@ impl PostgresqlBackend {
         // Test the connection
         panako_db::test_connection(&pool).await?;
         
-        Ok(Self { pool })
+        Ok(Self {
+            pool,
+            ingest_transaction: config.ingest_transaction,
+            ingest_batch_size: config.ingest_batch_size,
+        })
     }
-}
-
-#[async_trait]
-impl StorageBackend for PostgresqlBackend {
-    async fn load_fingerprints(&self, identifier: &str) -> Result<Vec<(u64, i32, i16, f32)>> {
-        // Get metadata by filename
+    
+    /// Use another transaction scope for `ingest_directory`
+    pub fn with_ingest_transaction(mut self, mode: IngestTransaction, batch_size: usize) -> Self {
+        self.ingest_transaction = mode;
+        self.ingest_batch_size = batch_size;
+        self
+    }
+    
+    /// Transaction scope used by `ingest_directory`
+    pub fn ingest_transaction(&self) -> IngestTransaction {
+        self.ingest_transaction
+    }
+    
+    /// Insert every fingerprint file of a directory (and its pack), in path order
+    ///
+    /// Files are committed according to the configured `IngestTransaction`.
+    /// The first file that fails to load or insert aborts the ingestion and
+    /// rolls back the open transaction: with `AllOrNothing` nothing is stored,
+    /// otherwise the files committed before remain and are counted in the
+    /// error's summary. With `skip_existing`, files whose identifier is
+    /// already stored (or inserted earlier in the open transaction) are
+    /// skipped. `on_progress` sees every file and every commit.
+    pub async fn ingest_directory<F>(
+        &self,
+        dir: &Path,
+        skip_existing: bool,
+        on_progress: F,
+    ) -> std::result::Result<DirectoryIngestSummary, DirectoryIngestError>
+    where
+        F: FnMut(IngestProgress<'_>),
+    {
+        let mut summary = DirectoryIngestSummary::default();
+        let mut pending = DirectoryIngestSummary::default();
+        match self
+            .ingest_directory_into(dir, skip_existing, on_progress, &mut summary, &mut pending)
+            .await
+        {
+            Ok(()) => Ok(summary),
+            Err(source) => Err(DirectoryIngestError { summary, rolled_back: pending.ingested, source }),
+        }
+    }
+    
+    /// `ingest_directory`, counting committed files in `summary` and the
+    /// files of the open transaction in `pending`
+    async fn ingest_directory_into<F>(
+        &self,
+        dir: &Path,
+        skip_existing: bool,
+        mut on_progress: F,
+        summary: &mut DirectoryIngestSummary,
+        pending: &mut DirectoryIngestSummary,
+    ) -> Result<()>
+    where
+        F: FnMut(IngestProgress<'_>),
+    {
+        use anyhow::Context;
+        
+        let files_per_commit = match self.ingest_transaction {
+            IngestTransaction::PerFile => 1,
+            IngestTransaction::AllOrNothing => usize::MAX,
+            IngestTransaction::Batched => self.ingest_batch_size.max(1),
+        };
+        
+        // Pack entries are read up front, loose files one at a time
+        let (files, packed) = resolve_pack_entries(dir, read_dir_pack(dir)?)?;
+        let mut entries: Vec<(PathBuf, Option<panako_fp::FpJsonFile>)> =
+            files.into_iter().map(|path| (path, None)).collect();
+        entries.extend(packed.into_iter().map(|(source, file)| (dir.join(source), Some(file))));
+        entries.sort_by(|a, b| a.0.cmp(&b.0));
+        
+        let mut client = self.pool.get().await?;
+        let mut transaction = client.transaction().await?;
+        
+        for (path, packed) in entries {
+            let fp_file = match packed {
+                Some(file) => file,
+                None => panako_fp::FpJsonFile::load_auto(&path)
+                    .with_context(|| format!("Failed to load {}", path.display()))?,
+            };
+            let fingerprints: Vec<(i64, i32, i16, f32)> = fp_file
+                .get_all_fingerprints()
+                .into_iter()
+                .map(|(hash, t1, f1, m1)| (hash as i64, t1, f1, m1))
+                .collect();
+            let metadata = metadata_from_fp_file(fp_file.metadata);
+            
+            if skip_existing
+                && panako_db::get_metadata_by_filename_with(&*transaction, &metadata.filename)
+                    .await?
+                    .is_some()
+            {
+                summary.skipped += 1;
+                on_progress(IngestProgress::Skipped { identifier: &metadata.filename });
+                continue;
+            }
+            
+            panako_db::insert_reference(&*transaction, &new_metadata_for_db(&metadata), &fingerprints)
+                .await
+                .with_context(|| format!("Failed to insert {}", path.display()))?;
+            pending.ingested += 1;
+            pending.fingerprints += fingerprints.len();
+            on_progress(IngestProgress::Ingested {
+                identifier: &metadata.filename,
+                fingerprints: fingerprints.len(),
+            });
+            
+            if pending.ingested == files_per_commit {
+                transaction.commit().await?;
+                on_progress(IngestProgress::Committed { files: pending.ingested });
+                summary.ingested += pending.ingested;
+                summary.fingerprints += pending.fingerprints;
+                *pending = DirectoryIngestSummary::default();
+                transaction = client.transaction().await?;
+            }
+        }
+        
+        transaction.commit().await?;
+        if pending.ingested > 0 {
+            on_progress(IngestProgress::Committed { files: pending.ingested });
+        }
+        summary.ingested += pending.ingested;
+        summary.fingerprints += pending.fingerprints;
+        *pending = DirectoryIngestSummary::default();
+        
+        Ok(())
+    }
+    
+    /// Stream the `(hash, t1, f1, m1)` fingerprints of a reference in batches
+    ///
+    /// Rows are fetched through a cursor (see `panako_db::stream_fingerprints`),
+    /// so only one batch is held at a time unless `on_batch` keeps them.
+    /// Returns the number of fingerprints streamed.
+    pub async fn stream_fingerprints<F>(&self, identifier: &str, mut on_batch: F) -> Result<u64>
+    where
+        F: FnMut(Vec<(u64, i32, i16, f32)>) -> Result<()>,
+    {
         let metadata = panako_db::get_metadata_by_filename(&self.pool, identifier)
             .await?
             .ok_or_else(|| anyhow::anyhow!("Fingerprint not found: {}", identifier))?;
         
-        // Get all fingerprints for this metadata
-        let db_fingerprints = panako_db::get_fingerprints_by_metadata(&self.pool, metadata.id).await?;
+        let query = panako_db::FingerprintQuery {
+            metadata_id: Some(metadata.id),
+            ..Default::default()
+        };
+        panako_db::stream_fingerprints(&self.pool, &query, STREAM_BATCH_SIZE, |batch| {
+            on_batch(batch.into_iter().map(|fp| (fp.hash as u64, fp.t1, fp.f1, fp.m1)).collect())
+        })
+        .await
+    }
+    
+    /// Load the `(hash, t1, f1, m1)` fingerprints of a reference, without the
+    /// row ids
+    async fn load_match_tuples(&self, metadata_id: i32) -> Result<Vec<(u64, i32, i16, f32)>> {
+        let query = panako_db::FingerprintQuery {
+            metadata_id: Some(metadata_id),
+            ..Default::default()
+        };
+        let tuples = panako_db::query_fingerprint_tuples(&self.pool, &query).await?;
         
-        // Convert to tuple format
-        let fingerprints = db_fingerprints
+        Ok(tuples
             .into_iter()
-            .map(|fp| (fp.hash as u64, fp.t1, fp.f1, fp.m1))
-            .collect();
+            .map(|(hash, t1, f1, m1)| (hash as u64, t1, f1, m1))
+            .collect())
+    }
+}
+
+#[async_trait]
+impl StorageBackend for PostgresqlBackend {
+    async fn load_fingerprints(&self, identifier: &str) -> Result<Vec<(u64, i32, i16, f32)>> {
+        let mut fingerprints = Vec::new();
+        self.stream_fingerprints(identifier, |batch| {
+            fingerprints.extend(batch);
+            Ok(())
+        })
+        .await?;
         
         Ok(fingerprints)
     }
@@ -326,13 +1404,8 @@ impl StorageBackend for PostgresqlBackend {
         let mut results = Vec::new();
         
         for metadata in all_metadata {
-            // Get fingerprints for each metadata
-            let db_fingerprints = panako_db::get_fingerprints_by_metadata(&self.pool, metadata.id).await?;
-            
-            let fingerprints = db_fingerprints
-                .into_iter()
-                .map(|fp| (fp.hash as u64, fp.t1, fp.f1, fp.m1))
-                .collect();
+            // Get fingerprints for each metadata (without row ids)
+            let fingerprints = self.load_match_tuples(metadata.id).await?;
             
             results.push((metadata.filename, fingerprints));
         }
@@ -346,42 +1419,16 @@ impl StorageBackend for PostgresqlBackend {
         fingerprints: &[(u64, i32, i16, f32)],
         metadata: &FingerprintMetadata,
     ) -> Result<()> {
-        // Insert metadata
-        let new_metadata = panako_db::NewFingerprintMetadata {
-            original_path: metadata.original_path.clone(),
-            filename: metadata.filename.clone(),
-            sample_rate: metadata.sample_rate as i32,
-            duration_ms: metadata.duration_ms as i32,
-            channels: metadata.channels as i16,
-        };
-        
-        let metadata_id = panako_db::insert_metadata(&self.pool, &new_metadata).await?;
-        
-        // Insert segmentation config (disabled by default)
-        let seg_config = panako_db::NewSegmentationConfig {
-            metadata_id,
-            enabled: false,
-            segment_duration_ms: None,
-            overlap_ms: None,
-        };
-        
-        panako_db::insert_segmentation_config(&self.pool, &seg_config).await?;
-        
-        // Convert fingerprints to database format
-        let db_fingerprints: Vec<panako_db::NewFingerprint> = fingerprints
+        let fingerprints: Vec<(i64, i32, i16, f32)> = fingerprints
             .iter()
-            .map(|(hash, t1, f1, m1)| panako_db::NewFingerprint {
-                metadata_id,
-                segment_id: None,
-                hash: *hash as i64,
-                t1: *t1,
-                f1: *f1,
-                m1: *m1,
-            })
+            .map(|&(hash, t1, f1, m1)| (hash as i64, t1, f1, m1))
             .collect();
         
-        // Batch insert fingerprints
-        panako_db::insert_fingerprints_batch(&self.pool, &db_fingerprints).await?;
+        // Metadata, segmentation config and fingerprints are stored together or not at all
+        let mut client = self.pool.get().await?;
+        let transaction = client.transaction().await?;
+        panako_db::insert_reference(&*transaction, &new_metadata_for_db(metadata), &fingerprints).await?;
+        transaction.commit().await?;
         
         Ok(())
     }
@@ -438,12 +1485,7 @@ impl StorageBackend for PostgresqlBackend {
         let mut results = Vec::new();
         
         for metadata in filtered_metadata {
-            let db_fingerprints = panako_db::get_fingerprints_by_metadata(&self.pool, metadata.id).await?;
-            
-            let fingerprints = db_fingerprints
-                .into_iter()
-                .map(|fp| (fp.hash as u64, fp.t1, fp.f1, fp.m1))
-                .collect();
+            let fingerprints = self.load_match_tuples(metadata.id).await?;
             
             results.push((metadata.filename, fingerprints));
         }
@@ -454,15 +1496,80 @@ impl StorageBackend for PostgresqlBackend {
     async fn get_metadata(&self, identifier: &str) -> Result<Option<FingerprintMetadata>> {
         let db_metadata = panako_db::get_metadata_by_filename(&self.pool, identifier).await?;
         
-        Ok(db_metadata.map(|meta| FingerprintMetadata {
-            filename: meta.filename,
-            original_path: meta.original_path,
-            algorithm: "panako".to_string(), // Default algorithm name
-            sample_rate: meta.sample_rate as u32,
-            duration_ms: meta.duration_ms as u32,
-            channels: meta.channels as u16,
-            created_at: meta.created_at.to_rfc3339(),
-        }))
+        Ok(db_metadata.map(metadata_from_db))
+    }
+    
+    async fn identifiers(&self) -> Result<Vec<String>> {
+        let mut identifiers: Vec<String> =
+            panako_db::get_all_metadata(&self.pool).await?.into_iter().map(|m| m.filename).collect();
+        identifiers.sort();
+        Ok(identifiers)
+    }
+    
+    async fn find_by_content_hash(&self, content_hash: &str) -> Result<Option<FingerprintMetadata>> {
+        let db_metadata = panako_db::get_metadata_by_content_hash(&self.pool, content_hash).await?;
+        
+        Ok(db_metadata.map(metadata_from_db))
+    }
+    
+    async fn get_metadata_by_content_id(&self, content_id: &str) -> Result<Option<FingerprintMetadata>> {
+        let db_metadata = panako_db::get_metadata_by_content_id(&self.pool, content_id).await?;
+        
+        Ok(db_metadata.map(metadata_from_db))
+    }
+    
+    async fn delete_fingerprints(&self, identifier: &str) -> Result<()> {
+        let metadata = panako_db::get_metadata_by_filename(&self.pool, identifier)
+            .await?
+            .ok_or_else(|| anyhow::anyhow!("Fingerprint not found: {}", identifier))?;
+        
+        panako_db::delete_metadata(&self.pool, metadata.id).await
+    }
+    
+    async fn fingerprints_by_hashes(&self, hashes: &[u64]) -> Result<Vec<(String, Vec<(u64, i32, i16, f32)>)>> {
+        let hashes: Vec<i64> = hashes.iter().map(|&hash| hash as i64).collect();
+        let postings = panako_db::get_postings_by_hashes(&self.pool, &hashes).await?;
+        
+        // Rows come ordered by filename
+        let mut references: ReferenceFingerprints = Vec::new();
+        for (filename, hash, t1, f1, m1) in postings {
+            let fingerprint = (hash as u64, t1, f1, m1);
+            match references.last_mut() {
+                Some((identifier, fingerprints)) if *identifier == filename => fingerprints.push(fingerprint),
+                _ => references.push((filename, vec![fingerprint])),
+            }
+        }
+        Ok(references)
+    }
+}
+
+/// Storage metadata as a new database row
+fn new_metadata_for_db(metadata: &FingerprintMetadata) -> panako_db::NewFingerprintMetadata {
+    panako_db::NewFingerprintMetadata {
+        original_path: metadata.original_path.clone(),
+        filename: metadata.filename.clone(),
+        sample_rate: metadata.sample_rate as i32,
+        duration_ms: metadata.duration_ms as i32,
+        channels: metadata.channels as i16,
+        tags: metadata.tags.clone(),
+        content_hash: metadata.content_hash.clone(),
+        content_id: metadata.content_id.clone(),
+    }
+}
+
+/// Convert database metadata to storage metadata
+fn metadata_from_db(meta: panako_db::FingerprintMetadata) -> FingerprintMetadata {
+    FingerprintMetadata {
+        filename: meta.filename,
+        original_path: meta.original_path,
+        algorithm: "panako".to_string(), // Default algorithm name
+        sample_rate: meta.sample_rate as u32,
+        duration_ms: meta.duration_ms as u32,
+        channels: meta.channels as u16,
+        created_at: meta.created_at.to_rfc3339(),
+        tags: meta.tags,
+        content_hash: meta.content_hash,
+        content_id: meta.content_id,
     }
 }
 
@@ -475,10 +1582,296 @@ mod tests {
         let config = FilesystemConfig {
             base_directory: "./test_db".to_string(),
             format: FileFormat::Bson,
+            on_corrupt_file: CorruptFilePolicy::FailFast,
         };
         let backend = FilesystemBackend::new(&config);
         assert_eq!(backend.base_dir, PathBuf::from("./test_db"));
         assert_eq!(backend.format, FileFormat::Bson);
+        assert_eq!(backend.on_corrupt_file, CorruptFilePolicy::FailFast);
+    }
+
+    #[tokio::test]
+    async fn test_corrupt_file_policies() {
+        let dir = std::env::temp_dir().join(format!("panako_corrupt_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let backend = FilesystemBackend::from_path(dir.to_str().unwrap(), FileFormat::Json);
+        let metadata = FingerprintMetadata {
+            filename: "good".to_string(),
+            original_path: "/audio/good.wav".to_string(),
+            algorithm: "panako".to_string(),
+            sample_rate: 16000,
+            duration_ms: 1000,
+            channels: 1,
+            created_at: String::new(),
+            tags: HashMap::new(),
+            content_hash: None,
+            content_id: None,
+        };
+        backend.save_fingerprints("good", &[(42, 10, 20, 1.0)], &metadata).await.unwrap();
+        let corrupt = dir.join("corrupt.json");
+        std::fs::write(&corrupt, "{ truncated").unwrap();
+
+        let skip = backend.load_all_fingerprints().await.unwrap();
+        assert_eq!(skip.len(), 1);
+        assert_eq!(skip[0].0, "good");
+
+        let fail_fast = FilesystemBackend::from_path(dir.to_str().unwrap(), FileFormat::Json)
+            .with_corrupt_file_policy(CorruptFilePolicy::FailFast);
+        let err = fail_fast.load_all_fingerprints().await.unwrap_err();
+        assert!(format!("{:#}", err).contains("corrupt.json"));
+
+        let collect = FilesystemBackend::from_path(dir.to_str().unwrap(), FileFormat::Json)
+            .with_corrupt_file_policy(CorruptFilePolicy::Collect);
+        let (loaded, failed) = collect.load_all_fingerprints_with_failures().unwrap();
+        assert_eq!(loaded.len(), 1);
+        assert_eq!(failed, vec![corrupt]);
+
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    /// Backend implementing only the required methods, to exercise the defaults
+    struct MemoryBackend(Vec<FingerprintMetadata>);
+    
+    #[async_trait]
+    impl StorageBackend for MemoryBackend {
+        async fn load_fingerprints(&self, _identifier: &str) -> Result<Vec<(u64, i32, i16, f32)>> {
+            Ok(Vec::new())
+        }
+        
+        async fn load_all_fingerprints(&self) -> Result<Vec<(String, Vec<(u64, i32, i16, f32)>)>> {
+            Ok(self.0.iter().rev().map(|m| (m.filename.clone(), Vec::new())).collect())
+        }
+        
+        async fn save_fingerprints(&self, _: &str, _: &[(u64, i32, i16, f32)], _: &FingerprintMetadata) -> Result<()> {
+            anyhow::bail!("read-only")
+        }
+        
+        async fn query_fingerprints(&self, _: &QueryCriteria) -> Result<Vec<(String, Vec<(u64, i32, i16, f32)>)>> {
+            self.load_all_fingerprints().await
+        }
+        
+        async fn get_metadata(&self, identifier: &str) -> Result<Option<FingerprintMetadata>> {
+            Ok(self.0.iter().find(|m| m.filename == identifier).cloned())
+        }
+        
+        async fn delete_fingerprints(&self, _: &str) -> Result<()> {
+            anyhow::bail!("read-only")
+        }
+    }
+    
+    #[tokio::test]
+    async fn test_default_lookups_scan_metadata_in_identifier_order() {
+        let metadata = |name: &str, content_id: &str| FingerprintMetadata {
+            filename: name.to_string(),
+            original_path: format!("/audio/{}.wav", name),
+            algorithm: "panako".to_string(),
+            sample_rate: 16000,
+            duration_ms: 1000,
+            channels: 1,
+            created_at: String::new(),
+            tags: HashMap::new(),
+            content_hash: Some(format!("hash_{}", name)),
+            content_id: Some(content_id.to_string()),
+        };
+        let backend = MemoryBackend(vec![metadata("a", "isrc-1"), metadata("b", "isrc-2"), metadata("c", "isrc-2")]);
+        
+        assert_eq!(backend.identifiers().await.unwrap(), ["a", "b", "c"]);
+        let by_id = backend.get_metadata_by_content_id("isrc-2").await.unwrap();
+        assert_eq!(by_id.map(|m| m.filename), Some("b".to_string()));
+        let by_hash = backend.find_by_content_hash("hash_c").await.unwrap();
+        assert_eq!(by_hash.map(|m| m.filename), Some("c".to_string()));
+        assert!(backend.get_metadata_by_content_id("isrc-3").await.unwrap().is_none());
+    }
+    
+    #[tokio::test]
+    async fn test_content_hash_index_follows_writes() {
+        let dir = std::env::temp_dir().join(format!("panako_content_hash_index_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let backend = FilesystemBackend::from_path(dir.to_str().unwrap(), FileFormat::Json);
+        let metadata = |name: &str| FingerprintMetadata {
+            filename: name.to_string(),
+            original_path: format!("/audio/{}.wav", name),
+            algorithm: "panako".to_string(),
+            sample_rate: 16000,
+            duration_ms: 1000,
+            channels: 1,
+            created_at: String::new(),
+            tags: HashMap::new(),
+            content_hash: Some(format!("hash_{}", name)),
+            content_id: None,
+        };
+        let fingerprints = [(42, 10, 20, 1.0)];
+        backend.save_fingerprints("a", &fingerprints, &metadata("a")).await.unwrap();
+        assert_eq!(backend.find_by_content_hash("hash_a").await.unwrap().map(|m| m.filename), Some("a".to_string()));
+        
+        // Written and deleted after the index was built
+        let outcome = ingest_fingerprints(&backend, "b", &fingerprints, &metadata("b"), DuplicatePolicy::Skip).await.unwrap();
+        assert_eq!(outcome, IngestOutcome::Inserted);
+        let outcome = ingest_fingerprints(&backend, "b2", &fingerprints, &metadata("b"), DuplicatePolicy::Skip).await.unwrap();
+        assert_eq!(outcome, IngestOutcome::Skipped { existing: "b".to_string() });
+        backend.delete_fingerprints("a").await.unwrap();
+        assert!(backend.find_by_content_hash("hash_a").await.unwrap().is_none());
+        
+        // Upper-case extensions are found, loaded and deleted like lower-case ones
+        let mut upper = panako_fp::FpJsonFile::new("/audio/c.wav".to_string(), "c".to_string(), 16000, 1000, 1);
+        upper.metadata.content_hash = Some("hash_c".to_string());
+        upper.save(&dir.join("c.JSON")).unwrap();
+        let fresh = FilesystemBackend::from_path(dir.to_str().unwrap(), FileFormat::Json);
+        let found = fresh.find_by_content_hash("hash_c").await.unwrap();
+        let loaded = fresh.get_metadata("c").await.unwrap();
+        fresh.delete_fingerprints("c").await.unwrap();
+        let remaining = fresh.identifiers().unwrap();
+        std::fs::remove_dir_all(&dir).ok();
+        
+        assert_eq!(found.map(|m| m.filename), Some("c".to_string()));
+        assert_eq!(loaded.map(|m| m.filename), Some("c".to_string()));
+        assert_eq!(remaining, ["b"]);
+    }
+
+    #[tokio::test]
+    async fn test_content_hash_index_persists_across_backends() {
+        let dir = std::env::temp_dir().join(format!("panako_content_hash_sidecar_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let write = |name: &str, hash: &str| {
+            let mut file = panako_fp::FpJsonFile::new(format!("/audio/{}.wav", name), name.to_string(), 16000, 1000, 1);
+            file.metadata.content_hash = Some(hash.to_string());
+            file.save(&dir.join(format!("{}.json", name))).unwrap();
+        };
+        write("a", "hash_a");
+        let backend = FilesystemBackend::from_path(dir.to_str().unwrap(), FileFormat::Json);
+        assert!(backend.find_by_content_hash("hash_a").await.unwrap().is_some());
+        let index_path = dir.join(CONTENT_HASH_INDEX_FILE_NAME);
+        
+        // An unchanged file is taken from the sidecar without being parsed
+        let mut entries: HashMap<String, ContentHashEntry> =
+            serde_json::from_slice(&std::fs::read(&index_path).unwrap()).unwrap();
+        entries.get_mut("a.json").unwrap().content_hash = Some("hash_sidecar".to_string());
+        std::fs::write(&index_path, serde_json::to_vec(&entries).unwrap()).unwrap();
+        let fresh = FilesystemBackend::from_path(dir.to_str().unwrap(), FileFormat::Json);
+        let found = fresh.find_by_content_hash("hash_sidecar").await.unwrap();
+        
+        // Files written since are read, deleted ones dropped
+        std::fs::remove_file(dir.join("a.json")).unwrap();
+        write("b", "hash_b");
+        let later = FilesystemBackend::from_path(dir.to_str().unwrap(), FileFormat::Json);
+        let found_b = later.find_by_content_hash("hash_b").await.unwrap();
+        let entries: HashMap<String, ContentHashEntry> =
+            serde_json::from_slice(&std::fs::read(&index_path).unwrap()).unwrap();
+        let identifiers = later.identifiers().unwrap();
+        std::fs::remove_dir_all(&dir).ok();
+        
+        assert_eq!(found.map(|m| m.filename), Some("a".to_string()));
+        assert_eq!(found_b.map(|m| m.filename), Some("b".to_string()));
+        assert_eq!(entries.keys().collect::<Vec<_>>(), ["b.json"]);
+        assert_eq!(identifiers, ["b"]);
+    }
+
+    #[tokio::test]
+    async fn test_packed_directory_loads_same_references() {
+        let dir = std::env::temp_dir().join(format!("panako_pack_dir_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let backend = FilesystemBackend::from_path(dir.to_str().unwrap(), FileFormat::Json);
+        for (r, name) in ["alpha", "beta", "gamma"].iter().enumerate() {
+            let metadata = FingerprintMetadata {
+                filename: name.to_string(),
+                original_path: format!("/audio/{}.wav", name),
+                algorithm: "panako".to_string(),
+                sample_rate: 16000,
+                duration_ms: 1000,
+                channels: 1,
+                created_at: String::new(),
+                tags: HashMap::new(),
+                content_hash: Some(format!("hash_{}", name)),
+                content_id: None,
+            };
+            let fingerprints: Vec<(u64, i32, i16, f32)> = (0..20 * (r + 1))
+                .map(|i| (i as u64 * 977 + r as u64, i as i32 * 4, (i % 50) as i16, i as f32 * 0.5))
+                .collect();
+            backend.save_fingerprints(name, &fingerprints, &metadata).await.unwrap();
+        }
+        let from_files = backend.load_all_fingerprints().await.unwrap();
+        
+        let summary = backend.pack().unwrap();
+        assert_eq!(summary.references, 3);
+        assert_eq!(summary.fingerprints, 20 + 40 + 60);
+        assert_eq!(summary.loose_files.len(), 3);
+        for path in &summary.loose_files {
+            std::fs::remove_file(path).unwrap();
+        }
+        
+        let from_pack = backend.load_all_fingerprints().await.unwrap();
+        assert_eq!(from_pack, from_files);
+        assert_eq!(backend.identifiers().unwrap(), ["alpha", "beta", "gamma"]);
+        assert_eq!(backend.load_fingerprints("beta").await.unwrap(), from_files[1].1);
+        let found = backend.find_by_content_hash("hash_gamma").await.unwrap();
+        assert_eq!(found.map(|m| m.filename), Some("gamma".to_string()));
+        
+        // Loose files added after packing are loaded alongside the pack
+        let metadata = FingerprintMetadata {
+            filename: "delta".to_string(),
+            original_path: "/audio/delta.wav".to_string(),
+            algorithm: "panako".to_string(),
+            sample_rate: 16000,
+            duration_ms: 1000,
+            channels: 1,
+            created_at: String::new(),
+            tags: HashMap::new(),
+            content_hash: None,
+            content_id: None,
+        };
+        backend.save_fingerprints("delta", &[(1, 2, 3, 4.0)], &metadata).await.unwrap();
+        assert_eq!(backend.load_all_fingerprints().await.unwrap().len(), 4);
+        
+        backend.delete_fingerprints("alpha").await.unwrap();
+        assert_eq!(backend.identifiers().unwrap(), ["beta", "delta", "gamma"]);
+        
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[tokio::test]
+    async fn test_reference_rewritten_after_packing_wins() {
+        let dir = std::env::temp_dir().join(format!("panako_pack_rewrite_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let backend = FilesystemBackend::from_path(dir.to_str().unwrap(), FileFormat::Json);
+        let metadata = |name: &str, duration_ms: u32| FingerprintMetadata {
+            filename: name.to_string(),
+            original_path: format!("/audio/{}.wav", name),
+            algorithm: "panako".to_string(),
+            sample_rate: 16000,
+            duration_ms,
+            channels: 1,
+            created_at: String::new(),
+            tags: HashMap::new(),
+            content_hash: None,
+            content_id: None,
+        };
+        backend.save_fingerprints("alpha", &[(1, 10, 20, 1.0)], &metadata("alpha", 1000)).await.unwrap();
+        backend.save_fingerprints("beta", &[(2, 10, 20, 1.0)], &metadata("beta", 1000)).await.unwrap();
+        backend.pack().unwrap();
+        
+        // Re-ingest beta: the loose file is newer than its pack entry
+        let rewritten = vec![(3, 30, 40, 2.0), (4, 50, 60, 2.0)];
+        backend.save_fingerprints("beta", &rewritten, &metadata("beta", 2000)).await.unwrap();
+        let all = backend.load_all_fingerprints().await.unwrap();
+        assert_eq!(all.len(), 2);
+        assert_eq!(all.iter().find(|(id, _)| id == "beta").map(|(_, fps)| fps), Some(&rewritten));
+        assert_eq!(backend.load_fingerprints("beta").await.unwrap(), rewritten);
+        assert_eq!(backend.get_metadata("beta").await.unwrap().unwrap().duration_ms, 2000);
+        
+        // Rewriting the pack on delete keeps the newer file in effect
+        backend.delete_fingerprints("alpha").await.unwrap();
+        assert_eq!(backend.load_all_fingerprints().await.unwrap().len(), 1);
+        assert_eq!(backend.load_fingerprints("beta").await.unwrap(), rewritten);
+        
+        // Packing again picks up the rewritten file
+        let summary = backend.pack().unwrap();
+        for path in &summary.loose_files {
+            std::fs::remove_file(path).unwrap();
+        }
+        assert_eq!(backend.load_fingerprints("beta").await.unwrap(), rewritten);
+        
+        std::fs::remove_dir_all(&dir).ok();
     }
 
     #[test]
@@ -490,8 +1883,119 @@ mod tests {
             user: "test_user".to_string(),
             password: "test_pass".to_string(),
             max_connections: 5,
+            ingest_transaction: IngestTransaction::AllOrNothing,
+            ingest_batch_size: 100,
         };
         let _backend = PostgresqlBackend::new(&config);
         // Just verify it can be created
     }
+
+    #[tokio::test]
+    async fn test_ingesting_same_audio_twice_is_duplicate() {
+        use crate::audio::AudioData;
+
+        let dir = std::env::temp_dir().join(format!("panako_ingest_{}", std::process::id()));
+        let backend = FilesystemBackend::from_path(dir.to_str().unwrap(), FileFormat::Json);
+
+        let audio = AudioData {
+            samples: (0..16000).map(|i| (i as f32 * 0.05).sin()).collect(),
+            sample_rate: 16000,
+            channels: 1,
+            duration_ms: 1000,
+        };
+        let fingerprints = vec![(42u64, 10, 20, 1.0f32), (43u64, 11, 21, 0.5f32)];
+        let metadata_for = |name: &str| FingerprintMetadata {
+            filename: name.to_string(),
+            original_path: format!("/audio/{}.wav", name),
+            algorithm: "panako".to_string(),
+            sample_rate: audio.sample_rate,
+            duration_ms: audio.duration_ms,
+            channels: audio.channels,
+            created_at: String::new(),
+            tags: HashMap::new(),
+            content_hash: Some(audio.content_hash()),
+            content_id: None,
+        };
+
+        let first = ingest_fingerprints(
+            &backend, "song", &fingerprints, &metadata_for("song"), DuplicatePolicy::Skip,
+        ).await.unwrap();
+        let second = ingest_fingerprints(
+            &backend, "song_copy", &fingerprints, &metadata_for("song_copy"), DuplicatePolicy::Skip,
+        ).await.unwrap();
+        let copy_exists = dir.join("song_copy.json").exists();
+        let replaced = ingest_fingerprints(
+            &backend, "song_copy", &fingerprints, &metadata_for("song_copy"), DuplicatePolicy::Replace,
+        ).await.unwrap();
+        let original_exists = dir.join("song.json").exists();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(first, IngestOutcome::Inserted);
+        assert_eq!(second, IngestOutcome::Skipped { existing: "song".to_string() });
+        assert!(!copy_exists);
+        assert_eq!(replaced, IngestOutcome::Replaced { existing: "song".to_string() });
+        assert!(!original_exists);
+    }
+
+    #[tokio::test]
+    #[ignore] // Requires PostgreSQL with migrations applied
+    async fn test_all_or_nothing_ingestion_commits_nothing_on_corrupt_file() {
+        let dir = std::env::temp_dir().join(format!("panako_pg_ingest_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let files = FilesystemBackend::from_path(dir.to_str().unwrap(), FileFormat::Json);
+        let names: Vec<String> = ["a", "c"].iter().map(|n| format!("ingest_{}_{}", n, std::process::id())).collect();
+        for name in &names {
+            let metadata = FingerprintMetadata {
+                filename: name.clone(),
+                original_path: format!("/audio/{}.wav", name),
+                algorithm: "panako".to_string(),
+                sample_rate: 16000,
+                duration_ms: 1000,
+                channels: 1,
+                created_at: String::new(),
+                tags: HashMap::new(),
+                content_hash: None,
+                content_id: None,
+            };
+            files.save_fingerprints(name, &[(42, 10, 20, 1.0), (43, 11, 21, 0.5)], &metadata).await.unwrap();
+        }
+        // Sorted between the two valid files
+        std::fs::write(dir.join(format!("ingest_b_{}.json", std::process::id())), "{ truncated").unwrap();
+
+        let config = PostgresqlConfig {
+            database: "panako".to_string(),
+            user: "panako_user".to_string(),
+            password: "panako_pass".to_string(),
+            ..PostgresqlConfig::default()
+        };
+        let stored = |backend: &PostgresqlBackend, name: &str| {
+            let pool = backend.pool.clone();
+            let name = name.to_string();
+            async move { panako_db::get_metadata_by_filename(&pool, &name).await.unwrap() }
+        };
+
+        let all_or_nothing = PostgresqlBackend::new(&config)
+            .await
+            .unwrap()
+            .with_ingest_transaction(IngestTransaction::AllOrNothing, 0);
+        let mut events = 0;
+        let result = all_or_nothing.ingest_directory(&dir, false, |_| events += 1).await;
+        let err = result.unwrap_err();
+        assert_eq!(events, 1, "{:#}", err.source);
+        assert_eq!((err.summary.ingested, err.rolled_back), (0, 1));
+        assert!(stored(&all_or_nothing, &names[0]).await.is_none());
+        assert!(stored(&all_or_nothing, &names[1]).await.is_none());
+
+        // Committing per file keeps the file before the corrupt one
+        let per_file = PostgresqlBackend::new(&config).await.unwrap();
+        let err = per_file.ingest_directory(&dir, false, |_| {}).await.unwrap_err();
+        assert_eq!((err.summary.ingested, err.rolled_back), (1, 0));
+        let first = stored(&per_file, &names[0]).await;
+        if let Some(metadata) = &first {
+            panako_db::delete_metadata(&per_file.pool, metadata.id).await.unwrap();
+        }
+        std::fs::remove_dir_all(&dir).ok();
+        assert!(first.is_some());
+        assert!(stored(&per_file, &names[1]).await.is_none());
+    }
 }
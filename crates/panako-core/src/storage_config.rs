@@ -4,7 +4,33 @@
 //! (filesystem vs PostgreSQL) and related parameters.
 
 use serde::{Deserialize, Serialize};
-use std::path::Path;
+use std::path::{Path, PathBuf};
+
+/// File name looked up by configuration discovery
+pub const CONFIG_FILE_NAME: &str = "config.toml";
+
+/// Where the storage configuration in use comes from
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConfigSource {
+    /// Path given on the command line
+    Explicit(PathBuf),
+    /// `config.toml` in the current directory
+    CurrentDir(PathBuf),
+    /// `$XDG_CONFIG_HOME/panako/config.toml` (or `~/.config/panako/config.toml`)
+    UserConfig(PathBuf),
+    /// No file found, or discovery disabled with `--no-config`
+    Defaults,
+}
+
+impl ConfigSource {
+    /// Path of the chosen file, if any
+    pub fn path(&self) -> Option<&Path> {
+        match self {
+            Self::Explicit(path) | Self::CurrentDir(path) | Self::UserConfig(path) => Some(path),
+            Self::Defaults => None,
+        }
+    }
+}
 
 /// Main configuration structure
 #[derive(Debug, Clone, Deserialize, Serialize)]
@@ -41,6 +67,9 @@ pub struct FilesystemConfig {
     pub base_directory: String,
     #[serde(default)]
     pub format: FileFormat,
+    /// What to do with fingerprint files that fail to load
+    #[serde(default)]
+    pub on_corrupt_file: CorruptFilePolicy,
 }
 
 impl Default for FilesystemConfig {
@@ -48,6 +77,7 @@ impl Default for FilesystemConfig {
         Self {
             base_directory: default_base_directory(),
             format: FileFormat::default(),
+            on_corrupt_file: CorruptFilePolicy::default(),
         }
     }
 }
@@ -57,18 +87,26 @@ fn default_base_directory() -> String {
 }
 
 /// File format for filesystem storage
-#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+#[derive(Debug, Clone, Default, Deserialize, Serialize, PartialEq)]
 #[serde(rename_all = "lowercase")]
 pub enum FileFormat {
     Json,
     Bson,
+    #[default]
     Auto, // Auto-detect based on file extension
 }
 
-impl Default for FileFormat {
-    fn default() -> Self {
-        FileFormat::Auto
-    }
+/// Handling of fingerprint files that fail to load from a directory
+#[derive(Debug, Clone, Copy, Default, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum CorruptFilePolicy {
+    /// Log a warning and load the remaining files
+    #[default]
+    SkipWithWarning,
+    /// Fail the whole load on the first corrupt file
+    FailFast,
+    /// Load the remaining files and report the failed paths
+    Collect,
 }
 
 /// PostgreSQL backend configuration
@@ -86,6 +124,12 @@ pub struct PostgresqlConfig {
     pub password: String,
     #[serde(default = "default_max_connections")]
     pub max_connections: u32,
+    /// How a directory ingestion commits (see `IngestTransaction`)
+    #[serde(default)]
+    pub ingest_transaction: IngestTransaction,
+    /// Files per commit with `IngestTransaction::Batched`
+    #[serde(default = "default_ingest_batch_size")]
+    pub ingest_batch_size: usize,
 }
 
 impl Default for PostgresqlConfig {
@@ -97,10 +141,27 @@ impl Default for PostgresqlConfig {
             user: default_user(),
             password: default_password(),
             max_connections: default_max_connections(),
+            ingest_transaction: IngestTransaction::default(),
+            ingest_batch_size: default_ingest_batch_size(),
         }
     }
 }
 
+/// Transaction scope of a directory ingestion into PostgreSQL
+/// (`PostgresqlBackend::ingest_directory`)
+#[derive(Debug, Clone, Copy, Default, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum IngestTransaction {
+    /// Commit every file on its own; a failure keeps the files before it
+    #[default]
+    PerFile,
+    /// One transaction for the whole directory; a failure commits nothing
+    AllOrNothing,
+    /// Commit every `ingest_batch_size` files, bounding the transaction size
+    /// of very large directories; a failure keeps the committed batches
+    Batched,
+}
+
 fn default_host() -> String {
     "localhost".to_string()
 }
@@ -120,6 +181,10 @@ fn default_max_connections() -> u32 {
     10
 }
 
+fn default_ingest_batch_size() -> usize {
+    1000
+}
+
 /// Matching configuration (extends existing PanakoConfig)
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct MatchingConfig {
@@ -186,6 +251,71 @@ impl PanakoStorageConfig {
         Ok(config)
     }
 
+    /// Choose the configuration file to use
+    ///
+    /// An explicit path always wins. Otherwise, unless `no_config` is set,
+    /// `config.toml` is looked up in the current directory, then in
+    /// `$XDG_CONFIG_HOME/panako/` (`~/.config/panako/` if unset); if neither
+    /// exists, the built-in defaults are used.
+    pub fn discover(explicit: Option<&Path>, no_config: bool) -> ConfigSource {
+        let current_dir = std::env::current_dir().unwrap_or_default();
+        let user_config_dir = std::env::var_os("XDG_CONFIG_HOME")
+            .filter(|dir| !dir.is_empty())
+            .map(PathBuf::from)
+            .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")));
+        Self::discover_in(explicit, no_config, &current_dir, user_config_dir.as_deref())
+    }
+
+    /// `discover` with the current and user configuration directories given
+    fn discover_in(
+        explicit: Option<&Path>,
+        no_config: bool,
+        current_dir: &Path,
+        user_config_dir: Option<&Path>,
+    ) -> ConfigSource {
+        if let Some(path) = explicit {
+            return ConfigSource::Explicit(path.to_path_buf());
+        }
+        if no_config {
+            return ConfigSource::Defaults;
+        }
+        let local = current_dir.join(CONFIG_FILE_NAME);
+        if local.is_file() {
+            return ConfigSource::CurrentDir(local);
+        }
+        if let Some(dir) = user_config_dir {
+            let user = dir.join("panako").join(CONFIG_FILE_NAME);
+            if user.is_file() {
+                return ConfigSource::UserConfig(user);
+            }
+        }
+        ConfigSource::Defaults
+    }
+
+    /// Discover (see `discover`) and load the configuration
+    ///
+    /// `defaults` is used when no file is chosen. The choice is logged at
+    /// info level; an explicit or discovered file that fails to load is an
+    /// error rather than a silent fallback.
+    pub fn load_discovered(
+        explicit: Option<&Path>,
+        no_config: bool,
+        defaults: impl FnOnce() -> Self,
+    ) -> anyhow::Result<(Self, ConfigSource)> {
+        let source = Self::discover(explicit, no_config);
+        let config = match source.path() {
+            Some(path) => {
+                log::info!("Using configuration {} ({:?})", path.display(), source);
+                Self::load(path)?
+            }
+            None => {
+                log::info!("Using built-in default configuration");
+                defaults()
+            }
+        };
+        Ok((config, source))
+    }
+
     /// Get PostgreSQL connection string
     pub fn connection_string(&self) -> Option<String> {
         match self.storage.backend {
@@ -285,6 +415,8 @@ mod tests {
             user = "test_user"
             password = "test_pass"
             max_connections = 20
+            ingest_transaction = "batched"
+            ingest_batch_size = 250
         "#;
         
         let config: PanakoStorageConfig = toml::from_str(toml_str).unwrap();
@@ -292,5 +424,47 @@ mod tests {
         assert_eq!(config.storage.postgresql.host, "db.example.com");
         assert_eq!(config.storage.postgresql.port, 5433);
         assert_eq!(config.storage.postgresql.database, "test_panako");
+        assert_eq!(config.storage.postgresql.ingest_transaction, IngestTransaction::Batched);
+        assert_eq!(config.storage.postgresql.ingest_batch_size, 250);
+        assert_eq!(PostgresqlConfig::default().ingest_transaction, IngestTransaction::PerFile);
+    }
+
+    #[test]
+    fn test_config_discovery_order() {
+        let root = std::env::temp_dir().join(format!("panako_discovery_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        let cwd = root.join("work");
+        let user_dir = root.join("xdg");
+        std::fs::create_dir_all(&cwd).unwrap();
+        std::fs::create_dir_all(user_dir.join("panako")).unwrap();
+        let discover = |explicit: Option<&Path>, no_config| {
+            PanakoStorageConfig::discover_in(explicit, no_config, &cwd, Some(&user_dir))
+        };
+
+        // Nothing present: defaults
+        assert_eq!(discover(None, false), ConfigSource::Defaults);
+
+        // User config directory
+        let user_config = user_dir.join("panako").join(CONFIG_FILE_NAME);
+        std::fs::write(&user_config, "").unwrap();
+        assert_eq!(discover(None, false), ConfigSource::UserConfig(user_config));
+
+        // Current directory takes precedence over the user directory
+        let local_config = cwd.join(CONFIG_FILE_NAME);
+        std::fs::write(&local_config, "").unwrap();
+        assert_eq!(discover(None, false), ConfigSource::CurrentDir(local_config));
+
+        // An explicit path beats both
+        let explicit = root.join("custom.toml");
+        assert_eq!(discover(Some(&explicit), false), ConfigSource::Explicit(explicit.clone()));
+
+        // --no-config ignores the present files
+        assert_eq!(discover(None, true), ConfigSource::Defaults);
+        assert_eq!(
+            PanakoStorageConfig::discover_in(None, true, &cwd, None).path(),
+            None
+        );
+
+        std::fs::remove_dir_all(&root).ok();
     }
 }
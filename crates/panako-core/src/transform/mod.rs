@@ -3,11 +3,16 @@
 //! Implements a Gabor-like transform using FFT + constant-Q filterbank
 //! to match the Java JGaborator behavior.
 
-use crate::config::PanakoConfig;
+use crate::config::{PanakoConfig, MAX_FFT_SIZE, MIN_FFT_SIZE};
 use anyhow::Result;
+use rayon::prelude::*;
 use rustfft::{FftPlanner, num_complex::Complex};
 use std::f32::consts::PI;
 
+mod whitening;
+
+pub(crate) use whitening::SpectralWhitener;
+
 /// Spectrogram representation
 #[derive(Debug, Clone)]
 pub struct Spectrogram {
@@ -17,56 +22,196 @@ pub struct Spectrogram {
     pub num_frames: usize,
     /// Number of frequency bins
     pub num_bins: usize,
+    /// Frames skipped by the energy gate (`frame_energy_gate_rms`), whose
+    /// magnitudes are all zero; empty when gating is off
+    pub gated_frames: Vec<bool>,
 }
 
-/// Compute spectral transform (Constant-Q approximation)
-pub fn compute_transform(samples: &[f32], config: &PanakoConfig) -> Result<Spectrogram> {
-    let hop_size = config.time_resolution;
-    let fft_size = config.audio_block_size;
-    
-    // Calculate number of frames
-    let num_frames = (samples.len() / hop_size).saturating_sub(1);
-    
-    // Calculate frequency bins for constant-Q
-    let num_bins = calculate_num_bins(config);
+impl Spectrogram {
+    /// Replace NaN and infinite magnitudes with 0.0
+    ///
+    /// Corrupt input or bad resampling can produce non-finite samples, which
+    /// the FFT spreads over the whole frame. Left in place they break the max
+    /// filter and the magnitude comparisons in fingerprint hashes. Returns the
+    /// number of values replaced.
+    pub fn sanitize(&mut self) -> usize {
+        let mut replaced = 0;
+        for value in self.magnitudes.iter_mut().flatten() {
+            if !value.is_finite() {
+                *value = 0.0;
+                replaced += 1;
+            }
+        }
+        replaced
+    }
     
-    // Initialize FFT planner
-    let mut planner = FftPlanner::new();
-    let fft = planner.plan_fft_forward(fft_size);
+    /// Check that all magnitudes are finite
+    pub fn is_finite(&self) -> bool {
+        self.magnitudes.iter().flatten().all(|m| m.is_finite())
+    }
     
-    // Hann window
-    let window = create_hann_window(fft_size);
+    /// Whether frame `t` was skipped by the energy gate
+    pub fn is_gated(&self, t: usize) -> bool {
+        self.gated_frames.get(t).copied().unwrap_or(false)
+    }
+}
+
+/// Per-frame part of the transform: windowed FFT mapped to constant-Q bins
+pub(crate) struct FrameTransform {
+    fft: std::sync::Arc<dyn rustfft::Fft<f32>>,
+    window: Vec<f32>,
+    pub(crate) fft_size: usize,
+    pub(crate) num_bins: usize,
+}
+
+impl FrameTransform {
+    pub(crate) fn new(config: &PanakoConfig) -> Result<Self> {
+        let fft_size = config.audio_block_size;
+        
+        // Guard against pathological FFT sizes even if the config was never validated
+        if !(MIN_FFT_SIZE..=MAX_FFT_SIZE).contains(&fft_size) {
+            anyhow::bail!(
+                "audio_block_size (FFT size) must be between {} and {} samples, got {}",
+                MIN_FFT_SIZE,
+                MAX_FFT_SIZE,
+                fft_size
+            );
+        }
+        
+        Ok(Self {
+            fft: FftPlanner::new().plan_fft_forward(fft_size),
+            window: create_hann_window(fft_size),
+            fft_size,
+            num_bins: calculate_num_bins(config),
+        })
+    }
     
-    // Process each frame
-    let mut magnitudes = Vec::with_capacity(num_frames);
+    /// Whether the energy gate skips a frame of these samples
+    pub(crate) fn is_gated(&self, samples: &[f32], config: &PanakoConfig) -> bool {
+        let Some(threshold) = config.frame_energy_gate_rms else {
+            return false;
+        };
+        let samples = &samples[..samples.len().min(self.fft_size)];
+        if samples.is_empty() {
+            return true;
+        }
+        let energy = samples.iter().map(|s| s * s).sum::<f32>() / samples.len() as f32;
+        energy.sqrt() < threshold
+    }
     
-    for frame_idx in 0..num_frames {
-        let start = frame_idx * hop_size;
-        let end = (start + fft_size).min(samples.len());
-        
+    /// Constant-Q magnitudes of one frame; `samples` shorter than the FFT
+    /// size are zero-padded
+    pub(crate) fn magnitudes(&self, samples: &[f32], config: &PanakoConfig) -> Vec<f32> {
         // Extract and window frame
-        let mut frame: Vec<Complex<f32>> = samples[start..end]
+        let mut frame: Vec<Complex<f32>> = samples[..samples.len().min(self.fft_size)]
             .iter()
             .enumerate()
-            .map(|(i, &s)| Complex::new(s * window[i], 0.0))
+            .map(|(i, &s)| Complex::new(s * self.window[i], 0.0))
             .collect();
         
         // Pad if needed
-        frame.resize(fft_size, Complex::new(0.0, 0.0));
+        frame.resize(self.fft_size, Complex::new(0.0, 0.0));
         
         // Compute FFT
-        fft.process(&mut frame);
+        self.fft.process(&mut frame);
         
         // Map FFT bins to constant-Q bins
-        let cq_magnitudes = map_to_constant_q(&frame, config, num_bins);
-        magnitudes.push(cq_magnitudes);
+        map_to_constant_q(&frame, config, self.num_bins)
     }
+}
+
+/// Compute spectral transform (Constant-Q approximation)
+///
+/// With `parallel_transform_chunk_s` set, longer audio is split into chunks
+/// of whole frames (each chunk's samples overlapping the next by up to one
+/// FFT window) that are transformed in parallel and stitched in order.
+/// Frames are independent, so the result equals the sequential pass.
+///
+/// With `frame_energy_gate_rms` set, frames quieter than the gate are not
+/// transformed; they are left at zero and marked in `gated_frames`.
+pub fn compute_transform(samples: &[f32], config: &PanakoConfig) -> Result<Spectrogram> {
+    let hop_size = config.time_resolution;
+    let frame_transform = FrameTransform::new(config)?;
     
-    Ok(Spectrogram {
-        magnitudes,
+    // Calculate number of frames
+    let num_frames = (samples.len() / hop_size).saturating_sub(1);
+    
+    let chunk_frames = config
+        .parallel_transform_chunk_s
+        .map(|chunk_s| ((chunk_s * config.sample_rate as f64) as usize / hop_size).max(1));
+    
+    let frames: Vec<Option<Vec<f32>>> = match chunk_frames {
+        Some(chunk_frames) if num_frames > chunk_frames => {
+            let chunks: Vec<(usize, usize)> = (0..num_frames)
+                .step_by(chunk_frames)
+                .map(|first| (first, (first + chunk_frames).min(num_frames)))
+                .collect();
+            log::debug!("Transforming {} frames in {} parallel chunks", num_frames, chunks.len());
+            chunks
+                .par_iter()
+                .map(|&(first, last)| transform_frames(samples, first..last, &frame_transform, config))
+                .collect::<Vec<_>>()
+                .into_iter()
+                .flatten()
+                .collect()
+        }
+        _ => transform_frames(samples, 0..num_frames, &frame_transform, config),
+    };
+    
+    let gated_frames = if config.frame_energy_gate_rms.is_some() {
+        let gated: Vec<bool> = frames.iter().map(Option::is_none).collect();
+        log::debug!("Energy gate skipped {} of {} frames", gated.iter().filter(|&&g| g).count(), num_frames);
+        gated
+    } else {
+        Vec::new()
+    };
+    let mut spectrogram = Spectrogram {
+        magnitudes: frames
+            .into_iter()
+            .map(|frame| frame.unwrap_or_else(|| vec![0.0; frame_transform.num_bins]))
+            .collect(),
         num_frames,
-        num_bins,
-    })
+        num_bins: frame_transform.num_bins,
+        gated_frames,
+    };
+    
+    let replaced = spectrogram.sanitize();
+    if replaced > 0 {
+        log::warn!(
+            "Replaced {} non-finite magnitudes with 0 (corrupt or badly resampled audio?)",
+            replaced
+        );
+    }
+    
+    if let Some(window) = config.whitening_window_frames() {
+        // Gated frames stay out of the running medians
+        let mut whitener = SpectralWhitener::new(window);
+        for (t, frame) in spectrogram.magnitudes.iter_mut().enumerate() {
+            if !spectrogram.gated_frames.get(t).copied().unwrap_or(false) {
+                whitener.whiten_frame(frame);
+            }
+        }
+    }
+    
+    Ok(spectrogram)
+}
+
+/// Magnitudes of the frames in `frames`, in order (None for gated frames)
+fn transform_frames(
+    samples: &[f32],
+    frames: std::ops::Range<usize>,
+    frame_transform: &FrameTransform,
+    config: &PanakoConfig,
+) -> Vec<Option<Vec<f32>>> {
+    let hop_size = config.time_resolution;
+    frames
+        .map(|frame_idx| {
+            let start = frame_idx * hop_size;
+            let end = (start + frame_transform.fft_size).min(samples.len());
+            let frame = &samples[start..end];
+            (!frame_transform.is_gated(frame, config)).then(|| frame_transform.magnitudes(frame, config))
+        })
+        .collect()
 }
 
 /// Calculate number of constant-Q bins
@@ -75,8 +220,24 @@ fn calculate_num_bins(config: &PanakoConfig) -> usize {
     (octaves * config.bands_per_octave as f32).ceil() as usize
 }
 
+/// Center frequency (Hz) of a constant-Q bin
+///
+/// By default the grid starts at `min_freq`. With `pitch_normalized` the grid
+/// is snapped so that the bin nearest to `ref_freq` is centered on it exactly.
+pub fn bin_center_frequency(bin_idx: usize, config: &PanakoConfig) -> f32 {
+    let bands = config.bands_per_octave as f32;
+    
+    if config.pitch_normalized {
+        // Bin index of ref_freq on the unaligned grid, rounded to a whole bin
+        let ref_bin = ((config.ref_freq / config.min_freq).log2() * bands).round();
+        config.ref_freq * 2.0_f32.powf((bin_idx as f32 - ref_bin) / bands)
+    } else {
+        config.min_freq * 2.0_f32.powf(bin_idx as f32 / bands)
+    }
+}
+
 /// Create Hann window
-fn create_hann_window(size: usize) -> Vec<f32> {
+pub(crate) fn create_hann_window(size: usize) -> Vec<f32> {
     (0..size)
         .map(|i| {
             let x = i as f32 / (size - 1) as f32;
@@ -96,9 +257,9 @@ fn map_to_constant_q(
     
     let mut cq_bins = vec![0.0; num_bins];
     
-    for bin_idx in 0..num_bins {
+    for (bin_idx, cq_bin) in cq_bins.iter_mut().enumerate() {
         // Calculate center frequency for this constant-Q bin
-        let freq = config.min_freq * 2.0_f32.powf(bin_idx as f32 / config.bands_per_octave as f32);
+        let freq = bin_center_frequency(bin_idx, config);
         
         // Map to FFT bin
         let fft_bin = (freq * fft_size as f32 / sample_rate) as usize;
@@ -106,7 +267,7 @@ fn map_to_constant_q(
         if fft_bin < fft_size / 2 {
             // Calculate magnitude
             let magnitude = fft_output[fft_bin].norm();
-            cq_bins[bin_idx] = magnitude;
+            *cq_bin = magnitude;
         }
     }
     
@@ -116,6 +277,89 @@ fn map_to_constant_q(
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::eventpoint::EventPointExtractor;
+    
+    #[test]
+    fn test_energy_gate_skips_silent_frames() {
+        // Alternating 1 s of melody and 1 s of a faint noise floor
+        let mut seed: u32 = 7;
+        let samples: Vec<f32> = (0..16000 * 8)
+            .map(|i| {
+                seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+                let noise = (seed >> 16) as f32 / 32768.0 - 1.0;
+                if (i / 16000) % 2 == 0 {
+                    let freq = [440.0, 660.0, 990.0, 1320.0][(i / 4000) % 4];
+                    (2.0 * PI * freq * i as f32 / 16000.0).sin() * 0.5 + noise * 0.05
+                } else {
+                    noise * 1e-4
+                }
+            })
+            .collect();
+        let plain = PanakoConfig::default();
+        let gated = PanakoConfig {
+            frame_energy_gate_rms: Some(0.001),
+            ..PanakoConfig::default()
+        };
+        
+        let ungated_spectrogram = compute_transform(&samples, &plain).unwrap();
+        let spectrogram = compute_transform(&samples, &gated).unwrap();
+        assert!(ungated_spectrogram.gated_frames.is_empty());
+        assert_eq!(spectrogram.gated_frames.len(), spectrogram.num_frames);
+        let num_gated = spectrogram.gated_frames.iter().filter(|&&g| g).count();
+        // Exactly the frames whose whole window lies in a quiet second
+        let quiet_frames = (0..spectrogram.num_frames)
+            .filter(|&t| {
+                let start = t * plain.time_resolution;
+                let end = (start + plain.audio_block_size).min(samples.len());
+                (start / 16000) % 2 == 1 && (start / 16000) == ((end - 1) / 16000)
+            })
+            .count();
+        assert_eq!(num_gated, quiet_frames);
+        for (t, frame) in spectrogram.magnitudes.iter().enumerate() {
+            if spectrogram.is_gated(t) {
+                assert!(frame.iter().all(|&m| m == 0.0));
+            }
+        }
+        
+        let extractor = EventPointExtractor::new(&plain);
+        let noise_floor_points = extractor
+            .extract(&ungated_spectrogram)
+            .unwrap()
+            .iter()
+            .filter(|ep| spectrogram.is_gated(ep.t as usize))
+            .count();
+        assert!(noise_floor_points > 0);
+        let event_points = extractor.extract(&spectrogram).unwrap();
+        assert!(!event_points.is_empty());
+        assert!(event_points.iter().all(|ep| !spectrogram.is_gated(ep.t as usize)));
+    }
+    
+    #[test]
+    fn test_parallel_chunked_transform_matches_sequential() {
+        let mut seed: u32 = 99;
+        let samples: Vec<f32> = (0..16000 * 7 + 301)
+            .map(|i| {
+                seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+                let noise = ((seed >> 16) as f32 / 32768.0 - 1.0) * 0.1;
+                (i as f32 * 0.07).sin() * 0.5 + noise
+            })
+            .collect();
+        let sequential = compute_transform(&samples, &PanakoConfig::default()).unwrap();
+        
+        // Chunk lengths that do and do not divide the frame count
+        for chunk_s in [0.5, 1.3, 100.0] {
+            let config = PanakoConfig {
+                parallel_transform_chunk_s: Some(chunk_s),
+                ..PanakoConfig::default()
+            };
+            let chunked = compute_transform(&samples, &config).unwrap();
+            assert_eq!(chunked.num_frames, sequential.num_frames);
+            assert_eq!(chunked.num_bins, sequential.num_bins);
+            for (t, (a, b)) in chunked.magnitudes.iter().zip(&sequential.magnitudes).enumerate() {
+                assert_eq!(a, b, "frame {} differs", t);
+            }
+        }
+    }
     
     #[test]
     fn test_hann_window() {
@@ -130,6 +374,47 @@ mod tests {
         let config = PanakoConfig::default();
         let num_bins = calculate_num_bins(&config);
         // 6 octaves * 85 bands = 510 bins
-        assert!(num_bins >= 500 && num_bins <= 520);
+        assert!((500..=520).contains(&num_bins));
+    }
+    
+    #[test]
+    fn test_bin_centers_align_to_ref_freq() {
+        let config = PanakoConfig {
+            ref_freq: 432.0,
+            pitch_normalized: true,
+            ..PanakoConfig::default()
+        };
+        let bands = config.bands_per_octave as f32;
+        
+        // Every bin center sits a whole number of bands away from ref_freq
+        for bin_idx in [0, 10, 100, 250, 400] {
+            let freq = bin_center_frequency(bin_idx, &config);
+            let steps = (freq / config.ref_freq).log2() * bands;
+            assert!((steps - steps.round()).abs() < 1e-3, "bin {} at {} Hz", bin_idx, freq);
+        }
+        
+        // And one of them is exactly ref_freq
+        let ref_bin = ((config.ref_freq / config.min_freq).log2() * bands).round() as usize;
+        assert!((bin_center_frequency(ref_bin, &config) - 432.0).abs() < 1e-3);
+        
+        // Without normalization the grid starts at min_freq
+        let default = PanakoConfig::default();
+        assert!((bin_center_frequency(0, &default) - default.min_freq).abs() < 1e-6);
+    }
+    
+    #[test]
+    fn test_non_finite_samples_are_sanitized() {
+        let config = PanakoConfig::default();
+        let mut samples: Vec<f32> = (0..16000)
+            .map(|i| (2.0 * PI * 1000.0 * i as f32 / 16000.0).sin())
+            .collect();
+        samples[4000] = f32::NAN;
+        samples[9000] = f32::INFINITY;
+        
+        let spectrogram = compute_transform(&samples, &config).unwrap();
+        
+        assert!(spectrogram.is_finite());
+        // Frames away from the corrupt samples keep their content
+        assert!(spectrogram.magnitudes[spectrogram.num_frames - 1].iter().any(|&m| m > 0.0));
     }
 }
@@ -0,0 +1,103 @@
+//! Per-bin spectral whitening with a running median
+//!
+//! EQ and broadcast processing scale whole frequency regions, which changes
+//! which bins hold the strongest peaks. Dividing each bin by its median over
+//! the preceding frames cancels such (slowly varying) gains and flattens the
+//! spectral envelope before event point extraction.
+
+use std::collections::VecDeque;
+
+/// Keeps medians from dividing by zero in silent bins
+const MEDIAN_FLOOR: f32 = 1e-9;
+
+/// Running per-bin median over the last `window` frames
+///
+/// The median is causal (the current frame and the ones before it), so
+/// streamed audio can be whitened frame by frame with the same result as a
+/// whole spectrogram.
+pub(crate) struct SpectralWhitener {
+    window: usize,
+    history: VecDeque<Vec<f32>>,
+    /// Values of each bin over the history, sorted ascending
+    sorted: Vec<Vec<f32>>,
+}
+
+impl SpectralWhitener {
+    pub(crate) fn new(window: usize) -> Self {
+        Self {
+            window: window.max(1),
+            history: VecDeque::new(),
+            sorted: Vec::new(),
+        }
+    }
+
+    /// Divide `frame` by the per-bin median of it and the preceding frames
+    ///
+    /// Magnitudes must be finite (sanitized).
+    pub(crate) fn whiten_frame(&mut self, frame: &mut [f32]) {
+        if self.sorted.len() != frame.len() {
+            self.sorted = vec![Vec::with_capacity(self.window); frame.len()];
+            self.history.clear();
+        }
+
+        if self.history.len() == self.window {
+            let oldest = self.history.pop_front().unwrap();
+            for (values, old) in self.sorted.iter_mut().zip(oldest) {
+                let pos = values.partition_point(|&v| v < old);
+                values.remove(pos);
+            }
+        }
+        for (values, &value) in self.sorted.iter_mut().zip(frame.iter()) {
+            let pos = values.partition_point(|&v| v < value);
+            values.insert(pos, value);
+        }
+        self.history.push_back(frame.to_vec());
+
+        for (value, values) in frame.iter_mut().zip(&self.sorted) {
+            let mid = values.len() / 2;
+            let median = if values.len() % 2 == 0 {
+                (values[mid - 1] + values[mid]) / 2.0
+            } else {
+                values[mid]
+            };
+            *value /= median.max(MEDIAN_FLOOR);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_running_median_is_causal() {
+        let mut whitener = SpectralWhitener::new(3);
+        let mut outputs = Vec::new();
+        for value in [2.0, 4.0, 6.0, 100.0, 1.0] {
+            let mut frame = [value];
+            whitener.whiten_frame(&mut frame);
+            outputs.push(frame[0]);
+        }
+        // Medians: 2, 3, 4, 6, 6
+        assert_eq!(outputs, [1.0, 4.0 / 3.0, 1.5, 100.0 / 6.0, 1.0 / 6.0]);
+    }
+
+    #[test]
+    fn test_constant_gain_per_bin_is_removed() {
+        let frames: Vec<Vec<f32>> = (0..50)
+            .map(|t| (0..4).map(|f| 1.0 + ((t * 7 + f * 3) % 11) as f32).collect())
+            .collect();
+        let gains = [0.1, 1.0, 5.0, 30.0];
+        let mut plain = SpectralWhitener::new(9);
+        let mut scaled = SpectralWhitener::new(9);
+        for frame in &frames {
+            let mut a = frame.clone();
+            let mut b: Vec<f32> = frame.iter().zip(gains).map(|(m, g)| m * g).collect();
+            plain.whiten_frame(&mut a);
+            scaled.whiten_frame(&mut b);
+            for (x, y) in a.iter().zip(&b) {
+                assert!((x - y).abs() < 1e-5, "{} vs {}", x, y);
+            }
+        }
+    }
+}
@@ -15,7 +15,8 @@ pub use models::{
 };
 pub use operations::{
     delete_metadata, get_all_metadata, get_fingerprint_summaries,
-    get_fingerprints_by_hash, get_fingerprints_by_metadata, get_metadata_by_filename,
-    get_metadata_by_id, get_segments_by_metadata, insert_fingerprints_batch,
-    insert_metadata, insert_segment, insert_segmentation_config, query_fingerprints,
+    get_fingerprints_by_hash, get_fingerprints_by_metadata, get_metadata_by_content_hash, get_metadata_by_content_id,
+    get_metadata_by_filename, get_metadata_by_filename_with, get_metadata_by_id, get_postings_by_hashes, get_segments_by_metadata, insert_fingerprints_batch,
+    insert_metadata, insert_reference, insert_segment, insert_segmentation_config, query_fingerprint_tuples,
+    query_fingerprints, stream_fingerprints,
 };
@@ -1,5 +1,6 @@
 use serde::{Deserialize, Serialize};
 use bson::Bson;
+use std::collections::HashMap;
 
 /// Represents metadata for a fingerprint file stored in the database
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -11,6 +12,15 @@ pub struct FingerprintMetadata {
     pub duration_ms: i32,
     pub channels: i16,
     pub created_at: chrono::DateTime<chrono::Utc>,
+    /// Free-form key/value tags (JSONB `tags` column)
+    #[serde(default)]
+    pub tags: HashMap<String, String>,
+    /// SHA-256 of the decoded audio (`content_hash` column)
+    #[serde(default)]
+    pub content_hash: Option<String>,
+    /// External content ID such as an ISRC or EIDR (`content_id` column)
+    #[serde(default)]
+    pub content_id: Option<String>,
 }
 
 /// Represents segmentation configuration
@@ -53,6 +63,12 @@ pub struct NewFingerprintMetadata {
     pub sample_rate: i32,
     pub duration_ms: i32,
     pub channels: i16,
+    #[serde(default)]
+    pub tags: HashMap<String, String>,
+    #[serde(default)]
+    pub content_hash: Option<String>,
+    #[serde(default)]
+    pub content_id: Option<String>,
 }
 
 /// Input structure for creating new segmentation config
@@ -1,5 +1,8 @@
 use anyhow::{Context, Result};
 use deadpool_postgres::Pool;
+use std::collections::HashMap;
+use tokio_postgres::types::Json;
+use tokio_postgres::GenericClient;
 
 use crate::models::*;
 
@@ -9,12 +12,15 @@ pub async fn insert_metadata(
     metadata: &NewFingerprintMetadata,
 ) -> Result<i32> {
     let client = pool.get().await?;
-    
+    insert_metadata_with(&**client, metadata).await
+}
+
+async fn insert_metadata_with<C: GenericClient>(client: &C, metadata: &NewFingerprintMetadata) -> Result<i32> {
     let row = client
         .query_one(
             "INSERT INTO fingerprint_metadata 
-             (original_path, filename, sample_rate, duration_ms, channels) 
-             VALUES ($1, $2, $3, $4, $5) 
+             (original_path, filename, sample_rate, duration_ms, channels, tags, content_hash, content_id) 
+             VALUES ($1, $2, $3, $4, $5, $6, $7, $8) 
              RETURNING id",
             &[
                 &metadata.original_path,
@@ -22,6 +28,9 @@ pub async fn insert_metadata(
                 &metadata.sample_rate,
                 &metadata.duration_ms,
                 &metadata.channels,
+                &Json(&metadata.tags),
+                &metadata.content_hash,
+                &metadata.content_id,
             ],
         )
         .await
@@ -36,7 +45,10 @@ pub async fn insert_segmentation_config(
     config: &NewSegmentationConfig,
 ) -> Result<i32> {
     let client = pool.get().await?;
-    
+    insert_segmentation_config_with(&**client, config).await
+}
+
+async fn insert_segmentation_config_with<C: GenericClient>(client: &C, config: &NewSegmentationConfig) -> Result<i32> {
     let row = client
         .query_one(
             "INSERT INTO segmentation_config 
@@ -89,6 +101,13 @@ pub async fn insert_fingerprints_batch(
     }
     
     let client = pool.get().await?;
+    insert_fingerprints_batch_with(&**client, fingerprints).await
+}
+
+async fn insert_fingerprints_batch_with<C: GenericClient>(client: &C, fingerprints: &[NewFingerprint]) -> Result<()> {
+    if fingerprints.is_empty() {
+        return Ok(());
+    }
     
     // Build the JSONB array
     let json_array = serde_json::to_value(fingerprints)
@@ -113,13 +132,48 @@ pub async fn insert_fingerprints_batch(
     Ok(())
 }
 
+/// Insert a whole reference through `client`: its metadata, a disabled
+/// segmentation config and its `(hash, t1, f1, m1)` fingerprints
+///
+/// With a transaction as `client`, several references can be committed (or
+/// rolled back) together. Returns the new metadata id.
+pub async fn insert_reference<C: GenericClient>(
+    client: &C,
+    metadata: &NewFingerprintMetadata,
+    fingerprints: &[(i64, i32, i16, f32)],
+) -> Result<i32> {
+    let metadata_id = insert_metadata_with(client, metadata).await?;
+    let seg_config = NewSegmentationConfig {
+        metadata_id,
+        enabled: false,
+        segment_duration_ms: None,
+        overlap_ms: None,
+    };
+    insert_segmentation_config_with(client, &seg_config).await?;
+    
+    let db_fingerprints: Vec<NewFingerprint> = fingerprints
+        .iter()
+        .map(|&(hash, t1, f1, m1)| NewFingerprint {
+            metadata_id,
+            segment_id: None,
+            hash,
+            t1,
+            f1,
+            m1,
+        })
+        .collect();
+    insert_fingerprints_batch_with(client, &db_fingerprints).await?;
+    
+    Ok(metadata_id)
+}
+
 /// Get metadata by ID
 pub async fn get_metadata_by_id(pool: &Pool, id: i32) -> Result<Option<FingerprintMetadata>> {
     let client = pool.get().await?;
     
     let row = client
         .query_opt(
-            "SELECT id, original_path, filename, sample_rate, duration_ms, channels, created_at 
+            "SELECT id, original_path, filename, sample_rate, duration_ms, channels, created_at, tags, content_hash, content_id 
              FROM fingerprint_metadata 
              WHERE id = $1",
             &[&id],
@@ -135,6 +189,9 @@ pub async fn get_metadata_by_id(pool: &Pool, id: i32) -> Result<Option<Fingerpri
         duration_ms: r.get(4),
         channels: r.get(5),
         created_at: r.get(6),
+        tags: r.get::<_, Json<HashMap<String, String>>>(7).0,
+        content_hash: r.get(8),
+        content_id: r.get(9),
     }))
 }
 
@@ -144,10 +201,20 @@ pub async fn get_metadata_by_filename(
     filename: &str,
 ) -> Result<Option<FingerprintMetadata>> {
     let client = pool.get().await?;
-    
+    get_metadata_by_filename_with(&**client, filename).await
+}
+
+/// Get metadata by filename through `client`
+///
+/// With a transaction as `client`, references inserted earlier in the same
+/// transaction are found too.
+pub async fn get_metadata_by_filename_with<C: GenericClient>(
+    client: &C,
+    filename: &str,
+) -> Result<Option<FingerprintMetadata>> {
     let row = client
         .query_opt(
-            "SELECT id, original_path, filename, sample_rate, duration_ms, channels, created_at 
+            "SELECT id, original_path, filename, sample_rate, duration_ms, channels, created_at, tags, content_hash, content_id 
              FROM fingerprint_metadata 
              WHERE filename = $1",
             &[&filename],
@@ -163,6 +230,76 @@ pub async fn get_metadata_by_filename(
         duration_ms: r.get(4),
         channels: r.get(5),
         created_at: r.get(6),
+        tags: r.get::<_, Json<HashMap<String, String>>>(7).0,
+        content_hash: r.get(8),
+        content_id: r.get(9),
+    }))
+}
+
+/// Get metadata by the content hash of the decoded audio
+pub async fn get_metadata_by_content_hash(
+    pool: &Pool,
+    content_hash: &str,
+) -> Result<Option<FingerprintMetadata>> {
+    let client = pool.get().await?;
+    
+    let row = client
+        .query_opt(
+            "SELECT id, original_path, filename, sample_rate, duration_ms, channels, created_at, tags, content_hash, content_id 
+             FROM fingerprint_metadata 
+             WHERE content_hash = $1",
+            &[&content_hash],
+        )
+        .await
+        .context("Failed to get metadata by content hash")?;
+    
+    Ok(row.map(|r| FingerprintMetadata {
+        id: r.get(0),
+        original_path: r.get(1),
+        filename: r.get(2),
+        sample_rate: r.get(3),
+        duration_ms: r.get(4),
+        channels: r.get(5),
+        created_at: r.get(6),
+        tags: r.get::<_, Json<HashMap<String, String>>>(7).0,
+        content_hash: r.get(8),
+        content_id: r.get(9),
+    }))
+}
+
+/// Get metadata by external content ID (ISRC, EIDR, ...)
+///
+/// Several references may share a content ID; the earliest registered one
+/// is returned.
+pub async fn get_metadata_by_content_id(
+    pool: &Pool,
+    content_id: &str,
+) -> Result<Option<FingerprintMetadata>> {
+    let client = pool.get().await?;
+    
+    let row = client
+        .query_opt(
+            "SELECT id, original_path, filename, sample_rate, duration_ms, channels, created_at, tags, content_hash, content_id 
+             FROM fingerprint_metadata 
+             WHERE content_id = $1 
+             ORDER BY id 
+             LIMIT 1",
+            &[&content_id],
+        )
+        .await
+        .context("Failed to get metadata by content ID")?;
+    
+    Ok(row.map(|r| FingerprintMetadata {
+        id: r.get(0),
+        original_path: r.get(1),
+        filename: r.get(2),
+        sample_rate: r.get(3),
+        duration_ms: r.get(4),
+        channels: r.get(5),
+        created_at: r.get(6),
+        tags: r.get::<_, Json<HashMap<String, String>>>(7).0,
+        content_hash: r.get(8),
+        content_id: r.get(9),
     }))
 }
 
@@ -172,7 +309,7 @@ pub async fn get_all_metadata(pool: &Pool) -> Result<Vec<FingerprintMetadata>> {
     
     let rows = client
         .query(
-            "SELECT id, original_path, filename, sample_rate, duration_ms, channels, created_at 
+            "SELECT id, original_path, filename, sample_rate, duration_ms, channels, created_at, tags, content_hash, content_id 
              FROM fingerprint_metadata 
              ORDER BY created_at DESC",
             &[],
@@ -190,6 +327,9 @@ pub async fn get_all_metadata(pool: &Pool) -> Result<Vec<FingerprintMetadata>> {
             duration_ms: r.get(4),
             channels: r.get(5),
             created_at: r.get(6),
+            tags: r.get::<_, Json<HashMap<String, String>>>(7).0,
+            content_hash: r.get(8),
+            content_id: r.get(9),
         })
         .collect())
 }
@@ -221,19 +361,11 @@ pub async fn get_segments_by_metadata(pool: &Pool, metadata_id: i32) -> Result<V
         .collect())
 }
 
-/// Query fingerprints with criteria
-pub async fn query_fingerprints(
-    pool: &Pool,
-    query: &FingerprintQuery,
-) -> Result<Vec<Fingerprint>> {
-    let client = pool.get().await?;
-    
-    let mut sql = String::from(
-        "SELECT id, metadata_id, segment_id, hash, t1, f1, m1 
-         FROM fingerprints 
-         WHERE 1=1",
-    );
-    
+/// Append the `FingerprintQuery` filters (and limit) to a `WHERE 1=1` query
+fn push_fingerprint_filters<'a>(
+    sql: &mut String,
+    query: &'a FingerprintQuery,
+) -> Vec<&'a (dyn tokio_postgres::types::ToSql + Sync)> {
     let mut param_count = 0;
     let mut params: Vec<&(dyn tokio_postgres::types::ToSql + Sync)> = Vec::new();
     
@@ -261,6 +393,36 @@ pub async fn query_fingerprints(
         params.push(limit);
     }
     
+    params
+}
+
+/// Map a `SELECT id, metadata_id, segment_id, hash, t1, f1, m1` row
+fn fingerprint_from_row(r: &tokio_postgres::Row) -> Fingerprint {
+    Fingerprint {
+        id: r.get(0),
+        metadata_id: r.get(1),
+        segment_id: r.get(2),
+        hash: r.get(3),
+        t1: r.get(4),
+        f1: r.get(5),
+        m1: r.get(6),
+    }
+}
+
+/// Query fingerprints with criteria
+pub async fn query_fingerprints(
+    pool: &Pool,
+    query: &FingerprintQuery,
+) -> Result<Vec<Fingerprint>> {
+    let client = pool.get().await?;
+    
+    let mut sql = String::from(
+        "SELECT id, metadata_id, segment_id, hash, t1, f1, m1 
+         FROM fingerprints 
+         WHERE 1=1",
+    );
+    let params = push_fingerprint_filters(&mut sql, query);
+    
     let rows = client
         .query(&sql, &params[..])
         .await
@@ -268,43 +430,145 @@ pub async fn query_fingerprints(
     
     Ok(rows
         .iter()
-        .map(|r| Fingerprint {
-            id: r.get(0),
-            metadata_id: r.get(1),
-            segment_id: r.get(2),
-            hash: r.get(3),
-            t1: r.get(4),
-            f1: r.get(5),
-            m1: r.get(6),
-        })
+        .map(fingerprint_from_row)
         .collect())
 }
 
+/// Stream fingerprints matching the criteria in batches of at most `batch_size` rows
+///
+/// Rows are fetched incrementally through a server-side portal, so memory stays
+/// bounded by one batch however large the `fingerprints` table is. `on_batch`
+/// is called for every batch; returning an error stops the stream. Returns the
+/// total number of rows streamed.
+pub async fn stream_fingerprints<F>(
+    pool: &Pool,
+    query: &FingerprintQuery,
+    batch_size: i32,
+    mut on_batch: F,
+) -> Result<u64>
+where
+    F: FnMut(Vec<Fingerprint>) -> Result<()>,
+{
+    if batch_size <= 0 {
+        anyhow::bail!("batch_size must be > 0");
+    }
+    
+    let mut client = pool.get().await?;
+    let transaction = client.transaction().await?;
+    
+    let mut sql = String::from(
+        "SELECT id, metadata_id, segment_id, hash, t1, f1, m1 
+         FROM fingerprints 
+         WHERE 1=1",
+    );
+    let params = push_fingerprint_filters(&mut sql, query);
+    
+    let portal = transaction
+        .bind(&sql, &params[..])
+        .await
+        .context("Failed to open fingerprint cursor")?;
+    
+    let mut total = 0;
+    loop {
+        let rows = transaction
+            .query_portal(&portal, batch_size)
+            .await
+            .context("Failed to fetch fingerprint batch")?;
+        let num_rows = rows.len();
+        if num_rows == 0 {
+            break;
+        }
+        total += num_rows as u64;
+        on_batch(rows.iter().map(fingerprint_from_row).collect())?;
+        if num_rows < batch_size as usize {
+            break;
+        }
+    }
+    
+    transaction.commit().await?;
+    Ok(total)
+}
+
+/// Query only the `(hash, t1, f1, m1)` columns of the fingerprints
+///
+/// Same criteria as `query_fingerprints`, but skips the ids to reduce
+/// transfer and allocation for large references.
+pub async fn query_fingerprint_tuples(
+    pool: &Pool,
+    query: &FingerprintQuery,
+) -> Result<Vec<(i64, i32, i16, f32)>> {
+    let client = pool.get().await?;
+    
+    let mut sql = String::from(
+        "SELECT hash, t1, f1, m1 
+         FROM fingerprints 
+         WHERE 1=1",
+    );
+    let params = push_fingerprint_filters(&mut sql, query);
+    
+    let rows = client
+        .query(&sql, &params[..])
+        .await
+        .context("Failed to query fingerprint tuples")?;
+    
+    Ok(rows.iter().map(|r| (r.get(0), r.get(1), r.get(2), r.get(3))).collect())
+}
+
 /// Get fingerprints by hash (optimized query using the index)
-pub async fn get_fingerprints_by_hash(pool: &Pool, hash: i64) -> Result<Vec<Fingerprint>> {
+///
+/// Rows are ordered by `metadata_id`, `t1` (then `id`), so a `limit` gives a
+/// deterministic prefix; `None` returns every row.
+pub async fn get_fingerprints_by_hash(
+    pool: &Pool,
+    hash: i64,
+    limit: Option<i64>,
+) -> Result<Vec<Fingerprint>> {
     let client = pool.get().await?;
     
+    // LIMIT NULL means no limit
     let rows = client
         .query(
             "SELECT id, metadata_id, segment_id, hash, t1, f1, m1 
              FROM fingerprints 
-             WHERE hash = $1",
-            &[&hash],
+             WHERE hash = $1
+             ORDER BY metadata_id, t1, id
+             LIMIT $2",
+            &[&hash, &limit],
         )
         .await
         .context("Failed to get fingerprints by hash")?;
     
     Ok(rows
         .iter()
-        .map(|r| Fingerprint {
-            id: r.get(0),
-            metadata_id: r.get(1),
-            segment_id: r.get(2),
-            hash: r.get(3),
-            t1: r.get(4),
-            f1: r.get(5),
-            m1: r.get(6),
-        })
+        .map(fingerprint_from_row)
+        .collect())
+}
+
+/// Matching postings `(filename, hash, t1, f1, m1)` of a set of hashes
+///
+/// One round trip for all hashes, using the hash index. Rows are ordered by
+/// filename, then `t1` (then `id`), so results are deterministic.
+pub async fn get_postings_by_hashes(
+    pool: &Pool,
+    hashes: &[i64],
+) -> Result<Vec<(String, i64, i32, i16, f32)>> {
+    let client = pool.get().await?;
+    
+    let rows = client
+        .query(
+            "SELECT m.filename, f.hash, f.t1, f.f1, f.m1 
+             FROM fingerprints f 
+             JOIN fingerprint_metadata m ON m.id = f.metadata_id 
+             WHERE f.hash = ANY($1)
+             ORDER BY m.filename, f.t1, f.id",
+            &[&hashes],
+        )
+        .await
+        .context("Failed to get postings by hashes")?;
+    
+    Ok(rows
+        .iter()
+        .map(|r| (r.get(0), r.get(1), r.get(2), r.get(3), r.get(4)))
         .collect())
 }
 
@@ -365,10 +629,256 @@ mod tests {
     // They are integration tests and should be run with:
     // cargo test --package panako-db -- --ignored
     
+    use super::*;
+    use crate::connection::create_pool;
+    
     #[tokio::test]
     #[ignore]
     async fn test_insert_and_retrieve_metadata() {
         // This would require a test database setup
         // Left as a placeholder for future integration tests
     }
+    
+    #[tokio::test]
+    #[ignore] // Requires PostgreSQL with migrations applied
+    async fn test_metadata_tags_round_trip() {
+        let pool = create_pool("localhost", 5432, "panako", "panako_user", "panako_pass", 10)
+            .unwrap();
+        
+        let filename = format!("tags_test_{}", std::process::id());
+        let mut tags = HashMap::new();
+        tags.insert("artist".to_string(), "Example Artist".to_string());
+        tags.insert("isrc".to_string(), "USRC17607839".to_string());
+        
+        let new_metadata = NewFingerprintMetadata {
+            original_path: format!("/audio/{}.wav", filename),
+            filename: filename.clone(),
+            sample_rate: 16000,
+            duration_ms: 5000,
+            channels: 1,
+            tags: tags.clone(),
+            content_hash: None,
+            content_id: None,
+        };
+        let id = insert_metadata(&pool, &new_metadata).await.unwrap();
+        
+        let loaded = get_metadata_by_filename(&pool, &filename).await.unwrap().unwrap();
+        delete_metadata(&pool, id).await.unwrap();
+        
+        assert_eq!(loaded.tags, tags);
+    }
+    
+    #[tokio::test]
+    #[ignore] // Requires PostgreSQL with migrations applied
+    async fn test_metadata_by_content_hash() {
+        let pool = create_pool("localhost", 5432, "panako", "panako_user", "panako_pass", 10)
+            .unwrap();
+        
+        let filename = format!("hash_test_{}", std::process::id());
+        let content_hash = format!("{:064x}", std::process::id());
+        
+        let new_metadata = NewFingerprintMetadata {
+            original_path: format!("/audio/{}.wav", filename),
+            filename: filename.clone(),
+            sample_rate: 16000,
+            duration_ms: 5000,
+            channels: 1,
+            tags: HashMap::new(),
+            content_hash: Some(content_hash.clone()),
+            content_id: None,
+        };
+        let id = insert_metadata(&pool, &new_metadata).await.unwrap();
+        
+        let loaded = get_metadata_by_content_hash(&pool, &content_hash).await.unwrap();
+        delete_metadata(&pool, id).await.unwrap();
+        
+        assert_eq!(loaded.map(|m| m.filename), Some(filename));
+    }
+    
+    #[tokio::test]
+    #[ignore] // Requires PostgreSQL with migrations applied
+    async fn test_metadata_by_content_id() {
+        let pool = create_pool("localhost", 5432, "panako", "panako_user", "panako_pass", 10)
+            .unwrap();
+        
+        let filename = format!("content_id_test_{}", std::process::id());
+        let content_id = format!("ZZ{:010}", std::process::id());
+        let new_metadata = NewFingerprintMetadata {
+            original_path: format!("/audio/{}.wav", filename),
+            filename: filename.clone(),
+            sample_rate: 16000,
+            duration_ms: 5000,
+            channels: 1,
+            tags: HashMap::new(),
+            content_hash: None,
+            content_id: Some(content_id.clone()),
+        };
+        let id = insert_metadata(&pool, &new_metadata).await.unwrap();
+        
+        let loaded = get_metadata_by_content_id(&pool, &content_id).await.unwrap();
+        let missing = get_metadata_by_content_id(&pool, "ZZ-not-registered").await.unwrap();
+        delete_metadata(&pool, id).await.unwrap();
+        
+        let loaded = loaded.unwrap();
+        assert_eq!(loaded.filename, filename);
+        assert_eq!(loaded.content_id, Some(content_id));
+        assert!(missing.is_none());
+    }
+    
+    #[tokio::test]
+    #[ignore] // Requires PostgreSQL with migrations applied
+    async fn test_tuple_query_matches_full_query() {
+        let pool = create_pool("localhost", 5432, "panako", "panako_user", "panako_pass", 10)
+            .unwrap();
+        
+        let filename = format!("tuple_test_{}", std::process::id());
+        let new_metadata = NewFingerprintMetadata {
+            original_path: format!("/audio/{}.wav", filename),
+            filename,
+            sample_rate: 16000,
+            duration_ms: 5000,
+            channels: 1,
+            tags: HashMap::new(),
+            content_hash: None,
+            content_id: None,
+        };
+        let metadata_id = insert_metadata(&pool, &new_metadata).await.unwrap();
+        
+        let fingerprints: Vec<NewFingerprint> = (0..50)
+            .map(|i| NewFingerprint {
+                metadata_id,
+                segment_id: None,
+                hash: 1_000_000 + i,
+                t1: i as i32 * 10,
+                f1: (i % 100) as i16,
+                m1: i as f32 * 0.25,
+            })
+            .collect();
+        insert_fingerprints_batch(&pool, &fingerprints).await.unwrap();
+        
+        let query = FingerprintQuery {
+            metadata_id: Some(metadata_id),
+            ..Default::default()
+        };
+        let full = query_fingerprints(&pool, &query).await.unwrap();
+        let tuples = query_fingerprint_tuples(&pool, &query).await.unwrap();
+        delete_metadata(&pool, metadata_id).await.unwrap();
+        
+        let mut expected: Vec<(i64, i32, i16, f32)> = full.iter().map(|fp| (fp.hash, fp.t1, fp.f1, fp.m1)).collect();
+        let mut actual = tuples;
+        expected.sort_by_key(|fp| fp.0);
+        actual.sort_by_key(|fp| fp.0);
+        assert!(actual.iter().any(|fp| fp.3 > 0.0));
+        assert_eq!(actual.len(), 50);
+        assert_eq!(actual, expected);
+    }
+    
+    #[tokio::test]
+    #[ignore] // Requires PostgreSQL with migrations applied
+    async fn test_stream_fingerprints_in_bounded_batches() {
+        let pool = create_pool("localhost", 5432, "panako", "panako_user", "panako_pass", 10)
+            .unwrap();
+        
+        let filename = format!("stream_test_{}", std::process::id());
+        let new_metadata = NewFingerprintMetadata {
+            original_path: format!("/audio/{}.wav", filename),
+            filename,
+            sample_rate: 16000,
+            duration_ms: 5000,
+            channels: 1,
+            tags: HashMap::new(),
+            content_hash: None,
+            content_id: None,
+        };
+        let metadata_id = insert_metadata(&pool, &new_metadata).await.unwrap();
+        
+        let fingerprints: Vec<NewFingerprint> = (0..50_000)
+            .map(|i| NewFingerprint {
+                metadata_id,
+                segment_id: None,
+                hash: i,
+                t1: i as i32,
+                f1: (i % 100) as i16,
+                m1: 0.5,
+            })
+            .collect();
+        for chunk in fingerprints.chunks(5_000) {
+            insert_fingerprints_batch(&pool, chunk).await.unwrap();
+        }
+        
+        let query = FingerprintQuery {
+            metadata_id: Some(metadata_id),
+            ..Default::default()
+        };
+        let mut largest_batch = 0;
+        let mut hash_sum = 0i64;
+        let total = stream_fingerprints(&pool, &query, 1_000, |batch| {
+            largest_batch = largest_batch.max(batch.len());
+            hash_sum += batch.iter().map(|fp| fp.hash).sum::<i64>();
+            Ok(())
+        })
+        .await;
+        delete_metadata(&pool, metadata_id).await.unwrap();
+        
+        assert_eq!(total.unwrap(), 50_000);
+        // At most one batch of rows is held at a time
+        assert_eq!(largest_batch, 1_000);
+        assert_eq!(hash_sum, (0..50_000i64).sum::<i64>());
+    }
+    
+    #[tokio::test]
+    #[ignore] // Requires PostgreSQL with migrations applied
+    async fn test_fingerprints_by_hash_limited_and_ordered() {
+        let pool = create_pool("localhost", 5432, "panako", "panako_user", "panako_pass", 10)
+            .unwrap();
+        
+        // A hash no other test uses
+        let hash = 7_000_000_000 + std::process::id() as i64;
+        let mut metadata_ids = Vec::new();
+        for r in 0..3 {
+            let filename = format!("by_hash_test_{}_{}", std::process::id(), r);
+            let new_metadata = NewFingerprintMetadata {
+                original_path: format!("/audio/{}.wav", filename),
+                filename,
+                sample_rate: 16000,
+                duration_ms: 5000,
+                channels: 1,
+                tags: HashMap::new(),
+                content_hash: None,
+                content_id: None,
+            };
+            let metadata_id = insert_metadata(&pool, &new_metadata).await.unwrap();
+            metadata_ids.push(metadata_id);
+            
+            // Inserted in descending time order
+            let fingerprints: Vec<NewFingerprint> = (0..100)
+                .rev()
+                .map(|i| NewFingerprint {
+                    metadata_id,
+                    segment_id: None,
+                    hash,
+                    t1: i * 7,
+                    f1: 50,
+                    m1: 0.5,
+                })
+                .collect();
+            insert_fingerprints_batch(&pool, &fingerprints).await.unwrap();
+        }
+        
+        let all = get_fingerprints_by_hash(&pool, hash, None).await.unwrap();
+        let limited = get_fingerprints_by_hash(&pool, hash, Some(150)).await.unwrap();
+        for metadata_id in &metadata_ids {
+            delete_metadata(&pool, *metadata_id).await.unwrap();
+        }
+        
+        assert_eq!(all.len(), 300);
+        let keys: Vec<(i32, i32)> = all.iter().map(|fp| (fp.metadata_id, fp.t1)).collect();
+        let mut sorted = keys.clone();
+        sorted.sort();
+        assert_eq!(keys, sorted);
+        
+        assert_eq!(limited.len(), 150);
+        let limited_keys: Vec<(i32, i32)> = limited.iter().map(|fp| (fp.metadata_id, fp.t1)).collect();
+        assert_eq!(limited_keys, keys[..150]);
+    }
 }
@@ -1,4 +1,16 @@
 //! .fp file format structures
+//!
+//! All integers and floats are little-endian on every platform, so files are
+//! portable between architectures. Layout:
+//!
+//! | section      | content                                                   |
+//! |--------------|-----------------------------------------------------------|
+//! | header       | 64 bytes, fields of `FpHeader` in declaration order, no   |
+//! |              | padding between fields                                    |
+//! | metadata     | algorithm id (8 bytes, NUL-padded), parameter JSON length |
+//! |              | u32 + JSON bytes, original filename (NUL-terminated)      |
+//! | fingerprints | 20 bytes each: hash u64, t1 i32, f1 i16, 2 zero bytes of  |
+//! |              | padding, m1 f32 (IEEE 754)                                |
 
 use serde::{Deserialize, Serialize};
 
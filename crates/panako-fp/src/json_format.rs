@@ -3,6 +3,7 @@
 //! New JSON-based format for storing fingerprints with metadata and segmentation support
 
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 
 /// Complete JSON fingerprint file structure
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -23,6 +24,113 @@ pub struct FpJsonMetadata {
     pub duration_ms: u32,
     pub channels: u16,
     pub created_at: String,
+    /// Free-form key/value tags (catalog metadata such as title or label)
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub tags: HashMap<String, String>,
+    /// SHA-256 of the decoded audio samples, used to detect re-ingestion
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content_hash: Option<String>,
+    /// External content ID of the reference (ISRC, EIDR, ...), reported
+    /// with every match
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content_id: Option<String>,
+    /// Analysis parameters the fingerprints were generated with
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fingerprint_params: Option<FingerprintParams>,
+}
+
+/// Analysis parameters that determine fingerprint hashes and times
+///
+/// A query only matches references fingerprinted with the same parameters,
+/// even for identical audio. Filter sizes are stored in bins and frames as
+/// actually used by the extractor.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FingerprintParams {
+    pub sample_rate: u32,
+    pub audio_block_size: usize,
+    pub time_resolution: usize,
+    pub min_freq: f32,
+    pub max_freq: f32,
+    pub bands_per_octave: u32,
+    pub pitch_normalized: bool,
+    pub ref_freq: f32,
+    pub freq_max_filter_bins: usize,
+    pub time_max_filter_frames: usize,
+    pub fp_min_freq_dist: i16,
+    pub fp_max_freq_dist: i16,
+    pub fp_min_time_dist: i32,
+    pub fp_max_time_dist: i32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pre_emphasis: Option<f32>,
+    /// Only adds hash variants, so it does not affect compatibility
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fuzzy_hash_epsilon: Option<f32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub magnitude_bucket_db: Option<f32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub whitening_window_frames: Option<usize>,
+    /// Hash width in bits when not the default 64
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hash_bits: Option<u32>,
+}
+
+impl FpJsonMetadata {
+    /// `created_at` as Unix seconds, or None if it is not an RFC 3339 time
+    pub fn created_at_unix_s(&self) -> Option<i64> {
+        chrono::DateTime::parse_from_rfc3339(&self.created_at)
+            .ok()
+            .map(|time| time.timestamp())
+    }
+}
+
+impl FingerprintParams {
+    /// Describe every parameter that differs from `other` as `name self vs other`
+    ///
+    /// `ref_freq` is only compared when pitch normalization is enabled on both
+    /// sides, as it has no effect otherwise. `fuzzy_hash_epsilon` is ignored.
+    pub fn differences(&self, other: &Self) -> Vec<String> {
+        let mut differences = Vec::new();
+        let mut compare = |name: &str, a: String, b: String| {
+            if a != b {
+                differences.push(format!("{} {} vs {}", name, a, b));
+            }
+        };
+
+        compare("sample_rate", self.sample_rate.to_string(), other.sample_rate.to_string());
+        compare("audio_block_size", self.audio_block_size.to_string(), other.audio_block_size.to_string());
+        compare("time_resolution", self.time_resolution.to_string(), other.time_resolution.to_string());
+        compare("min_freq", self.min_freq.to_string(), other.min_freq.to_string());
+        compare("max_freq", self.max_freq.to_string(), other.max_freq.to_string());
+        compare("bands_per_octave", self.bands_per_octave.to_string(), other.bands_per_octave.to_string());
+        compare("pitch_normalized", self.pitch_normalized.to_string(), other.pitch_normalized.to_string());
+        if self.pitch_normalized && other.pitch_normalized {
+            compare("ref_freq", self.ref_freq.to_string(), other.ref_freq.to_string());
+        }
+        compare("freq_max_filter_bins", self.freq_max_filter_bins.to_string(), other.freq_max_filter_bins.to_string());
+        compare("time_max_filter_frames", self.time_max_filter_frames.to_string(), other.time_max_filter_frames.to_string());
+        compare("fp_min_freq_dist", self.fp_min_freq_dist.to_string(), other.fp_min_freq_dist.to_string());
+        compare("fp_max_freq_dist", self.fp_max_freq_dist.to_string(), other.fp_max_freq_dist.to_string());
+        compare("fp_min_time_dist", self.fp_min_time_dist.to_string(), other.fp_min_time_dist.to_string());
+        compare("fp_max_time_dist", self.fp_max_time_dist.to_string(), other.fp_max_time_dist.to_string());
+        compare("pre_emphasis", format!("{:?}", self.pre_emphasis), format!("{:?}", other.pre_emphasis));
+        compare(
+            "magnitude_bucket_db",
+            format!("{:?}", self.magnitude_bucket_db),
+            format!("{:?}", other.magnitude_bucket_db),
+        );
+        compare(
+            "whitening_window_frames",
+            format!("{:?}", self.whitening_window_frames),
+            format!("{:?}", other.whitening_window_frames),
+        );
+        compare(
+            "hash_bits",
+            self.hash_bits.unwrap_or(64).to_string(),
+            other.hash_bits.unwrap_or(64).to_string(),
+        );
+
+        differences
+    }
 }
 
 /// Segmentation configuration
@@ -75,6 +183,10 @@ impl FpJsonFile {
                 duration_ms,
                 channels,
                 created_at: chrono::Utc::now().to_rfc3339(),
+                tags: HashMap::new(),
+                content_hash: None,
+                content_id: None,
+                fingerprint_params: None,
             },
             segmentation: JsonSegmentationConfig {
                 enabled: false,
@@ -117,7 +229,8 @@ impl FpJsonFile {
     /// Load from JSON file
     pub fn load(path: &std::path::Path) -> anyhow::Result<Self> {
         let json_str = std::fs::read_to_string(path)?;
-        let fp_file: FpJsonFile = serde_json::from_str(&json_str)?;
+        let mut fp_file: FpJsonFile = serde_json::from_str(&json_str)?;
+        fp_file.reconcile_counts(path);
         Ok(fp_file)
     }
 
@@ -131,10 +244,33 @@ impl FpJsonFile {
     /// Load from BSON file
     pub fn load_bson(path: &std::path::Path) -> anyhow::Result<Self> {
         let bson_data = std::fs::read(path)?;
-        let fp_file: FpJsonFile = bson::from_slice(&bson_data)?;
+        let mut fp_file: FpJsonFile = bson::from_slice(&bson_data)?;
+        fp_file.reconcile_counts(path);
         Ok(fp_file)
     }
 
+    /// Set every segment's `num_fingerprints` to its actual number of
+    /// fingerprints, warning about (hand-edited or corrupt) files where they
+    /// disagree; the fingerprint vector is always trusted. Returns the number
+    /// of segments corrected.
+    pub fn reconcile_counts(&mut self, path: &std::path::Path) -> usize {
+        let mut corrected = 0;
+        for segment in &mut self.segments {
+            if segment.num_fingerprints != segment.fingerprints.len() {
+                log::warn!(
+                    "{}: segment {} declares {} fingerprints but has {}, using the actual count",
+                    path.display(),
+                    segment.segment_id,
+                    segment.num_fingerprints,
+                    segment.fingerprints.len()
+                );
+                segment.num_fingerprints = segment.fingerprints.len();
+                corrected += 1;
+            }
+        }
+        corrected
+    }
+
     /// Load from file (auto-detect format based on extension)
     pub fn load_auto(path: &std::path::Path) -> anyhow::Result<Self> {
         let extension = path
@@ -142,12 +278,38 @@ impl FpJsonFile {
             .and_then(|s| s.to_str())
             .unwrap_or("json");
         
-        match extension {
-            "bson" => Self::load_bson(path),
-            _ => Self::load(path), // Default to JSON
+        if extension.eq_ignore_ascii_case("bson") {
+            Self::load_bson(path)
+        } else {
+            Self::load(path) // Default to JSON
         }
     }
 
+    /// Prune every segment down to `keep_fraction` of its fingerprints
+    ///
+    /// Keeps an evenly time-distributed subset (by `t1`) so the remaining
+    /// fingerprints still cover the whole segment, and updates the per-segment
+    /// counts. Useful for shrinking reference files without re-decoding audio.
+    pub fn prune(&mut self, keep_fraction: f64) -> anyhow::Result<()> {
+        if !(keep_fraction > 0.0 && keep_fraction <= 1.0) {
+            anyhow::bail!("keep_fraction must be in (0, 1], got {}", keep_fraction);
+        }
+
+        for segment in &mut self.segments {
+            let total = segment.fingerprints.len();
+            let keep = ((total as f64 * keep_fraction).round() as usize).clamp(total.min(1), total);
+
+            segment.fingerprints.sort_by_key(|fp| fp.t1);
+            let fingerprints = std::mem::take(&mut segment.fingerprints);
+            segment.fingerprints = (0..keep)
+                .map(|j| fingerprints[j * total / keep].clone())
+                .collect();
+            segment.num_fingerprints = segment.fingerprints.len();
+        }
+
+        Ok(())
+    }
+
     /// Get all fingerprints from all segments as tuples
     pub fn get_all_fingerprints(&self) -> Vec<(u64, i32, i16, f32)> {
         self.segments
@@ -214,6 +376,69 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_tags_round_trip() {
+        let mut fp_file = FpJsonFile::new(
+            "/path/to/audio.wav".to_string(),
+            "audio".to_string(),
+            16000,
+            5000,
+            1,
+        );
+        fp_file.metadata.tags.insert("artist".to_string(), "Example".to_string());
+        fp_file.metadata.tags.insert("isrc".to_string(), "USRC17607839".to_string());
+
+        let json_str = serde_json::to_string(&fp_file).unwrap();
+        let from_json: FpJsonFile = serde_json::from_str(&json_str).unwrap();
+        assert_eq!(from_json.metadata.tags, fp_file.metadata.tags);
+
+        let bson_data = bson::to_vec(&fp_file).unwrap();
+        let from_bson: FpJsonFile = bson::from_slice(&bson_data).unwrap();
+        assert_eq!(from_bson.metadata.tags, fp_file.metadata.tags);
+
+        // Files written before tags existed still load
+        let legacy = json_str.replace(
+            &format!(",\"tags\":{}", serde_json::to_string(&fp_file.metadata.tags).unwrap()),
+            "",
+        );
+        assert!(!legacy.contains("tags"));
+        let from_legacy: FpJsonFile = serde_json::from_str(&legacy).unwrap();
+        assert!(from_legacy.metadata.tags.is_empty());
+    }
+
+    #[test]
+    fn test_prune_halves_fingerprints() {
+        let mut fp_file = FpJsonFile::new(
+            "/path/to/audio.wav".to_string(),
+            "audio".to_string(),
+            16000,
+            5000,
+            1,
+        );
+        let fingerprints: Vec<FpJsonFingerprint> = (0..100)
+            .map(|i| FpJsonFingerprint { hash: i, t1: i as i32 * 5, f1: 50, m1: 1.0 })
+            .collect();
+        fp_file.add_segment(FpJsonSegment {
+            segment_id: 0,
+            start_time_s: 0.0,
+            end_time_s: 5.0,
+            num_fingerprints: fingerprints.len(),
+            fingerprints,
+        });
+
+        fp_file.prune(0.5).unwrap();
+
+        let segment = &fp_file.segments[0];
+        assert_eq!(segment.fingerprints.len(), 50);
+        assert_eq!(segment.num_fingerprints, 50);
+        // Evenly spread over the original time range
+        assert_eq!(segment.fingerprints.first().unwrap().t1, 0);
+        assert!(segment.fingerprints.last().unwrap().t1 >= 480);
+
+        assert!(fp_file.prune(0.0).is_err());
+        assert!(fp_file.prune(1.5).is_err());
+    }
+
     #[test]
     fn test_bson_size_reduction() {
         let mut fp_file = FpJsonFile::new(
@@ -317,5 +542,47 @@ mod tests {
             assert!(reduction > 0.0); // Some reduction expected
         }
     }
-}
 
+    #[test]
+    fn test_created_at_unix_s() {
+        let mut fp_file = FpJsonFile::new("/audio/a.wav".to_string(), "a".to_string(), 16000, 1000, 1);
+        assert!(fp_file.metadata.created_at_unix_s().unwrap() > 1_700_000_000);
+        fp_file.metadata.created_at = "2025-03-01T12:00:00+01:00".to_string();
+        assert_eq!(fp_file.metadata.created_at_unix_s(), Some(1_740_826_800));
+        fp_file.metadata.created_at = String::new();
+        assert_eq!(fp_file.metadata.created_at_unix_s(), None);
+    }
+
+    #[test]
+    fn test_wrong_fingerprint_count_is_corrected_on_load() {
+        let mut fp_file = FpJsonFile::new("/audio/a.wav".to_string(), "a".to_string(), 16000, 20000, 1);
+        for (segment_id, declared) in [(0, 500), (1, 0), (2, 3)] {
+            fp_file.add_segment(FpJsonSegment {
+                segment_id,
+                start_time_s: segment_id as f64 * 10.0,
+                end_time_s: segment_id as f64 * 10.0 + 10.0,
+                num_fingerprints: declared,
+                fingerprints: (0..3)
+                    .map(|i| FpJsonFingerprint { hash: i, t1: i as i32, f1: 10, m1: 1.0 })
+                    .collect(),
+            });
+        }
+
+        let dir = std::env::temp_dir();
+        let json_path = dir.join(format!("panako_wrong_count_{}.json", std::process::id()));
+        let bson_path = dir.join(format!("panako_wrong_count_{}.bson", std::process::id()));
+        fp_file.save(&json_path).unwrap();
+        fp_file.save_bson(&bson_path).unwrap();
+        let from_json = FpJsonFile::load_auto(&json_path).unwrap();
+        let from_bson = FpJsonFile::load_auto(&bson_path).unwrap();
+        std::fs::remove_file(&json_path).ok();
+        std::fs::remove_file(&bson_path).ok();
+
+        for loaded in [from_json, from_bson] {
+            assert!(loaded.segments.iter().all(|segment| segment.num_fingerprints == 3));
+            assert_eq!(loaded.get_all_fingerprints().len(), 9);
+        }
+        assert_eq!(fp_file.reconcile_counts(std::path::Path::new("a.json")), 2);
+        assert_eq!(fp_file.reconcile_counts(std::path::Path::new("a.json")), 0);
+    }
+}
@@ -2,10 +2,16 @@
 
 pub mod format;
 pub mod json_format;
+pub mod pack;
 pub mod reader;
+pub mod signature;
 pub mod writer;
 
 pub use format::{FpFile, FpHeader, FpMetadata, SegmentationInfo, SegmentMetadata, MAGIC, VERSION};
-pub use json_format::{FpJsonFile, FpJsonMetadata, FpJsonSegment, FpJsonFingerprint, JsonSegmentationConfig};
+pub use json_format::{
+    FingerprintParams, FpJsonFile, FpJsonMetadata, FpJsonSegment, FpJsonFingerprint, JsonSegmentationConfig,
+};
+pub use pack::{read_pack, write_pack, PackIndex, PACK_FILE_NAME};
 pub use reader::FpReader;
+pub use signature::{minhash_similarity, MINHASH_SIZE};
 pub use writer::FpWriter;
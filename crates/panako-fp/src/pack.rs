@@ -0,0 +1,342 @@
+//! Packed archive of a whole fingerprint directory
+//!
+//! Loading thousands of small `.json`/`.bson` files is dominated by directory
+//! enumeration and per-file parsing. A pack holds every file of a directory
+//! in one file: fingerprints as fixed-size binary records and the rest of
+//! each file in a small JSON index. Layout (little-endian):
+//!
+//! | section      | content                                                   |
+//! |--------------|-----------------------------------------------------------|
+//! | header       | magic `PNKPACK1`, version u32, entry count u32,           |
+//! |              | fingerprint count u64, index length u64                   |
+//! | fingerprints | 18 bytes each: hash u64, t1 i32, f1 i16, m1 f32           |
+//! | index        | JSON array of entries: source file name, the file without |
+//! |              | fingerprints, first record and per-segment record counts  |
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::io::{BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use crate::json_format::{FpJsonFile, FpJsonFingerprint};
+
+/// Name of the pack file inside a fingerprint directory
+pub const PACK_FILE_NAME: &str = "fingerprints.pack";
+
+const PACK_MAGIC: &[u8; 8] = b"PNKPACK1";
+const PACK_VERSION: u32 = 1;
+const HEADER_LEN: usize = 32;
+const RECORD_LEN: usize = 18;
+
+#[derive(Serialize, Deserialize)]
+struct PackEntry {
+    /// File name the entry was packed from (e.g. `song.json`)
+    source: String,
+    /// The file with all segment fingerprints removed
+    file: FpJsonFile,
+    /// Index of the entry's first fingerprint record
+    offset: u64,
+    /// Number of fingerprint records of each segment
+    segment_counts: Vec<u64>,
+}
+
+/// Write `(source file name, file)` pairs to a pack at `path`
+///
+/// The pack is written to a temporary file next to `path` and renamed into
+/// place, so readers never see a partial pack.
+pub fn write_pack(path: &Path, files: &[(String, FpJsonFile)]) -> Result<()> {
+    let mut entries = Vec::with_capacity(files.len());
+    let mut offset = 0u64;
+    for (source, file) in files {
+        let mut stripped = file.clone();
+        let segment_counts: Vec<u64> = stripped
+            .segments
+            .iter_mut()
+            .map(|segment| std::mem::take(&mut segment.fingerprints).len() as u64)
+            .collect();
+        entries.push(PackEntry {
+            source: source.clone(),
+            file: stripped,
+            offset,
+            segment_counts: segment_counts.clone(),
+        });
+        offset += segment_counts.iter().sum::<u64>();
+    }
+    let index = serde_json::to_vec(&entries)?;
+
+    let tmp_path = path.with_extension("pack.tmp");
+    let file = std::fs::File::create(&tmp_path)
+        .with_context(|| format!("Failed to create pack file {}", tmp_path.display()))?;
+    let mut writer = BufWriter::new(file);
+    writer.write_all(PACK_MAGIC)?;
+    writer.write_all(&PACK_VERSION.to_le_bytes())?;
+    writer.write_all(&(entries.len() as u32).to_le_bytes())?;
+    writer.write_all(&offset.to_le_bytes())?;
+    writer.write_all(&(index.len() as u64).to_le_bytes())?;
+    for (_, file) in files {
+        for fp in file.segments.iter().flat_map(|segment| &segment.fingerprints) {
+            writer.write_all(&fp.hash.to_le_bytes())?;
+            writer.write_all(&fp.t1.to_le_bytes())?;
+            writer.write_all(&fp.f1.to_le_bytes())?;
+            writer.write_all(&fp.m1.to_le_bytes())?;
+        }
+    }
+    writer.write_all(&index)?;
+    writer.flush()?;
+    drop(writer);
+
+    std::fs::rename(&tmp_path, path)
+        .with_context(|| format!("Failed to move pack file into place at {}", path.display()))?;
+    Ok(())
+}
+
+/// Sizes read from a pack header, checked against the file length
+struct PackHeader {
+    num_entries: usize,
+    num_records: usize,
+    index_start: usize,
+}
+
+impl PackHeader {
+    fn parse(header: &[u8], file_len: u64, path: &Path) -> Result<Self> {
+        if header.len() < HEADER_LEN || &header[..8] != PACK_MAGIC {
+            anyhow::bail!("{} is not a fingerprint pack", path.display());
+        }
+        let version = u32::from_le_bytes(header[8..12].try_into().unwrap());
+        if version != PACK_VERSION {
+            anyhow::bail!("Unsupported pack version {} in {}", version, path.display());
+        }
+        let num_entries = u32::from_le_bytes(header[12..16].try_into().unwrap()) as usize;
+        let num_records = u64::from_le_bytes(header[16..24].try_into().unwrap());
+        let index_len = u64::from_le_bytes(header[24..32].try_into().unwrap());
+
+        let index_start = num_records
+            .checked_mul(RECORD_LEN as u64)
+            .and_then(|records_len| records_len.checked_add(HEADER_LEN as u64));
+        let expected_len = index_start.and_then(|start| start.checked_add(index_len));
+        match (index_start, expected_len) {
+            (Some(index_start), Some(expected_len)) if expected_len == file_len => Ok(Self {
+                num_entries,
+                num_records: num_records as usize,
+                index_start: index_start as usize,
+            }),
+            _ => anyhow::bail!(
+                "Pack file {} is truncated or corrupt ({} bytes, header declares {} records and a {} byte index)",
+                path.display(),
+                file_len,
+                num_records,
+                index_len
+            ),
+        }
+    }
+
+    /// Parse the index and check every entry against the record count
+    fn parse_index(&self, index: &[u8], path: &Path) -> Result<Vec<PackEntry>> {
+        let entries: Vec<PackEntry> = serde_json::from_slice(index)
+            .with_context(|| format!("Invalid index in pack file {}", path.display()))?;
+        if entries.len() != self.num_entries {
+            anyhow::bail!("Pack file {} has inconsistent entry count", path.display());
+        }
+        for entry in &entries {
+            let end = entry
+                .segment_counts
+                .iter()
+                .try_fold(entry.offset, |end, &count| end.checked_add(count));
+            if entry.segment_counts.len() != entry.file.segments.len()
+                || end.is_none_or(|end| end > self.num_records as u64)
+            {
+                anyhow::bail!("Entry {} of pack file {} is corrupt", entry.source, path.display());
+            }
+        }
+        Ok(entries)
+    }
+}
+
+fn decode_record(r: &[u8]) -> FpJsonFingerprint {
+    FpJsonFingerprint {
+        hash: u64::from_le_bytes(r[0..8].try_into().unwrap()),
+        t1: i32::from_le_bytes(r[8..12].try_into().unwrap()),
+        f1: i16::from_le_bytes(r[12..14].try_into().unwrap()),
+        m1: f32::from_le_bytes(r[14..18].try_into().unwrap()),
+    }
+}
+
+/// Fill the segments of a checked entry from its records, `records[0]`
+/// being the entry's first record
+fn fill_segments(entry: &PackEntry, records: &[u8], path: &Path) -> FpJsonFile {
+    let mut file = entry.file.clone();
+    let mut next = 0;
+    for (segment, &count) in file.segments.iter_mut().zip(&entry.segment_counts) {
+        segment.fingerprints = (next..next + count as usize)
+            .map(|i| decode_record(&records[i * RECORD_LEN..(i + 1) * RECORD_LEN]))
+            .collect();
+        next += count as usize;
+    }
+    file.reconcile_counts(path);
+    file
+}
+
+/// Read a pack written by `write_pack`, as `(source file name, file)` pairs
+pub fn read_pack(path: &Path) -> Result<Vec<(String, FpJsonFile)>> {
+    let bytes = std::fs::read(path).with_context(|| format!("Failed to read pack file {}", path.display()))?;
+    let header = PackHeader::parse(&bytes, bytes.len() as u64, path)?;
+    let entries = header.parse_index(&bytes[header.index_start..], path)?;
+
+    Ok(entries
+        .iter()
+        .map(|entry| {
+            let records = &bytes[HEADER_LEN + entry.offset as usize * RECORD_LEN..header.index_start];
+            (entry.source.clone(), fill_segments(entry, records, path))
+        })
+        .collect())
+}
+
+/// Index of a pack, read without its fingerprint records
+///
+/// Opening reads only the header and the JSON index; the records of an
+/// entry are read on demand by `load`. Use this for lookups by source or
+/// metadata, and `read_pack` to load every entry.
+pub struct PackIndex {
+    path: PathBuf,
+    entries: Vec<PackEntry>,
+}
+
+impl PackIndex {
+    pub fn open(path: &Path) -> Result<Self> {
+        let mut file =
+            std::fs::File::open(path).with_context(|| format!("Failed to read pack file {}", path.display()))?;
+        let file_len = file.metadata()?.len();
+        let mut header = [0u8; HEADER_LEN];
+        if file.read_exact(&mut header).is_err() {
+            anyhow::bail!("{} is not a fingerprint pack", path.display());
+        }
+        let header = PackHeader::parse(&header, file_len, path)?;
+        let mut index = Vec::new();
+        file.seek(SeekFrom::Start(header.index_start as u64))?;
+        file.read_to_end(&mut index)?;
+        let entries = header.parse_index(&index, path)?;
+        Ok(Self { path: path.to_path_buf(), entries })
+    }
+
+    /// `(source file name, file without fingerprints)` of every entry
+    pub fn entries(&self) -> impl Iterator<Item = (&str, &FpJsonFile)> + '_ {
+        self.entries.iter().map(|entry| (entry.source.as_str(), &entry.file))
+    }
+
+    /// Load the entry at position `i` of `entries`, with its fingerprints
+    pub fn load(&self, i: usize) -> Result<FpJsonFile> {
+        let entry = self.entries.get(i).context("Pack entry out of range")?;
+        let num_records: u64 = entry.segment_counts.iter().sum();
+        let mut records = vec![0u8; num_records as usize * RECORD_LEN];
+        let mut file = std::fs::File::open(&self.path)
+            .with_context(|| format!("Failed to read pack file {}", self.path.display()))?;
+        file.seek(SeekFrom::Start(HEADER_LEN as u64 + entry.offset * RECORD_LEN as u64))?;
+        file.read_exact(&mut records)
+            .with_context(|| format!("Pack file {} is truncated", self.path.display()))?;
+        Ok(fill_segments(entry, &records, &self.path))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::json_format::FpJsonSegment;
+
+    fn sample_file(name: &str, segments: &[usize]) -> FpJsonFile {
+        let mut file = FpJsonFile::new(format!("/audio/{}.wav", name), name.to_string(), 16000, 5000, 1);
+        file.metadata.tags.insert("label".to_string(), name.to_uppercase());
+        for (segment_id, &count) in segments.iter().enumerate() {
+            file.add_segment(FpJsonSegment {
+                segment_id,
+                start_time_s: segment_id as f64 * 20.0,
+                end_time_s: segment_id as f64 * 20.0 + 25.0,
+                num_fingerprints: count,
+                fingerprints: (0..count)
+                    .map(|i| FpJsonFingerprint {
+                        hash: u64::MAX - i as u64 * 31,
+                        t1: i as i32 * 3 - 5,
+                        f1: -(i as i16),
+                        m1: i as f32 * 0.25,
+                    })
+                    .collect(),
+            });
+        }
+        file
+    }
+
+    #[test]
+    fn test_pack_round_trip() {
+        let path = std::env::temp_dir().join(format!("panako_pack_{}.pack", std::process::id()));
+        let mut miscounted = sample_file("d", &[4]);
+        miscounted.segments[0].num_fingerprints = 40;
+        let files = vec![
+            ("a.json".to_string(), sample_file("a", &[10])),
+            ("b.bson".to_string(), sample_file("b", &[])),
+            ("c.json".to_string(), sample_file("c", &[7, 0, 3])),
+        ];
+        write_pack(&path, &files).unwrap();
+        let loaded = read_pack(&path).unwrap();
+        std::fs::remove_file(&path).ok();
+
+        assert_eq!(loaded.len(), files.len());
+        for ((source, file), (expected_source, expected)) in loaded.iter().zip(&files) {
+            assert_eq!(source, expected_source);
+            assert_eq!(serde_json::to_value(file).unwrap(), serde_json::to_value(expected).unwrap());
+        }
+
+        // A wrong per-segment count is corrected to the stored fingerprints
+        write_pack(&path, &[("d.json".to_string(), miscounted)]).unwrap();
+        let loaded = read_pack(&path).unwrap();
+        std::fs::remove_file(&path).ok();
+        assert_eq!(loaded[0].1.segments[0].num_fingerprints, 4);
+    }
+
+    #[test]
+    fn test_invalid_pack_is_rejected() {
+        let path = std::env::temp_dir().join(format!("panako_bad_pack_{}.pack", std::process::id()));
+        std::fs::write(&path, b"PNKPACK1 but far too short").unwrap();
+        let result = read_pack(&path);
+        std::fs::remove_file(&path).ok();
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_corrupt_header_sizes_are_errors() {
+        let path = std::env::temp_dir().join(format!("panako_overflow_pack_{}.pack", std::process::id()));
+        write_pack(&path, &[("a.json".to_string(), sample_file("a", &[10]))]).unwrap();
+        let valid = std::fs::read(&path).unwrap();
+
+        // Record count overflowing the size computation
+        let mut overflowing = valid.clone();
+        overflowing[16..24].copy_from_slice(&(u64::MAX / 4).to_le_bytes());
+        // Index length overflowing it
+        let mut huge_index = valid.clone();
+        huge_index[24..32].copy_from_slice(&u64::MAX.to_le_bytes());
+        // File cut inside the records
+        let truncated = valid[..HEADER_LEN + 5 * RECORD_LEN].to_vec();
+
+        for bytes in [overflowing, huge_index, truncated] {
+            std::fs::write(&path, &bytes).unwrap();
+            assert!(read_pack(&path).is_err());
+            assert!(PackIndex::open(&path).is_err());
+        }
+        std::fs::remove_file(&path).ok();
+    }
+
+    #[test]
+    fn test_pack_index_loads_single_entry() {
+        let path = std::env::temp_dir().join(format!("panako_pack_index_{}.pack", std::process::id()));
+        let files = vec![
+            ("a.json".to_string(), sample_file("a", &[10])),
+            ("c.json".to_string(), sample_file("c", &[7, 0, 3])),
+        ];
+        write_pack(&path, &files).unwrap();
+        let index = PackIndex::open(&path).unwrap();
+        let sources: Vec<&str> = index.entries().map(|(source, _)| source).collect();
+        assert_eq!(sources, ["a.json", "c.json"]);
+        assert!(index.entries().all(|(_, file)| file.get_all_fingerprints().is_empty()));
+        let loaded = index.load(1).unwrap();
+        std::fs::remove_file(&path).ok();
+        assert_eq!(serde_json::to_value(loaded).unwrap(), serde_json::to_value(&files[1].1).unwrap());
+    }
+}
@@ -160,3 +160,94 @@ impl FpReader {
         Ok(f32::from_le_bytes(buf))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::writer::FpWriter;
+    use crate::VERSION;
+
+    /// A two-fingerprint file in the documented layout; any change to how
+    /// files are read or written breaks this test.
+    const GOLDEN_FP: &[u8] = &[
+        // Header: magic "FPAN", version 1, flags 0
+        0x46, 0x50, 0x41, 0x4E, 0x01, 0x00, 0x00, 0x00,
+        // metadata_size 20
+        0x14, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        // payload_size 40
+        0x28, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        // payload_size_compressed 0
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        // num_fingerprints 2, sample_rate 16000
+        0x02, 0x00, 0x00, 0x00, 0x80, 0x3E, 0x00, 0x00,
+        // duration_ms 5000, channels 1, reserved1 0
+        0x88, 0x13, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00,
+        // checksum 0
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        // reserved2 0
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        // Metadata: "PANAKO\0\0", params length 2, "{}", "a.wav\0"
+        0x50, 0x41, 0x4E, 0x41, 0x4B, 0x4F, 0x00, 0x00,
+        0x02, 0x00, 0x00, 0x00, 0x7B, 0x7D,
+        0x61, 0x2E, 0x77, 0x61, 0x76, 0x00,
+        // Fingerprint (0x0123456789ABCDEF, t1 -5, f1 -3, m1 0.5)
+        0xEF, 0xCD, 0xAB, 0x89, 0x67, 0x45, 0x23, 0x01,
+        0xFB, 0xFF, 0xFF, 0xFF, 0xFD, 0xFF, 0x00, 0x00, 0x00, 0x00, 0x00, 0x3F,
+        // Fingerprint (42, t1 1000, f1 128, m1 -1.25)
+        0x2A, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0xE8, 0x03, 0x00, 0x00, 0x80, 0x00, 0x00, 0x00, 0x00, 0x00, 0xA0, 0xBF,
+    ];
+
+    fn temp_path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("panako_fp_{}_{}.fp", name, std::process::id()))
+    }
+
+    #[test]
+    fn test_read_golden_file() {
+        let path = temp_path("golden_read");
+        std::fs::write(&path, GOLDEN_FP).unwrap();
+        let fp_file = FpReader::read(&path).unwrap();
+        std::fs::remove_file(&path).ok();
+
+        let header = &fp_file.header;
+        assert_eq!(header.magic, MAGIC);
+        assert_eq!(header.version, VERSION);
+        assert!(!header.is_compressed());
+        assert_eq!(header.metadata_size, 20);
+        assert_eq!(header.payload_size, 40);
+        assert_eq!(header.num_fingerprints, 2);
+        assert_eq!(header.sample_rate, 16000);
+        assert_eq!(header.duration_ms, 5000);
+        assert_eq!(header.channels, 1);
+
+        assert_eq!(fp_file.metadata.algorithm_id, "PANAKO");
+        assert_eq!(fp_file.metadata.algorithm_params, "{}");
+        assert_eq!(fp_file.metadata.original_filename, "a.wav");
+
+        assert_eq!(
+            fp_file.fingerprints,
+            vec![(0x0123_4567_89AB_CDEF, -5, -3, 0.5), (42, 1000, 128, -1.25)]
+        );
+    }
+
+    #[test]
+    fn test_write_matches_golden_file() {
+        let fp_file = FpFile {
+            header: FpHeader::new(20, 40, 2, 16000, 5000, 1),
+            metadata: FpMetadata {
+                algorithm_id: "PANAKO".to_string(),
+                algorithm_params: "{}".to_string(),
+                original_filename: "a.wav".to_string(),
+                segmentation: None,
+            },
+            fingerprints: vec![(0x0123_4567_89AB_CDEF, -5, -3, 0.5), (42, 1000, 128, -1.25)],
+        };
+
+        let path = temp_path("golden_write");
+        FpWriter::new().write(&path, &fp_file).unwrap();
+        let bytes = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).ok();
+
+        assert_eq!(bytes, GOLDEN_FP);
+    }
+}
@@ -0,0 +1,205 @@
+//! Compact whole-file signatures for coarse deduplication
+//!
+//! A MinHash over the set of fingerprint hashes estimates how much two files
+//! share without comparing their fingerprints: the fraction of equal entries
+//! in two signatures approximates the Jaccard similarity of the hash sets.
+//! `FpJsonFile::coarse_signature` folds the first few entries into one
+//! `u64`, so likely duplicates land in the same bucket of a plain hash map
+//! before detailed matching. `FpJsonFile::similarity` is the exact,
+//! time-aligned comparison of two files.
+
+use std::collections::{HashMap, HashSet};
+
+use crate::json_format::FpJsonFile;
+
+/// Number of hash functions in a MinHash signature
+pub const MINHASH_SIZE: usize = 64;
+/// Number of leading MinHash entries folded into the coarse signature
+const COARSE_BAND: usize = 4;
+/// Time offsets (frames) within this distance count as the same alignment
+const OFFSET_TOLERANCE: i32 = 1;
+
+/// SplitMix64 finalizer, used as a family of hash functions via the seed
+fn mix64(mut x: u64) -> u64 {
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d049bb133111eb);
+    x ^ (x >> 31)
+}
+
+fn seed(index: usize) -> u64 {
+    mix64(0x9e3779b97f4a7c15u64.wrapping_mul(index as u64 + 1))
+}
+
+/// Fraction of positions at which two MinHash signatures agree, an estimate
+/// of the Jaccard similarity of the underlying hash sets
+pub fn minhash_similarity(a: &[u64], b: &[u64]) -> f64 {
+    let len = a.len().min(b.len());
+    if len == 0 {
+        return 0.0;
+    }
+    a.iter().zip(b).filter(|(x, y)| x == y).count() as f64 / len as f64
+}
+
+impl FpJsonFile {
+    /// MinHash signature (`MINHASH_SIZE` entries) of the file's distinct
+    /// fingerprint hashes; all entries are `u64::MAX` for an empty file
+    pub fn minhash_signature(&self) -> Vec<u64> {
+        let hashes: HashSet<u64> = self
+            .segments
+            .iter()
+            .flat_map(|segment| segment.fingerprints.iter().map(|fp| fp.hash))
+            .collect();
+
+        let mut signature = vec![u64::MAX; MINHASH_SIZE];
+        for hash in hashes {
+            for (i, min) in signature.iter_mut().enumerate() {
+                *min = (*min).min(mix64(hash ^ seed(i)));
+            }
+        }
+        signature
+    }
+
+    /// Single `u64` bucket key for coarse deduplication
+    ///
+    /// Identical fingerprint sets always share it; two files whose hash sets
+    /// have Jaccard similarity `s` share it with probability about `s^4`, so
+    /// unrelated files practically never collide. Segmentation, metadata and
+    /// fingerprint order do not affect it.
+    pub fn coarse_signature(&self) -> u64 {
+        self.minhash_signature()[..COARSE_BAND]
+            .iter()
+            .fold(0, |acc, &min| mix64(acc ^ min))
+    }
+
+    /// Similarity in [0, 1] of two files of the same analysis parameters
+    ///
+    /// The number of fingerprints (distinct hash and `t1`) the two files
+    /// share at one common time offset, over the fingerprints of the larger
+    /// file. A file compared with itself scores 1, an excerpt of a file
+    /// scores about its share of the file's length, and unrelated audio
+    /// scores close to 0. Both files must store whole-file times, as `fpgen`
+    /// does for segmented files too.
+    pub fn similarity(&self, other: &FpJsonFile) -> f64 {
+        let distinct = |file: &FpJsonFile| -> HashSet<(u64, i32)> {
+            file.segments
+                .iter()
+                .flat_map(|segment| segment.fingerprints.iter().map(|fp| (fp.hash, fp.t1)))
+                .collect()
+        };
+        let (a, b) = (distinct(self), distinct(other));
+        let (small, large) = if a.len() <= b.len() { (a, b) } else { (b, a) };
+        if small.is_empty() {
+            return 0.0;
+        }
+
+        let mut times: HashMap<u64, Vec<i32>> = HashMap::new();
+        for &(hash, t1) in &large {
+            times.entry(hash).or_default().push(t1);
+        }
+        let mut offsets: HashMap<i32, usize> = HashMap::new();
+        for (hash, t1) in &small {
+            for t in times.get(hash).into_iter().flatten() {
+                *offsets.entry(t - t1).or_default() += 1;
+            }
+        }
+
+        let best = offsets
+            .keys()
+            .map(|&offset| {
+                (offset - OFFSET_TOLERANCE..=offset + OFFSET_TOLERANCE)
+                    .filter_map(|o| offsets.get(&o))
+                    .sum::<usize>()
+            })
+            .max()
+            .unwrap_or(0);
+        (best as f64 / large.len() as f64).min(1.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::json_format::{FpJsonFingerprint, FpJsonSegment};
+
+    fn file_with_hashes(name: &str, hashes: impl Iterator<Item = u64>, segment_len: usize) -> FpJsonFile {
+        let mut file = FpJsonFile::new(format!("/audio/{}.wav", name), name.to_string(), 16000, 60000, 1);
+        let fingerprints: Vec<FpJsonFingerprint> = hashes
+            .enumerate()
+            .map(|(i, hash)| FpJsonFingerprint { hash, t1: i as i32 * 10, f1: 40, m1: 1.0 })
+            .collect();
+        for (segment_id, chunk) in fingerprints.chunks(segment_len).enumerate() {
+            file.add_segment(FpJsonSegment {
+                segment_id,
+                start_time_s: segment_id as f64 * 10.0,
+                end_time_s: segment_id as f64 * 10.0 + 10.0,
+                num_fingerprints: chunk.len(),
+                fingerprints: chunk.to_vec(),
+            });
+        }
+        file
+    }
+
+    #[test]
+    fn test_identical_files_share_signature() {
+        let a = file_with_hashes("a", (0..2000).map(|i| i * 7919), 2000);
+        // Same hashes under another name, split into segments and reversed
+        let b = file_with_hashes("b", (0..2000).rev().map(|i| i * 7919), 300);
+        assert_eq!(a.coarse_signature(), b.coarse_signature());
+        assert_eq!(minhash_similarity(&a.minhash_signature(), &b.minhash_signature()), 1.0);
+    }
+
+    #[test]
+    fn test_different_files_do_not_collide() {
+        let signatures: HashSet<u64> = (0..50u64)
+            .map(|k| file_with_hashes("f", (0..1000).map(|i| mix64(k * 1_000_000 + i)), 1000).coarse_signature())
+            .collect();
+        assert_eq!(signatures.len(), 50);
+
+        let a = file_with_hashes("a", (0..1000).map(mix64), 1000);
+        let b = file_with_hashes("b", (0..1000).map(|i| mix64(i + 5_000_000)), 1000);
+        assert!(minhash_similarity(&a.minhash_signature(), &b.minhash_signature()) < 0.1);
+    }
+
+    #[test]
+    fn test_near_duplicates_have_high_similarity() {
+        // 95% of the hashes shared (Jaccard about 0.9)
+        let a = file_with_hashes("a", (0..2000).map(mix64), 2000);
+        let b = file_with_hashes("b", (100..2100).map(mix64), 2000);
+        let similarity = minhash_similarity(&a.minhash_signature(), &b.minhash_signature());
+        assert!(similarity > 0.75, "similarity {}", similarity);
+    }
+
+    #[test]
+    fn test_similarity_is_time_aligned() {
+        let hashes: Vec<u64> = (0..500).map(mix64).collect();
+        let a = file_with_hashes("a", hashes.iter().copied(), 100);
+        assert_eq!(a.similarity(&a), 1.0);
+
+        // An excerpt at another position only covers a fifth of the file,
+        // whichever side it is compared from
+        let mut excerpt = file_with_hashes("b", hashes[200..300].iter().copied(), 100);
+        for fp in &mut excerpt.segments[0].fingerprints {
+            fp.t1 -= 1500;
+        }
+        assert_eq!(excerpt.similarity(&a), 0.2);
+        assert_eq!(a.similarity(&excerpt), 0.2);
+
+        // Same hashes in another order share no common offset
+        let mut shuffled: Vec<u64> = hashes.clone();
+        shuffled.reverse();
+        let shuffled = file_with_hashes("c", shuffled.into_iter(), 500);
+        assert!(a.similarity(&shuffled) < 0.05);
+
+        let unrelated = file_with_hashes("d", (0..500).map(|i| mix64(i + 9_000_000)), 500);
+        assert_eq!(a.similarity(&unrelated), 0.0);
+        let empty = FpJsonFile::new("/audio/e.wav".to_string(), "e".to_string(), 16000, 0, 1);
+        assert_eq!(a.similarity(&empty), 0.0);
+    }
+
+    #[test]
+    fn test_empty_file_signature() {
+        let empty = FpJsonFile::new("/audio/e.wav".to_string(), "e".to_string(), 16000, 0, 1);
+        assert!(empty.minhash_signature().iter().all(|&min| min == u64::MAX));
+        assert_eq!(minhash_similarity(&[], &[]), 0.0);
+    }
+}
@@ -0,0 +1,12 @@
+-- Panako PostgreSQL Schema
+-- Migration 002: free-form key/value tags on fingerprint metadata
+
+ALTER TABLE fingerprint_metadata
+    ADD COLUMN IF NOT EXISTS tags JSONB NOT NULL DEFAULT '{}'::jsonb;
+
+ALTER TABLE fingerprint_metadata
+    DROP CONSTRAINT IF EXISTS chk_tags_object;
+ALTER TABLE fingerprint_metadata
+    ADD CONSTRAINT chk_tags_object CHECK (jsonb_typeof(tags) = 'object');
+
+CREATE INDEX IF NOT EXISTS idx_metadata_tags ON fingerprint_metadata USING GIN (tags);
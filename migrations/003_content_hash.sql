@@ -0,0 +1,9 @@
+-- Panako PostgreSQL Schema
+-- Migration 003: content hash of the decoded audio for idempotent ingestion
+
+ALTER TABLE fingerprint_metadata
+    ADD COLUMN IF NOT EXISTS content_hash VARCHAR(64);
+
+CREATE UNIQUE INDEX IF NOT EXISTS idx_content_hash
+    ON fingerprint_metadata(content_hash)
+    WHERE content_hash IS NOT NULL;
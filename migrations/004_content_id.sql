@@ -0,0 +1,10 @@
+-- Panako PostgreSQL Schema
+-- Migration 004: external content ID (ISRC, EIDR, ...) of each reference
+
+ALTER TABLE fingerprint_metadata
+    ADD COLUMN IF NOT EXISTS content_id VARCHAR(64);
+
+-- Not unique: several references (edits, versions) may share a content ID
+CREATE INDEX IF NOT EXISTS idx_content_id
+    ON fingerprint_metadata(content_id)
+    WHERE content_id IS NOT NULL;